@@ -55,7 +55,6 @@ def main():
 
     # Stats table
     lines.append("/// Number of scan positions per known kingdom (for diagnostics).")
-    lines.append("#[allow(dead_code)]")
     lines.append("pub const KINGDOM_STATS: &[(u32, u16, u16)] = &[")
     lines.append("    // (kingdom, historical_spawns, scan_positions)")
 
@@ -0,0 +1,209 @@
+//! Game accounts and how the scanner rotates between them
+//! (`MERCY_ACCOUNTS_FILE`, `MERCY_ACCOUNT_ROTATION`,
+//! `MERCY_ACCOUNT_COOLDOWN_MINS`).
+//!
+//! Heavy scanning on one account is risky, so more accounts can be listed in
+//! a JSON file next to the primary one from `MERCY_TB_EMAIL`. Every new
+//! browser session logs in with the least recently used account whose
+//! cooldown has passed. With `kingdom` rotation the scanner also starts a new
+//! session before each kingdom. When every account is still cooling down,
+//! the login waits for the first one to come free.
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Account {
+    pub email: String,
+    pub password: String,
+    /// Base32 TOTP secret for the 2FA prompt; without it the code is entered
+    /// through `POST /login/2fa`
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+}
+
+/// When the scanner moves on to the next account (`MERCY_ACCOUNT_ROTATION`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountRotation {
+    /// With each new browser session (start, crash or scheduled restart).
+    #[default]
+    Session,
+    /// Before each kingdom, relaunching the browser in between.
+    Kingdom,
+}
+
+impl std::str::FromStr for AccountRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "session" => Ok(AccountRotation::Session),
+            "kingdom" => Ok(AccountRotation::Kingdom),
+            other => Err(format!("unknown account rotation: {other}")),
+        }
+    }
+}
+
+/// Accounts from a JSON file: `[{"email", "password", "totp_secret"?}]`.
+pub fn load_accounts_file(path: &Path) -> Result<Vec<Account>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// An account as reported in `/status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountStatus {
+    pub email: String,
+    pub active: bool,
+    pub last_used: Option<DateTime<Utc>>,
+    /// Set while the account is cooling down
+    pub cooldown_until: Option<DateTime<Utc>>,
+    pub sessions: u32,
+    pub kingdoms_scanned: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Usage {
+    last_used: Option<DateTime<Utc>>,
+    sessions: u32,
+    kingdoms_scanned: u32,
+}
+
+/// Usage of each configured account (by index), and which one is logged in.
+#[derive(Debug)]
+pub struct AccountPool {
+    usage: Vec<Usage>,
+    active: Option<usize>,
+    /// Kingdoms scanned by the active account since it logged in
+    session_kingdoms: u32,
+    cooldown: chrono::Duration,
+}
+
+impl AccountPool {
+    pub fn new(accounts: usize, cooldown: chrono::Duration) -> Self {
+        Self {
+            usage: vec![Usage::default(); accounts],
+            active: None,
+            session_kingdoms: 0,
+            cooldown,
+        }
+    }
+
+    /// Pick the account for a new session and make it the active one,
+    /// releasing the previous one. Returns its index and how long it still
+    /// has to cool down (zero when some account is free, or there's only
+    /// one). `None` without accounts (cookie login only).
+    pub fn select(&mut self, now: DateTime<Utc>) -> Option<(usize, chrono::Duration)> {
+        self.release(now);
+        let (index, usage) = self
+            .usage
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, usage)| usage.last_used)?;
+        let wait = match usage.last_used {
+            Some(last) if self.usage.len() > 1 => {
+                (last + self.cooldown - now).max(chrono::Duration::zero())
+            }
+            _ => chrono::Duration::zero(),
+        };
+        self.active = Some(index);
+        self.session_kingdoms = 0;
+        self.usage[index].sessions += 1;
+        self.usage[index].last_used = Some(now + wait);
+        Some((index, wait))
+    }
+
+    /// The active account finished a kingdom.
+    pub fn kingdom_scanned(&mut self, now: DateTime<Utc>) {
+        if let Some(index) = self.active {
+            self.usage[index].kingdoms_scanned += 1;
+            self.usage[index].last_used = Some(now);
+            self.session_kingdoms += 1;
+        }
+    }
+
+    /// Whether `kingdom` rotation should switch accounts before the next
+    /// kingdom: the active one has scanned one and there's another to use.
+    pub fn rotation_due(&self) -> bool {
+        self.usage.len() > 1 && self.session_kingdoms > 0
+    }
+
+    /// The active account's session ended.
+    pub fn release(&mut self, now: DateTime<Utc>) {
+        if let Some(index) = self.active.take() {
+            self.usage[index].last_used = Some(now);
+        }
+    }
+
+    pub fn status(&self, accounts: &[Account], now: DateTime<Utc>) -> Vec<AccountStatus> {
+        accounts
+            .iter()
+            .zip(&self.usage)
+            .enumerate()
+            .map(|(index, (account, usage))| {
+                let active = self.active == Some(index);
+                AccountStatus {
+                    email: account.email.clone(),
+                    active,
+                    last_used: usage.last_used,
+                    cooldown_until: usage
+                        .last_used
+                        .map(|last| last + self.cooldown)
+                        .filter(|&until| !active && until > now),
+                    sessions: usage.sessions,
+                    kingdoms_scanned: usage.kingdoms_scanned,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_pool_rotation() {
+        let t0 = Utc::now();
+        let minutes = chrono::Duration::minutes;
+        let mut pool = AccountPool::new(2, chrono::Duration::minutes(30));
+
+        // Unused accounts first, in order
+        assert_eq!(pool.select(t0), Some((0, minutes(0))));
+        assert!(!pool.rotation_due());
+        pool.kingdom_scanned(t0 + minutes(10));
+        assert!(pool.rotation_due());
+        assert_eq!(pool.select(t0 + minutes(10)), Some((1, minutes(0))));
+
+        // Both used: the first comes free 30 minutes after its release
+        assert_eq!(pool.select(t0 + minutes(20)), Some((0, minutes(20))));
+        let accounts = vec![
+            Account {
+                email: "a@example.com".into(),
+                password: String::new(),
+                totp_secret: None,
+            },
+            Account {
+                email: "b@example.com".into(),
+                password: String::new(),
+                totp_secret: None,
+            },
+        ];
+        let status = pool.status(&accounts, t0 + minutes(20));
+        assert!(status[0].active && status[0].cooldown_until.is_none());
+        assert_eq!(status[1].cooldown_until, Some(t0 + minutes(50)));
+        assert_eq!((status[0].sessions, status[0].kingdoms_scanned), (2, 1));
+
+        // A single account never waits
+        let mut single = AccountPool::new(1, chrono::Duration::minutes(30));
+        single.select(t0);
+        assert_eq!(single.select(t0 + minutes(1)), Some((0, minutes(0))));
+        assert!(!single.rotation_due());
+        assert_eq!(
+            AccountPool::new(0, chrono::Duration::minutes(30)).select(t0),
+            None
+        );
+    }
+}
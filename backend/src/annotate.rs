@@ -0,0 +1,318 @@
+//! Debug screenshot annotation: match boxes and scores drawn onto the frame,
+//! so a saved screenshot shows what the detector saw without cross-referencing
+//! log lines. Also renders a scan plan (`GET /scan-plan?format=png`) as a map
+//! of the steps and what each one sees.
+
+use anyhow::{Context, Result};
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut};
+use imageproc::rect::Rect;
+
+use crate::detector::{self, PreparedRef, SCALE_DOWN, TemplateMatch};
+use crate::viewport::Viewport;
+
+const VIEWPORT_COLOR: Rgb<u8> = Rgb([60, 140, 255]);
+/// Candidates that did not make the cut (below threshold or rejected).
+const CANDIDATE_COLOR: Rgb<u8> = Rgb([255, 200, 0]);
+const ACCEPTED_COLOR: Rgb<u8> = Rgb([0, 255, 80]);
+
+/// Pixel size of one glyph dot.
+const LABEL_SCALE: u32 = 2;
+
+/// Game units per pixel of a rendered scan plan (a 1024-unit kingdom at 512 px).
+const PLAN_UNITS_PER_PX: u32 = 2;
+const PLAN_SIZE: u32 = 1024 / PLAN_UNITS_PER_PX;
+
+/// Decode a PNG screenshot, outline the viewport, draw a template-sized box
+/// and score label for every candidate (accepted matches in green, the rest
+/// in yellow) and re-encode it. Match coordinates are full-screenshot pixels;
+/// `origin` is where the PNG's top-left pixel sits in the full screenshot
+/// (non-zero for a capture clipped to a region).
+pub fn annotate_screenshot(
+    png: &[u8],
+    origin: (u32, u32),
+    viewport: Viewport,
+    refs: &[PreparedRef],
+    candidates: &[TemplateMatch],
+    accepted: &[TemplateMatch],
+) -> Result<Vec<u8>> {
+    let mut image = decode(png, origin, viewport)?;
+    let origin = (origin.0 as i32, origin.1 as i32);
+    for m in candidates {
+        draw_match(&mut image, origin, refs, m, CANDIDATE_COLOR);
+    }
+    for m in accepted {
+        draw_match(&mut image, origin, refs, m, ACCEPTED_COLOR);
+    }
+    detector::encode_png(&image)
+}
+
+/// Annotate a full screenshot with its best match, green if it clears its
+/// template's threshold, and label the box with the game coordinates
+/// `estimate` (kingdom, x, y) the match is estimated to be at.
+pub fn annotate_best_match(
+    png: &[u8],
+    viewport: Viewport,
+    refs: &[PreparedRef],
+    best: Option<&TemplateMatch>,
+    estimate: Option<(u32, u32, u32)>,
+) -> Result<Vec<u8>> {
+    let mut image = decode(png, (0, 0), viewport)?;
+    if let Some(m) = best {
+        let accepted = refs.get(m.template).is_some_and(|r| m.score >= r.threshold);
+        let color = if accepted {
+            ACCEPTED_COLOR
+        } else {
+            CANDIDATE_COLOR
+        };
+        let (left, below) = draw_match(&mut image, (0, 0), refs, m, color);
+        if let Some((k, x, y)) = estimate {
+            draw_label(
+                &mut image,
+                left,
+                below,
+                &format!("K:{k} X:{x} Y:{y}"),
+                color,
+            );
+        }
+    }
+    detector::encode_png(&image)
+}
+
+/// Render the map `positions` a scan visits, in order: the area each step
+/// sees (`footprint`, game units wide and high) outlined around it, the path
+/// between them and the first step marked. Labelled with the step count.
+pub fn render_scan_plan(positions: &[(u32, u32)], footprint: (u32, u32)) -> Result<Vec<u8>> {
+    let mut image = RgbImage::from_pixel(PLAN_SIZE, PLAN_SIZE, Rgb([20, 20, 20]));
+    let to_px = |v: u32| (v / PLAN_UNITS_PER_PX) as i32;
+    let (w, h) = (
+        (footprint.0 / PLAN_UNITS_PER_PX).max(1),
+        (footprint.1 / PLAN_UNITS_PER_PX).max(1),
+    );
+    for &(x, y) in positions {
+        draw_hollow_rect_mut(
+            &mut image,
+            Rect::at(to_px(x) - (w / 2) as i32, to_px(y) - (h / 2) as i32).of_size(w, h),
+            VIEWPORT_COLOR,
+        );
+    }
+    for pair in positions.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        draw_line_segment_mut(
+            &mut image,
+            (to_px(from.0) as f32, to_px(from.1) as f32),
+            (to_px(to.0) as f32, to_px(to.1) as f32),
+            CANDIDATE_COLOR,
+        );
+    }
+    if let Some(&(x, y)) = positions.first() {
+        draw_filled_rect_mut(
+            &mut image,
+            Rect::at(to_px(x) - 3, to_px(y) - 3).of_size(7, 7),
+            ACCEPTED_COLOR,
+        );
+    }
+    draw_label(
+        &mut image,
+        2,
+        2,
+        &positions.len().to_string(),
+        CANDIDATE_COLOR,
+    );
+    detector::encode_png(&image)
+}
+
+/// Decode a PNG screenshot and outline the viewport on it.
+fn decode(png: &[u8], origin: (u32, u32), viewport: Viewport) -> Result<RgbImage> {
+    let mut image = image::load_from_memory(png)
+        .context("failed to decode screenshot")?
+        .into_rgb8();
+    if viewport.width() > 0 && viewport.height() > 0 {
+        draw_hollow_rect_mut(
+            &mut image,
+            Rect::at(
+                viewport.left as i32 - origin.0 as i32,
+                viewport.top as i32 - origin.1 as i32,
+            )
+            .of_size(viewport.width(), viewport.height()),
+            VIEWPORT_COLOR,
+        );
+    }
+    Ok(image)
+}
+
+/// Box the template footprint around `m` and label it with its score.
+/// Returns the box's left edge and the first row below what was drawn.
+fn draw_match(
+    image: &mut RgbImage,
+    origin: (i32, i32),
+    refs: &[PreparedRef],
+    m: &TemplateMatch,
+    color: Rgb<u8>,
+) -> (i32, i32) {
+    let (w, h) = refs
+        .get(m.template)
+        .map_or((16, 16), |r| (r.width * SCALE_DOWN, r.height * SCALE_DOWN));
+    let left = m.x as i32 - (w / 2) as i32 - origin.0;
+    let top = m.y as i32 - (h / 2) as i32 - origin.1;
+    let rect = Rect::at(left, top).of_size(w.max(1), h.max(1));
+    draw_hollow_rect_mut(image, rect, color);
+    // Double line so the box stays visible on busy terrain
+    if w > 2 && h > 2 {
+        draw_hollow_rect_mut(
+            image,
+            Rect::at(left + 1, top + 1).of_size(w - 2, h - 2),
+            color,
+        );
+    }
+
+    let label = format!("{:.3}", m.score);
+    let label_h = 5 * LABEL_SCALE + 2;
+    let bottom = top + h as i32;
+    if top >= label_h as i32 {
+        draw_label(image, left, top - label_h as i32, &label, color);
+        (left, bottom)
+    } else {
+        draw_label(image, left, bottom, &label, color);
+        (left, bottom + label_h as i32)
+    }
+}
+
+/// Draw `text` (digits, `.`, `-`, `:` and `K`/`X`/`Y` only) on a dark backing box with its
+/// top-left at (`x`, `y`).
+fn draw_label(image: &mut RgbImage, x: i32, y: i32, text: &str, color: Rgb<u8>) {
+    let advance = 4 * LABEL_SCALE;
+    let width = advance * text.chars().count() as u32 + LABEL_SCALE;
+    draw_filled_rect_mut(
+        image,
+        Rect::at(x, y).of_size(width, 5 * LABEL_SCALE + 2),
+        Rgb([0, 0, 0]),
+    );
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else {
+            continue;
+        };
+        let gx = x + (LABEL_SCALE + i as u32 * advance) as i32;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                draw_filled_rect_mut(
+                    image,
+                    Rect::at(
+                        gx + (col * LABEL_SCALE) as i32,
+                        y + 1 + (row as u32 * LABEL_SCALE) as i32,
+                    )
+                    .of_size(LABEL_SCALE, LABEL_SCALE),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// 3×5 bitmap glyph, one row per entry (top first), high bit on the left.
+/// Scores and coordinates need only a few characters, so there's no font
+/// dependency.
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::detector::{RefImage, prepare_reference_images};
+
+    #[test]
+    fn test_render_scan_plan() {
+        let png = render_scan_plan(&[(512, 512), (600, 512)], (40, 40)).unwrap();
+        let out = image::load_from_memory(&png).unwrap().into_rgb8();
+        assert_eq!(out.dimensions(), (PLAN_SIZE, PLAN_SIZE));
+        // The first step is marked, the second outlined
+        assert_eq!(*out.get_pixel(256, 256), ACCEPTED_COLOR);
+        assert_eq!(*out.get_pixel(300 - 10, 256 + 4), VIEWPORT_COLOR);
+    }
+
+    #[test]
+    fn test_annotate_draws_boxes_and_label() {
+        let frame = RgbImage::from_pixel(200, 120, Rgb([40, 40, 40]));
+        let mut png = Vec::new();
+        frame
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let template = RgbImage::from_fn(20, 20, |x, y| Rgb([(x * 12) as u8, (y * 12) as u8, 0]));
+        let refs = prepare_reference_images(&[RefImage::new(
+            "t",
+            Arc::new(DynamicImage::ImageRgb8(template)),
+        )]);
+        let m = TemplateMatch {
+            x: 100,
+            y: 60,
+            score: 0.987,
+            template: 0,
+            channels: None,
+            subpixel: (0.0, 0.0),
+        };
+        let viewport = Viewport {
+            left: 10,
+            top: 10,
+            right: 190,
+            bottom: 110,
+        };
+
+        let out = annotate_screenshot(&png, (0, 0), viewport, &refs, &[], std::slice::from_ref(&m))
+            .unwrap();
+        let out = image::load_from_memory(&out).unwrap().into_rgb8();
+        assert_eq!(*out.get_pixel(10, 50), VIEWPORT_COLOR);
+        assert_eq!(
+            *out.get_pixel(90, 60),
+            ACCEPTED_COLOR,
+            "left edge of the box"
+        );
+        assert_eq!(*out.get_pixel(100, 60), Rgb([40, 40, 40]), "box is hollow");
+        let label_px = (90..130).flat_map(|x| (38..50).map(move |y| (x, y)));
+        assert!(
+            label_px
+                .filter(|&(x, y)| *out.get_pixel(x, y) == ACCEPTED_COLOR)
+                .count()
+                > 10,
+            "score label drawn above the box"
+        );
+
+        let out =
+            annotate_best_match(&png, viewport, &refs, Some(&m), Some((111, 506, 732))).unwrap();
+        let out = image::load_from_memory(&out).unwrap().into_rgb8();
+        assert_eq!(*out.get_pixel(90, 60), ACCEPTED_COLOR);
+        let coords_px = (90..190).flat_map(|x| (70..82).map(move |y| (x, y)));
+        assert!(
+            coords_px
+                .filter(|&(x, y)| *out.get_pixel(x, y) == ACCEPTED_COLOR)
+                .count()
+                > 50,
+            "coordinates drawn below the box"
+        );
+    }
+}
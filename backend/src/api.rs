@@ -1,105 +1,201 @@
 use std::sync::Arc;
 
-use axum::extract::{Path, Query, State};
-use axum::http::{HeaderMap, StatusCode, header};
-use axum::response::IntoResponse;
+use axum::extract::{DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::detector::{self, PreparedRef};
+use crate::accounts::AccountStatus;
+use crate::annotate;
+use crate::audit::{AuditEntry, AuditLog};
+use crate::browser::GameBrowser;
+use crate::config::{Config, ConfigOverrides};
+use crate::detector::{self, ChannelScores, DetectorHandle, PreparedScreenshot};
+use crate::driver::Key;
+use crate::frame::CanvasFrame;
+use crate::health::Health;
+use crate::locate;
+use crate::metrics::{CounterValues, Gauge, StorageUsage};
+use crate::recent_errors::RecentError;
+use crate::recorder;
+use crate::reload;
+use crate::report;
+use crate::resources::ResourceUsage;
 use crate::scanner;
-use crate::state::{AppState, ScannerPhase};
-
-pub fn router(state: AppState, ref_images: Arc<Vec<PreparedRef>>) -> Router {
+use crate::screenshot_history::{Shot, shared_body};
+use crate::screenshots;
+use crate::state::{
+    AppState, AppStateInner, Challenge, ExchangeBook, ExchangeDetails, MercExchange, ScannerPhase,
+    StateSnapshot,
+};
+use crate::supervisor::{self, ScannerFailure};
+use crate::target::TargetProfile;
+use crate::viewport::Viewport;
+
+pub fn router(
+    state: AppState,
+    config: Arc<Config>,
+    exchanges: Arc<ExchangeBook>,
+    detectors: Arc<DetectorHandle>,
+    audit: Arc<AuditLog>,
+) -> Router {
+    let api = ApiState {
+        app: state,
+        audit,
+        config,
+        exchanges,
+        detectors,
+    };
     Router::new()
         .route("/start", post(start_scan))
         .route("/stop", post(stop_scan))
         .route("/pause", post(pause_scan))
         .route("/prepare", post(prepare_session))
         .route("/logout", post(logout_session))
+        .route("/login/2fa", post(submit_two_factor))
         .route("/status", get(get_status))
         .route("/exchanges", get(get_exchanges))
+        .route("/console", get(get_console))
+        .route("/events/recent", get(get_recent_events))
+        .route("/stats", get(get_stats))
+        .route("/state/export", get(export_state))
+        .route("/state/import", post(import_state))
         .route(
-            "/exchanges/{index}/screenshot",
-            get(get_exchange_screenshot),
+            "/config",
+            get(get_config).patch(patch_config).delete(reset_config),
         )
+        .route("/config/reload", post(reload_config))
+        .route("/exchanges/{id}", get(get_exchange))
+        .route("/exchanges/{id}/screenshot", get(get_exchange_screenshot))
+        .route("/recordings", get(get_recordings))
+        .route("/recordings/{name}", get(get_recording))
         .route("/screenshot", get(get_screenshot))
+        .route("/screenshot/annotated", get(get_annotated_screenshot))
+        .route("/screenshots/recent", get(get_recent_screenshots))
+        .route("/screenshots/recent/{id}", get(get_recent_screenshot))
         .route("/goto", get(goto_coords))
+        .route("/verify", post(verify_location))
+        .route("/control", post(control_browser))
         .route("/detect", get(detect_match))
+        .route(
+            "/detect/batch",
+            post(detect_batch).layer(DefaultBodyLimit::max(DETECT_BATCH_BODY_LIMIT)),
+        )
         .route("/scan-kingdom", post(scan_kingdom_handler))
-        .with_state(ApiState {
-            app: state,
-            ref_images,
-        })
+        .route("/scan/custom", post(scan_custom))
+        .route("/scan-plan", get(get_scan_plan))
+        .route("/refs/from-screenshot", post(ref_from_screenshot))
+        .route("/audit", get(get_audit))
+        .layer(middleware::from_fn_with_state(api.clone(), audit_calls))
+        .with_state(api)
 }
 
 #[derive(Clone)]
 struct ApiState {
     app: AppState,
-    ref_images: Arc<Vec<PreparedRef>>,
+    /// The same as the state's, so auth checks don't take its lock.
+    config: Arc<Config>,
+    /// The same as the state's; locked separately from it.
+    exchanges: Arc<ExchangeBook>,
+    detectors: Arc<DetectorHandle>,
+    audit: Arc<AuditLog>,
 }
 
-fn check_auth(headers: &HeaderMap, expected_token: &str) -> Result<(), StatusCode> {
-    let auth = headers
+/// The bearer token of a request, if it has one.
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
         .get("authorization")
         .and_then(|v| v.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+}
 
-    if let Some(token) = auth.strip_prefix("Bearer ")
-        && token == expected_token
-    {
+fn check_auth(headers: &HeaderMap, expected_token: &str) -> Result<(), StatusCode> {
+    let token = bearer(headers).ok_or(StatusCode::UNAUTHORIZED)?;
+    if token == expected_token {
         return Ok(());
     }
 
     Err(StatusCode::UNAUTHORIZED)
 }
 
-async fn start_scan(
-    State(api): State<ApiState>,
-    headers: HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
-    let token = {
-        let state = api.app.lock().await;
-        state.config.auth_token.clone()
+/// Record authenticated control calls (any method but `GET`, and
+/// `GET /goto`) and their response status in the audit log.
+async fn audit_calls(State(api): State<ApiState>, request: Request, next: Next) -> Response {
+    let audited = request.method() != Method::GET || request.uri().path() == "/goto";
+    let caller = match bearer(request.headers()) {
+        Some(token) if token == api.config.auth_token => Some("api"),
+        Some(token) if api.config.admin_token.as_deref() == Some(token) => Some("admin"),
+        _ => None,
+    };
+    let (Some(caller), true) = (caller, audited) else {
+        return next.run(request).await;
     };
-    check_auth(&headers, &token)?;
 
-    let mut state = api.app.lock().await;
+    let method = request.method().to_string();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| request.uri().path().to_string(), |p| p.to_string());
+    let response = next.run(request).await;
+    api.audit.record(AuditEntry {
+        time: chrono::Utc::now(),
+        caller: caller.into(),
+        method,
+        path,
+        status: response.status().as_u16(),
+    });
+    response
+}
 
-    match state.phase {
-        ScannerPhase::Paused => {
-            // Resume: set phase to Scanning and wake the paused scanner
-            state.phase = ScannerPhase::Scanning;
-            state.pause_notify.notify_one();
-            Ok(Json(json!({"status": "resumed"})))
-        }
-        ScannerPhase::Idle | ScannerPhase::Ready => {
-            // Stop existing scanner handle if any
-            if let Some(handle) = state.scanner_handle.take() {
-                handle.abort();
-            }
+/// `?token=` for the image endpoints.
+#[derive(Deserialize)]
+struct TokenParams {
+    token: Option<String>,
+}
 
-            // Clear exchanges and start fresh
-            state.exchanges.clear();
-            state.current_kingdom = None;
+/// [`check_auth`], or the token as `?token=`, so images can be embedded as
+/// `<img src>` where no header can be set. Only for `GET` image endpoints:
+/// everything else needs the header.
+fn check_image_auth(
+    headers: &HeaderMap,
+    token: Option<&str>,
+    expected_token: &str,
+) -> Result<(), StatusCode> {
+    if token == Some(expected_token) {
+        return Ok(());
+    }
+    check_auth(headers, expected_token)
+}
 
-            let app_state = api.app.clone();
-            let ref_images = api.ref_images.clone();
-            let handle = tokio::spawn(async move {
-                if let Err(e) = scanner::run_scan(app_state.clone(), ref_images).await {
-                    tracing::error!("scanner error: {e:#}");
-                    let mut state = app_state.lock().await;
-                    state.phase = ScannerPhase::Idle;
-                }
-            });
+/// Like [`check_auth`], against `MERCY_ADMIN_TOKEN`. Without one the
+/// admin endpoints are off.
+fn check_admin(headers: &HeaderMap, config: &Config) -> Result<(), StatusCode> {
+    let token = config.admin_token.as_deref().ok_or(StatusCode::FORBIDDEN)?;
+    check_auth(headers, token)
+}
 
-            state.scanner_handle = Some(handle);
+/// Start a scan, with an optional JSON body of [`ConfigOverrides`] for this
+/// run, or resume a paused one.
+async fn start_scan(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    body: Option<Json<ConfigOverrides>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let run_overrides = body.map(|Json(o)| o).unwrap_or_default();
+    run_overrides.validate().map_err(|e| {
+        tracing::warn!("invalid start parameters: {e}");
+        StatusCode::BAD_REQUEST
+    })?;
 
-            Ok(Json(json!({"status": "started"})))
-        }
-        ScannerPhase::Scanning | ScannerPhase::Preparing => Err(StatusCode::CONFLICT),
+    match supervisor::start(&api.app, &api.detectors, run_overrides).await {
+        Some(status) => Ok(Json(json!({ "status": status }))),
+        None => Err(StatusCode::CONFLICT),
     }
 }
 
@@ -107,29 +203,9 @@ async fn stop_scan(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let token = {
-        let state = api.app.lock().await;
-        state.config.auth_token.clone()
-    };
-    check_auth(&headers, &token)?;
-
-    let mut state = api.app.lock().await;
-
-    if let Some(handle) = state.scanner_handle.take() {
-        handle.abort();
-    }
-
-    // Wake any paused waiter so it can exit
-    state.pause_notify.notify_one();
-
-    // Keep browser alive: Ready if browser exists, Idle otherwise
-    state.phase = if state.browser.is_some() {
-        ScannerPhase::Ready
-    } else {
-        ScannerPhase::Idle
-    };
-    state.manual_scan_kingdom = None;
+    check_auth(&headers, &api.config.auth_token)?;
 
+    supervisor::stop(&api.app).await;
     Ok(Json(json!({"status": "stopped"})))
 }
 
@@ -137,24 +213,12 @@ async fn pause_scan(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let token = {
-        let state = api.app.lock().await;
-        state.config.auth_token.clone()
-    };
-    check_auth(&headers, &token)?;
-
-    let mut state = api.app.lock().await;
+    check_auth(&headers, &api.config.auth_token)?;
 
-    match state.phase {
-        ScannerPhase::Scanning => {
-            state.phase = ScannerPhase::Paused;
-            Ok(Json(json!({"status": "paused"})))
-        }
-        ScannerPhase::Paused => {
-            // Idempotent
-            Ok(Json(json!({"status": "paused"})))
-        }
-        _ => Err(StatusCode::CONFLICT),
+    if supervisor::pause(&api.app).await {
+        Ok(Json(json!({"status": "paused"})))
+    } else {
+        Err(StatusCode::CONFLICT)
     }
 }
 
@@ -162,13 +226,9 @@ async fn prepare_session(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let token = {
-        let state = api.app.lock().await;
-        state.config.auth_token.clone()
-    };
-    check_auth(&headers, &token)?;
+    check_auth(&headers, &api.config.auth_token)?;
 
-    let state = api.app.lock().await;
+    let state = api.app.read().await;
 
     match state.phase {
         ScannerPhase::Idle => {
@@ -178,15 +238,17 @@ async fn prepare_session(
             tokio::spawn(async move {
                 if let Err(e) = scanner::prepare_browser(&app_state).await {
                     tracing::error!("prepare failed: {e:#}");
-                    let mut s = app_state.lock().await;
-                    s.phase = ScannerPhase::Idle;
+                    let mut s = app_state.write().await;
+                    s.set_phase(ScannerPhase::Idle);
                 }
             });
 
             Ok(Json(json!({"status": "preparing"})))
         }
         ScannerPhase::Ready | ScannerPhase::Paused => Ok(Json(json!({"status": "ready"}))),
-        ScannerPhase::Preparing | ScannerPhase::Scanning => Err(StatusCode::CONFLICT),
+        ScannerPhase::Preparing | ScannerPhase::WaitingFor2fa | ScannerPhase::Scanning => {
+            Err(StatusCode::CONFLICT)
+        }
     }
 }
 
@@ -194,13 +256,9 @@ async fn logout_session(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let token = {
-        let state = api.app.lock().await;
-        state.config.auth_token.clone()
-    };
-    check_auth(&headers, &token)?;
+    check_auth(&headers, &api.config.auth_token)?;
 
-    let mut state = api.app.lock().await;
+    let mut state = api.app.write().await;
 
     // Abort scanner if running
     if let Some(handle) = state.scanner_handle.take() {
@@ -210,13 +268,44 @@ async fn logout_session(
     // Wake any paused waiter so it can exit
     state.pause_notify.notify_one();
 
+    // Cancels a login waiting for a 2FA code
+    state.two_factor_tx = None;
+
     // Drop browser (kills Chromium)
     state.browser = None;
-    state.phase = ScannerPhase::Idle;
+    state.api_tab = None;
+    state.health = None;
+    state.resources = None;
+    state.accounts.release(chrono::Utc::now());
+    state.set_phase(ScannerPhase::Idle);
 
     Ok(Json(json!({"status": "logged_out"})))
 }
 
+#[derive(Deserialize)]
+struct TwoFactorRequest {
+    code: String,
+}
+
+/// Hand a 2FA code to a login waiting in `WaitingFor2fa`.
+async fn submit_two_factor(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(body): Json<TwoFactorRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let mut state = api.app.write().await;
+
+    let code: String = body.code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let tx = state.two_factor_tx.take().ok_or(StatusCode::CONFLICT)?;
+    tx.send(code).map_err(|_| StatusCode::CONFLICT)?;
+
+    Ok(Json(json!({"status": "submitted"})))
+}
+
 #[derive(Serialize)]
 struct StatusResponse {
     phase: ScannerPhase,
@@ -225,22 +314,81 @@ struct StatusResponse {
     current_kingdom: Option<u32>,
     exchanges_found: usize,
     manual_scan_kingdom: Option<u32>,
+    viewport: Viewport,
+    /// Game canvas on the page, while a browser is up (see `frame.rs`).
+    canvas: Option<CanvasFrame>,
+    /// Latest browser health check (see `health.rs`).
+    health: Option<Health>,
+    /// Browser memory use and session age (see `resources.rs`).
+    resources: Option<ResourceUsage>,
+    /// Configured game accounts, which one is logged in and their cooldowns.
+    accounts: Vec<AccountStatus>,
+    /// Operational counters since startup (see `metrics.rs`).
+    counters: CounterValues,
+    /// Use of the capped in-memory and on-disk stores.
+    storage: StorageUsage,
+    /// Chrome DevTools endpoint of the browser (`MERCY_DEBUG_PORT`).
+    devtools_url: Option<String>,
+    /// Set while the scanner is paused at a captcha/verification challenge.
+    challenge: Option<Challenge>,
+    /// Last failure of the scan loop, and when it restarts (see
+    /// `supervisor.rs`).
+    scanner_failure: Option<ScannerFailure>,
+    /// Recent scanner and browser errors, oldest first (see
+    /// `recent_errors.rs`).
+    recent_errors: Vec<RecentError>,
 }
 
 async fn get_status(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_auth(&headers, &api.config.auth_token)?;
+    let dir = api.config.screenshot_dir.clone();
+    let (files, bytes) = tokio::task::spawn_blocking(move || screenshots::usage(&dir))
+        .await
+        .map_err(|e| {
+            tracing::error!("screenshot usage task panicked: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .unwrap_or_else(|e| {
+            tracing::warn!("failed to read the screenshot directory: {e}");
+            (0, 0)
+        });
+    let state = api.app.read().await;
+    let storage = StorageUsage {
+        exchanges: Gauge::new(api.exchanges.len(), api.exchanges.max_len()),
+        events: Gauge::new(state.events.len(), Some(state.events.capacity())),
+        screenshots: Gauge::new(files, api.config.screenshot_dir_max_files),
+        screenshot_bytes: Gauge {
+            used: bytes,
+            max: api.config.screenshot_dir_max_mb.map(|mb| mb * 1024 * 1024),
+        },
+    };
 
     Ok(Json(StatusResponse {
         phase: state.phase,
         running: state.phase == ScannerPhase::Scanning,
         paused: state.phase == ScannerPhase::Paused,
         current_kingdom: state.current_kingdom,
-        exchanges_found: state.exchanges.len(),
+        exchanges_found: api.exchanges.len(),
         manual_scan_kingdom: state.manual_scan_kingdom,
+        viewport: state.viewport,
+        canvas: state.browser.as_ref().map(|b| b.frame()),
+        health: state.health.clone(),
+        resources: state.resources.clone(),
+        counters: state.counters.values(),
+        storage,
+        accounts: state
+            .accounts
+            .status(&state.config.accounts, chrono::Utc::now()),
+        devtools_url: state
+            .browser
+            .as_ref()
+            .and_then(|b| b.devtools_url().map(str::to_owned)),
+        challenge: state.challenge.clone(),
+        scanner_failure: state.scanner_failure.clone(),
+        recent_errors: state.errors.recent(),
     }))
 }
 
@@ -248,29 +396,245 @@ async fn get_exchanges(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_auth(&headers, &api.config.auth_token)?;
+
+    Ok(Json(api.exchanges.list()))
+}
 
-    Ok(Json(state.exchanges.clone()))
+/// One exchange with everything known about it: the compact list entry plus
+/// the scan's scores, pattern and confirmation, and the re-checks since.
+#[derive(Serialize)]
+struct ExchangeDetailResponse {
+    #[serde(flatten)]
+    exchange: MercExchange,
+    #[serde(flatten)]
+    details: ExchangeDetails,
+    /// `/exchanges/{id}/screenshot`, while the file is there
+    screenshot: Option<String>,
+}
+
+async fn get_exchange(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let mut exchange = api.exchanges.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let screenshot = match exchange.screenshot.take() {
+        Some(path) if tokio::fs::try_exists(&path).await.unwrap_or(false) => {
+            Some(format!("/exchanges/{id}/screenshot"))
+        }
+        _ => None,
+    };
+    Ok(Json(ExchangeDetailResponse {
+        details: std::mem::take(&mut exchange.details),
+        exchange,
+        screenshot,
+    }))
+}
+
+fn config_response(state: &AppStateInner) -> serde_json::Value {
+    json!({
+        "overrides": state.overrides,
+        "run_overrides": state.run_overrides,
+        "effective": ConfigOverrides::effective(&state.effective_config()),
+    })
+}
+
+/// Runtime config overrides and the scan settings in effect.
+async fn get_config(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    Ok(Json(config_response(&*api.app.read().await)))
+}
+
+/// Set runtime overrides, keeping the ones not in the body. A running scan
+/// picks them up on its next pass.
+async fn patch_config(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(body): Json<ConfigOverrides>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    body.validate().map_err(|e| {
+        tracing::warn!("invalid config overrides: {e}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let mut state = api.app.write().await;
+    state.overrides = state.overrides.merged(&body);
+    tracing::info!("config overrides: {:?}", state.overrides);
+    Ok(Json(config_response(&state)))
+}
+
+/// Drop all runtime overrides, back to the env config.
+async fn reset_config(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let mut state = api.app.write().await;
+    state.overrides = ConfigOverrides::default();
+    Ok(Json(config_response(&state)))
+}
+
+/// Re-read the env config, as on SIGHUP, and apply what can change while
+/// running. Lists the changed fields that were applied and those that wait
+/// for a restart.
+async fn reload_config(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let reload = reload::reload(&api.app, &api.detectors)
+        .await
+        .map_err(|e| {
+            tracing::error!("config reload failed, keeping the running config: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(json!({
+        "applied": reload.applied,
+        "deferred": reload.deferred,
+    })))
+}
+
+/// Snapshot of the exchanges, kingdom stats and last scan times, for
+/// `POST /state/import` on another instance.
+async fn export_state(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    Ok(Json(api.app.read().await.export()))
+}
+
+/// Replace this instance's knowledge with an exported snapshot. Only while
+/// no scan is running.
+async fn import_state(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(snapshot): Json<StateSnapshot>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let mut state = api.app.clone().write_owned().await;
+    if !matches!(state.phase, ScannerPhase::Idle | ScannerPhase::Ready) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let exchanges = snapshot.exchanges.len();
+    let kingdoms = snapshot.last_kingdom_scan.len();
+    // Storing the exchanges waits for the database
+    tokio::task::spawn_blocking(move || state.import(snapshot))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tracing::info!("imported {exchanges} exchange(s) and last scans of {kingdoms} kingdom(s)");
+
+    Ok(Json(json!({"status": "imported", "exchanges": exchanges})))
+}
+
+/// Scan statistics of each kingdom since startup.
+async fn get_stats(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let state = api.app.read().await;
+
+    Ok(Json(json!({ "kingdoms": state.kingdom_stats })))
+}
+
+#[derive(Deserialize)]
+struct EventParams {
+    /// Most recent events to return (default 100).
+    limit: Option<usize>,
+    /// Only events of the last this many minutes.
+    minutes: Option<i64>,
+}
+
+/// The time `ago` before now, for the `?minutes=` and `?seconds=` filters.
+/// 400 when that's out of range.
+fn time_ago(ago: Option<chrono::TimeDelta>) -> Result<chrono::DateTime<chrono::Utc>, StatusCode> {
+    ago.and_then(|d| chrono::Utc::now().checked_sub_signed(d))
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
+/// Recent scanner events (phase changes, detections, confirmations,
+/// errors), oldest first.
+async fn get_recent_events(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<EventParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let since = params
+        .minutes
+        .map(|minutes| time_ago(chrono::TimeDelta::try_minutes(minutes)))
+        .transpose()?;
+    let state = api.app.read().await;
+
+    Ok(Json(
+        state.events.recent(params.limit.unwrap_or(100), since),
+    ))
+}
+
+/// Recent control calls (see `audit.rs`), oldest first. Takes the same
+/// `limit` and `minutes` as `/events/recent`.
+async fn get_audit(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<EventParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let since = params
+        .minutes
+        .map(|minutes| time_ago(chrono::TimeDelta::try_minutes(minutes)))
+        .transpose()?;
+    Ok(Json(api.audit.recent(params.limit.unwrap_or(100), since)))
+}
+
+#[derive(Deserialize)]
+struct ConsoleParams {
+    /// Most recent entries to return (default 100).
+    limit: Option<usize>,
+}
+
+/// Recent console messages and exceptions of the game tabs, oldest first.
+async fn get_console(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<ConsoleParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let console = api.app.read().await.console.clone();
+    Ok(Json(console.recent(params.limit.unwrap_or(100))))
 }
 
 async fn get_exchange_screenshot(
     State(api): State<ApiState>,
     headers: HeaderMap,
-    Path(index): Path<usize>,
+    Path(id): Path<u64>,
+    Query(params): Query<TokenParams>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_image_auth(&headers, params.token.as_deref(), &api.config.auth_token)?;
 
-    let exchange = state.exchanges.get(index).ok_or(StatusCode::NOT_FOUND)?;
-    let png = exchange
-        .screenshot_png
-        .clone()
-        .ok_or(StatusCode::NOT_FOUND)?;
+    let exchange = api.exchanges.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let path = exchange.screenshot.ok_or(StatusCode::NOT_FOUND)?;
     let filename = format!(
         "exchange_k{}_{}_{}.png",
         exchange.kingdom, exchange.x, exchange.y
     );
+    let png = screenshots::load(path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
 
     Ok((
         [
@@ -284,26 +648,179 @@ async fn get_exchange_screenshot(
     ))
 }
 
-async fn get_screenshot(
+#[derive(Deserialize)]
+struct ScreenshotHistoryParams {
+    /// Most recent screenshots to return (default: all kept).
+    limit: Option<usize>,
+    /// Only screenshots of the last this many seconds.
+    seconds: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct ShotEntry {
+    #[serde(flatten)]
+    shot: Shot,
+    /// `/screenshots/recent/{id}`, while it is kept
+    url: String,
+}
+
+/// The screenshots kept in memory (see `screenshot_history.rs`), oldest
+/// first, with where each was taken.
+async fn get_recent_screenshots(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<ScreenshotHistoryParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let history = api.app.read().await.screenshot_history.clone();
+
+    let since = params
+        .seconds
+        .map(|seconds| time_ago(chrono::TimeDelta::try_seconds(seconds)))
+        .transpose()?;
+    let shots = history.recent(params.limit.unwrap_or(usize::MAX), since);
+    Ok(Json(
+        shots
+            .into_iter()
+            .map(|shot| ShotEntry {
+                url: format!("/screenshots/recent/{}", shot.id),
+                shot,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+async fn get_recent_screenshot(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+    Query(params): Query<TokenParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_image_auth(&headers, params.token.as_deref(), &api.config.auth_token)?;
+    let history = api.app.read().await.screenshot_history.clone();
+
+    let shot = history.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok((
+        [(header::CONTENT_TYPE, shot.content_type())],
+        shared_body(shot.bytes),
+    ))
+}
+
+#[derive(Serialize)]
+struct RecordingEntry {
+    name: String,
+    size: u64,
+}
+
+async fn get_recordings(
     State(api): State<ApiState>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_auth(&headers, &api.config.auth_token)?;
+    let dir = api.config.recording_dir.clone();
+
+    let recordings = dir
+        .map(|dir| recorder::list_recordings(&dir))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, size)| RecordingEntry { name, size })
+        .collect::<Vec<_>>();
+    Ok(Json(recordings))
+}
+
+async fn get_recording(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let dir = api
+        .config
+        .recording_dir
+        .clone()
+        .ok_or(StatusCode::NOT_FOUND)?;
 
+    if !recorder::is_recording_name(&name) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let gif = tokio::fs::read(dir.join(&name))
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/gif".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}\""),
+            ),
+        ],
+        gif,
+    ))
+}
+
+/// The tab API requests should drive: the scanner's, or with `MERCY_API_TAB`
+/// a second tab, opened on first use (which takes as long as loading the
+/// game). `scan_tab` forces the scanner's tab.
+async fn api_browser(api: &ApiState, scan_tab: bool) -> Result<Arc<GameBrowser>, StatusCode> {
+    let state = api.app.read().await;
     let browser = state
         .browser
         .clone()
         .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
-    drop(state); // Release lock before async screenshot
+    if scan_tab || !state.config.api_tab {
+        return Ok(browser);
+    }
+    if let Some(tab) = state.api_tab.clone()
+        && tab.is_alive()
+    {
+        return Ok(tab);
+    }
+    drop(state); // Opening a tab loads the whole game client
+
+    let tab = Arc::new(browser.open_tab().await.map_err(|e| {
+        tracing::error!("failed to open API tab: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?);
+    let mut state = api.app.write().await;
+    // The session may have been replaced while the tab loaded
+    let same_session = state
+        .browser
+        .as_ref()
+        .is_some_and(|b| Arc::ptr_eq(b, &browser));
+    if !same_session {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    // Another request may have opened one meanwhile; keep the first
+    Ok(state.api_tab.get_or_insert(tab).clone())
+}
 
-    let png_bytes = browser.take_screenshot().await.map_err(|e| {
+#[derive(Deserialize)]
+struct ScreenshotParams {
+    /// `scan` for the scanner's tab when `MERCY_API_TAB` is on.
+    tab: Option<String>,
+    token: Option<String>,
+}
+
+async fn get_screenshot(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<ScreenshotParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_image_auth(&headers, params.token.as_deref(), &api.config.auth_token)?;
+
+    let browser = api_browser(&api, params.tab.as_deref() == Some("scan")).await?;
+
+    let png_bytes = Arc::new(browser.take_screenshot().await.map_err(|e| {
         tracing::error!("screenshot failed: {e:#}");
         StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    })?);
 
     // Store for detect to reuse
-    api.app.lock().await.last_screenshot = Some(png_bytes.clone());
+    api.app
+        .write()
+        .await
+        .keep_screenshot(png_bytes.clone(), browser.position());
 
     Ok((
         [
@@ -313,7 +830,69 @@ async fn get_screenshot(
                 "inline; filename=\"screenshot.png\"".to_owned(),
             ),
         ],
-        png_bytes,
+        shared_body(png_bytes),
+    ))
+}
+
+/// A fresh screenshot with the best match boxed and labelled with its score
+/// and, when the map position is known from the last goto, the game
+/// coordinates it's estimated at. Takes `?tab=scan` like `/screenshot`.
+async fn get_annotated_screenshot(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<ScreenshotParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_image_auth(&headers, params.token.as_deref(), &api.config.auth_token)?;
+
+    let browser = api_browser(&api, params.tab.as_deref() == Some("scan")).await?;
+    let png_bytes = Arc::new(browser.take_screenshot().await.map_err(|e| {
+        tracing::error!("screenshot failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?);
+    let position = browser.position();
+
+    let (viewport, transform) = {
+        let mut state = api.app.write().await;
+        // Store for detect to reuse
+        state.keep_screenshot(png_bytes.clone(), position);
+        (state.viewport, state.config.map_transform)
+    };
+
+    let detector = api.detectors.current();
+    let png = tokio::task::spawn_blocking(move || {
+        let screenshot = PreparedScreenshot::from_bytes(&png_bytes, viewport)?;
+        let best = detector.find_best_match(&screenshot);
+        let estimate = best.as_ref().zip(position).map(|(m, (k, nav_x, nav_y))| {
+            let (x, y) = locate::estimate(&transform, (nav_x, nav_y), m.position());
+            (k, x, y)
+        });
+        annotate::annotate_best_match(
+            &png_bytes,
+            viewport,
+            detector.refs(),
+            best.as_ref(),
+            estimate,
+        )
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("annotation task failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .map_err(|e| {
+        tracing::error!("failed to annotate screenshot: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                "inline; filename=\"screenshot_annotated.png\"".to_owned(),
+            ),
+        ],
+        png,
     ))
 }
 
@@ -329,14 +908,9 @@ async fn goto_coords(
     headers: HeaderMap,
     Query(params): Query<GotoParams>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_auth(&headers, &api.config.auth_token)?;
 
-    let browser = state
-        .browser
-        .clone()
-        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
-    drop(state);
+    let browser = api_browser(&api, false).await?;
 
     browser
         .navigate_to_coords(params.k, params.x, params.y)
@@ -346,13 +920,16 @@ async fn goto_coords(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let png_bytes = browser.take_screenshot().await.map_err(|e| {
+    let png_bytes = Arc::new(browser.take_screenshot().await.map_err(|e| {
         tracing::error!("screenshot failed: {e:#}");
         StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    })?);
 
     // Store for detect to reuse
-    api.app.lock().await.last_screenshot = Some(png_bytes.clone());
+    api.app
+        .write()
+        .await
+        .keep_screenshot(png_bytes.clone(), browser.position());
 
     let filename = format!("goto_k{}_{}_{}.png", params.k, params.x, params.y);
     Ok((
@@ -363,27 +940,221 @@ async fn goto_coords(
                 format!("inline; filename=\"{filename}\""),
             ),
         ],
-        png_bytes,
+        shared_body(png_bytes),
     ))
 }
 
+#[derive(Deserialize)]
+struct VerifyRequest {
+    k: u32,
+    x: u32,
+    y: u32,
+}
+
+#[derive(Serialize)]
+struct VerifyResponse {
+    present: bool,
+    score: Option<f32>,
+    pixel_x: Option<u32>,
+    pixel_y: Option<u32>,
+    /// Pixel distance of the match from screen center, per axis
+    error_x: Option<f64>,
+    error_y: Option<f64>,
+    template: Option<String>,
+    /// The verification screenshot, base64-encoded PNG
+    screenshot: String,
+}
+
+/// Check a location for the target right away, as the scanner re-verifies
+/// known exchanges. Without `MERCY_API_TAB` this shares the scanner's tab,
+/// waiting for its current step to finish; the scan carries on after.
+async fn verify_location(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(body): Json<VerifyRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+
+    let browser = api_browser(&api, false).await?;
+    let config = api.app.read().await.effective_config().for_kingdom(body.k);
+    let detector = api.detectors.current();
+
+    let outcome = scanner::verify_exchange(
+        &browser,
+        &api.app,
+        body.k,
+        body.x,
+        body.y,
+        detector.as_ref(),
+        &config,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("verify failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let screenshot = base64::engine::general_purpose::STANDARD.encode(&outcome.screenshot);
+    // Store for detect to reuse
+    api.app
+        .write()
+        .await
+        .keep_screenshot(Arc::new(outcome.screenshot), Some((body.k, body.x, body.y)));
+
+    let best = outcome.best.as_ref();
+    Ok(Json(VerifyResponse {
+        present: outcome.present,
+        score: best.map(|m| m.score),
+        pixel_x: best.map(|m| m.x),
+        pixel_y: best.map(|m| m.y),
+        error_x: outcome.pixel_error.map(|(x, _)| x),
+        error_y: outcome.pixel_error.map(|(_, y)| y),
+        template: best.and_then(|m| detector.refs().get(m.template).map(|r| r.name.clone())),
+        screenshot,
+    }))
+}
+
+/// One input for `POST /control`. Positions are reference pixels, as in
+/// `/screenshot`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ControlAction {
+    Click {
+        x: f64,
+        y: f64,
+    },
+    /// Type into whatever has focus
+    Type {
+        text: String,
+    },
+    Key {
+        key: String,
+    },
+    Scroll {
+        x: f64,
+        y: f64,
+        #[serde(default)]
+        delta_x: f64,
+        #[serde(default)]
+        delta_y: f64,
+    },
+}
+
+/// Keys `POST /control` can press (those both browser engines know).
+const CONTROL_KEYS: [&str; 4] = ["Escape", "Enter", "Tab", "Backspace"];
+
+/// Drive the scanner's tab by hand, to get past a dialog or challenge the
+/// scanner can't. Admin token only, and only while the scanner isn't
+/// scanning (paused, or ready after `/prepare` or `/stop`).
+async fn control_browser(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(action): Json<ControlAction>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_admin(&headers, &api.config)?;
+
+    let phase = api.app.read().await.phase;
+    if !matches!(phase, ScannerPhase::Paused | ScannerPhase::Ready) {
+        tracing::warn!("control refused while {phase:?}, pause the scanner first");
+        return Err(StatusCode::CONFLICT);
+    }
+    let browser = api_browser(&api, true).await?;
+
+    match &action {
+        // Could be a password
+        ControlAction::Type { text } => tracing::info!(
+            "manual control: typing {} character(s)",
+            text.chars().count()
+        ),
+        other => tracing::info!("manual control: {other:?}"),
+    }
+    let _view = browser.lock_view().await;
+    let result = match action {
+        ControlAction::Click { x, y } => browser.click_at_cdp_full(x, y).await,
+        ControlAction::Type { text } => browser.type_text(&text).await,
+        ControlAction::Key { key } => {
+            let key = CONTROL_KEYS
+                .into_iter()
+                .find(|k| k.eq_ignore_ascii_case(&key))
+                .ok_or_else(|| {
+                    tracing::warn!("unsupported control key {key}");
+                    StatusCode::BAD_REQUEST
+                })?;
+            browser.press_key(Key::Named(key)).await
+        }
+        ControlAction::Scroll {
+            x,
+            y,
+            delta_x,
+            delta_y,
+        } => browser.scroll_at(x, y, delta_x, delta_y).await,
+    };
+    result.map_err(|e| {
+        tracing::error!("manual control failed: {e:#}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(json!({"status": "ok"})))
+}
+
+#[derive(Deserialize)]
+struct DetectParams {
+    /// Also return the N strongest candidates with per-channel scores.
+    top: Option<usize>,
+    /// Return the correlation score heatmap as a PNG instead of JSON.
+    #[serde(default)]
+    heatmap: bool,
+    /// Score a match needs to count as found (default [`DETECT_THRESHOLD`]).
+    threshold: Option<f32>,
+    /// Match another target's templates from the assets directory, by its
+    /// name or file base name, instead of the active ones.
+    target: Option<String>,
+}
+
 #[derive(Serialize)]
 struct DetectResponse {
     found: bool,
     threshold: f32,
+    target: String,
     pixel_x: Option<u32>,
     pixel_y: Option<u32>,
     score: Option<f32>,
     game_dx: Option<i32>,
     game_dy: Option<i32>,
+    template: Option<String>,
+    channels: Option<ChannelScores>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    candidates: Vec<DetectCandidate>,
 }
 
+#[derive(Serialize)]
+struct DetectCandidate {
+    pixel_x: u32,
+    pixel_y: u32,
+    score: f32,
+    template: Option<String>,
+    channels: Option<ChannelScores>,
+}
+
+/// Upper bound for `/detect?top=N` (each candidate needs a full all-channel pass).
+const MAX_DETECT_CANDIDATES: usize = 50;
+
+/// `/detect`'s default threshold, lower than the scanner's for manual
+/// testing.
+const DETECT_THRESHOLD: f32 = 0.88;
+
 async fn detect_match(
     State(api): State<ApiState>,
     headers: HeaderMap,
+    Query(params): Query<DetectParams>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_auth(&headers, &api.config.auth_token)?;
+    let threshold = params.threshold.unwrap_or(DETECT_THRESHOLD);
+    if !(0.0..=1.0).contains(&threshold) {
+        tracing::warn!("detect threshold {threshold} is not within 0-1");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let state = api.app.read().await;
 
     // Reuse the last screenshot from goto/refresh instead of taking a new one,
     // because the game view drifts after navigation.
@@ -391,43 +1162,233 @@ async fn detect_match(
         tracing::error!("no screenshot available — use goto or refresh first");
         StatusCode::BAD_REQUEST
     })?;
+    let viewport = state.viewport;
+    let transform = state.config.map_transform;
+    let active_target = state.config.search_target.clone();
     drop(state);
 
-    let screenshot = image::load_from_memory(&png_bytes).map_err(|e| {
-        tracing::error!("decode failed: {e:#}");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    let decode = move || {
+        PreparedScreenshot::from_bytes(&png_bytes, viewport).map_err(|e| {
+            tracing::error!("decode failed: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+    };
 
-    let best = detector::find_best_match(&screenshot, &api.ref_images);
+    let (target, detector) = match params.target {
+        Some(name) => {
+            // Reading, decoding and preparing templates is blocking work
+            let detectors = api.detectors.clone();
+            tokio::task::spawn_blocking(move || -> Result<_, StatusCode> {
+                let (profile, refs) = TargetProfile::load(&name).map_err(|e| {
+                    tracing::warn!("detect target {name:?} not loaded: {e:#}");
+                    StatusCode::NOT_FOUND
+                })?;
+                Ok((profile.name, detectors.with_refs(&refs)))
+            })
+            .await
+            .map_err(|e| {
+                tracing::error!("detector build failed: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })??
+        }
+        None => (active_target, api.detectors.current()),
+    };
 
-    // Lower threshold for manual testing — scanner uses MATCH_THRESHOLD
-    const DETECT_THRESHOLD: f32 = 0.88;
+    if params.heatmap {
+        // Scoring every position and encoding the map are CPU-bound
+        let png = tokio::task::spawn_blocking(move || -> Result<_, StatusCode> {
+            let screenshot = decode()?;
+            let heatmap = detector.score_heatmap(&screenshot).ok_or_else(|| {
+                tracing::error!("heatmap not supported by the active detection backend");
+                StatusCode::NOT_IMPLEMENTED
+            })?;
+            detector::encode_png(&heatmap).map_err(|e| {
+                tracing::error!("{e:#}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("heatmap task failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })??;
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "inline; filename=\"detect_heatmap.png\"",
+                ),
+            ],
+            png,
+        )
+            .into_response());
+    }
+
+    // Decoding and matching (up to 50 full candidate passes) are CPU-bound
+    let top = params.top;
+    let (best, candidates) = tokio::task::spawn_blocking(move || -> Result<_, StatusCode> {
+        let screenshot = decode()?;
+        let best = detector.find_best_match(&screenshot);
+
+        let template_name = |idx: usize| detector.refs().get(idx).map(|r| r.name.clone());
+        let candidates = match top {
+            Some(n) if n > 0 => detector
+                .find_top_matches(&screenshot, n.min(MAX_DETECT_CANDIDATES))
+                .into_iter()
+                .map(|m| DetectCandidate {
+                    pixel_x: m.x,
+                    pixel_y: m.y,
+                    score: m.score,
+                    template: template_name(m.template),
+                    channels: m.channels,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let best = best.map(|m| (template_name(m.template), m));
+        Ok((best, candidates))
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("detect task failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })??;
 
     let resp = match best {
-        Some(m) => {
-            let (gdx, gdy) = scanner::pixel_to_game_offset(m.x, m.y);
+        Some((template, m)) => {
+            let (px, py) = m.position();
+            let (gdx, gdy) = transform.pixel_to_game_offset(px, py);
             DetectResponse {
-                found: m.score >= DETECT_THRESHOLD,
-                threshold: DETECT_THRESHOLD,
+                found: m.score >= threshold,
+                threshold,
+                target,
                 pixel_x: Some(m.x),
                 pixel_y: Some(m.y),
                 score: Some(m.score),
                 game_dx: Some(gdx),
                 game_dy: Some(gdy),
+                template,
+                channels: m.channels,
+                candidates,
             }
         }
         None => DetectResponse {
             found: false,
-            threshold: DETECT_THRESHOLD,
+            threshold,
+            target,
             pixel_x: None,
             pixel_y: None,
             score: None,
             game_dx: None,
             game_dy: None,
+            template: None,
+            channels: None,
+            candidates,
         },
     };
 
-    Ok(Json(resp))
+    Ok(Json(resp).into_response())
+}
+
+/// Upper bound on images per `/detect/batch` request.
+const MAX_BATCH_IMAGES: usize = 64;
+
+/// Request body limit for `/detect/batch` (base64 PNGs are ~1-2 MB each).
+const DETECT_BATCH_BODY_LIMIT: usize = 128 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct DetectBatchRequest {
+    /// Base64-encoded screenshots (PNG or any format `image` can decode).
+    images: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DetectBatchResult {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    matches: Vec<DetectCandidate>,
+}
+
+async fn detect_batch(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(body): Json<DetectBatchRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let state = api.app.read().await;
+    let viewport = state.viewport;
+    drop(state);
+
+    if body.images.is_empty() || body.images.len() > MAX_BATCH_IMAGES {
+        tracing::warn!(
+            "detect/batch: {} images, expected 1..={MAX_BATCH_IMAGES}",
+            body.images.len()
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let detector = api.detectors.current();
+    let results = tokio::task::spawn_blocking(move || {
+        // Decode up front so undecodable entries are reported but don't stop the batch
+        let mut errors = vec![None; body.images.len()];
+        let mut images = Vec::with_capacity(body.images.len());
+        let mut image_indices = Vec::with_capacity(body.images.len());
+        for (i, encoded) in body.images.iter().enumerate() {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| format!("invalid base64: {e}"))
+                .and_then(|bytes| {
+                    image::load_from_memory(&bytes).map_err(|e| format!("decode failed: {e}"))
+                });
+            match decoded {
+                Ok(img) => {
+                    images.push(img);
+                    image_indices.push(i);
+                }
+                Err(e) => errors[i] = Some(e),
+            }
+        }
+        drop(body);
+
+        let template_name = |idx: usize| detector.refs().get(idx).map(|r| r.name.clone());
+        let mut results: Vec<DetectBatchResult> = errors
+            .into_iter()
+            .enumerate()
+            .map(|(index, error)| DetectBatchResult {
+                index,
+                error,
+                matches: Vec::new(),
+            })
+            .collect();
+        let batch = detector.find_matches_batch(&images, viewport);
+        for (i, matches) in image_indices.into_iter().zip(batch) {
+            match matches {
+                Ok(matches) => {
+                    results[i].matches = matches
+                        .into_iter()
+                        .map(|m| DetectCandidate {
+                            pixel_x: m.x,
+                            pixel_y: m.y,
+                            score: m.score,
+                            template: template_name(m.template),
+                            channels: m.channels,
+                        })
+                        .collect();
+                }
+                Err(e) => results[i].error = Some(format!("{e:#}")),
+            }
+        }
+        results
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("detect/batch task failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(json!({ "results": results })))
 }
 
 #[derive(Deserialize)]
@@ -440,8 +1401,8 @@ async fn scan_kingdom_handler(
     headers: HeaderMap,
     Json(body): Json<ScanKingdomRequest>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let state = api.app.lock().await;
-    check_auth(&headers, &state.config.auth_token)?;
+    check_auth(&headers, &api.config.auth_token)?;
+    let state = api.app.read().await;
 
     match state.phase {
         ScannerPhase::Scanning | ScannerPhase::Paused => {
@@ -459,27 +1420,283 @@ async fn scan_kingdom_handler(
         }
         ScannerPhase::Ready | ScannerPhase::Idle => {
             drop(state);
+            spawn_one_shot_scan(&api, body.kingdom, None);
+            Ok(Json(json!({"status": "started"})))
+        }
+        ScannerPhase::Preparing | ScannerPhase::WaitingFor2fa => Err(StatusCode::CONFLICT),
+    }
+}
 
-            let app_state = api.app.clone();
-            let ref_images = api.ref_images.clone();
-            let kingdom = body.kingdom;
-            tokio::spawn(async move {
-                if let Err(e) =
-                    scanner::run_single_kingdom_scan(app_state.clone(), ref_images, kingdom).await
-                {
-                    tracing::error!("one-shot scan error: {e:#}");
-                    let mut s = app_state.lock().await;
-                    s.manual_scan_kingdom = None;
-                    s.phase = if s.browser.is_some() {
-                        ScannerPhase::Ready
-                    } else {
-                        ScannerPhase::Idle
-                    };
-                }
-            });
+/// Scan `kingdom` once in the background, of `custom` positions if given.
+fn spawn_one_shot_scan(api: &ApiState, kingdom: u32, custom: Option<Vec<(u32, u32)>>) {
+    let app_state = api.app.clone();
+    let detectors = api.detectors.clone();
+    tokio::spawn(async move {
+        if let Err(e) =
+            scanner::run_single_kingdom_scan(app_state.clone(), detectors, kingdom, custom).await
+        {
+            tracing::error!("one-shot scan error: {e:#}");
+            report::error("scanner", format!("{e:#}"), Some(kingdom), None);
+            let mut s = app_state.write().await;
+            s.errors.record("scanner", format!("{e:#}"), Some(kingdom));
+            s.manual_scan_kingdom = None;
+            let phase = if s.browser.is_some() {
+                ScannerPhase::Ready
+            } else {
+                ScannerPhase::Idle
+            };
+            s.set_phase(phase);
+        }
+    });
+}
 
-            Ok(Json(json!({"status": "started"})))
+#[derive(Deserialize)]
+struct CustomScanRequest {
+    kingdom: u32,
+    /// `[x, y]` pairs, visited in order
+    positions: Vec<(u32, u32)>,
+}
+
+#[derive(Deserialize)]
+struct CustomScanParams {
+    /// The kingdom of a CSV body
+    kingdom: Option<u32>,
+}
+
+/// Upper bound for the positions of `POST /scan/custom` (the grid has 1024).
+const MAX_CUSTOM_POSITIONS: usize = 4096;
+
+/// Scan one kingdom once, at the positions given instead of the configured
+/// pattern's: a JSON `{"kingdom", "positions"}` body, or a `text/csv` one
+/// (see [`scanner::parse_positions_csv`]) with `?kingdom=`. Only while no
+/// scan is running.
+async fn scan_custom(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<CustomScanParams>,
+    body: String,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|t| t.starts_with("text/csv"));
+    let (kingdom, positions) = if csv {
+        let kingdom = params.kingdom.ok_or_else(|| {
+            tracing::warn!("custom scan CSV without ?kingdom=");
+            StatusCode::BAD_REQUEST
+        })?;
+        (kingdom, scanner::parse_positions_csv(&body, kingdom))
+    } else {
+        let request: CustomScanRequest = serde_json::from_str(&body).map_err(|e| {
+            tracing::warn!("invalid custom scan request: {e}");
+            StatusCode::BAD_REQUEST
+        })?;
+        (request.kingdom, request.positions)
+    };
+    if positions.is_empty()
+        || positions.len() > MAX_CUSTOM_POSITIONS
+        || positions.iter().any(|&(x, y)| x > 1023 || y > 1023)
+    {
+        tracing::warn!(
+            "custom scan needs 1-{MAX_CUSTOM_POSITIONS} positions within 0-1023, got {}",
+            positions.len()
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let phase = api.app.read().await.phase;
+    match phase {
+        ScannerPhase::Ready | ScannerPhase::Idle => {
+            let total = positions.len();
+            spawn_one_shot_scan(&api, kingdom, Some(positions));
+            Ok(Json(json!({"status": "started", "positions": total})))
         }
-        ScannerPhase::Preparing => Err(StatusCode::CONFLICT),
+        _ => Err(StatusCode::CONFLICT),
     }
 }
+
+#[derive(Deserialize)]
+struct ScanPlanParams {
+    /// Kingdom to plan for; defaults to the first configured one
+    k: Option<u32>,
+    pattern: Option<String>,
+    rings: Option<u32>,
+    coverage: Option<u32>,
+    /// `png` for a rendered map instead of JSON
+    format: Option<String>,
+    /// Accepted for the PNG only; the JSON needs the header
+    token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ScanPlanResponse {
+    kingdom: u32,
+    pattern: String,
+    rings: Option<u32>,
+    /// Game units one step sees, wide and high
+    footprint: (u32, u32),
+    positions: Vec<(u32, u32)>,
+}
+
+/// The positions a scan of one kingdom would visit, in order, with the
+/// current config and any pattern parameters given. Starts nothing.
+async fn get_scan_plan(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Query(params): Query<ScanPlanParams>,
+) -> Result<Response, StatusCode> {
+    let png = params.format.as_deref() == Some("png");
+    if png {
+        check_image_auth(&headers, params.token.as_deref(), &api.config.auth_token)?;
+    } else {
+        check_auth(&headers, &api.config.auth_token)?;
+    }
+    let overrides = ConfigOverrides {
+        scan_pattern: params.pattern,
+        scan_rings: params.rings,
+        known_coverage: params.coverage,
+        ..Default::default()
+    };
+    overrides.validate().map_err(|e| {
+        tracing::warn!("invalid scan plan parameters: {e}");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    let (config, viewport) = {
+        let state = api.app.read().await;
+        (state.effective_config(), state.viewport)
+    };
+    let kingdom = params
+        .k
+        .or_else(|| config.kingdoms.first().copied())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let config = overrides.apply(&config).for_kingdom(kingdom);
+    let transform = config.map_transform;
+    let footprint = (
+        (viewport.width() as f64 / transform.px_per_game_x).round() as u32,
+        (viewport.height() as f64 / transform.px_per_game_y).round() as u32,
+    );
+    let plan_config = config.clone();
+    let positions =
+        tokio::task::spawn_blocking(move || scanner::scan_positions(&plan_config, kingdom))
+            .await
+            .map_err(|e| {
+                tracing::error!("scan plan task failed: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+    if png {
+        let png =
+            tokio::task::spawn_blocking(move || annotate::render_scan_plan(&positions, footprint))
+                .await
+                .map_err(|e| {
+                    tracing::error!("scan plan render task failed: {e}");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+                .map_err(|e| {
+                    tracing::error!("failed to render scan plan: {e:#}");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+        let filename = format!("scan_plan_k{kingdom}_{}.png", config.scan_pattern);
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "image/png".to_owned()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("inline; filename=\"{filename}\""),
+                ),
+            ],
+            png,
+        )
+            .into_response());
+    }
+
+    Ok(Json(ScanPlanResponse {
+        kingdom,
+        rings: scanner::scan_rings(&config),
+        pattern: config.scan_pattern.clone(),
+        footprint,
+        positions,
+    })
+    .into_response())
+}
+
+#[derive(Deserialize)]
+struct RefFromScreenshotRequest {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Crop a region of the last screenshot (full-screenshot pixel coordinates),
+/// save it as a new reference template for the search target, and start
+/// matching with it immediately.
+async fn ref_from_screenshot(
+    State(api): State<ApiState>,
+    headers: HeaderMap,
+    Json(body): Json<RefFromScreenshotRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    check_auth(&headers, &api.config.auth_token)?;
+    let state = api.app.read().await;
+
+    let png_bytes = state.last_screenshot.clone().ok_or_else(|| {
+        tracing::error!("no screenshot available — use goto or refresh first");
+        StatusCode::BAD_REQUEST
+    })?;
+    let search_target = state.config.search_target.clone();
+    drop(state);
+
+    let detectors = api.detectors.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let screenshot = image::load_from_memory(&png_bytes).map_err(|e| {
+            tracing::error!("decode failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        let fits_x = body
+            .x
+            .checked_add(body.width)
+            .is_some_and(|r| r <= screenshot.width());
+        let fits_y = body
+            .y
+            .checked_add(body.height)
+            .is_some_and(|b| b <= screenshot.height());
+        if body.width < 10 || body.height < 10 || !fits_x || !fits_y {
+            tracing::warn!(
+                "rejecting template crop {}x{} at ({}, {}) for {}x{} screenshot",
+                body.width,
+                body.height,
+                body.x,
+                body.y,
+                screenshot.width(),
+                screenshot.height()
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let crop = screenshot.crop_imm(body.x, body.y, body.width, body.height);
+        let reference = detector::save_reference(&search_target, crop).map_err(|e| {
+            tracing::error!("failed to save reference: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let name = reference.name.clone();
+        let templates = detectors.add_reference(reference);
+        tracing::info!("activated reference {name}, {templates} template(s) active");
+        Ok((name, templates))
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("reference task failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let (name, templates) = result?;
+    Ok(Json(json!({
+        "name": name,
+        "width": body.width,
+        "height": body.height,
+        "templates": templates,
+    })))
+}
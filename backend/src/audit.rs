@@ -0,0 +1,121 @@
+//! Audit trail of control calls, for `GET /audit`: who started, stopped,
+//! paused, navigated or reconfigured the scanner, when, and with what
+//! result. Every call that changes something (any method but `GET`, and
+//! `GET /goto`, which moves the map), and every MQTT switch command, is
+//! appended to `MERCY_AUDIT_LOG` as a JSON line, and the last [`CAPACITY`] are kept in memory, read back from
+//! the file (and its last rotated segment) at startup.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::logfile::{self, Rotation};
+
+/// Entries kept in memory for `GET /audit`.
+const CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    /// Which token authenticated the call: `api` or `admin`, or `mqtt` for
+    /// a switch command
+    pub caller: String,
+    pub method: String,
+    /// Path and query
+    pub path: String,
+    /// The response status
+    pub status: u16,
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    file: PathBuf,
+    rotation: Rotation,
+}
+
+impl AuditLog {
+    /// A log appending to `file`, starting with the last entries already in
+    /// it.
+    pub fn open(file: PathBuf, rotation: Rotation) -> Self {
+        let mut entries = VecDeque::new();
+        if let Ok(text) = logfile::read_recent(&file) {
+            for entry in text
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+            {
+                if entries.len() == CAPACITY {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+        }
+        Self {
+            entries: Mutex::new(entries),
+            file,
+            rotation,
+        }
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        match serde_json::to_string(&entry) {
+            Ok(line) => logfile::append(&self.file, &self.rotation, &(line + "\n")),
+            Err(e) => tracing::warn!("failed to write audit log {}: {e}", self.file.display()),
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The last `limit` entries no older than `since`, oldest first.
+    pub fn recent(&self, limit: usize, since: Option<DateTime<Utc>>) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let matching: Vec<_> = entries
+            .iter()
+            .filter(|e| since.is_none_or(|since| e.time >= since))
+            .collect();
+        let skip = matching.len().saturating_sub(limit);
+        matching.into_iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_reopened() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let entry = |path: &str, status| AuditEntry {
+            time: Utc::now(),
+            caller: "api".into(),
+            method: "POST".into(),
+            path: path.into(),
+            status,
+        };
+
+        let log = AuditLog::open(path.clone(), Rotation::default());
+        log.record(entry("/start", 200));
+        log.record(entry("/stop", 200));
+        log.record(entry("/pause", 409));
+        assert_eq!(log.recent(2, None)[0].path, "/stop");
+
+        logfile::flush();
+        let reopened = AuditLog::open(path, Rotation::default());
+        assert_eq!(
+            reopened.recent(usize::MAX, None),
+            log.recent(usize::MAX, None)
+        );
+        assert!(
+            reopened
+                .recent(10, Some(Utc::now() + chrono::Duration::seconds(1)))
+                .is_empty()
+        );
+    }
+}
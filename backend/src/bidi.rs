@@ -0,0 +1,619 @@
+//! Firefox over WebDriver BiDi (`MERCY_BROWSER=firefox`), for hosts that
+//! can't run Chromium's sandbox and dbus setup.
+//!
+//! Firefox is launched with a fresh profile and `--remote-debugging-port=0`,
+//! and prints the websocket it picked to stderr. The user agent and language
+//! go into the profile's `user.js`, the timezone into `TZ`. `MERCY_STEALTH`'s
+//! patches imitate Chrome, so only the webdriver override is injected here.
+//!
+//! BiDi screenshots can't be scaled, so clips that need it (a canvas that
+//! isn't 1920×1080, the goto probes) are resized here and come back as PNG.
+//! Firefox can't encode WebP; `MERCY_SCREENSHOT_FORMAT=webp` captures JPEG.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use async_tungstenite::tungstenite::Message;
+use base64::Engine;
+use chromiumoxide::cdp::browser_protocol::network::CookieParam;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::{Sink, SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+use crate::browser::{BrowserError, ScreenshotFormat};
+use crate::config::Config;
+use crate::console::{ConsoleEntry, ConsoleLog};
+use crate::driver::{Capture, Driver, Evaluation, Key, KeyAction, MouseAction, Tab};
+use crate::stealth::{DEFAULT_USER_AGENT, Fingerprint};
+
+/// How long Firefox may take to open its BiDi websocket.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a command may take (navigation waits for the page's `load`).
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What Firefox prints to stderr once BiDi is up, followed by the URL.
+const LISTENING: &str = "WebDriver BiDi listening on ";
+
+type WsSink = Pin<Box<dyn Sink<Message, Error = async_tungstenite::tungstenite::Error> + Send>>;
+
+/// The websocket to Firefox's BiDi session: matches command responses to
+/// their callers and copies `log.entryAdded` events into the console log.
+struct Connection {
+    sink: tokio::sync::Mutex<WsSink>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+    next_id: AtomicU64,
+    connected: AtomicBool,
+    console: Arc<ConsoleLog>,
+}
+
+impl Connection {
+    async fn send(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, tx);
+        let message = json!({"id": id, "method": method, "params": params});
+        self.sink
+            .lock()
+            .await
+            .send(Message::Text(message.to_string()))
+            .await
+            .with_context(|| format!("{method}: failed to send"))?;
+
+        match tokio::time::timeout(COMMAND_TIMEOUT, rx).await {
+            Ok(Ok(Ok(result))) => Ok(result),
+            Ok(Ok(Err(error))) => anyhow::bail!("{method}: {error}"),
+            Ok(Err(_)) => anyhow::bail!("{method}: browser connection closed"),
+            Err(_) => {
+                self.pending
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&id);
+                anyhow::bail!("{method}: timed out after {COMMAND_TIMEOUT:?}")
+            }
+        }
+    }
+
+    fn dispatch(&self, message: Value) {
+        let reply = match message["type"].as_str() {
+            Some("success") => Ok(message["result"].clone()),
+            Some("error") => Err(format!(
+                "{}: {}",
+                message["error"].as_str().unwrap_or("error"),
+                message["message"].as_str().unwrap_or_default()
+            )),
+            Some("event") => {
+                if message["method"] == "log.entryAdded" {
+                    self.console.push(log_entry(&message["params"]));
+                }
+                return;
+            }
+            _ => return,
+        };
+        let Some(id) = message["id"].as_u64() else {
+            if let Err(error) = reply {
+                tracing::warn!("browser error: {error}");
+            }
+            return;
+        };
+        let waiter = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+        if let Some(waiter) = waiter {
+            let _ = waiter.send(reply);
+        }
+    }
+}
+
+/// A `log.entryAdded` event as a console entry; uncaught errors are
+/// `exception`s as with Chromium.
+fn log_entry(params: &Value) -> ConsoleEntry {
+    let level = match params["type"].as_str() {
+        Some("javascript") => "exception",
+        _ => params["level"].as_str().unwrap_or("log"),
+    };
+    ConsoleEntry {
+        time: Utc::now(),
+        level: level.to_owned(),
+        text: params["text"].as_str().unwrap_or_default().to_owned(),
+    }
+}
+
+pub struct Firefox {
+    conn: Arc<Connection>,
+    /// Killed when dropped
+    _child: tokio::process::Child,
+    _profile_dir: tempfile::TempDir,
+    pid: Option<u32>,
+}
+
+impl Firefox {
+    pub async fn launch(config: &Config, console: Arc<ConsoleLog>) -> Result<Self> {
+        let fingerprint = Fingerprint {
+            stealth: false,
+            ..Fingerprint::from_config(config)
+        };
+        let profile_dir = tempfile::tempdir().context("failed to create temp profile dir")?;
+        std::fs::write(profile_dir.path().join("user.js"), user_prefs(&fingerprint))
+            .context("failed to write Firefox prefs")?;
+
+        let executable = config.firefox_path.as_deref().unwrap_or("firefox");
+        let mut command = tokio::process::Command::new(executable);
+        command
+            .arg("--remote-debugging-port=0")
+            .arg("--no-remote")
+            .arg("--profile")
+            .arg(profile_dir.path())
+            .args(["--width", "1920", "--height", "1080"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if config.headless {
+            command.arg("--headless");
+        }
+        if let Some(ref timezone) = fingerprint.timezone {
+            command.env("TZ", timezone);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| BrowserError::LaunchFailed(format!("{executable}: {e}")))?;
+        let pid = child.id();
+
+        let stderr = child.stderr.take().context("no stderr")?;
+        let mut lines = BufReader::new(stderr).lines();
+        let ws_url = tokio::time::timeout(STARTUP_TIMEOUT, async {
+            while let Some(line) = lines.next_line().await? {
+                if let Some(url) = line.split_once(LISTENING).map(|(_, url)| url.trim()) {
+                    return Ok(url.to_owned());
+                }
+            }
+            Err(std::io::Error::other("Firefox exited"))
+        })
+        .await
+        .map_err(|_| BrowserError::LaunchFailed("Firefox didn't open a BiDi port".into()))?
+        .map_err(|e| BrowserError::LaunchFailed(e.to_string()))?;
+        // Keep draining stderr so Firefox never blocks on a full pipe
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!("firefox: {line}");
+            }
+        });
+
+        let session_url = format!("{ws_url}/session");
+        tracing::info!("connecting to Firefox at {session_url}");
+        let (ws, _) = async_tungstenite::tokio::connect_async(session_url.as_str())
+            .await
+            .map_err(|e| BrowserError::ConnectFailed(format!("{session_url}: {e}")))?;
+        let (sink, mut stream) = ws.split();
+        let conn = Arc::new(Connection {
+            sink: tokio::sync::Mutex::new(Box::pin(sink)),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            connected: AtomicBool::new(true),
+            console,
+        });
+        let reader = conn.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                if let Message::Text(text) = message
+                    && let Ok(message) = serde_json::from_str(&text)
+                {
+                    reader.dispatch(message);
+                }
+            }
+            tracing::warn!("browser connection closed");
+            reader.connected.store(false, Ordering::Relaxed);
+            // Fails every command still waiting
+            reader
+                .pending
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clear();
+        });
+
+        conn.send("session.new", json!({"capabilities": {}}))
+            .await
+            .context("failed to start BiDi session")?;
+        conn.send("session.subscribe", json!({"events": ["log.entryAdded"]}))
+            .await
+            .context("failed to listen for console messages")?;
+        conn.send(
+            "script.addPreloadScript",
+            json!({"functionDeclaration": format!("() => {{ {} }}", fingerprint.init_script())}),
+        )
+        .await
+        .context("failed to inject init script")?;
+
+        Ok(Firefox {
+            conn,
+            _child: child,
+            _profile_dir: profile_dir,
+            pid,
+        })
+    }
+
+    async fn open_tab(&self, new_window: bool) -> Result<Box<dyn Tab>> {
+        let created = self
+            .conn
+            .send(
+                "browsingContext.create",
+                json!({"type": if new_window { "window" } else { "tab" }}),
+            )
+            .await
+            .context("failed to create new page")?;
+        let context = created["context"]
+            .as_str()
+            .context("no context in browsingContext.create result")?
+            .to_owned();
+        self.conn
+            .send(
+                "browsingContext.setViewport",
+                json!({
+                    "context": context,
+                    "viewport": {"width": 1920, "height": 1080},
+                    "devicePixelRatio": 1,
+                }),
+            )
+            .await
+            .context("failed to set viewport")?;
+        Ok(Box::new(FirefoxTab {
+            conn: self.conn.clone(),
+            context,
+        }))
+    }
+}
+
+impl Driver for Firefox {
+    fn new_tab(&self, new_window: bool) -> BoxFuture<'_, Result<Box<dyn Tab>>> {
+        Box::pin(self.open_tab(new_window))
+    }
+
+    fn is_connected(&self) -> bool {
+        self.conn.connected.load(Ordering::Relaxed)
+    }
+
+    fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.conn.send("browser.close", json!({})).await?;
+            Ok(())
+        })
+    }
+}
+
+/// `user.js` for the temp profile: the fingerprint, and no first-run pages.
+/// The default user agent is Chrome's, so it's only overridden when set.
+fn user_prefs(fp: &Fingerprint) -> String {
+    let mut prefs = vec![
+        ("browser.shell.checkDefaultBrowser", json!(false)),
+        ("browser.startup.homepage_override.mstone", json!("ignore")),
+        ("datareporting.policy.dataSubmissionEnabled", json!(false)),
+        ("toolkit.telemetry.reportingpolicy.firstRun", json!(false)),
+        ("webgl.force-enabled", json!(true)),
+    ];
+    if fp.user_agent != DEFAULT_USER_AGENT {
+        prefs.push(("general.useragent.override", json!(fp.user_agent)));
+    }
+    if let Some(languages) = fp.accept_language() {
+        prefs.push(("intl.accept_languages", json!(languages)));
+    }
+    prefs
+        .into_iter()
+        .map(|(name, value)| format!("user_pref(\"{name}\", {value});\n"))
+        .collect()
+}
+
+/// A BiDi `RemoteValue` as plain JSON, like CDP's `returnByValue`. Values
+/// with no JSON equivalent (functions, nodes, `NaN`) become `null`.
+fn remote_value_json(value: &Value) -> Value {
+    match value["type"].as_str() {
+        Some("string" | "boolean") => value["value"].clone(),
+        Some("number") => match value["value"] {
+            Value::Number(_) => value["value"].clone(),
+            Value::String(ref s) if s == "-0" => json!(0),
+            _ => Value::Null,
+        },
+        Some("bigint") => value["value"]
+            .as_str()
+            .and_then(|s| s.parse::<i64>().ok())
+            .map_or(Value::Null, Value::from),
+        Some("array" | "set") => value["value"].as_array().map_or(Value::Null, |items| {
+            items.iter().map(remote_value_json).collect()
+        }),
+        Some("object" | "map") => value["value"].as_array().map_or(Value::Null, |entries| {
+            entries
+                .iter()
+                .filter_map(|entry| {
+                    let key = match entry[0] {
+                        Value::String(ref key) => key.clone(),
+                        ref key => remote_value_json(key).as_str()?.to_owned(),
+                    };
+                    Some((key, remote_value_json(&entry[1])))
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }),
+        _ => Value::Null,
+    }
+}
+
+/// WebDriver code point of a key.
+fn key_value(key: Key) -> Result<String> {
+    Ok(match key {
+        Key::Named("Escape") => "\u{E00C}".into(),
+        Key::Named("Enter") => "\u{E007}".into(),
+        Key::Named("Tab") => "\u{E004}".into(),
+        Key::Named("Backspace") => "\u{E003}".into(),
+        Key::Named(other) => anyhow::bail!("unsupported key {other}"),
+        Key::Char(ch) | Key::Ctrl(ch) => ch.to_string(),
+    })
+}
+
+const CONTROL: &str = "\u{E009}";
+
+struct FirefoxTab {
+    conn: Arc<Connection>,
+    /// BiDi browsing context id
+    context: String,
+}
+
+impl FirefoxTab {
+    async fn perform(&self, source: Value) -> Result<()> {
+        self.conn
+            .send(
+                "input.performActions",
+                json!({"context": self.context, "actions": [source]}),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn capture(&self, capture: Capture) -> Result<Vec<u8>> {
+        let scaled = capture
+            .clip
+            .is_some_and(|clip| (clip.scale - 1.0).abs() > 1e-3);
+        let (mime, quality) = match capture.format {
+            ScreenshotFormat::Png => ("image/png", None),
+            _ if scaled => ("image/png", None),
+            ScreenshotFormat::Jpeg | ScreenshotFormat::Webp => ("image/jpeg", capture.quality),
+        };
+        let mut format = json!({"type": mime});
+        if let Some(quality) = quality {
+            format["quality"] = json!(quality as f64 / 100.0);
+        }
+        let mut params = json!({"context": self.context, "origin": "viewport", "format": format});
+        if let Some(clip) = capture.clip {
+            params["clip"] = json!({
+                "type": "box",
+                "x": clip.x,
+                "y": clip.y,
+                "width": clip.width,
+                "height": clip.height,
+            });
+        }
+        let result = self
+            .conn
+            .send("browsingContext.captureScreenshot", params)
+            .await?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(result["data"].as_str().context("no screenshot data")?)
+            .context("bad screenshot data")?;
+
+        let Some(clip) = capture.clip.filter(|_| scaled) else {
+            return Ok(bytes);
+        };
+        let image = image::load_from_memory(&bytes).context("failed to decode screenshot")?;
+        let width = (clip.width * clip.scale).round().max(1.0) as u32;
+        let height = (clip.height * clip.scale).round().max(1.0) as u32;
+        let mut png = Vec::new();
+        image
+            .resize_exact(width, height, image::imageops::FilterType::Triangle)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .context("failed to encode screenshot")?;
+        Ok(png)
+    }
+}
+
+impl Tab for FirefoxTab {
+    fn goto<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.conn
+                .send(
+                    "browsingContext.navigate",
+                    json!({"context": self.context, "url": url, "wait": "complete"}),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn reload(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.conn
+                .send(
+                    "browsingContext.reload",
+                    json!({"context": self.context, "wait": "complete"}),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn url(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let tree = self
+                .conn
+                .send(
+                    "browsingContext.getTree",
+                    json!({"root": self.context, "maxDepth": 0}),
+                )
+                .await?;
+            Ok(tree["contexts"][0]["url"].as_str().map(str::to_owned))
+        })
+    }
+
+    fn evaluate<'a>(&'a self, expression: &'a str) -> BoxFuture<'a, Result<Evaluation>> {
+        Box::pin(async move {
+            let result = self
+                .conn
+                .send(
+                    "script.evaluate",
+                    json!({
+                        "expression": expression,
+                        "target": {"context": self.context},
+                        "awaitPromise": true,
+                        "resultOwnership": "none",
+                    }),
+                )
+                .await?;
+            if result["type"] == "exception" {
+                anyhow::bail!(
+                    "script threw: {}",
+                    result["exceptionDetails"]["text"]
+                        .as_str()
+                        .unwrap_or_default()
+                );
+            }
+            Ok(Evaluation(remote_value_json(&result["result"])))
+        })
+    }
+
+    fn screenshot(&self, capture: Capture) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(self.capture(capture))
+    }
+
+    fn mouse(&self, action: MouseAction, x: f64, y: f64) -> BoxFuture<'_, Result<()>> {
+        let to = json!({
+            "type": "pointerMove",
+            "x": x.round() as i64,
+            "y": y.round() as i64,
+            "origin": "viewport",
+        });
+        let actions = match action {
+            // The button state carries over from the press
+            MouseAction::Move | MouseAction::Drag => vec![to],
+            MouseAction::Press => vec![to, json!({"type": "pointerDown", "button": 0})],
+            MouseAction::Release => vec![to, json!({"type": "pointerUp", "button": 0})],
+        };
+        Box::pin(self.perform(json!({
+            "type": "pointer",
+            "id": "mouse",
+            "parameters": {"pointerType": "mouse"},
+            "actions": actions,
+        })))
+    }
+
+    fn key(&self, action: KeyAction, key: Key) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let value = key_value(key)?;
+            let actions = match (action, key) {
+                (KeyAction::Down, Key::Ctrl(_)) => vec![
+                    json!({"type": "keyDown", "value": CONTROL}),
+                    json!({"type": "keyDown", "value": value}),
+                ],
+                (KeyAction::Up, Key::Ctrl(_)) => vec![
+                    json!({"type": "keyUp", "value": value}),
+                    json!({"type": "keyUp", "value": CONTROL}),
+                ],
+                (KeyAction::Down, _) => vec![json!({"type": "keyDown", "value": value})],
+                (KeyAction::Up, _) => vec![json!({"type": "keyUp", "value": value})],
+            };
+            self.perform(json!({"type": "key", "id": "keyboard", "actions": actions}))
+                .await
+        })
+    }
+
+    fn wheel(&self, x: f64, y: f64, dx: f64, dy: f64) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.perform(json!({
+            "type": "wheel",
+            "id": "wheel",
+            "actions": [{
+                "type": "scroll",
+                "x": x.round() as i64,
+                "y": y.round() as i64,
+                "deltaX": dx.round() as i64,
+                "deltaY": dy.round() as i64,
+                "origin": "viewport",
+            }],
+        })))
+    }
+
+    fn set_cookies(&self, cookies: Vec<CookieParam>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            for cookie in cookies {
+                let mut partial = json!({
+                    "name": cookie.name,
+                    "value": {"type": "string", "value": cookie.value},
+                    "domain": cookie.domain.as_deref().unwrap_or_default(),
+                    "path": cookie.path.as_deref().unwrap_or("/"),
+                });
+                if let Some(secure) = cookie.secure {
+                    partial["secure"] = json!(secure);
+                }
+                if let Some(http_only) = cookie.http_only {
+                    partial["httpOnly"] = json!(http_only);
+                }
+                if let Some(ref same_site) = cookie.same_site {
+                    partial["sameSite"] = json!(same_site.as_ref().to_ascii_lowercase());
+                }
+                if let Some(ref expires) = cookie.expires {
+                    partial["expiry"] = json!(*expires.inner() as i64);
+                }
+                self.conn
+                    .send("storage.setCookie", json!({"cookie": partial}))
+                    .await
+                    .with_context(|| format!("failed to set cookie {}", cookie.name))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_value_json() {
+        let value = json!({"type": "object", "value": [
+            ["ok", {"type": "boolean", "value": true}],
+            ["rect", {"type": "array", "value": [
+                {"type": "number", "value": 0},
+                {"type": "number", "value": 40.5},
+                {"type": "number", "value": "NaN"},
+            ]}],
+            ["text", {"type": "null"}],
+            ["fn", {"type": "function"}],
+        ]});
+        assert_eq!(
+            remote_value_json(&value),
+            json!({"ok": true, "rect": [0, 40.5, null], "text": null, "fn": null})
+        );
+        assert_eq!(
+            remote_value_json(&json!({"type": "undefined"})),
+            Value::Null
+        );
+
+        let entry = log_entry(&json!({"type": "javascript", "level": "error", "text": "boom"}));
+        assert_eq!(
+            (entry.level.as_str(), entry.text.as_str()),
+            ("exception", "boom")
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! Summarise the exchange log (`MERCY_EXCHANGE_LOG`, `exchanges.jsonl`):
+//! spawns per kingdom and where they cluster, scan durations, how often
+//! each scan pattern's detections were confirmed, and how near misses
+//! clicked after dry passes (`MERCY_RELAX_AFTER_PASSES`) fared, for setting
+//! the threshold. `--csv` writes the
+//! confirmed spawns as a known-locations CSV (`kingdom,x,y`, the format of
+//! `assets/known_locations.csv` and `MERCY_KNOWN_LOCATIONS`).
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde::Deserialize;
+
+/// Clustered spawns listed per kingdom.
+const TOP_CELLS: usize = 5;
+
+#[derive(Debug, Parser)]
+#[command(about = "Summarise the exchange log for scan pattern tuning")]
+struct Args {
+    /// Exchange log [default: MERCY_EXCHANGE_LOG or exchanges.jsonl]
+    log: Option<PathBuf>,
+
+    /// Side of the cells spawns are clustered into, in game units (as the
+    /// `known` pattern groups them)
+    #[arg(long, default_value_t = 25)]
+    cell: u32,
+
+    /// Write the confirmed spawns as a known-locations CSV
+    #[arg(long, value_name = "FILE")]
+    csv: Option<PathBuf>,
+}
+
+/// The fields of an exchange log line this reads.
+#[derive(Debug, Deserialize)]
+struct LogEntry {
+    kingdom: u32,
+    x: u32,
+    y: u32,
+    /// Coordinates read from the popup (otherwise a calibrated estimate)
+    confirmed: bool,
+    /// Added as a new exchange (not a duplicate of a known one)
+    stored: bool,
+    initial_score: f32,
+    scan_pattern: String,
+    scan_duration_secs: Option<f64>,
+    /// Clicked as a near miss, below the threshold
+    #[serde(default)]
+    near_miss: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct KingdomStats {
+    spawns: usize,
+    locations: usize,
+    /// Spawns per cell origin, most first
+    cells: Vec<((u32, u32), usize)>,
+    mean_scan_secs: Option<f64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct PatternStats {
+    detections: usize,
+    confirmed: usize,
+    stored: usize,
+    mean_score: f64,
+    mean_scan_secs: Option<f64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct NearMissStats {
+    retried: usize,
+    confirmed: usize,
+    /// Lowest scan score of a confirmed near miss
+    lowest_confirmed: Option<f32>,
+}
+
+/// Parse the log, skipping unreadable lines. Returns the entries and how
+/// many lines were skipped.
+fn parse(log: &str) -> (Vec<LogEntry>, usize) {
+    let mut invalid = 0;
+    let entries = log
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|line| {
+            let entry = serde_json::from_str(line).ok();
+            invalid += entry.is_none() as usize;
+            entry
+        })
+        .collect();
+    (entries, invalid)
+}
+
+/// A stored entry is a new spawn: duplicates of a known exchange are logged
+/// but not stored.
+fn spawns(entries: &[LogEntry]) -> impl Iterator<Item = &LogEntry> {
+    entries.iter().filter(|e| e.stored)
+}
+
+fn mean(values: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let (sum, n) = values
+        .into_iter()
+        .fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+    (n > 0).then(|| sum / n as f64)
+}
+
+fn kingdom_stats(entries: &[LogEntry], cell: u32) -> BTreeMap<u32, KingdomStats> {
+    let mut by_kingdom: BTreeMap<u32, Vec<&LogEntry>> = BTreeMap::new();
+    for entry in spawns(entries) {
+        by_kingdom.entry(entry.kingdom).or_default().push(entry);
+    }
+    by_kingdom
+        .into_iter()
+        .map(|(kingdom, spawns)| {
+            let locations: HashSet<_> = spawns.iter().map(|e| (e.x, e.y)).collect();
+            let mut cells: HashMap<(u32, u32), usize> = HashMap::new();
+            for e in &spawns {
+                *cells
+                    .entry((e.x / cell * cell, e.y / cell * cell))
+                    .or_default() += 1;
+            }
+            let mut cells: Vec<_> = cells.into_iter().collect();
+            cells.sort_by_key(|&((x, y), n)| (std::cmp::Reverse(n), y, x));
+            let stats = KingdomStats {
+                spawns: spawns.len(),
+                locations: locations.len(),
+                cells,
+                mean_scan_secs: mean(spawns.iter().filter_map(|e| e.scan_duration_secs)),
+            };
+            (kingdom, stats)
+        })
+        .collect()
+}
+
+fn pattern_stats(entries: &[LogEntry]) -> BTreeMap<&str, PatternStats> {
+    let mut by_pattern: BTreeMap<&str, Vec<&LogEntry>> = BTreeMap::new();
+    for entry in entries.iter().filter(|e| !e.near_miss) {
+        by_pattern
+            .entry(entry.scan_pattern.as_str())
+            .or_default()
+            .push(entry);
+    }
+    by_pattern
+        .into_iter()
+        .map(|(pattern, entries)| {
+            let stats = PatternStats {
+                detections: entries.len(),
+                confirmed: entries.iter().filter(|e| e.confirmed).count(),
+                stored: entries.iter().filter(|e| e.stored).count(),
+                mean_score: mean(entries.iter().map(|e| e.initial_score as f64)).unwrap_or(0.0),
+                mean_scan_secs: mean(
+                    entries
+                        .iter()
+                        .filter(|e| e.stored)
+                        .filter_map(|e| e.scan_duration_secs),
+                ),
+            };
+            (pattern, stats)
+        })
+        .collect()
+}
+
+fn near_miss_stats(entries: &[LogEntry]) -> NearMissStats {
+    let near_misses: Vec<_> = entries.iter().filter(|e| e.near_miss).collect();
+    let confirmed: Vec<_> = near_misses.iter().filter(|e| e.confirmed).collect();
+    NearMissStats {
+        retried: near_misses.len(),
+        confirmed: confirmed.len(),
+        lowest_confirmed: confirmed
+            .iter()
+            .map(|e| e.initial_score)
+            .min_by(f32::total_cmp),
+    }
+}
+
+/// Confirmed spawns as `kingdom,x,y` lines, by kingdom.
+fn known_locations_csv(entries: &[LogEntry]) -> String {
+    let mut rows: Vec<_> = spawns(entries)
+        .filter(|e| e.confirmed)
+        .map(|e| (e.kingdom, e.x, e.y))
+        .collect();
+    rows.sort_by_key(|&(k, ..)| k);
+    rows.iter()
+        .map(|(k, x, y)| format!("{k},{x},{y}\n"))
+        .collect()
+}
+
+fn secs(value: Option<f64>) -> String {
+    value.map_or("-".into(), |s| format!("{s:.0}s"))
+}
+
+fn main() {
+    let args = Args::parse();
+    if args.cell == 0 {
+        eprintln!("--cell must be at least 1");
+        std::process::exit(1);
+    }
+
+    let path = args.log.unwrap_or_else(|| {
+        std::env::var("MERCY_EXCHANGE_LOG")
+            .unwrap_or_else(|_| "exchanges.jsonl".into())
+            .into()
+    });
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {e}", path.display());
+        std::process::exit(1);
+    });
+    let (entries, invalid) = parse(&text);
+    println!(
+        "{}: {} detection(s), {} spawn(s)",
+        path.display(),
+        entries.len(),
+        spawns(&entries).count()
+    );
+    if invalid > 0 {
+        println!("Skipped {invalid} unreadable line(s)");
+    }
+
+    println!();
+    println!("Spawns by kingdom (cells of {} game units):", args.cell);
+    for (kingdom, stats) in kingdom_stats(&entries, args.cell) {
+        let cells: Vec<_> = stats
+            .cells
+            .iter()
+            .take(TOP_CELLS)
+            .map(|((x, y), n)| format!("({x},{y})×{n}"))
+            .collect();
+        println!(
+            "  K:{kingdom:<4} {:>4} spawn(s) at {:>4} location(s), mean scan {:>5}, top cells {}",
+            stats.spawns,
+            stats.locations,
+            secs(stats.mean_scan_secs),
+            cells.join(" ")
+        );
+    }
+
+    println!();
+    println!("By scan pattern:");
+    for (pattern, stats) in pattern_stats(&entries) {
+        println!(
+            "  {pattern:<8} {:>5} detection(s), {:>5.1}% confirmed, {:>5.1}% stored, mean score {:.4}, mean scan {}",
+            stats.detections,
+            100.0 * stats.confirmed as f64 / stats.detections as f64,
+            100.0 * stats.stored as f64 / stats.detections as f64,
+            stats.mean_score,
+            secs(stats.mean_scan_secs)
+        );
+    }
+    let near_misses = near_miss_stats(&entries);
+    if near_misses.retried > 0 {
+        println!(
+            "  near misses {:>5} clicked, {:>5.1}% confirmed, lowest confirmed score {}",
+            near_misses.retried,
+            100.0 * near_misses.confirmed as f64 / near_misses.retried as f64,
+            near_misses
+                .lowest_confirmed
+                .map_or("-".into(), |s| format!("{s:.4}"))
+        );
+    }
+    let overall = mean(spawns(&entries).filter_map(|e| e.scan_duration_secs));
+    println!();
+    println!("Mean scan duration to a spawn: {}", secs(overall));
+
+    if let Some(csv) = args.csv {
+        match std::fs::write(&csv, known_locations_csv(&entries)) {
+            Ok(()) => println!("Known locations written to {}", csv.display()),
+            Err(e) => eprintln!("Failed to write {}: {e}", csv.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"
+{"timestamp":"2025-01-01T00:00:00Z","kingdom":111,"x":506,"y":638,"confirmed":true,"stored":true,"initial_score":0.98,"calibration_score":0.97,"scan_pattern":"known","scan_duration_secs":120.0}
+{"timestamp":"2025-01-01T01:00:00Z","kingdom":111,"x":506,"y":638,"confirmed":true,"stored":false,"initial_score":0.96,"calibration_score":null,"scan_pattern":"known","scan_duration_secs":60.0}
+{"timestamp":"2025-01-02T00:00:00Z","kingdom":111,"x":510,"y":630,"confirmed":false,"stored":true,"initial_score":0.94,"calibration_score":0.95,"scan_pattern":"known","scan_duration_secs":null}
+{"timestamp":"2025-01-02T00:00:00Z","kingdom":109,"x":100,"y":200,"confirmed":false,"stored":false,"initial_score":0.90,"calibration_score":null,"scan_pattern":"grid","scan_duration_secs":300.0}
+{"timestamp":"2025-01-03T00:00:00Z","kingdom":109,"x":300,"y":400,"confirmed":false,"stored":false,"initial_score":0.97,"calibration_score":null,"scan_pattern":"grid","scan_duration_secs":300.0,"near_miss":true}
+{"timestamp":"2025-01-03T00:00:00Z","kingdom":109,"x":310,"y":400,"confirmed":true,"stored":true,"initial_score":0.96,"calibration_score":0.97,"scan_pattern":"grid","scan_duration_secs":300.0,"near_miss":true}
+not json
+"#;
+
+    #[test]
+    fn test_analyze() {
+        let (entries, invalid) = parse(LOG);
+        assert_eq!((entries.len(), invalid), (6, 1));
+
+        let kingdoms = kingdom_stats(&entries, 25);
+        assert_eq!(kingdoms[&109].spawns, 1, "from the near miss");
+        let k111 = &kingdoms[&111];
+        assert_eq!((k111.spawns, k111.locations), (2, 2));
+        assert_eq!(k111.cells, [((500, 625), 2)]);
+        assert_eq!(k111.mean_scan_secs, Some(120.0));
+
+        let patterns = pattern_stats(&entries);
+        let known = &patterns["known"];
+        assert_eq!((known.detections, known.confirmed, known.stored), (3, 2, 2));
+        assert!((known.mean_score - 0.96).abs() < 1e-6);
+        assert_eq!(
+            patterns["grid"].detections, 1,
+            "near misses aren't detections"
+        );
+        assert_eq!(patterns["grid"].mean_scan_secs, None);
+
+        assert_eq!(
+            near_miss_stats(&entries),
+            NearMissStats {
+                retried: 2,
+                confirmed: 1,
+                lowest_confirmed: Some(0.96),
+            }
+        );
+
+        assert_eq!(known_locations_csv(&entries), "109,310,400\n111,506,638\n");
+    }
+}
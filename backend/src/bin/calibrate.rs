@@ -0,0 +1,327 @@
+//! Solve the pixel-to-game transform (`MERCY_SCREEN_CENTER`,
+//! `MERCY_PX_PER_GAME`, `MERCY_TILT_Y`) from screenshots of one building at
+//! known game coordinates, each taken after navigating to a known point.
+//!
+//! Where the reference is found in each screenshot, relative to where the map
+//! was navigated, gives
+//!
+//! ```text
+//! pixel_x = center_x + px_per_game_x * dx
+//! pixel_y = center_y + tilt_y * dx + px_per_game_y * dy
+//! ```
+//!
+//! with (dx, dy) the building's offset from the navigated point. Two
+//! screenshots solve the X row and, with the tilt held at `MERCY_TILT_Y`, the
+//! Y row; three or more with independent offsets solve the tilt too.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Parser;
+
+use mercy::detector::{self, MatchBackend, MatchOptions, RefImage};
+use mercy::target::TargetProfile;
+use mercy::viewport::Viewport;
+
+/// `pixel_dy` per game X unit when it isn't solved (the current default).
+const DEFAULT_TILT_Y: f64 = -1.50;
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Solve the screen center and pixel-to-game scale from screenshots",
+    after_help = "Take the screenshots with the running service: GET /goto?k=&x=&y= returns one \
+                  navigated to that point. Use points a few tiles from the building, offset in \
+                  both X and Y. The building's own coordinates come from its popup."
+)]
+struct Args {
+    /// Game coordinates of the building, as X,Y
+    #[arg(short, long, value_name = "X,Y", value_parser = parse_point)]
+    target: (i32, i32),
+
+    /// Screenshots with the coordinates navigated to before each, as
+    /// FILE@X,Y
+    #[arg(required = true, num_args = 2.., value_parser = parse_shot)]
+    screenshots: Vec<(PathBuf, (i32, i32))>,
+
+    /// Reference image of the building [default: the MERCY_SEARCH_TARGET
+    /// templates]
+    #[arg(short, long, value_name = "FILE")]
+    reference: Option<PathBuf>,
+}
+
+/// Where the building was found in one screenshot.
+#[derive(Debug, Clone, Copy)]
+struct Observation {
+    /// Offset of the building from the navigated point, in game units
+    dx: f64,
+    dy: f64,
+    px: f64,
+    py: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Solution {
+    center_x: f64,
+    center_y: f64,
+    px_per_game_x: f64,
+    px_per_game_y: f64,
+    tilt_y: f64,
+    /// False when the tilt was held at the given value
+    tilt_solved: bool,
+}
+
+impl Solution {
+    fn predict(&self, o: &Observation) -> (f64, f64) {
+        (
+            self.center_x + self.px_per_game_x * o.dx,
+            self.center_y + self.tilt_y * o.dx + self.px_per_game_y * o.dy,
+        )
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let refs = match args.reference {
+        Some(ref path) => {
+            let image = image::open(path).unwrap_or_else(|e| {
+                eprintln!("Failed to load reference image {}: {e}", path.display());
+                std::process::exit(1);
+            });
+            vec![RefImage::new(path.display().to_string(), Arc::new(image))]
+        }
+        None => {
+            let target = std::env::var("MERCY_SEARCH_TARGET")
+                .unwrap_or_else(|_| "Mercenary Exchange Core".into());
+            match TargetProfile::load(&target) {
+                Ok((_, refs)) => refs,
+                Err(e) => {
+                    eprintln!("Failed to load the {target:?} templates: {e:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    let detector = detector::new_detector(
+        MatchBackend::default(),
+        detector::prepare_reference_images(&refs),
+        MatchOptions::default(),
+    );
+
+    let (tx, ty) = args.target;
+    let mut observations = Vec::new();
+    for (path, (nx, ny)) in &args.screenshots {
+        let screenshot = image::open(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {e}", path.display());
+            std::process::exit(1);
+        });
+        // The building can be anywhere on screen, UI included
+        let whole = Viewport {
+            left: 0,
+            top: 0,
+            right: screenshot.width(),
+            bottom: screenshot.height(),
+        };
+        let prepared = detector::PreparedScreenshot::new(&screenshot, whole);
+        let Some(m) = detector.find_best_match(&prepared) else {
+            eprintln!("{}: reference not found", path.display());
+            std::process::exit(1);
+        };
+        let (px, py) = m.position();
+        println!(
+            "{}: navigated to ({nx}, {ny}), building at pixel ({px:.1}, {py:.1}), score={:.4}",
+            path.display(),
+            m.score
+        );
+        observations.push(Observation {
+            dx: (tx - nx) as f64,
+            dy: (ty - ny) as f64,
+            px,
+            py,
+        });
+    }
+
+    let tilt = std::env::var("MERCY_TILT_Y")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_TILT_Y);
+    let solution = match solve(&observations, tilt) {
+        Ok(solution) => solution,
+        Err(e) => {
+            eprintln!("Can't solve: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    println!();
+    for o in &observations {
+        let (x, y) = solution.predict(o);
+        println!(
+            "offset ({:+}, {:+}): residual ({:+.1}, {:+.1}) px",
+            o.dx,
+            o.dy,
+            o.px - x,
+            o.py - y
+        );
+    }
+    if !solution.tilt_solved {
+        println!("tilt held at {tilt:.2}; add a screenshot to solve it too");
+    }
+    println!();
+    println!(
+        "MERCY_SCREEN_CENTER={:.0},{:.0}",
+        solution.center_x, solution.center_y
+    );
+    println!(
+        "MERCY_PX_PER_GAME={:.2},{:.2}",
+        solution.px_per_game_x, solution.px_per_game_y
+    );
+    println!("MERCY_TILT_Y={:.2}", solution.tilt_y);
+}
+
+/// Least-squares fit of the transform. The tilt is only solved when the
+/// offsets leave it determined; otherwise it's held at `tilt`.
+fn solve(observations: &[Observation], tilt: f64) -> Result<Solution, String> {
+    // X row: pixel_x = center_x + px_per_game_x * dx
+    let rows_x: Vec<_> = observations.iter().map(|o| vec![1.0, o.dx]).collect();
+    let xs: Vec<_> = observations.iter().map(|o| o.px).collect();
+    let [center_x, px_per_game_x] = least_squares::<2>(&rows_x, &xs)
+        .ok_or("the screenshots need different X offsets from the building")?;
+
+    // Y row: pixel_y = center_y + tilt_y * dx + px_per_game_y * dy
+    let rows_y: Vec<_> = observations.iter().map(|o| vec![1.0, o.dx, o.dy]).collect();
+    let ys: Vec<_> = observations.iter().map(|o| o.py).collect();
+    let (center_y, tilt_y, px_per_game_y, tilt_solved) = match (observations.len() > 2)
+        .then(|| least_squares::<3>(&rows_y, &ys))
+    {
+        Some(Some([center_y, tilt_y, px_per_game_y])) => (center_y, tilt_y, px_per_game_y, true),
+        _ => {
+            let rows: Vec<_> = observations.iter().map(|o| vec![1.0, o.dy]).collect();
+            let ys: Vec<_> = observations.iter().map(|o| o.py - tilt * o.dx).collect();
+            let [center_y, px_per_game_y] = least_squares::<2>(&rows, &ys)
+                .ok_or("the screenshots need different Y offsets from the building")?;
+            (center_y, tilt, px_per_game_y, false)
+        }
+    };
+
+    Ok(Solution {
+        center_x,
+        center_y,
+        px_per_game_x,
+        px_per_game_y,
+        tilt_y,
+        tilt_solved,
+    })
+}
+
+/// Solve the normal equations of `rows · β = ys` by Gaussian elimination.
+/// None when the system is (near) singular.
+fn least_squares<const N: usize>(rows: &[Vec<f64>], ys: &[f64]) -> Option<[f64; N]> {
+    let mut a = [[0.0; N]; N];
+    let mut b = [0.0; N];
+    for (row, y) in rows.iter().zip(ys) {
+        for i in 0..N {
+            for j in 0..N {
+                a[i][j] += row[i] * row[j];
+            }
+            b[i] += row[i] * y;
+        }
+    }
+
+    for col in 0..N {
+        let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..N {
+            let f = a[row][col] / pivot_row[col];
+            for (v, p) in a[row].iter_mut().zip(pivot_row).skip(col) {
+                *v -= f * p;
+            }
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = [0.0; N];
+    for i in (0..N).rev() {
+        let sum: f64 = (i + 1..N).map(|k| a[i][k] * x[k]).sum();
+        x[i] = (b[i] - sum) / a[i][i];
+    }
+    Some(x)
+}
+
+fn parse_point(s: &str) -> Result<(i32, i32), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected X,Y, got {s}"))?;
+    let parse = |v: &str| v.trim().parse().map_err(|e| format!("{v}: {e}"));
+    Ok((parse(x)?, parse(y)?))
+}
+
+fn parse_shot(s: &str) -> Result<(PathBuf, (i32, i32)), String> {
+    let (file, point) = s
+        .rsplit_once('@')
+        .ok_or_else(|| format!("expected FILE@X,Y, got {s}"))?;
+    Ok((file.into(), parse_point(point)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(truth: &Solution, dx: f64, dy: f64) -> Observation {
+        let mut o = Observation {
+            dx,
+            dy,
+            px: 0.0,
+            py: 0.0,
+        };
+        (o.px, o.py) = truth.predict(&o);
+        o
+    }
+
+    #[test]
+    fn test_solve() {
+        let truth = Solution {
+            center_x: 760.0,
+            center_y: 400.0,
+            px_per_game_x: 49.40,
+            px_per_game_y: 28.32,
+            tilt_y: -1.50,
+            tilt_solved: true,
+        };
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+
+        let three = [
+            observe(&truth, -10.0, 0.0),
+            observe(&truth, 16.0, 12.0),
+            observe(&truth, 4.0, -8.0),
+        ];
+        let solved = solve(&three, 0.0).unwrap();
+        assert!(solved.tilt_solved);
+        assert!(close(solved.tilt_y, truth.tilt_y));
+        assert!(close(solved.center_y, truth.center_y));
+        assert!(close(solved.px_per_game_x, truth.px_per_game_x));
+
+        // Two screenshots: the tilt is held at the given value
+        let solved = solve(&three[..2], -1.50).unwrap();
+        assert!(!solved.tilt_solved);
+        assert!(close(solved.center_x, truth.center_x));
+        assert!(close(solved.px_per_game_y, truth.px_per_game_y));
+
+        let same_x = [observe(&truth, 5.0, 0.0), observe(&truth, 5.0, 10.0)];
+        assert!(solve(&same_x, -1.50).is_err());
+    }
+
+    #[test]
+    fn test_parse_shot() {
+        assert_eq!(
+            parse_shot("shots/a@b.png@500,512").unwrap(),
+            ("shots/a@b.png".into(), (500, 512))
+        );
+        assert!(parse_shot("a.png").is_err());
+        assert!(parse_point("500").is_err());
+    }
+}
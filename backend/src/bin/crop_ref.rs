@@ -0,0 +1,219 @@
+//! Crop a reference template out of a screenshot and save it for a search
+//! target. The crop is centred on a pixel position, or on the point a goto
+//! clicks when nothing is matched (the screen center), and is added to the
+//! target's `<target>_refs.json` manifest in `MERCY_ASSETS_DIR` (or
+//! `./assets`) the same way `POST /refs/from-screenshot` adds one.
+//!
+//! `--mask` makes the background transparent: pixels close to the median
+//! colour of the crop's border get alpha 0. The detector doesn't read alpha
+//! yet and still matches the whole rectangle.
+
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Parser};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use mercy::detector;
+
+/// Where a goto clicks when the target isn't matched (the default
+/// `MERCY_SCREEN_CENTER`).
+const DEFAULT_SCREEN_CENTER: (u32, u32) = (760, 400);
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Crop a reference template from a screenshot",
+    group(ArgGroup::new("position").required(true).args(["at", "goto"]))
+)]
+struct Args {
+    /// Screenshot to crop from
+    screenshot: PathBuf,
+
+    /// Centre of the crop, in screenshot pixels
+    #[arg(long, value_name = "X,Y", value_parser = parse_pair)]
+    at: Option<(u32, u32)>,
+
+    /// Centre the crop on a goto's click point, MERCY_SCREEN_CENTER
+    /// [default: 760,400]
+    #[arg(long)]
+    goto: bool,
+
+    /// Template size in pixels
+    #[arg(short, long, value_name = "WxH", default_value = "70x35", value_parser = parse_size)]
+    size: (u32, u32),
+
+    /// Make pixels close to the border's median colour transparent
+    #[arg(long)]
+    mask: bool,
+
+    /// Largest per-channel difference from the background colour that
+    /// `--mask` clears
+    #[arg(long, default_value_t = 24, requires = "mask")]
+    tolerance: u8,
+
+    /// Search target to add the template to [default: MERCY_SEARCH_TARGET or
+    /// "Mercenary Exchange Core"]
+    #[arg(short, long)]
+    target: Option<String>,
+
+    /// Write the template to this file instead of the target's assets
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let screenshot = image::open(&args.screenshot).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {e}", args.screenshot.display());
+        std::process::exit(1);
+    });
+
+    let center = args.at.unwrap_or_else(|| {
+        std::env::var("MERCY_SCREEN_CENTER")
+            .ok()
+            .and_then(|v| parse_pair(&v).ok())
+            .unwrap_or(DEFAULT_SCREEN_CENTER)
+    });
+    let template = match crop(&screenshot, center, args.size) {
+        Some(template) => template,
+        None => {
+            eprintln!(
+                "A {}x{} crop around ({}, {}) doesn't fit the {}x{} screenshot",
+                args.size.0,
+                args.size.1,
+                center.0,
+                center.1,
+                screenshot.width(),
+                screenshot.height()
+            );
+            std::process::exit(1);
+        }
+    };
+    let template = if args.mask {
+        let masked = mask_background(&template, args.tolerance);
+        let kept = masked.pixels().filter(|p| p[3] > 0).count();
+        println!(
+            "Mask keeps {kept} of {} pixels",
+            masked.width() * masked.height()
+        );
+        DynamicImage::ImageRgba8(masked)
+    } else {
+        template
+    };
+
+    if let Some(path) = args.output {
+        if let Err(e) = template.save(&path) {
+            eprintln!("Failed to write {}: {e}", path.display());
+            std::process::exit(1);
+        }
+        println!("Wrote {}", path.display());
+        return;
+    }
+
+    let target = args.target.unwrap_or_else(|| {
+        std::env::var("MERCY_SEARCH_TARGET").unwrap_or_else(|_| "Mercenary Exchange Core".into())
+    });
+    match detector::save_reference(&target, template) {
+        Ok(reference) => println!(
+            "Added {} ({}x{}) to the {target:?} templates",
+            reference.name,
+            reference.image.width(),
+            reference.image.height()
+        ),
+        Err(e) => {
+            eprintln!("Failed to save the template: {e:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The `size` crop centred on `center`, or None if it would leave the image.
+fn crop(image: &DynamicImage, center: (u32, u32), size: (u32, u32)) -> Option<DynamicImage> {
+    let (w, h) = size;
+    let x = center.0.checked_sub(w / 2)?;
+    let y = center.1.checked_sub(h / 2)?;
+    let fits = x.checked_add(w).is_some_and(|r| r <= image.width())
+        && y.checked_add(h).is_some_and(|b| b <= image.height());
+    fits.then(|| image.crop_imm(x, y, w, h))
+}
+
+/// `template` with alpha 0 wherever it's within `tolerance` of the median
+/// border colour, taken as the background around the building.
+fn mask_background(template: &DynamicImage, tolerance: u8) -> RgbaImage {
+    let (w, h) = template.dimensions();
+    let border: Vec<_> = template
+        .pixels()
+        .filter(|&(x, y, _)| x == 0 || y == 0 || x == w - 1 || y == h - 1)
+        .map(|(_, _, p)| p)
+        .collect();
+    let background: [u8; 3] = std::array::from_fn(|c| {
+        let mut values: Vec<_> = border.iter().map(|p| p[c]).collect();
+        values.sort_unstable();
+        values[values.len() / 2]
+    });
+
+    let mut masked = template.to_rgba8();
+    for pixel in masked.pixels_mut() {
+        let Rgba([r, g, b, _]) = *pixel;
+        let close = [r, g, b]
+            .iter()
+            .zip(background)
+            .all(|(&v, bg)| v.abs_diff(bg) <= tolerance);
+        pixel[3] = if close { 0 } else { 255 };
+    }
+    masked
+}
+
+fn parse_pair(s: &str) -> Result<(u32, u32), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected X,Y, got {s}"))?;
+    let parse = |v: &str| v.trim().parse().map_err(|e| format!("{v}: {e}"));
+    Ok((parse(x)?, parse(y)?))
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WxH, got {s}"))?;
+    let parse = |v: &str| match v.trim().parse() {
+        Ok(n) if n >= 10 => Ok(n),
+        Ok(_) => Err("templates are at least 10 pixels a side".to_string()),
+        Err(e) => Err(format!("{v}: {e}")),
+    };
+    Ok((parse(w)?, parse(h)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crop_and_mask() {
+        // Grass with a red building in the middle
+        let mut screenshot = image::RgbImage::from_pixel(100, 80, image::Rgb([40, 120, 40]));
+        for y in 35..45 {
+            for x in 45..55 {
+                screenshot.put_pixel(x, y, image::Rgb([200, 30, 30]));
+            }
+        }
+        let screenshot = DynamicImage::ImageRgb8(screenshot);
+
+        let template = crop(&screenshot, (50, 40), (20, 16)).unwrap();
+        assert_eq!(template.dimensions(), (20, 16));
+        assert!(crop(&screenshot, (5, 40), (20, 16)).is_none());
+        assert!(crop(&screenshot, (95, 40), (20, 16)).is_none());
+
+        let masked = mask_background(&template, 24);
+        assert_eq!(masked.get_pixel(0, 0)[3], 0);
+        assert_eq!(masked.get_pixel(10, 8)[3], 255);
+        assert_eq!(masked.pixels().filter(|p| p[3] > 0).count(), 100);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("70x35").unwrap(), (70, 35));
+        assert!(parse_size("5x35").is_err());
+        assert!(parse_size("70").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Render where exchanges spawn: one 1024×1024 PNG per kingdom, a pixel per
+//! game coordinate (X to the right, Y down), each find location drawn as a
+//! dot coloured by how often it spawned there. Reads the exchange log
+//! (stored finds) or, with `--known`, a `kingdom,x,y` CSV such as
+//! `assets/known_locations.csv`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use clap::Parser;
+use image::{Rgb, RgbImage};
+use imageproc::drawing::draw_filled_circle_mut;
+use serde::Deserialize;
+
+const MAP_SIZE: u32 = 1024;
+const BACKGROUND: Rgb<u8> = Rgb([24, 26, 30]);
+const GRID: Rgb<u8> = Rgb([44, 48, 54]);
+/// Game units between grid lines.
+const GRID_STEP: u32 = 128;
+
+#[derive(Debug, Parser)]
+#[command(about = "Render per-kingdom maps of where exchanges spawn")]
+struct Args {
+    /// Exchange log [default: MERCY_EXCHANGE_LOG or exchanges.jsonl]
+    log: Option<PathBuf>,
+
+    /// Read spawns from a kingdom,x,y CSV instead of the exchange log
+    #[arg(long, value_name = "FILE", conflicts_with = "log")]
+    known: Option<PathBuf>,
+
+    /// Only these kingdoms, comma-separated [default: all]
+    #[arg(short, long, value_delimiter = ',')]
+    kingdoms: Vec<u32>,
+
+    /// Directory to write heatmap_k<K>.png files to
+    #[arg(short, long, default_value = "heatmaps")]
+    out: PathBuf,
+
+    /// Dot radius in pixels
+    #[arg(long, default_value_t = 4)]
+    radius: i32,
+}
+
+/// The fields of an exchange log line this reads.
+#[derive(Debug, Deserialize)]
+struct LogEntry {
+    kingdom: u32,
+    x: u32,
+    y: u32,
+    stored: bool,
+}
+
+/// Spawns per location, by kingdom.
+type Spawns = BTreeMap<u32, HashMap<(u32, u32), u32>>;
+
+fn read_log(text: &str) -> Spawns {
+    let mut spawns = Spawns::new();
+    for entry in text
+        .lines()
+        .filter_map(|l| serde_json::from_str::<LogEntry>(l).ok())
+        .filter(|e| e.stored)
+    {
+        add(&mut spawns, entry.kingdom, entry.x, entry.y);
+    }
+    spawns
+}
+
+fn read_known(text: &str) -> Spawns {
+    let mut spawns = Spawns::new();
+    for line in text.lines() {
+        let fields: Vec<u32> = line
+            .split(',')
+            .filter_map(|f| f.trim().parse().ok())
+            .collect();
+        if let [k, x, y] = fields[..] {
+            add(&mut spawns, k, x, y);
+        }
+    }
+    spawns
+}
+
+fn add(spawns: &mut Spawns, kingdom: u32, x: u32, y: u32) {
+    if x < MAP_SIZE && y < MAP_SIZE {
+        *spawns
+            .entry(kingdom)
+            .or_default()
+            .entry((x, y))
+            .or_default() += 1;
+    }
+}
+
+/// Blue (once) through green to red (the most spawns).
+fn ramp(t: f32) -> Rgb<u8> {
+    let channel = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+    Rgb([
+        channel(2.0 * t - 1.0),
+        channel(1.0 - (2.0 * t - 1.0).abs()),
+        channel(1.0 - 2.0 * t),
+    ])
+}
+
+/// One kingdom's map. Rarer spots are drawn first so busy ones stay on top.
+fn render(locations: &HashMap<(u32, u32), u32>, radius: i32) -> RgbImage {
+    let mut image = RgbImage::from_fn(MAP_SIZE, MAP_SIZE, |x, y| {
+        if x % GRID_STEP == 0 || y % GRID_STEP == 0 {
+            GRID
+        } else {
+            BACKGROUND
+        }
+    });
+
+    let max = locations.values().copied().max().unwrap_or(1);
+    let mut dots: Vec<_> = locations.iter().collect();
+    dots.sort_by_key(|&(&(x, y), &n)| (n, y, x));
+    for (&(x, y), &n) in dots {
+        let t = if max > 1 {
+            (n - 1) as f32 / (max - 1) as f32
+        } else {
+            0.0
+        };
+        draw_filled_circle_mut(&mut image, (x as i32, y as i32), radius, ramp(t));
+    }
+    image
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let path = args.known.clone().or(args.log).unwrap_or_else(|| {
+        std::env::var("MERCY_EXCHANGE_LOG")
+            .unwrap_or_else(|_| "exchanges.jsonl".into())
+            .into()
+    });
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {e}", path.display());
+        std::process::exit(1);
+    });
+    let mut spawns = if args.known.is_some() {
+        read_known(&text)
+    } else {
+        read_log(&text)
+    };
+    if !args.kingdoms.is_empty() {
+        spawns.retain(|k, _| args.kingdoms.contains(k));
+    }
+    if spawns.is_empty() {
+        eprintln!("No spawns in {}", path.display());
+        std::process::exit(1);
+    }
+
+    if let Err(e) = std::fs::create_dir_all(&args.out) {
+        eprintln!("Failed to create {}: {e}", args.out.display());
+        std::process::exit(1);
+    }
+    let mut failed = false;
+    for (kingdom, locations) in &spawns {
+        let file = args.out.join(format!("heatmap_k{kingdom}.png"));
+        match render(locations, args.radius).save(&file) {
+            Ok(()) => println!(
+                "K:{kingdom}: {} spawn(s) at {} location(s), at most {} in one spot -> {}",
+                locations.values().sum::<u32>(),
+                locations.len(),
+                locations.values().max().unwrap_or(&0),
+                file.display()
+            ),
+            Err(e) => {
+                eprintln!("Failed to write {}: {e}", file.display());
+                failed = true;
+            }
+        }
+    }
+    println!("Colours run from blue (one spawn) through green to red (the most in that kingdom)");
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let log = r#"{"kingdom":111,"x":506,"y":638,"stored":true}
+{"kingdom":111,"x":506,"y":638,"stored":true}
+{"kingdom":111,"x":506,"y":638,"stored":false}
+{"kingdom":111,"x":100,"y":200,"stored":true}
+{"kingdom":109,"x":5000,"y":1,"stored":true}"#;
+        let spawns = read_log(log);
+        assert_eq!(spawns.len(), 1, "off-map spawns are dropped");
+        assert_eq!(spawns[&111][&(506, 638)], 2);
+        assert_eq!(
+            read_known("111,506,638\n111,506,638\n")[&111][&(506, 638)],
+            2
+        );
+
+        let image = render(&spawns[&111], 3);
+        assert_eq!(image.dimensions(), (MAP_SIZE, MAP_SIZE));
+        assert_eq!(*image.get_pixel(506, 638), ramp(1.0));
+        assert_eq!(*image.get_pixel(100, 200), ramp(0.0));
+        assert_eq!(*image.get_pixel(300, 300), BACKGROUND);
+        assert_eq!(*image.get_pixel(256, 300), GRID);
+    }
+}
@@ -0,0 +1,46 @@
+//! Smoke test of a deployment: launch the browser, log in, check a
+//! screenshot of the game isn't blank and that navigating to 512,512 in the
+//! first kingdom works. Prints a `PASS`/`FAIL` line per step and exits
+//! non-zero at the first failure (see `mercy::login_check`).
+//!
+//! Reads the same `MERCY_*` settings as the service, and takes the account
+//! locks like it, so it refuses to run against an account in use.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use mercy::{cli, instance, login_check};
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Log in, load the map and exit, non-zero on a failure",
+    after_help = "Anything not given here is read from the MERCY_* environment variables \
+                  (see .env.example)."
+)]
+struct Args {
+    /// File of MERCY_*=value lines (the .env.example format) for variables
+    /// not already set
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Run Chromium without a window [env: MERCY_HEADLESS]
+    #[arg(long)]
+    headless: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut vars = Vec::new();
+    if args.headless {
+        vars.push(("MERCY_HEADLESS".to_string(), "1".to_string()));
+    }
+    let source = cli::export(vars, args.config)?;
+    let config = source.load().context("failed to load configuration")?;
+    let _instance = instance::acquire(&config)?;
+    if !login_check::run(config) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
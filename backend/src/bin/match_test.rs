@@ -1,16 +1,91 @@
+//! Run template matching over screenshots, for tuning thresholds against a
+//! corpus of debug captures. Screenshots can be files, directories (every
+//! image in them) or glob patterns; `--csv` and `--json` write a per-file
+//! summary, and score percentiles are printed at the end.
+
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use mercy::detector;
+use clap::Parser;
+use serde::Serialize;
+
+use mercy::{detector, viewport};
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+
+const PERCENTILES: &[u32] = &[10, 25, 50, 75, 90];
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Template-match screenshots against a reference image",
+    after_help = "Match settings are read from the MERCY_* variables (MERCY_DETECTOR, \
+                  MERCY_MATCH_METHOD, MERCY_VIEWPORT, ...)."
+)]
+struct Args {
+    /// Reference image
+    reference: PathBuf,
+
+    /// Screenshots: files, directories or glob patterns (quoted)
+    #[arg(required = true)]
+    screenshots: Vec<String>,
+
+    /// Scores to report pass/fail at, comma-separated [default: 0.90,0.95
+    /// and the match threshold]
+    #[arg(short, long, value_delimiter = ',')]
+    thresholds: Vec<f32>,
+
+    /// Write the per-file results as CSV
+    #[arg(long, value_name = "FILE")]
+    csv: Option<PathBuf>,
+
+    /// Write the per-file results and aggregates as JSON
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+}
+
+/// Best match of one screenshot.
+#[derive(Debug, Serialize)]
+struct FileResult {
+    file: String,
+    /// None when the detector gave no result
+    score: Option<f32>,
+    x: Option<u32>,
+    y: Option<u32>,
+    /// Matches above each template's own threshold
+    above_threshold: usize,
+    /// Whether `score` reaches each of the thresholds
+    pass: Vec<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    files: usize,
+    /// Files without a correlation result
+    no_result: usize,
+    /// Files passing each threshold
+    passed: Vec<ThresholdCount>,
+    min: Option<f32>,
+    max: Option<f32>,
+    /// Best-score percentiles (nearest rank), as `p<N>`
+    percentiles: Vec<(String, f32)>,
+}
+
+#[derive(Debug, Serialize)]
+struct ThresholdCount {
+    threshold: f32,
+    passed: usize,
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: match_test <reference.png> <screenshot.png> [screenshot2.png ...]");
-        std::process::exit(1);
-    }
+    let args = Args::parse();
+    let thresholds = if args.thresholds.is_empty() {
+        vec![0.90, 0.95, detector::MATCH_THRESHOLD]
+    } else {
+        args.thresholds.clone()
+    };
 
-    let ref_path = &args[1];
-    let ref_img = Arc::new(image::open(ref_path).unwrap_or_else(|e| {
+    let ref_path = args.reference.display().to_string();
+    let ref_img = Arc::new(image::open(&args.reference).unwrap_or_else(|e| {
         eprintln!("Failed to load reference image {ref_path}: {e}");
         std::process::exit(1);
     }));
@@ -21,16 +96,82 @@ fn main() {
         ref_img.height()
     );
 
-    let prepared = detector::prepare_reference_images(&[ref_img]);
+    let prepared =
+        detector::prepare_reference_images(&[detector::RefImage::new(ref_path.as_str(), ref_img)]);
     println!(
-        "Prepared: {}x{} RGB per-channel",
+        "Prepared: {}x{} per-channel",
         prepared[0].width, prepared[0].height
     );
     println!("Threshold: {:.4}", detector::MATCH_THRESHOLD);
+
+    let match_opts = detector::MatchOptions {
+        phash_max_distance: std::env::var("MERCY_PHASH_MAX_DISTANCE")
+            .ok()
+            .and_then(|v| v.parse().ok()),
+        coarse_factor: std::env::var("MERCY_COARSE_FACTOR")
+            .ok()
+            .and_then(|v| v.parse().ok()),
+        color_space: std::env::var("MERCY_COLOR_SPACE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default(),
+        onnx_model: std::env::var("MERCY_ONNX_MODEL").ok().map(Into::into),
+        channel_weights: std::env::var("MERCY_CHANNELS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default(),
+        aggregation: std::env::var("MERCY_CHANNEL_AGGREGATION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default(),
+        method: std::env::var("MERCY_MATCH_METHOD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default(),
+        ab_log: std::env::var("MERCY_MATCH_AB_LOG").is_ok_and(|v| v == "1" || v == "true"),
+    };
+    let backend: detector::MatchBackend = std::env::var("MERCY_DETECTOR")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default();
+    if let Some(d) = match_opts.phash_max_distance {
+        println!("Hash prefilter: max distance {d}");
+    }
+    if let Some(f) = match_opts.coarse_factor {
+        println!("Coarse-to-fine: 1/{f} first pass");
+    }
+    println!("Color space: {:?}", match_opts.color_space);
+    println!(
+        "Channels: {:?} ({:?})",
+        match_opts.channel_weights, match_opts.aggregation
+    );
+    println!("Method: {:?}", match_opts.method);
+    println!("Backend: {backend:?}");
     println!();
 
-    for screenshot_path in &args[2..] {
-        let screenshot = match image::open(screenshot_path) {
+    let detector = detector::new_detector(backend, prepared, match_opts);
+    let default_viewport = std::env::var("MERCY_VIEWPORT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default();
+    let anchors = viewport::load_ui_anchors().unwrap_or_else(|e| {
+        eprintln!("Failed to load UI anchors: {e:#}");
+        Vec::new()
+    });
+
+    let mut paths = Vec::new();
+    for arg in &args.screenshots {
+        match expand(arg) {
+            Ok(found) if found.is_empty() => eprintln!("No images in {arg}"),
+            Ok(found) => paths.extend(found),
+            Err(e) => eprintln!("Bad screenshot argument {arg}: {e}"),
+        }
+    }
+
+    let mut results = Vec::new();
+    for path in &paths {
+        let screenshot_path = path.display().to_string();
+        let screenshot = match image::open(path) {
             Ok(img) => img,
             Err(e) => {
                 eprintln!("Failed to load {screenshot_path}: {e}");
@@ -38,11 +179,22 @@ fn main() {
             }
         };
 
-        let best = detector::find_best_match(&screenshot, &prepared);
-        let matches = detector::find_matches(&screenshot, &prepared).unwrap_or_default();
+        let viewport = viewport::detect_viewport(&screenshot, &anchors, default_viewport);
+        let screenshot = detector::PreparedScreenshot::new(&screenshot, viewport);
+        let best = detector.find_best_match(&screenshot);
+        if std::env::var("MERCY_DEBUG_HEATMAP").is_ok_and(|v| v == "1" || v == "true")
+            && let Some(heatmap) = detector.score_heatmap(&screenshot)
+        {
+            let heatmap_path = format!("{screenshot_path}.heatmap.png");
+            match heatmap.save(&heatmap_path) {
+                Ok(()) => println!("{screenshot_path}: heatmap written to {heatmap_path}"),
+                Err(e) => eprintln!("Failed to write {heatmap_path}: {e}"),
+            }
+        }
+        let matches = detector.find_matches(&screenshot).unwrap_or_default();
 
         match best {
-            Some(m) => {
+            Some(ref m) => {
                 let status = if m.score >= detector::MATCH_THRESHOLD {
                     "MATCH"
                 } else {
@@ -60,5 +212,214 @@ fn main() {
                 println!("{screenshot_path}: no correlation result");
             }
         }
+        results.push(FileResult {
+            file: screenshot_path,
+            score: best.as_ref().map(|m| m.score),
+            x: best.as_ref().map(|m| m.x),
+            y: best.as_ref().map(|m| m.y),
+            above_threshold: matches.len(),
+            pass: thresholds
+                .iter()
+                .map(|&t| best.as_ref().is_some_and(|m| m.score >= t))
+                .collect(),
+        });
+    }
+
+    let summary = summarize(&results, &thresholds);
+    print_summary(&summary);
+
+    if let Some(ref path) = args.csv {
+        match std::fs::write(path, to_csv(&results, &thresholds)) {
+            Ok(()) => println!("CSV written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write {}: {e}", path.display()),
+        }
+    }
+    if let Some(ref path) = args.json {
+        let json = serde_json::json!({
+            "reference": ref_path,
+            "thresholds": thresholds,
+            "files": results,
+            "summary": summary,
+        });
+        match std::fs::write(
+            path,
+            serde_json::to_string_pretty(&json).unwrap_or_default(),
+        ) {
+            Ok(()) => println!("JSON written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write {}: {e}", path.display()),
+        }
+    }
+}
+
+/// The screenshots an argument names: itself, the images in a directory, or
+/// the files a glob pattern matches, sorted.
+fn expand(arg: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(arg);
+    let mut paths: Vec<PathBuf> = if path.is_dir() {
+        std::fs::read_dir(path)
+            .map_err(|e| e.to_string())?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| is_image(p))
+            .collect()
+    } else if arg.contains(['*', '?', '[']) {
+        glob::glob(arg)
+            .map_err(|e| e.to_string())?
+            .filter_map(Result::ok)
+            .filter(|p| p.is_file())
+            .collect()
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+fn summarize(results: &[FileResult], thresholds: &[f32]) -> Summary {
+    let mut scores: Vec<f32> = results.iter().filter_map(|r| r.score).collect();
+    scores.sort_by(f32::total_cmp);
+    Summary {
+        files: results.len(),
+        no_result: results.len() - scores.len(),
+        passed: thresholds
+            .iter()
+            .enumerate()
+            .map(|(i, &threshold)| ThresholdCount {
+                threshold,
+                passed: results.iter().filter(|r| r.pass[i]).count(),
+            })
+            .collect(),
+        min: scores.first().copied(),
+        max: scores.last().copied(),
+        percentiles: PERCENTILES
+            .iter()
+            .filter_map(|&p| Some((format!("p{p}"), percentile(&scores, p)?)))
+            .collect(),
+    }
+}
+
+/// Nearest-rank percentile of sorted scores.
+fn percentile(sorted: &[f32], p: u32) -> Option<f32> {
+    let rank = (p as usize * sorted.len()).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied()
+}
+
+fn print_summary(summary: &Summary) {
+    println!();
+    println!(
+        "Files: {} ({} without a result)",
+        summary.files, summary.no_result
+    );
+    for count in &summary.passed {
+        println!(
+            "  >= {:.4}: {}/{}",
+            count.threshold, count.passed, summary.files
+        );
+    }
+    if let (Some(min), Some(max)) = (summary.min, summary.max) {
+        let percentiles: Vec<String> = summary
+            .percentiles
+            .iter()
+            .map(|(name, score)| format!("{name}={score:.4}"))
+            .collect();
+        println!(
+            "Scores: min={min:.4} {} max={max:.4}",
+            percentiles.join(" ")
+        );
+    }
+}
+
+fn to_csv(results: &[FileResult], thresholds: &[f32]) -> String {
+    let field = |v: Option<String>| v.unwrap_or_default();
+    let mut csv = String::from("file,score,x,y,above_threshold");
+    for t in thresholds {
+        csv.push_str(&format!(",pass_{t}"));
+    }
+    csv.push('\n');
+    for r in results {
+        let file = if r.file.contains([',', '"', '\n']) {
+            format!("\"{}\"", r.file.replace('"', "\"\""))
+        } else {
+            r.file.clone()
+        };
+        csv.push_str(&format!(
+            "{file},{},{},{},{}",
+            field(r.score.map(|s| format!("{s:.4}"))),
+            field(r.x.map(|x| x.to_string())),
+            field(r.y.map(|y| y.to_string())),
+            r.above_threshold
+        ));
+        for pass in &r.pass {
+            csv.push_str(if *pass { ",1" } else { ",0" });
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let result = |file: &str, score: Option<f32>| FileResult {
+            file: file.into(),
+            score,
+            x: score.map(|_| 10),
+            y: score.map(|_| 20),
+            above_threshold: 0,
+            pass: vec![score.is_some_and(|s| s >= 0.9)],
+        };
+        let results: Vec<_> = [0.5, 0.95, 0.8, 0.99]
+            .iter()
+            .enumerate()
+            .map(|(i, &s)| result(&format!("s{i}.png"), Some(s)))
+            .chain([result("a,b.png", None)])
+            .collect();
+
+        let summary = summarize(&results, &[0.9]);
+        assert_eq!((summary.files, summary.no_result), (5, 1));
+        assert_eq!(summary.passed[0].passed, 2);
+        assert_eq!((summary.min, summary.max), (Some(0.5), Some(0.99)));
+        assert_eq!(summary.percentiles[2], ("p50".into(), 0.8));
+        assert_eq!(summary.percentiles[4], ("p90".into(), 0.99));
+
+        let csv = to_csv(&results, &[0.9]);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "file,score,x,y,above_threshold,pass_0.9");
+        assert_eq!(lines[2], "s1.png,0.9500,10,20,0,1");
+        assert_eq!(lines[5], "\"a,b.png\",,,,0,0");
+    }
+
+    #[test]
+    fn test_expand_directory_and_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.png", "a.jpg", "notes.txt"] {
+            std::fs::write(dir.path().join(name), []).unwrap();
+        }
+        let names = |paths: Vec<PathBuf>| -> Vec<String> {
+            paths
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let dir_arg = dir.path().to_str().unwrap();
+        assert_eq!(names(expand(dir_arg).unwrap()), ["a.jpg", "b.png"]);
+        assert_eq!(
+            names(expand(&format!("{dir_arg}/*.png")).unwrap()),
+            ["b.png"]
+        );
+        assert_eq!(
+            expand("missing.png").unwrap(),
+            [PathBuf::from("missing.png")]
+        );
     }
 }
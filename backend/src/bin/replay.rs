@@ -0,0 +1,367 @@
+//! Replay recorded scan steps through detection, the coordinate estimate,
+//! calibration and confirmation, without a game session, and report what the
+//! scanner would have stored. Catches regressions in the coordinate math
+//! against a corpus of known outcomes.
+//!
+//! Each step is a `<name>.json` sidecar in the directory, next to its scan
+//! screenshot:
+//!
+//! ```json
+//! {
+//!     "kingdom": 111,
+//!     "x": 512,
+//!     "y": 512,
+//!     "screenshot": "step_012.png",
+//!     "goto": "step_012_goto.png",
+//!     "popup_text": "Mercenary Exchange (K:111 X:506 Y:638)",
+//!     "expected": {"x": 506, "y": 638}
+//! }
+//! ```
+//!
+//! `x`/`y` are the coordinates navigated to for the scan step. The scan
+//! screenshot is the full page or just the viewport, and defaults to
+//! `<name>.png`. `goto` is the screenshot taken after navigating to the
+//! estimate; without it the estimate isn't calibrated. `popup_text` is what
+//! the popup read, if one opened. With `expected` (or `"expected": null` for
+//! a step that must not store anything) the step passes or fails, and any
+//! failure exits non-zero.
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use mercy::detector::{self, Detector, PreparedScreenshot};
+use mercy::locate::{self, CALIBRATION_ROI_HALF, Confirmation, MapTransform, VerifyCriteria};
+use mercy::target::TargetProfile;
+use mercy::viewport::{self, Viewport};
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Replay recorded scan steps through the confirmation pipeline",
+    after_help = "Templates come from MERCY_SEARCH_TARGET; match settings, MERCY_VIEWPORT, \
+                  MERCY_SCREEN_CENTER, MERCY_PX_PER_GAME, MERCY_TILT_Y and the \
+                  MERCY_VERIFY_* thresholds are read like the service reads them."
+)]
+struct Args {
+    /// Directory of scan steps (`*.json` sidecars and their screenshots)
+    dir: PathBuf,
+
+    /// Write the per-step results as JSON
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+}
+
+/// Sidecar metadata of one recorded scan step.
+#[derive(Debug, Deserialize)]
+struct Step {
+    kingdom: u32,
+    /// Coordinates navigated to for the scan screenshot
+    x: u32,
+    y: u32,
+    /// Scan screenshot, relative to the sidecar [default: `<name>.png`]
+    #[serde(default)]
+    screenshot: Option<String>,
+    /// Screenshot after navigating to the estimate
+    #[serde(default)]
+    goto: Option<String>,
+    #[serde(default)]
+    popup_text: Option<String>,
+    /// What should be stored: absent to only report, null for nothing
+    #[serde(default, deserialize_with = "present")]
+    expected: Option<Option<Coords>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+struct Coords {
+    x: u32,
+    y: u32,
+}
+
+/// Distinguishes `"expected": null` from a missing field.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// What the pipeline made of one step.
+#[derive(Debug, Serialize)]
+struct StepResult {
+    step: String,
+    /// Best scan match score (None: no match above threshold)
+    score: Option<f32>,
+    estimate: Option<Coords>,
+    calibration_score: Option<f32>,
+    refined: Option<Coords>,
+    /// Debug form of [`Confirmation`]
+    outcome: Option<String>,
+    /// Coordinates the scanner would store
+    stored: Option<Coords>,
+    /// None when the step has no expectation
+    pass: Option<bool>,
+}
+
+/// Everything a step is replayed against.
+struct Pipeline {
+    detector: std::sync::Arc<dyn Detector>,
+    target: TargetProfile,
+    transform: MapTransform,
+    criteria: VerifyCriteria,
+    viewport: Viewport,
+    anchors: Vec<viewport::UiAnchor>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let search_target =
+        std::env::var("MERCY_SEARCH_TARGET").unwrap_or_else(|_| "Mercenary Exchange Core".into());
+    let (target, refs) = TargetProfile::load(&search_target).unwrap_or_else(|e| {
+        eprintln!("Failed to load the {search_target:?} templates: {e:#}");
+        std::process::exit(1);
+    });
+    let detector = detector::new_detector(
+        env_parse("MERCY_DETECTOR").unwrap_or_default(),
+        detector::prepare_reference_images(&refs),
+        match_options(),
+    );
+
+    let transform = map_transform();
+    let criteria = VerifyCriteria {
+        min_score: target
+            .verify_min_score
+            .or_else(|| env_parse("MERCY_VERIFY_MIN_SCORE"))
+            .unwrap_or(0.90),
+        max_offset_px: target
+            .verify_max_offset_px
+            .or_else(|| env_parse("MERCY_VERIFY_MAX_OFFSET_PX"))
+            .unwrap_or(80),
+        center: transform.center(),
+    };
+    let pipeline = Pipeline {
+        detector,
+        target,
+        transform,
+        criteria,
+        viewport: env_parse("MERCY_VIEWPORT").unwrap_or_default(),
+        anchors: viewport::load_ui_anchors().unwrap_or_else(|e| {
+            eprintln!("Failed to load UI anchors: {e:#}");
+            Vec::new()
+        }),
+    };
+
+    let mut sidecars: Vec<_> = match std::fs::read_dir(&args.dir) {
+        Ok(entries) => entries
+            .filter_map(|e| Some(e.ok()?.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to read {}: {e}", args.dir.display());
+            std::process::exit(1);
+        }
+    };
+    sidecars.sort();
+
+    let mut results = Vec::new();
+    for sidecar in &sidecars {
+        match replay_step(&pipeline, sidecar) {
+            Ok(result) => {
+                print_result(&result);
+                results.push(result);
+            }
+            Err(e) => eprintln!("{}: {e:#}", sidecar.display()),
+        }
+    }
+
+    let checked = results.iter().filter(|r| r.pass.is_some()).count();
+    let failed = results.iter().filter(|r| r.pass == Some(false)).count();
+    let stored = results.iter().filter(|r| r.stored.is_some()).count();
+    println!();
+    println!(
+        "{} step(s) replayed, {stored} stored, {checked} checked, {failed} failed",
+        results.len()
+    );
+
+    if let Some(path) = args.json {
+        let json = serde_json::to_string_pretty(&results).expect("results serialize");
+        match std::fs::write(&path, json) {
+            Ok(()) => println!("JSON written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write {}: {e}", path.display()),
+        }
+    }
+    if failed > 0 || results.len() < sidecars.len() {
+        std::process::exit(1);
+    }
+}
+
+/// Run one recorded step through the pipeline, the way `confirm_match` does
+/// in a live scan.
+fn replay_step(pipeline: &Pipeline, sidecar: &Path) -> anyhow::Result<StepResult> {
+    let text = std::fs::read_to_string(sidecar)?;
+    let step: Step = serde_json::from_str(&text)?;
+    let dir = sidecar.parent().unwrap_or(Path::new("."));
+    let name = sidecar
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+
+    let scan_file = step.screenshot.clone().unwrap_or(format!("{name}.png"));
+    let scan = open(&dir.join(scan_file))?;
+    let scan = if (scan.width(), scan.height())
+        == (pipeline.viewport.width(), pipeline.viewport.height())
+    {
+        PreparedScreenshot::from_cropped(&scan, pipeline.viewport)
+    } else {
+        let viewport = viewport::detect_viewport(&scan, &pipeline.anchors, pipeline.viewport);
+        PreparedScreenshot::new(&scan, viewport)
+    };
+
+    let mut result = StepResult {
+        step: name,
+        score: None,
+        estimate: None,
+        calibration_score: None,
+        refined: None,
+        outcome: None,
+        stored: None,
+        pass: None,
+    };
+    let matches = pipeline.detector.find_matches(&scan)?;
+    if let Some(m) = matches.first() {
+        let (est_x, est_y) = locate::estimate(&pipeline.transform, (step.x, step.y), m.position());
+        result.score = Some(m.score);
+        result.estimate = Some(Coords { x: est_x, y: est_y });
+
+        let calibration = match step.goto {
+            Some(ref goto) => {
+                let goto = open(&dir.join(goto))?;
+                let goto = PreparedScreenshot::new(&goto, pipeline.viewport);
+                let roi = pipeline.transform.center_roi(CALIBRATION_ROI_HALF);
+                pipeline.detector.find_best_match(&goto.region(roi))
+            }
+            None => None,
+        };
+        result.calibration_score = calibration.as_ref().map(|c| c.score);
+        let refined = locate::refine(&pipeline.transform, (est_x, est_y), calibration.as_ref());
+        result.refined = Some(Coords {
+            x: refined.x,
+            y: refined.y,
+        });
+
+        let confirmation = locate::confirm(
+            step.popup_text.as_deref(),
+            &pipeline.target.popup,
+            &pipeline.criteria,
+            calibration.as_ref(),
+        );
+        result.outcome = Some(format!("{confirmation:?}"));
+        result.stored = match confirmation {
+            Confirmation::Popup { kingdom, x, y } if kingdom == step.kingdom => {
+                Some(Coords { x, y })
+            }
+            Confirmation::Popup { kingdom, x, y } => {
+                eprintln!(
+                    "{}: popup is in K:{kingdom} ({x}, {y}), not K:{}",
+                    result.step, step.kingdom
+                );
+                Some(Coords { x, y })
+            }
+            Confirmation::Calibrated => result.refined,
+            _ => None,
+        };
+    }
+    result.pass = step.expected.map(|expected| expected == result.stored);
+    Ok(result)
+}
+
+fn print_result(result: &StepResult) {
+    let coords = |c: Option<Coords>| c.map_or("-".into(), |c| format!("({}, {})", c.x, c.y));
+    let status = match result.pass {
+        Some(true) => "PASS",
+        Some(false) => "FAIL",
+        None => "",
+    };
+    match result.score {
+        Some(score) => println!(
+            "{}: score={score:.4} estimate={} calibration={} refined={} {} stored={} {status}",
+            result.step,
+            coords(result.estimate),
+            result
+                .calibration_score
+                .map_or("-".into(), |s| format!("{s:.4}")),
+            coords(result.refined),
+            result.outcome.as_deref().unwrap_or_default(),
+            coords(result.stored),
+        ),
+        None => println!("{}: no match {status}", result.step),
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<image::DynamicImage> {
+    image::open(path).map_err(|e| anyhow::anyhow!("failed to load {}: {e}", path.display()))
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
+
+fn env_pair(name: &str) -> Option<(f64, f64)> {
+    let value = std::env::var(name).ok()?;
+    let (x, y) = value.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+fn map_transform() -> MapTransform {
+    let mut transform = MapTransform::default();
+    if let Some((x, y)) = env_pair("MERCY_SCREEN_CENTER") {
+        (transform.center_x, transform.center_y) = (x, y);
+    }
+    if let Some((x, y)) = env_pair("MERCY_PX_PER_GAME").filter(|&(x, y)| x > 0.0 && y > 0.0) {
+        (transform.px_per_game_x, transform.px_per_game_y) = (x, y);
+    }
+    if let Some(tilt) = env_parse("MERCY_TILT_Y") {
+        transform.tilt_y = tilt;
+    }
+    transform
+}
+
+fn match_options() -> detector::MatchOptions {
+    detector::MatchOptions {
+        phash_max_distance: env_parse("MERCY_PHASH_MAX_DISTANCE"),
+        coarse_factor: env_parse("MERCY_COARSE_FACTOR"),
+        color_space: env_parse("MERCY_COLOR_SPACE").unwrap_or_default(),
+        onnx_model: std::env::var("MERCY_ONNX_MODEL").ok().map(Into::into),
+        channel_weights: env_parse("MERCY_CHANNELS").unwrap_or_default(),
+        aggregation: env_parse("MERCY_CHANNEL_AGGREGATION").unwrap_or_default(),
+        method: env_parse("MERCY_MATCH_METHOD").unwrap_or_default(),
+        ab_log: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_expected() {
+        let step: Step = serde_json::from_str(r#"{"kingdom": 111, "x": 512, "y": 512}"#).unwrap();
+        assert_eq!(step.expected, None);
+
+        let step: Step =
+            serde_json::from_str(r#"{"kingdom": 111, "x": 512, "y": 512, "expected": null}"#)
+                .unwrap();
+        assert_eq!(step.expected, Some(None));
+
+        let step: Step = serde_json::from_str(
+            r#"{"kingdom": 111, "x": 512, "y": 512, "goto": "g.png",
+                "expected": {"x": 506, "y": 638}}"#,
+        )
+        .unwrap();
+        assert_eq!(step.expected, Some(Some(Coords { x: 506, y: 638 })));
+        assert_eq!(step.goto.as_deref(), Some("g.png"));
+    }
+}
@@ -1,194 +1,808 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
 use anyhow::{Context, Result};
-use chromiumoxide::Page;
-use chromiumoxide::browser::{Browser, BrowserConfig};
-use chromiumoxide::cdp::browser_protocol::page::CaptureScreenshotFormat;
-use chromiumoxide::handler::viewport::Viewport;
-use chromiumoxide::page::ScreenshotParams;
-use futures::StreamExt;
+use chromiumoxide::cdp::browser_protocol::network::CookieParam;
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 use tokio::time::{Duration, sleep};
 
+use crate::bidi::Firefox;
+use crate::chromium::Chromium;
 use crate::config::Config;
+use crate::console::ConsoleLog;
+use crate::driver::{BrowserKind, Capture, Driver, Key, KeyAction, MouseAction, Tab};
+use crate::frame::{self, CanvasFrame};
+use crate::metrics::{self, Counters};
+use crate::ui::{self, UiCheck, UiElement, UiPoints, UiTemplate};
 
 #[derive(Debug, Error)]
 pub enum BrowserError {
     #[error("browser launch failed: {0}")]
     LaunchFailed(String),
 
+    #[error("failed to connect to browser: {0}")]
+    ConnectFailed(String),
+
     #[error("element not found: {0}")]
     ElementNotFound(String),
 
     #[error("screenshot failed: {0}")]
     ScreenshotFailed(String),
+
+    #[error("navigation to K:{0} X:{1} Y:{2} failed: the map didn't move")]
+    NavigationFailed(u32, u32, u32),
+
+    #[error("login failed while {step}: {reason}")]
+    LoginFailed { step: LoginStep, reason: String },
 }
 
-pub struct GameBrowser {
-    _browser: Browser,
-    _profile_dir: tempfile::TempDir,
-    page: Page,
-    navigate_delay: Duration,
+/// A step of the form login, named when it fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginStep {
+    OpenSite,
+    CookieBanner,
+    LoginTab,
+    FillForm,
+    Submit,
+    TwoFactor,
+    WaitForCanvas,
 }
 
-impl GameBrowser {
-    pub async fn launch(config: &Config) -> Result<Self> {
-        let chromium_path = config.chromium_path.clone();
-
-        // Use a fresh temp profile each launch so no cookies/state persist between runs
-        let user_data_dir = tempfile::tempdir().context("failed to create temp profile dir")?;
-
-        let mut builder = BrowserConfig::builder()
-            .no_sandbox()
-            .window_size(1920, 1080)
-            .viewport(Viewport {
-                width: 1920,
-                height: 1080,
-                device_scale_factor: Some(1.0),
-                ..Default::default()
-            })
-            .arg("--disable-dev-shm-usage")
-            .arg("--force-device-scale-factor=1")
-            .arg("--user-agent=Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36")
-            // Use the tempdir via the builder method (not .arg()) so chromiumoxide
-            // doesn't silently override it with /tmp/chromiumoxide-runner.
-            .user_data_dir(user_data_dir.path());
+impl std::fmt::Display for LoginStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LoginStep::OpenSite => "opening the site",
+            LoginStep::CookieBanner => "accepting the cookie banner",
+            LoginStep::LoginTab => "switching to the login form",
+            LoginStep::FillForm => "filling in credentials",
+            LoginStep::Submit => "submitting the login form",
+            LoginStep::TwoFactor => "answering the 2FA prompt",
+            LoginStep::WaitForCanvas => "waiting for the game canvas",
+        })
+    }
+}
+
+/// What the site did with a submitted login form.
+#[derive(Debug, PartialEq, Eq)]
+enum SubmitOutcome {
+    LoggedIn,
+    TwoFactor,
+    /// The form stayed up with this error message (e.g. a wrong password),
+    /// which retrying won't fix.
+    Rejected(String),
+}
+
+fn login_failed(step: LoginStep, reason: String) -> anyhow::Error {
+    BrowserError::LoginFailed { step, reason }.into()
+}
 
-        if config.headless {
-            // Use new headless mode which supports WebGL (unlike old --headless).
-            // .with_head() prevents chromiumoxide from adding the old --headless flag,
-            // then we add --headless=new ourselves.
-            builder = builder.with_head().arg("--headless=new");
-        } else {
-            // Non-headless: use xvfb-run on servers for a virtual display.
-            builder = builder.with_head();
+/// Consecutive failed screenshots after which the browser is considered dead
+/// even though the connection is still up (e.g. a hung renderer).
+const MAX_SCREENSHOT_FAILURES: u32 = 3;
+
+/// The one-time-code input of the login form's 2FA step.
+const TWO_FACTOR_INPUT: &str = "input[autocomplete=\"one-time-code\"], input[name*=\"otp\" i], input[name*=\"2fa\" i], input[name*=\"totp\" i], #login input[name*=\"code\" i]";
+
+// Selectors for each login step, tried in order until one matches a visible
+// element: what the site uses today first, then looser fallbacks that
+// survive a redesign.
+const COOKIE_BANNER: &[&str] = &["#didomi-notice", "#didomi-popup"];
+const COOKIE_ACCEPT: &[&str] = &[
+    "#didomi-notice-agree-button",
+    "#didomi-popup .didomi-button-highlight",
+    "button[id*=\"agree\" i]",
+    "button[id*=\"accept\" i]",
+];
+const LOGIN_TAB: &[&str] = &[
+    "#registration .popup-manager-trigger[data-target=\"login\"]",
+    ".popup-manager-trigger[data-target=\"login\"]",
+    "[data-target=\"login\"]",
+    "a[href*=\"login\" i]",
+];
+const LOGIN_FORM: &[&str] = &["#login form", "form:has(input[type=\"password\"])"];
+const EMAIL_INPUT: &[&str] = &[
+    "input[name=\"email\"]",
+    "input[type=\"email\"]",
+    "input[autocomplete=\"username\"]",
+];
+const PASSWORD_INPUT: &[&str] = &["input[name=\"password\"]", "input[type=\"password\"]"];
+const SUBMIT_BUTTON: &[&str] = &[
+    "button[data-handler=\"login_form_handler\"]",
+    "button[type=\"submit\"]",
+    "input[type=\"submit\"]",
+    "button",
+];
+const LOGIN_ERROR: &[&str] = &["[class*=\"error\" i]", "[role=\"alert\"]"];
+const GAME_CANVAS: &[&str] = &["#unityCanvas", "canvas", "iframe[src*=\"game\" i]"];
+
+/// Helpers for login-page scripts: `visible(el)`, and `first(selectors,
+/// root)` for the first visible match of any selector. Selectors the browser
+/// can't parse are skipped.
+const LOGIN_JS_PRELUDE: &str = r#"
+    const visible = el => el !== null && el.getClientRects().length > 0;
+    const first = (sels, root = document) => {
+        for (const sel of sels) {
+            let found;
+            try { found = root.querySelectorAll(sel); } catch (e) { continue; }
+            for (const el of found) { if (visible(el)) return el; }
         }
+        return null;
+    };
+"#;
+
+/// Attempts per login step before the login fails.
+const LOGIN_STEP_ATTEMPTS: u32 = 3;
+const LOGIN_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long the site may take to react to a submitted login form.
+const LOGIN_SUBMIT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long the game canvas may take to appear after logging in.
+const CANVAS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Encoding of detection captures (`MERCY_SCREENSHOT_FORMAT`). Lossy formats
+/// are cheaper to encode in Chromium and to decode here; evidence and debug
+/// screenshots stay PNG.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScreenshotFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl std::str::FromStr for ScreenshotFormat {
+    type Err = String;
 
-        if let Some(ref path) = chromium_path {
-            builder = builder.chrome_executable(path);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(ScreenshotFormat::Png),
+            "jpeg" | "jpg" => Ok(ScreenshotFormat::Jpeg),
+            "webp" => Ok(ScreenshotFormat::Webp),
+            other => Err(format!("unknown screenshot format: {other}")),
         }
+    }
+}
 
-        let browser_config = builder
-            .build()
-            .map_err(|e| BrowserError::LaunchFailed(e.to_string()))?;
+/// How the scanner moves the map between steps (`MERCY_NAVIGATION`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NavigationMode {
+    /// Type each position into the coordinate search dialog.
+    #[default]
+    Search,
+    /// Drag the map from the last known position. The search dialog is only
+    /// used to find a starting point in each kingdom.
+    Drag,
+}
 
-        let (browser, mut handler) = Browser::launch(browser_config)
-            .await
-            .map_err(|e| BrowserError::LaunchFailed(e.to_string()))?;
+impl std::str::FromStr for NavigationMode {
+    type Err = String;
 
-        // Spawn the browser event handler
-        tokio::spawn(async move {
-            while let Some(event) = handler.next().await {
-                let _ = event;
-            }
-        });
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "search" => Ok(NavigationMode::Search),
+            "drag" => Ok(NavigationMode::Drag),
+            other => Err(format!("unknown navigation mode: {other}")),
+        }
+    }
+}
 
-        let page = browser
-            .new_page("about:blank")
-            .await
-            .context("failed to create new page")?;
+/// Where the pointer is assumed to start: the middle of the window (page
+/// pixels).
+const MOUSE_START: (f64, f64) = (960.0, 540.0);
 
-        // Override navigator.webdriver to avoid detection
-        page.execute(
-            chromiumoxide::cdp::browser_protocol::page::AddScriptToEvaluateOnNewDocumentParams::new(
-                "Object.defineProperty(navigator, 'webdriver', { get: () => false });".to_string(),
-            ),
-        )
-        .await
-        .context("failed to inject webdriver override")?;
+/// Max distance (px) a click lands from the requested point.
+const CLICK_JITTER_PX: f64 = 2.0;
+
+/// Largest drag (reference px per axis) of a pan: from the drag start at
+/// (960, 500) the pointer stays on the map, clear of the UI bars.
+const MAX_DRAG_PX: (f64, f64) = (700.0, 340.0);
+
+/// Times the coordinate search dialog is tried before a goto gives up.
+const NAV_ATTEMPTS: u32 = 3;
+
+/// Reference-frame region compared before and after a goto: the map around
+/// the screen center, clear of the UI bars.
+const NAV_PROBE_RECT: (f64, f64, f64, f64) = (360.0, 150.0, 800.0, 500.0);
+
+/// Scale of the probe captures; only large-scale change matters.
+const NAV_PROBE_SCALE: f64 = 0.25;
+
+/// Mean absolute luma difference (0-255) between the probes above which the
+/// map counts as moved.
+const NAV_MIN_CHANGE: f64 = 6.0;
+
+/// The browser process (or the connection to it), shared by all of its tabs.
+struct Session {
+    driver: Box<dyn Driver>,
+    /// DevTools endpoint opened for operators (`MERCY_DEBUG_PORT`)
+    devtools_url: Option<String>,
+    launched_at: DateTime<Utc>,
+    /// Set once the browser is due a scheduled restart (see `resources.rs`)
+    restart_reason: Mutex<Option<String>>,
+}
+
+/// One tab of the game. [`GameBrowser::launch`] opens the first; more can be
+/// opened with [`GameBrowser::open_tab`] so on-demand API requests don't move
+/// the scanner's map.
+pub struct GameBrowser {
+    session: Arc<Session>,
+    page: Box<dyn Tab>,
+    navigate_delay: Duration,
+    ui: UiPoints,
+    /// Where the game canvas is on the page, measured once the game loads
+    frame: Mutex<CanvasFrame>,
+    /// Last position the synthetic pointer was moved to (page pixels)
+    mouse_pos: Mutex<(f64, f64)>,
+    /// (kingdom, x, y) the map is centered on, as far as the last goto or
+    /// pan knows; `None` after the client (re)loads
+    position: Mutex<Option<(u32, u32, u32)>>,
+    /// Held while navigating and capturing what's there, so an API request
+    /// and the scanner sharing the tab don't move the map under each other
+    view: tokio::sync::Mutex<()>,
+    /// Encoding and quality (1-100, lossy formats only) of region captures
+    capture_format: ScreenshotFormat,
+    capture_quality: u8,
+    screenshot_failures: AtomicU32,
+    /// Set by [`GameBrowser::mark_dead`] when the health checks write it off
+    dead: AtomicBool,
+    counters: Arc<Counters>,
+}
+
+impl GameBrowser {
+    /// Launch the configured browser (`MERCY_BROWSER`): Chromium locally,
+    /// or attached to `config.cdp_url` if set, or Firefox.
+    pub async fn launch(
+        config: &Config,
+        console: Arc<ConsoleLog>,
+        counters: Arc<Counters>,
+    ) -> Result<Self> {
+        let driver: Box<dyn Driver> = match config.browser {
+            BrowserKind::Chromium => Box::new(Chromium::launch(config, console).await?),
+            BrowserKind::Firefox => Box::new(Firefox::launch(config, console).await?),
+        };
+
+        let devtools_url = config
+            .debug_port
+            .filter(|_| config.browser == BrowserKind::Chromium && config.cdp_url.is_none())
+            .map(|port| format!("http://{}:{port}", config.debug_address));
+        if let Some(ref url) = devtools_url {
+            tracing::info!("Chrome DevTools available at {url} (chrome://inspect)");
+        }
+
+        let session = Arc::new(Session {
+            driver,
+            devtools_url,
+            launched_at: Utc::now(),
+            restart_reason: Mutex::new(None),
+        });
+        let page = session.driver.new_tab(false).await?;
 
         Ok(GameBrowser {
-            _browser: browser,
-            _profile_dir: user_data_dir,
+            session,
             page,
             navigate_delay: Duration::from_millis(config.navigate_delay_ms),
+            ui: config.ui_points,
+            frame: Mutex::new(CanvasFrame::default()),
+            mouse_pos: Mutex::new(MOUSE_START),
+            position: Mutex::new(None),
+            view: tokio::sync::Mutex::new(()),
+            capture_format: config.screenshot_format,
+            capture_quality: config.screenshot_quality,
+            screenshot_failures: AtomicU32::new(0),
+            dead: AtomicBool::new(false),
+            counters,
         })
     }
 
-    pub async fn login(&self, email: &str, password: &str) -> Result<()> {
+    /// Open another tab of the game in this (logged-in) browser, in its own
+    /// window, and bring it to the same zoomed-out map view. Shares cookies
+    /// with this tab, so it doesn't log in again.
+    pub async fn open_tab(&self) -> Result<GameBrowser> {
+        let url = self
+            .page
+            .url()
+            .await
+            .context("failed to read game URL")?
+            .context("game tab has no URL")?;
+        tracing::info!("opening extra game tab at {url}");
+        let page = self.session.driver.new_tab(true).await?;
+        page.goto(&url)
+            .await
+            .context("failed to load game in new tab")?;
+
+        let tab = GameBrowser {
+            session: self.session.clone(),
+            page,
+            navigate_delay: self.navigate_delay,
+            ui: self.ui,
+            frame: Mutex::new(self.frame()),
+            mouse_pos: Mutex::new(MOUSE_START),
+            position: Mutex::new(None),
+            view: tokio::sync::Mutex::new(()),
+            capture_format: self.capture_format,
+            capture_quality: self.capture_quality,
+            screenshot_failures: AtomicU32::new(0),
+            dead: AtomicBool::new(false),
+            counters: self.counters.clone(),
+        };
+        tab.enter_map().await;
+        Ok(tab)
+    }
+
+    /// Exclusive use of the map view until the guard is dropped.
+    pub async fn lock_view(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.view.lock().await
+    }
+
+    /// Whether the browser still looks usable: the connection is up,
+    /// screenshots haven't failed [`MAX_SCREENSHOT_FAILURES`] times in a row
+    /// and it hasn't been [marked dead](GameBrowser::mark_dead).
+    pub fn is_alive(&self) -> bool {
+        self.session.driver.is_connected()
+            && self.screenshot_failures.load(Ordering::Relaxed) < MAX_SCREENSHOT_FAILURES
+            && !self.dead.load(Ordering::Relaxed)
+    }
+
+    /// The browser's process id, for a locally launched browser.
+    pub fn pid(&self) -> Option<u32> {
+        self.session.driver.pid()
+    }
+
+    pub fn launched_at(&self) -> DateTime<Utc> {
+        self.session.launched_at
+    }
+
+    /// Close the browser (all its tabs) and wait up to `timeout` for it to
+    /// exit, so its temp profile can be removed once the last tab is
+    /// dropped. An attached browser only loses our tabs.
+    pub async fn close(&self, timeout: Duration) {
+        if let Err(e) = self.session.driver.close().await {
+            // The connection often drops before the reply arrives
+            tracing::debug!("browser close: {e:#}");
+        }
+        if self.session.driver.pid().is_none() {
+            return;
+        }
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.session.driver.is_connected() && tokio::time::Instant::now() < deadline {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Flag the browser for a restart at the scanner's next safe point.
+    /// Returns false if it already was.
+    pub fn request_restart(&self, reason: &str) -> bool {
+        let mut pending = self
+            .session
+            .restart_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let first = pending.is_none();
+        *pending = Some(reason.to_owned());
+        first
+    }
+
+    /// Why the browser is due a restart, once it is.
+    pub fn restart_requested(&self) -> Option<String> {
+        self.session
+            .restart_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// DevTools endpoint of the browser when `MERCY_DEBUG_PORT` opened one.
+    pub fn devtools_url(&self) -> Option<&str> {
+        self.session.devtools_url.as_deref()
+    }
+
+    /// Write this tab off, so the scanner relaunches the browser at its next
+    /// step.
+    pub fn mark_dead(&self) {
+        self.dead.store(true, Ordering::Relaxed);
+    }
+
+    /// Cheap liveness probe: evaluate `1+1` in the page.
+    pub async fn ping(&self) -> Result<()> {
+        let value: i64 = self
+            .page
+            .evaluate("1+1")
+            .await
+            .context("evaluate failed")?
+            .into_value()
+            .context("unexpected ping result")?;
+        anyhow::ensure!(value == 2, "unexpected ping result {value}");
+        Ok(())
+    }
+
+    /// Log in through the site's login form. If the account has 2FA, the
+    /// code for the prompt that follows comes from `two_factor`.
+    ///
+    /// Each step is checked and retried on its own, trying every selector in
+    /// its list, and a step that keeps failing is reported as
+    /// [`BrowserError::LoginFailed`].
+    #[tracing::instrument(skip_all, fields(phase = "preparing"))]
+    pub async fn login<F, Fut>(&self, email: &str, password: &str, two_factor: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
         tracing::info!("logging in as {email}");
-        // Navigate directly to English version of the site
-        tracing::info!("navigating to totalbattle.com/en/");
-        self.page
-            .goto("https://totalbattle.com/en/")
+        self.open_site().await?;
+        self.accept_cookie_banner().await;
+
+        tracing::info!("switching to login form");
+        self.login_step(LoginStep::LoginTab, || self.open_login_form())
+            .await?;
+
+        tracing::info!("filling credentials");
+        self.login_step(LoginStep::FillForm, || {
+            self.fill_credentials(email, password)
+        })
+        .await?;
+
+        tracing::info!("clicking login button");
+        let outcome = self
+            .login_step(LoginStep::Submit, || self.submit_login())
+            .await?;
+        if let SubmitOutcome::Rejected(message) = outcome {
+            return Err(login_failed(
+                LoginStep::Submit,
+                format!("the site said \"{message}\""),
+            ));
+        }
+
+        if outcome == SubmitOutcome::TwoFactor {
+            tracing::info!("2FA prompt shown");
+            let code = two_factor()
+                .await
+                .map_err(|e| login_failed(LoginStep::TwoFactor, format!("no code: {e:#}")))?;
+            self.submit_two_factor(&code)
+                .await
+                .map_err(|e| login_failed(LoginStep::TwoFactor, format!("{e:#}")))?;
+            sleep(Duration::from_secs(3)).await;
+            if self.two_factor_prompt().await? {
+                return Err(login_failed(LoginStep::TwoFactor, "code rejected".into()));
+            }
+        }
+
+        self.wait_for_canvas().await?;
+        self.enter_map().await;
+        tracing::info!("login and setup complete");
+        Ok(())
+    }
+
+    /// Run one login step, retrying it up to [`LOGIN_STEP_ATTEMPTS`] times.
+    async fn login_step<T, Fut>(
+        &self,
+        step: LoginStep,
+        mut attempt: impl FnMut() -> Fut,
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut reason = String::new();
+        for n in 1..=LOGIN_STEP_ATTEMPTS {
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    reason = format!("{e:#}");
+                    tracing::warn!(
+                        "login: {step} failed (attempt {n}/{LOGIN_STEP_ATTEMPTS}): {reason}"
+                    );
+                    if n < LOGIN_STEP_ATTEMPTS {
+                        sleep(LOGIN_RETRY_DELAY).await;
+                    }
+                }
+            }
+        }
+        Err(login_failed(step, reason))
+    }
+
+    /// Evaluate a login-page script: `body` runs after [`LOGIN_JS_PRELUDE`]
+    /// inside a function, and its return value is deserialized.
+    async fn login_eval<T: serde::de::DeserializeOwned>(&self, body: &str) -> Result<T> {
+        let value = self
+            .page
+            .evaluate(&format!("(function() {{ {LOGIN_JS_PRELUDE} {body} }})()"))
             .await
-            .context("failed to navigate to totalbattle.com")?;
+            .context("script failed")?
+            .into_value()
+            .context("unexpected script result")?;
+        Ok(value)
+    }
 
-        sleep(Duration::from_secs(5)).await;
+    /// Open the English site and wait for the document.
+    async fn open_site(&self) -> Result<()> {
+        tracing::info!("navigating to totalbattle.com/en/");
+        self.login_step(LoginStep::OpenSite, || async move {
+            self.page
+                .goto("https://totalbattle.com/en/")
+                .await
+                .context("navigation failed")?;
+            sleep(Duration::from_secs(5)).await;
+            let ready: bool = self
+                .login_eval(
+                    "return document.readyState !== 'loading' && location.hostname.endsWith('totalbattle.com');",
+                )
+                .await?;
+            if !ready {
+                anyhow::bail!("page didn't load");
+            }
+            Ok(())
+        })
+        .await
+    }
 
-        // Accept cookie consent banner (Didomi)
+    /// Accept the cookie consent banner (Didomi) if it's showing. The form
+    /// is filled by script, so a banner that won't go away is only a warning.
+    async fn accept_cookie_banner(&self) {
         tracing::info!("accepting cookies");
-        self.click_by_selector("#didomi-notice-agree-button")
-            .await
-            .ok();
+        let accepted = self
+            .login_step(LoginStep::CookieBanner, || async move {
+                let banner = format!(
+                    "return first({}) !== null || first({}) !== null;",
+                    js_list(COOKIE_BANNER),
+                    js_list(COOKIE_ACCEPT)
+                );
+                if !self.login_eval::<bool>(&banner).await? {
+                    return Ok(());
+                }
+                let clicked: bool = self
+                    .login_eval(&format!(
+                        "const btn = first({}); if (btn) btn.click(); return btn !== null;",
+                        js_list(COOKIE_ACCEPT)
+                    ))
+                    .await?;
+                if !clicked {
+                    anyhow::bail!("no accept button found");
+                }
+                sleep(Duration::from_secs(1)).await;
+                if self.login_eval::<bool>(&banner).await? {
+                    anyhow::bail!("banner still showing after accepting");
+                }
+                Ok(())
+            })
+            .await;
+        if let Err(e) = accepted {
+            tracing::warn!("{e:#}; continuing");
+        }
+    }
+
+    /// Switch the site's popup from registration to its login form.
+    async fn open_login_form(&self) -> Result<()> {
+        let form_open = format!(
+            "const form = first({}); return form !== null && first({}, form) !== null;",
+            js_list(LOGIN_FORM),
+            js_list(PASSWORD_INPUT)
+        );
+        if self.login_eval::<bool>(&form_open).await? {
+            return Ok(());
+        }
+        let clicked: bool = self
+            .login_eval(&format!(
+                "const trigger = first({}); if (trigger) trigger.click(); return trigger !== null;",
+                js_list(LOGIN_TAB)
+            ))
+            .await?;
+        if !clicked {
+            anyhow::bail!("no login link found");
+        }
+        sleep(Duration::from_secs(2)).await;
+        if !self.login_eval::<bool>(&form_open).await? {
+            anyhow::bail!("login form didn't open");
+        }
+        Ok(())
+    }
+
+    /// Fill the login form and read the values back.
+    async fn fill_credentials(&self, email: &str, password: &str) -> Result<()> {
+        let problem: Option<String> = self
+            .login_eval(&format!(
+                r#"
+                const form = first({form});
+                if (!form) return 'no login form';
+                const fill = (sels, value, what) => {{
+                    const input = first(sels, form);
+                    if (!input) return 'no ' + what + ' input';
+                    input.focus();
+                    input.value = value;
+                    input.dispatchEvent(new Event('input', {{ bubbles: true }}));
+                    input.dispatchEvent(new Event('change', {{ bubbles: true }}));
+                    return input.value === value ? null : what + ' input rejected the value';
+                }};
+                return fill({email_sels}, {email}, 'email') || fill({password_sels}, {password}, 'password');
+                "#,
+                form = js_list(LOGIN_FORM),
+                email_sels = js_list(EMAIL_INPUT),
+                password_sels = js_list(PASSWORD_INPUT),
+                email = serde_json::to_string(email)?,
+                password = serde_json::to_string(password)?,
+            ))
+            .await?;
+        if let Some(problem) = problem {
+            anyhow::bail!(problem);
+        }
         sleep(Duration::from_secs(1)).await;
+        Ok(())
+    }
 
-        // Click "Log In" link inside the visible registration popup.
-        // This is a span with data-target="login" inside #registration.
-        tracing::info!("switching to login form");
-        self.page
-            .evaluate(
+    /// Submit the login form and wait for the site to react.
+    async fn submit_login(&self) -> Result<SubmitOutcome> {
+        let submitted: bool = self
+            .login_eval(&format!(
                 r#"
-                (function() {
-                    const trigger = document.querySelector('#registration .popup-manager-trigger[data-target="login"]');
-                    if (trigger) { trigger.click(); return true; }
-                    return false;
-                })()
+                const form = first({form});
+                if (!form) return false;
+                const btn = first({buttons}, form);
+                if (btn) {{ btn.click(); }} else {{ form.requestSubmit(); }}
+                return true;
                 "#,
-            )
+                form = js_list(LOGIN_FORM),
+                buttons = js_list(SUBMIT_BUTTON),
+            ))
+            .await?;
+        if !submitted {
+            // An earlier attempt went through after all
+            let canvas = format!("return first({}) !== null;", js_list(GAME_CANVAS));
+            if self.login_eval::<bool>(&canvas).await? {
+                return Ok(SubmitOutcome::LoggedIn);
+            }
+            anyhow::bail!("no login form to submit");
+        }
+
+        let state = format!(
+            r#"
+            const twoFactor = first({two_factor});
+            if (twoFactor) return 'two_factor';
+            const form = first({form});
+            if (!form) return 'done';
+            const error = first({errors}, form);
+            return error && error.textContent.trim() ? 'error:' + error.textContent.trim() : 'form';
+            "#,
+            two_factor = js_list(&[TWO_FACTOR_INPUT]),
+            form = js_list(LOGIN_FORM),
+            errors = js_list(LOGIN_ERROR),
+        );
+        let started = std::time::Instant::now();
+        loop {
+            sleep(Duration::from_secs(1)).await;
+            // The page may be navigating away, which fails the script
+            let state = self.login_eval::<String>(&state).await.ok();
+            match state.as_deref() {
+                Some("done") => return Ok(SubmitOutcome::LoggedIn),
+                Some("two_factor") => return Ok(SubmitOutcome::TwoFactor),
+                _ if started.elapsed() < LOGIN_SUBMIT_TIMEOUT => {}
+                None => anyhow::bail!("page didn't settle after submitting"),
+                Some(s) => {
+                    return match s.strip_prefix("error:") {
+                        Some(message) => Ok(SubmitOutcome::Rejected(message.to_owned())),
+                        None => anyhow::bail!("login form still showing after submitting"),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Wait for the game canvas (or the frame hosting it) to appear.
+    async fn wait_for_canvas(&self) -> Result<()> {
+        let started = std::time::Instant::now();
+        let present = format!("return first({}) !== null;", js_list(GAME_CANVAS));
+        while started.elapsed() < CANVAS_TIMEOUT {
+            if self.login_eval::<bool>(&present).await.unwrap_or(false) {
+                return Ok(());
+            }
+            sleep(Duration::from_secs(1)).await;
+        }
+        Err(login_failed(
+            LoginStep::WaitForCanvas,
+            format!("no game canvas after {CANVAS_TIMEOUT:?}"),
+        ))
+    }
+
+    /// Whether a visible one-time-code input (the 2FA step of the login
+    /// form) is on the page.
+    async fn two_factor_prompt(&self) -> Result<bool> {
+        let shown = self
+            .page
+            .evaluate(&format!(
+                r#"
+                (function() {{
+                    const el = document.querySelector('{TWO_FACTOR_INPUT}');
+                    return el !== null && el.getClientRects().length > 0;
+                }})()
+                "#
+            ))
             .await
-            .context("failed to click login tab")?;
-        sleep(Duration::from_secs(2)).await;
+            .context("failed to check for 2FA prompt")?
+            .into_value()
+            .unwrap_or(false);
+        Ok(shown)
+    }
 
-        // Fill email and password in #login form specifically
-        tracing::info!("filling credentials");
-        self.page
-            .evaluate(format!(
+    /// Type `code` into the 2FA input and submit its form.
+    async fn submit_two_factor(&self, code: &str) -> Result<()> {
+        tracing::info!("submitting 2FA code");
+        let submitted: bool = self
+            .page
+            .evaluate(&format!(
                 r#"
                 (function() {{
-                    const loginForm = document.querySelector('#login form');
-                    if (!loginForm) return 'no login form';
-                    const emailInput = loginForm.querySelector('input[name="email"]');
-                    const pwInput = loginForm.querySelector('input[name="password"]');
-                    if (emailInput) {{
-                        emailInput.focus();
-                        emailInput.value = '{email}';
-                        emailInput.dispatchEvent(new Event('input', {{ bubbles: true }}));
-                        emailInput.dispatchEvent(new Event('change', {{ bubbles: true }}));
-                    }}
-                    if (pwInput) {{
-                        pwInput.focus();
-                        pwInput.value = '{password}';
-                        pwInput.dispatchEvent(new Event('input', {{ bubbles: true }}));
-                        pwInput.dispatchEvent(new Event('change', {{ bubbles: true }}));
-                    }}
-                    return 'filled';
+                    const input = document.querySelector('{TWO_FACTOR_INPUT}');
+                    if (!input) return false;
+                    input.focus();
+                    input.value = '{code}';
+                    input.dispatchEvent(new Event('input', {{ bubbles: true }}));
+                    input.dispatchEvent(new Event('change', {{ bubbles: true }}));
+                    const form = input.closest('form');
+                    const btn = form && form.querySelector('button[type="submit"], button:not([type])');
+                    if (btn) {{ btn.click(); }} else if (form) {{ form.requestSubmit(); }}
+                    return true;
                 }})()
                 "#,
-                email = email.replace('\'', "\\'"),
-                password = password.replace('\'', "\\'"),
+                // Only digits reach here (see `POST /login/2fa`), but keep the JS string intact
+                code = code.replace('\'', "\\'"),
             ))
             .await
-            .context("failed to fill credentials")?;
-        sleep(Duration::from_secs(1)).await;
+            .context("failed to submit 2FA code")?
+            .into_value()
+            .unwrap_or(false);
+        if !submitted {
+            return Err(BrowserError::ElementNotFound("2FA code input".into()).into());
+        }
+        Ok(())
+    }
 
-        // Click the login submit button inside #login form
-        tracing::info!("clicking login button");
+    /// Log in by injecting session cookies instead of filling the login form.
+    /// Returns false when the site still shows its login/registration popup,
+    /// i.e. the cookies were rejected or have expired.
+    #[tracing::instrument(skip_all, fields(phase = "preparing"))]
+    pub async fn login_with_cookies(&self, cookies: Vec<CookieParam>) -> Result<bool> {
+        tracing::info!("injecting {} session cookie(s)", cookies.len());
         self.page
+            .set_cookies(cookies)
+            .await
+            .context("failed to set cookies")?;
+
+        self.open_site().await?;
+        self.accept_cookie_banner().await;
+
+        let logged_out: bool = self
+            .page
             .evaluate(
                 r#"
                 (function() {
-                    const btn = document.querySelector('#login form button[data-handler="login_form_handler"]');
-                    if (btn) { btn.click(); return true; }
-                    return false;
+                    return ['#login', '#registration'].some(sel => {
+                        const el = document.querySelector(sel);
+                        return el !== null && el.offsetParent !== null;
+                    });
                 })()
                 "#,
             )
             .await
-            .context("failed to click login button")?;
-        sleep(Duration::from_secs(1)).await;
+            .context("failed to check login state")?
+            .into_value()
+            .unwrap_or(false);
+        if logged_out {
+            tracing::warn!("session cookies rejected, login form is showing");
+            return Ok(false);
+        }
+
+        self.enter_map().await;
+        tracing::info!("cookie login and setup complete");
+        Ok(true)
+    }
 
+    /// Wait for the game client to load, dismiss its popups, open the world
+    /// map and zoom out to the calibrated scale.
+    async fn enter_map(&self) {
         // Wait for game to load
         tracing::info!("waiting for game to load");
         sleep(Duration::from_secs(20)).await;
+        self.measure_frame().await;
+        self.set_position(None);
 
         // Dismiss popups by dispatching Escape key events directly to the
         // Unity canvas element (CDP keyboard events don't reach Unity).
@@ -198,9 +812,14 @@ impl GameBrowser {
             sleep(Duration::from_secs(2)).await;
         }
 
-        // Click the MAP button in the bottom toolbar (second button, x≈680)
+        let ui_templates = ui::load_ui_templates();
+        self.check_ui_elements(&[UiElement::MapButton], &ui_templates)
+            .await;
+
+        // Click the MAP button in the bottom toolbar (second button)
         tracing::info!("clicking MAP button");
-        self.click_at_cdp_full(680.0, 1045.0).await.ok();
+        let map = self.ui.map_button;
+        self.click_at_cdp_full(map.x, map.y).await.ok();
         sleep(Duration::from_secs(5)).await;
 
         // Dismiss any popups on the map
@@ -208,19 +827,114 @@ impl GameBrowser {
             self.send_canvas_escape().await;
             sleep(Duration::from_secs(1)).await;
         }
+        self.check_ui_elements(&[UiElement::ZoomOut, UiElement::Search], &ui_templates)
+            .await;
 
-        // Zoom out by clicking the "-" button at (1818, 1025) via CDP
+        // Zoom out by clicking the "-" button via CDP
         tracing::info!("zooming out");
         for i in 0..8 {
-            self.click_at_cdp_full(1818.0, 1025.0).await.ok();
+            self.zoom_out().await.ok();
             sleep(Duration::from_millis(600)).await;
             if i == 3 || i == 7 {
                 sleep(Duration::from_secs(1)).await;
             }
         }
         sleep(Duration::from_secs(2)).await;
-        tracing::info!("login and setup complete");
-        Ok(())
+    }
+
+    /// Where the game canvas is on the page. All coordinates taken and
+    /// screenshots returned by this type are in the 1920×1080 reference frame
+    /// (see `frame.rs`) and mapped through this.
+    pub fn frame(&self) -> CanvasFrame {
+        *self.frame.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Read the game canvas's size and position (the window's, if there's
+    /// no canvas yet) and warn when it isn't the reference 1920×1080.
+    async fn measure_frame(&self) {
+        let rect = self
+            .page
+            .evaluate(
+                r#"
+                (function() {
+                    const canvas = document.getElementById('unityCanvas');
+                    const r = canvas ? canvas.getBoundingClientRect() : null;
+                    if (r && r.width > 0 && r.height > 0) return [r.left, r.top, r.width, r.height];
+                    return [0, 0, window.innerWidth, window.innerHeight];
+                })()
+                "#,
+            )
+            .await
+            .ok()
+            .and_then(|r| r.into_value::<[f64; 4]>().ok());
+        let Some([left, top, width, height]) = rect.filter(|r| r[2] >= 1.0 && r[3] >= 1.0) else {
+            tracing::warn!("could not measure the game canvas, assuming 1920×1080");
+            return;
+        };
+
+        let measured = CanvasFrame {
+            left,
+            top,
+            width,
+            height,
+        };
+        if !measured.is_reference() {
+            tracing::warn!(
+                "game canvas is {width}×{height} at ({left}, {top}), not {}×{}: scaling clicks and captures from the reference frame",
+                frame::REFERENCE_WIDTH,
+                frame::REFERENCE_HEIGHT
+            );
+            if !measured.aspect_matches() {
+                tracing::warn!(
+                    "game canvas is not 16:9: UI positions and detection scale are approximate, use a 16:9 window"
+                );
+            }
+        }
+        *self.frame.lock().unwrap_or_else(|e| e.into_inner()) = measured;
+    }
+
+    /// Screenshot-check that each of `elements` with a template is under its
+    /// configured click point, logging where it is instead if not. Only
+    /// warns: a failed check never blocks login.
+    async fn check_ui_elements(&self, elements: &[UiElement], templates: &[UiTemplate]) {
+        let templates: Vec<_> = templates
+            .iter()
+            .filter(|t| elements.contains(&t.element))
+            .collect();
+        if templates.is_empty() {
+            return;
+        }
+        let image = match self.take_screenshot().await.and_then(|bytes| {
+            image::load_from_memory(&bytes).context("failed to decode screenshot")
+        }) {
+            Ok(image) => image,
+            Err(e) => {
+                tracing::warn!("UI point check skipped: {e:#}");
+                return;
+            }
+        };
+
+        for template in templates {
+            let element = template.element;
+            let point = self.ui.get(element);
+            match ui::check_ui_point(&image, point, &template.image) {
+                UiCheck::Found { score } => {
+                    tracing::info!("UI {} at ({point}) score={score:.4}", element.name())
+                }
+                UiCheck::Moved { at, score } => tracing::warn!(
+                    "UI {} found at ({at}) instead of ({point}) score={score:.4}; set {}={:.0},{:.0} if the game UI changed",
+                    element.name(),
+                    element.env_var(),
+                    at.x,
+                    at.y
+                ),
+                UiCheck::Missing { best } => tracing::warn!(
+                    "UI {} not found near ({point}) (best={best:.4}); check {}",
+                    element.name(),
+                    element.env_var()
+                ),
+            }
+        }
     }
 
     #[allow(dead_code)]
@@ -264,12 +978,78 @@ impl GameBrowser {
         self.navigate_to_coords(kingdom, 512, 512).await
     }
 
-    /// Navigate to specific coordinates using the minimap search dialog.
-    /// Clicks the magnifying glass, types K/X/Y values, and clicks Go.
+    /// Navigate to specific coordinates using the minimap search dialog, and
+    /// check that the map actually moved: the view around the screen center
+    /// is compared before and after. If typing was swallowed (or the dialog
+    /// never opened), the dialog is dismissed and the sequence retried up to
+    /// [`NAV_ATTEMPTS`] times before failing with
+    /// [`BrowserError::NavigationFailed`].
+    #[tracing::instrument(skip_all, fields(kingdom = kingdom, x = x, y = y))]
     pub async fn navigate_to_coords(&self, kingdom: u32, x: u32, y: u32) -> Result<()> {
+        metrics::bump(&self.counters.navigations);
+        let target = (kingdom, x, y);
+        // Already there: the map won't move, so there's nothing to compare
+        let revisit = self.position() == Some(target);
+        let before = match revisit {
+            true => None,
+            false => self
+                .nav_probe()
+                .await
+                .inspect_err(|e| tracing::warn!("goto check skipped: {e:#}"))
+                .ok(),
+        };
+
+        for attempt in 1..=NAV_ATTEMPTS {
+            if attempt > 1 {
+                // Close the dialog if swallowed typing left it open
+                self.send_canvas_escape().await;
+                sleep(Duration::from_millis(250)).await;
+            }
+            self.enter_coords(kingdom, x, y).await?;
+            let Some(ref before) = before else {
+                tracing::info!("navigated to K:{kingdom} X:{x} Y:{y}");
+                self.set_position(Some(target));
+                return Ok(());
+            };
+
+            let after = self.nav_probe().await?;
+            let change = mean_abs_diff(before, &after);
+            if change >= NAV_MIN_CHANGE {
+                tracing::info!("navigated to K:{kingdom} X:{x} Y:{y}");
+                self.set_position(Some(target));
+                return Ok(());
+            }
+            tracing::warn!(
+                "goto K:{kingdom} X:{x} Y:{y} didn't move the map (change {change:.1}, attempt {attempt}/{NAV_ATTEMPTS})"
+            );
+        }
+        self.set_position(None);
+        Err(BrowserError::NavigationFailed(kingdom, x, y).into())
+    }
+
+    /// Small grayscale capture of [`NAV_PROBE_RECT`].
+    async fn nav_probe(&self) -> Result<image::GrayImage> {
+        let (x, y, w, h) = NAV_PROBE_RECT;
+        let mut clip = self.frame().capture_clip(x, y, w, h);
+        clip.scale *= NAV_PROBE_SCALE;
+        let bytes = self
+            .capture(Capture {
+                format: ScreenshotFormat::Jpeg,
+                quality: Some(60),
+                clip: Some(clip),
+            })
+            .await?;
+        Ok(image::load_from_memory(&bytes)
+            .context("failed to decode goto probe")?
+            .to_luma8())
+    }
+
+    /// Type K/X/Y into the coordinate search dialog and confirm.
+    async fn enter_coords(&self, kingdom: u32, x: u32, y: u32) -> Result<()> {
         // Click the magnifying glass icon (2nd button above the minimap)
         tracing::info!("opening coordinate search dialog");
-        self.click_at_cdp_full(83.0, 865.0).await?;
+        self.click_at_cdp_full(self.ui.search.x, self.ui.search.y)
+            .await?;
         sleep(Duration::from_millis(250)).await;
 
         // K field should be focused by default.
@@ -292,52 +1072,70 @@ impl GameBrowser {
         // Press Enter to confirm — dialog auto-closes and game flies to destination
         self.send_canvas_enter().await;
         sleep(self.navigate_delay).await;
+        Ok(())
+    }
+
+    /// (kingdom, x, y) the map is centered on, if known.
+    pub fn position(&self) -> Option<(u32, u32, u32)> {
+        *self.position.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_position(&self, position: Option<(u32, u32, u32)>) {
+        *self.position.lock().unwrap_or_else(|e| e.into_inner()) = position;
+    }
+
+    /// Take `position` as where the map is, without moving it.
+    pub fn assume_position(&self, kingdom: u32, x: u32, y: u32) {
+        self.set_position(Some((kingdom, x, y)));
+    }
+
+    /// Stop trusting the tracked position, so the next navigation goes
+    /// through the search dialog rather than panning from it.
+    pub fn forget_position(&self) {
+        self.set_position(None);
+    }
 
-        tracing::info!("navigated to K:{kingdom} X:{x} Y:{y}");
+    /// Move the view by (dx, dy) reference pixels to `target`, in as many
+    /// drags of at most [`MAX_DRAG_PX`] as that takes (drag navigation).
+    #[tracing::instrument(skip_all, fields(kingdom = target.0, x = target.1, y = target.2))]
+    pub async fn pan_to(&self, target: (u32, u32, u32), dx: f64, dy: f64) -> Result<()> {
+        metrics::bump(&self.counters.navigations);
+        let (max_x, max_y) = MAX_DRAG_PX;
+        let drags = (dx.abs() / max_x).max(dy.abs() / max_y).ceil().max(1.0);
+        let (step_x, step_y) = (dx / drags, dy / drags);
+        // Unknown until the pan completes
+        self.set_position(None);
+        for _ in 0..drags as u32 {
+            self.drag_map(step_x.round() as i32, step_y.round() as i32)
+                .await?;
+        }
+        self.set_position(Some(target));
+        sleep(self.navigate_delay).await;
+        let (k, x, y) = target;
+        tracing::info!("panned to K:{k} X:{x} Y:{y}");
         Ok(())
     }
 
-    /// Drag the map by (dx, dy) pixels. Positive dx moves the viewport right
-    /// (drags left), positive dy moves viewport down (drags up).
-    #[allow(dead_code)]
+    /// Drag the map by (dx, dy) reference pixels. Positive dx moves the
+    /// viewport right (drags left), positive dy moves viewport down (drags up).
     pub async fn drag_map(&self, dx: i32, dy: i32) -> Result<()> {
-        use chromiumoxide::cdp::browser_protocol::input::{
-            DispatchMouseEventParams, DispatchMouseEventType, MouseButton,
-        };
-
         // Start from center of the game viewport area (excluding UI bars)
-        let start_x = 960.0;
-        let start_y = 500.0;
+        let frame = self.frame();
+        let (start_x, start_y) = frame.to_page(960.0, 500.0);
         // To move viewport right, we drag the map to the left (negative mouse movement)
-        let end_x = start_x - dx as f64;
-        let end_y = start_y - dy as f64;
+        let end_x = start_x - dx as f64 * frame.scale_x();
+        let end_y = start_y - dy as f64 * frame.scale_y();
 
         // Move to start
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MouseMoved)
-                    .x(start_x)
-                    .y(start_y)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Move, start_x, start_y)
             .await
             .context("drag: move to start")?;
         sleep(Duration::from_millis(50)).await;
 
         // Press
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MousePressed)
-                    .x(start_x)
-                    .y(start_y)
-                    .button(MouseButton::Left)
-                    .click_count(1)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Press, start_x, start_y)
             .await
             .context("drag: press")?;
         sleep(Duration::from_millis(50)).await;
@@ -349,16 +1147,7 @@ impl GameBrowser {
             let mx = start_x + (end_x - start_x) * frac;
             let my = start_y + (end_y - start_y) * frac;
             self.page
-                .execute(
-                    DispatchMouseEventParams::builder()
-                        .r#type(DispatchMouseEventType::MouseMoved)
-                        .x(mx)
-                        .y(my)
-                        .button(MouseButton::Left)
-                        .buttons(1_i64)
-                        .build()
-                        .unwrap(),
-                )
+                .mouse(MouseAction::Drag, mx, my)
                 .await
                 .context("drag: move step")?;
             sleep(Duration::from_millis(30)).await;
@@ -366,41 +1155,60 @@ impl GameBrowser {
 
         // Release
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MouseReleased)
-                    .x(end_x)
-                    .y(end_y)
-                    .button(MouseButton::Left)
-                    .click_count(1)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Release, end_x, end_y)
             .await
             .context("drag: release")?;
 
+        *self.mouse_pos.lock().unwrap_or_else(|e| e.into_inner()) = (end_x, end_y);
+
         sleep(Duration::from_secs(1)).await;
         Ok(())
     }
 
+    /// PNG of the game canvas in reference pixels (the whole page when the
+    /// canvas is the reference one).
     pub async fn take_screenshot(&self) -> Result<Vec<u8>> {
-        let screenshot = self
-            .page
-            .screenshot(
-                ScreenshotParams::builder()
-                    .format(CaptureScreenshotFormat::Png)
-                    .build(),
-            )
-            .await
-            .map_err(|e| BrowserError::ScreenshotFailed(e.to_string()))?;
+        let frame = self.frame();
+        let mut capture = Capture::png();
+        if !frame.is_reference() {
+            capture.clip =
+                Some(frame.capture_clip(0.0, 0.0, frame::REFERENCE_WIDTH, frame::REFERENCE_HEIGHT));
+        }
+        self.capture(capture).await
+    }
+
+    /// Screenshot of just the `w`×`h` region at (`x`, `y`), in reference
+    /// pixels, for detection: encoded in the configured capture format (PNG
+    /// by default). Cheaper to transfer and decode than a full frame when
+    /// only the game viewport is needed.
+    pub async fn take_screenshot_region(&self, x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u8>> {
+        let clip = self
+            .frame()
+            .capture_clip(x as f64, y as f64, w as f64, h as f64);
+        self.capture(Capture {
+            format: self.capture_format,
+            quality: (self.capture_format != ScreenshotFormat::Png).then_some(self.capture_quality),
+            clip: Some(clip),
+        })
+        .await
+    }
+
+    async fn capture(&self, capture: Capture) -> Result<Vec<u8>> {
+        metrics::bump(&self.counters.screenshots);
+        let screenshot = self.page.screenshot(capture).await.map_err(|e| {
+            self.screenshot_failures.fetch_add(1, Ordering::Relaxed);
+            BrowserError::ScreenshotFailed(e.to_string())
+        })?;
+        self.screenshot_failures.store(0, Ordering::Relaxed);
 
         Ok(screenshot)
     }
 
     #[allow(dead_code)]
     pub async fn click_at(&self, x: f64, y: f64) -> Result<()> {
+        let (x, y) = self.frame().to_page(x, y);
         self.page
-            .evaluate(format!(
+            .evaluate(&format!(
                 r#"
                 (function() {{
                     const el = document.elementFromPoint({x}, {y});
@@ -422,113 +1230,90 @@ impl GameBrowser {
 
     #[allow(dead_code)]
     pub async fn click_at_cdp(&self, x: f64, y: f64) -> Result<()> {
-        use chromiumoxide::cdp::browser_protocol::input::{
-            DispatchMouseEventParams, DispatchMouseEventType, MouseButton,
-        };
-
+        let (x, y) = self.frame().to_page(x, y);
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MousePressed)
-                    .x(x)
-                    .y(y)
-                    .button(MouseButton::Left)
-                    .click_count(1)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Press, x, y)
             .await
             .context("mouse press failed")?;
 
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MouseReleased)
-                    .x(x)
-                    .y(y)
-                    .button(MouseButton::Left)
-                    .click_count(1)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Release, x, y)
             .await
             .context("mouse release failed")?;
 
         Ok(())
     }
 
+    /// Click like a player would: move along a curved path from the last
+    /// pointer position with varying speed, land a pixel or two off the exact
+    /// target, and hold the button for a human-length press.
     pub async fn click_at_cdp_full(&self, x: f64, y: f64) -> Result<()> {
-        use chromiumoxide::cdp::browser_protocol::input::{
-            DispatchMouseEventParams, DispatchMouseEventType, MouseButton,
-        };
-
-        // Move mouse to position first (Unity needs this)
-        self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MouseMoved)
-                    .x(x)
-                    .y(y)
-                    .build()
-                    .unwrap(),
-            )
-            .await
-            .context("mouse move failed")?;
+        let (x, y) = self.frame().to_page(x, y);
+        let mut rng = fastrand::Rng::new();
+        let from = *self.mouse_pos.lock().unwrap_or_else(|e| e.into_inner());
+        let path = human_mouse_path(from, jitter_target((x, y), &mut rng), &mut rng);
+        let (x, y) = path.last().map_or((x, y), |s| (s.x, s.y));
+
+        // Unity needs the pointer to arrive before the press
+        for step in &path {
+            sleep(step.delay).await;
+            self.page
+                .mouse(MouseAction::Move, step.x, step.y)
+                .await
+                .context("mouse move failed")?;
+        }
+        *self.mouse_pos.lock().unwrap_or_else(|e| e.into_inner()) = (x, y);
 
-        sleep(Duration::from_millis(50)).await;
+        sleep(Duration::from_millis(rng.u64(40..120))).await;
 
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MousePressed)
-                    .x(x)
-                    .y(y)
-                    .button(MouseButton::Left)
-                    .click_count(1)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Press, x, y)
             .await
             .context("mouse press failed")?;
 
-        sleep(Duration::from_millis(50)).await;
+        sleep(Duration::from_millis(rng.u64(60..140))).await;
 
         self.page
-            .execute(
-                DispatchMouseEventParams::builder()
-                    .r#type(DispatchMouseEventType::MouseReleased)
-                    .x(x)
-                    .y(y)
-                    .button(MouseButton::Left)
-                    .click_count(1)
-                    .build()
-                    .unwrap(),
-            )
+            .mouse(MouseAction::Release, x, y)
             .await
             .context("mouse release failed")?;
 
         Ok(())
     }
 
+    /// Scroll the mouse wheel at (`x`, `y`) in reference pixels by
+    /// (`dx`, `dy`) page pixels.
+    pub async fn scroll_at(&self, x: f64, y: f64, dx: f64, dy: f64) -> Result<()> {
+        let (x, y) = self.frame().to_page(x, y);
+        self.page
+            .wheel(x, y, dx, dy)
+            .await
+            .context("mouse wheel failed")
+    }
+
     pub async fn read_popup_text(&self) -> Result<Option<String>> {
+        metrics::bump(&self.counters.popup_reads);
         let result = self
             .page
             .evaluate(
                 r#"
                 (function() {
-                    // Look for popup/modal/tooltip text containing coordinates
+                    // Look for popup/modal/tooltip text containing coordinates,
+                    // in whatever labels the client's language uses (K:, К:, R:, 王国:)
+                    // (never digits, so a timer like 1:23:45:6 doesn't pass for coordinates)
+                    const coords = /[^\s\d:：]{1,3}\s*[:：]\s*\d+\s*[^\s\d:：]{1,2}\s*[:：]\s*\d+\s*[^\s\d:：]{1,2}\s*[:：]\s*\d+/;
                     const popups = document.querySelectorAll(
                         '.popup, .modal, .tooltip, .tile-info, [class*="popup"], [class*="modal"], [class*="info"]'
                     );
                     for (const popup of popups) {
                         const text = popup.textContent || '';
-                        if (text.includes('K:') && text.includes('X:') && text.includes('Y:')) {
+                        if (coords.test(text)) {
                             return text;
                         }
                     }
                     // Fallback: search the body for any coordinate pattern
                     const body = document.body.textContent || '';
-                    const match = body.match(/[\s\S]*?\(K:\d+\s*X:\d+\s*Y:\d+\)[\s\S]*?/);
+                    const match = body.match(/[\s\S]*?\([^\s\d:：]{1,2}\s*[:：]\s*\d+\s*[^\s\d:：]{1,2}\s*[:：]\s*\d+\s*[^\s\d:：]{1,2}\s*[:：]\s*\d+\)[\s\S]*?/);
                     if (match) return match[0];
                     return null;
                 })()
@@ -541,33 +1326,102 @@ impl GameBrowser {
         Ok(text)
     }
 
+    /// Look for a captcha or anti-bot verification page in the DOM.
+    /// Returns a short description of what matched.
+    pub async fn detect_challenge(&self) -> Result<Option<String>> {
+        let result = self
+            .page
+            .evaluate(
+                r#"
+                (function() {
+                    const selectors = [
+                        'iframe[src*="recaptcha"]',
+                        'iframe[src*="hcaptcha"]',
+                        'iframe[src*="challenges.cloudflare.com"]',
+                        'iframe[src*="arkoselabs"]',
+                        '.g-recaptcha',
+                        '.h-captcha',
+                        '.cf-turnstile',
+                        '#challenge-form',
+                        '#cf-challenge-running',
+                        '[id*="captcha" i]',
+                        '[class*="captcha" i]',
+                    ];
+                    for (const sel of selectors) {
+                        const el = document.querySelector(sel);
+                        // Ignore hidden leftovers (e.g. an invisible reCAPTCHA badge container)
+                        if (el && el.getClientRects().length > 0) return sel;
+                    }
+                    if (/^just a moment/i.test(document.title)) return 'title: ' + document.title;
+                    return null;
+                })()
+                "#,
+            )
+            .await
+            .context("failed to check for challenge")?;
+
+        Ok(result.into_value::<Option<String>>().unwrap_or(None))
+    }
+
+    /// Check the page for the game's "connection lost" dialog. Returns the
+    /// dialog text when one is showing.
+    pub async fn detect_disconnect(&self) -> Result<Option<String>> {
+        let result = self
+            .page
+            .evaluate(
+                r#"
+                (function() {
+                    const pattern = /connection (was |has been )?(lost|interrupted|closed)|lost connection|disconnected from( the)? server/i;
+                    for (const el of document.querySelectorAll('div, p, span, h1, h2, h3')) {
+                        // Own text only, so a wrapper doesn't match for its children
+                        const text = Array.from(el.childNodes)
+                            .filter(n => n.nodeType === 3)
+                            .map(n => n.textContent.trim())
+                            .join(' ');
+                        if (pattern.test(text) && el.getClientRects().length > 0) {
+                            return text.slice(0, 80);
+                        }
+                    }
+                    return null;
+                })()
+                "#,
+            )
+            .await
+            .context("failed to check for disconnect dialog")?;
+
+        Ok(result.into_value::<Option<String>>().unwrap_or(None))
+    }
+
+    /// Recover from the disconnect dialog: click its reload button at
+    /// `button` (reference pixels), or reload the page if it wasn't located,
+    /// then wait for the client and set the map up again.
+    pub async fn reload_game(&self, button: Option<(f64, f64)>) -> Result<()> {
+        match button {
+            Some((x, y)) => {
+                tracing::info!("clicking reload button at ({x:.0}, {y:.0})");
+                self.click_at_cdp_full(x, y).await?;
+            }
+            None => {
+                tracing::info!("reloading the game page");
+                self.page
+                    .reload()
+                    .await
+                    .context("failed to reload game page")?;
+            }
+        }
+        self.enter_map().await;
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub async fn press_escape(&self) -> Result<()> {
-        use chromiumoxide::cdp::browser_protocol::input::{
-            DispatchKeyEventParams, DispatchKeyEventType,
-        };
-
         self.page
-            .execute(
-                DispatchKeyEventParams::builder()
-                    .r#type(DispatchKeyEventType::KeyDown)
-                    .key("Escape")
-                    .code("Escape")
-                    .build()
-                    .unwrap(),
-            )
+            .key(KeyAction::Down, Key::Named("Escape"))
             .await
             .context("escape key failed")?;
 
         self.page
-            .execute(
-                DispatchKeyEventParams::builder()
-                    .r#type(DispatchKeyEventType::KeyUp)
-                    .key("Escape")
-                    .code("Escape")
-                    .build()
-                    .unwrap(),
-            )
+            .key(KeyAction::Up, Key::Named("Escape"))
             .await
             .ok();
 
@@ -579,8 +1433,9 @@ impl GameBrowser {
     /// controls that may not respond to CDP Input.dispatchMouseEvent.
     #[allow(dead_code)]
     async fn click_canvas_at(&self, x: f64, y: f64) {
+        let (x, y) = self.frame().to_page(x, y);
         self.page
-            .evaluate(format!(
+            .evaluate(&format!(
                 r#"
                 (function() {{
                     const canvas = document.getElementById('unityCanvas');
@@ -627,12 +1482,24 @@ impl GameBrowser {
             .ok();
     }
 
-    #[allow(dead_code)]
+    /// Zoom the map out one step with the zoom-out button.
+    pub async fn zoom_out(&self) -> Result<()> {
+        self.click_at_cdp_full(self.ui.zoom_out.x, self.ui.zoom_out.y)
+            .await
+    }
+
+    /// Zoom the map in one step with a mouse wheel notch (there's no
+    /// zoom-in click point configured).
+    pub async fn zoom_in(&self) {
+        self.scroll_canvas(-100.0).await;
+    }
+
     async fn scroll_canvas(&self, delta_y: f64) {
+        let (x, y) = self.frame().to_page(960.0, 540.0);
         // Dispatch wheel event directly to the Unity canvas via JS
         // (CDP mouse wheel events don't reach Unity)
         self.page
-            .evaluate(format!(
+            .evaluate(&format!(
                 r#"
                 (function() {{
                     const canvas = document.getElementById('unityCanvas');
@@ -640,8 +1507,8 @@ impl GameBrowser {
                     canvas.focus();
                     canvas.dispatchEvent(new WheelEvent('wheel', {{
                         deltaY: {delta_y},
-                        clientX: 960,
-                        clientY: 540,
+                        clientX: {x},
+                        clientY: {y},
                         bubbles: true
                     }}));
                 }})()
@@ -654,7 +1521,7 @@ impl GameBrowser {
     #[allow(dead_code)]
     async fn send_canvas_key(&self, key: &str, code: &str, key_code: u32) {
         self.page
-            .evaluate(format!(
+            .evaluate(&format!(
                 r#"
                 (function() {{
                     const canvas = document.getElementById('unityCanvas');
@@ -669,35 +1536,13 @@ impl GameBrowser {
             .ok();
     }
 
-    #[allow(dead_code)]
-    async fn press_key(&self, key: &str, code: &str) -> Result<()> {
-        use chromiumoxide::cdp::browser_protocol::input::{
-            DispatchKeyEventParams, DispatchKeyEventType,
-        };
-
+    pub async fn press_key(&self, key: Key) -> Result<()> {
         self.page
-            .execute(
-                DispatchKeyEventParams::builder()
-                    .r#type(DispatchKeyEventType::KeyDown)
-                    .key(key)
-                    .code(code)
-                    .build()
-                    .unwrap(),
-            )
+            .key(KeyAction::Down, key)
             .await
             .context("key down failed")?;
 
-        self.page
-            .execute(
-                DispatchKeyEventParams::builder()
-                    .r#type(DispatchKeyEventType::KeyUp)
-                    .key(key)
-                    .code(code)
-                    .build()
-                    .unwrap(),
-            )
-            .await
-            .ok();
+        self.page.key(KeyAction::Up, key).await.ok();
 
         Ok(())
     }
@@ -742,7 +1587,7 @@ impl GameBrowser {
 
         let result = self
             .page
-            .evaluate(js)
+            .evaluate(&js)
             .await
             .context(format!("click_by_text({text}) failed"))?;
 
@@ -756,60 +1601,25 @@ impl GameBrowser {
     /// Select all text in the currently focused input and type new text.
     /// Uses CDP keyboard events which work with Unity WebGL's hidden input elements.
     async fn select_all_and_type(&self, text: &str) -> Result<()> {
-        use chromiumoxide::cdp::browser_protocol::input::{
-            DispatchKeyEventParams, DispatchKeyEventType,
-        };
-
         // Ctrl+A to select all
         self.page
-            .execute(
-                DispatchKeyEventParams::builder()
-                    .r#type(DispatchKeyEventType::KeyDown)
-                    .key("a")
-                    .code("KeyA")
-                    .modifiers(2) // 2 = Ctrl modifier
-                    .build()
-                    .unwrap(),
-            )
+            .key(KeyAction::Down, Key::Ctrl('a'))
             .await
             .context("Ctrl+A keydown failed")?;
-        self.page
-            .execute(
-                DispatchKeyEventParams::builder()
-                    .r#type(DispatchKeyEventType::KeyUp)
-                    .key("a")
-                    .code("KeyA")
-                    .modifiers(2)
-                    .build()
-                    .unwrap(),
-            )
-            .await
-            .ok();
+        self.page.key(KeyAction::Up, Key::Ctrl('a')).await.ok();
         sleep(Duration::from_millis(50)).await;
 
-        // Type each character
+        self.type_text(text).await
+    }
+
+    /// Type `text` into whatever has focus, one key press per character.
+    pub async fn type_text(&self, text: &str) -> Result<()> {
         for ch in text.chars() {
             self.page
-                .execute(
-                    DispatchKeyEventParams::builder()
-                        .r#type(DispatchKeyEventType::KeyDown)
-                        .key(ch.to_string())
-                        .text(ch.to_string())
-                        .build()
-                        .unwrap(),
-                )
+                .key(KeyAction::Down, Key::Char(ch))
                 .await
                 .context("char keydown failed")?;
-            self.page
-                .execute(
-                    DispatchKeyEventParams::builder()
-                        .r#type(DispatchKeyEventType::KeyUp)
-                        .key(ch.to_string())
-                        .build()
-                        .unwrap(),
-                )
-                .await
-                .ok();
+            self.page.key(KeyAction::Up, Key::Char(ch)).await.ok();
             sleep(Duration::from_millis(30)).await;
         }
 
@@ -849,50 +1659,98 @@ impl GameBrowser {
             .await
             .ok();
     }
+}
 
-    async fn click_by_selector(&self, selector: &str) -> Result<()> {
-        let js = format!(
-            r#"
-            (function() {{
-                const el = document.querySelector('{selector}');
-                if (el) {{
-                    el.click();
-                    return true;
-                }}
-                return false;
-            }})()
-            "#,
-            selector = selector.replace('\'', "\\'"),
-        );
+/// One pointer sample on a synthetic mouse path: where to move, and how
+/// long to wait before moving there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MouseStep {
+    x: f64,
+    y: f64,
+    delay: Duration,
+}
 
-        let result = self
-            .page
-            .evaluate(js)
-            .await
-            .context(format!("click_by_selector({selector}) failed"))?;
+/// `selectors` as a JS array literal.
+fn js_list(selectors: &[&str]) -> String {
+    serde_json::to_string(selectors).unwrap_or_else(|_| "[]".into())
+}
 
-        let clicked = result.into_value::<bool>().unwrap_or(false);
-        if !clicked {
-            return Err(BrowserError::ElementNotFound(format!("selector: {selector}")).into());
-        }
-        Ok(())
+/// Mean absolute per-pixel difference (0-255) between two grayscale images
+/// of the same size; 255 when the sizes differ.
+fn mean_abs_diff(a: &image::GrayImage, b: &image::GrayImage) -> f64 {
+    if a.dimensions() != b.dimensions() || a.is_empty() {
+        return 255.0;
     }
+    let total: u64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&p, &q)| p.abs_diff(q) as u64)
+        .sum();
+    total as f64 / a.as_raw().len() as f64
 }
 
-/// Extract coordinates from popup text like "(K:111 X:506 Y:638)"
-pub fn parse_popup_coords(text: &str) -> Option<(u32, u32, u32)> {
-    // Try pattern: K:NNN X:NNN Y:NNN
-    let k = extract_number_after(text, "K:")?;
-    let x = extract_number_after(text, "X:")?;
-    let y = extract_number_after(text, "Y:")?;
-    Some((k, x, y))
+/// A point within [`CLICK_JITTER_PX`] of `target`.
+fn jitter_target((x, y): (f64, f64), rng: &mut fastrand::Rng) -> (f64, f64) {
+    let angle = rng.f64() * std::f64::consts::TAU;
+    let r = rng.f64().sqrt() * CLICK_JITTER_PX;
+    (x + r * angle.cos(), y + r * angle.sin())
 }
 
-fn extract_number_after(text: &str, prefix: &str) -> Option<u32> {
-    let idx = text.find(prefix)?;
-    let after = &text[idx + prefix.len()..];
-    let num_str: String = after.chars().take_while(|c| c.is_ascii_digit()).collect();
-    num_str.parse().ok()
+/// Pointer samples from `from` to `to` (ending exactly on `to`): a cubic
+/// Bézier bowed to one side, walked with a minimum-jerk profile so the
+/// pointer accelerates then slows into the target, with jittered timing.
+/// Longer moves take longer, roughly per Fitts's law.
+fn human_mouse_path(from: (f64, f64), to: (f64, f64), rng: &mut fastrand::Rng) -> Vec<MouseStep> {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let dist = dx.hypot(dy);
+    let steps = ((dist / 25.0) as usize).clamp(8, 40);
+    let total_ms = 120.0 + 90.0 * (1.0 + dist / 30.0).log2() * (0.8 + 0.4 * rng.f64());
+
+    // Control points a third and two thirds along, pushed off the line
+    let (nx, ny) = if dist > 0.0 {
+        (-dy / dist, dx / dist)
+    } else {
+        (0.0, 0.0)
+    };
+    let side = if rng.bool() { 1.0 } else { -1.0 };
+    let b1 = side * dist * (0.05 + 0.15 * rng.f64());
+    let b2 = b1 * (0.6 + 0.4 * rng.f64());
+    let c1 = (from.0 + dx / 3.0 + nx * b1, from.1 + dy / 3.0 + ny * b1);
+    let c2 = (
+        from.0 + 2.0 * dx / 3.0 + nx * b2,
+        from.1 + 2.0 * dy / 3.0 + ny * b2,
+    );
+
+    (1..=steps)
+        .map(|i| {
+            let t = i as f64 / steps as f64;
+            let s = t * t * t * (10.0 - 15.0 * t + 6.0 * t * t);
+            let u = 1.0 - s;
+            let (x, y) = if i == steps {
+                to
+            } else {
+                (
+                    u * u * u * from.0
+                        + 3.0 * u * u * s * c1.0
+                        + 3.0 * u * s * s * c2.0
+                        + s * s * s * to.0,
+                    u * u * u * from.1
+                        + 3.0 * u * u * s * c1.1
+                        + 3.0 * u * s * s * c2.1
+                        + s * s * s * to.1,
+                )
+            };
+            // Equal time slices with ±30% noise; the easing makes the
+            // distance per slice (the speed) vary
+            let ms = total_ms / steps as f64 * (0.7 + 0.6 * rng.f64());
+            MouseStep {
+                x,
+                y,
+                delay: Duration::from_micros((ms * 1000.0) as u64),
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -900,15 +1758,44 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_parse_popup_coords() {
-        assert_eq!(
-            parse_popup_coords("Mercenary Exchange (K:111 X:506 Y:638)"),
-            Some((111, 506, 638))
-        );
-        assert_eq!(
-            parse_popup_coords("(K:109 X:100 Y:200)"),
-            Some((109, 100, 200))
-        );
-        assert_eq!(parse_popup_coords("no coords here"), None);
+    fn test_mean_abs_diff() {
+        let a = image::GrayImage::from_pixel(8, 8, image::Luma([100]));
+        let b =
+            image::GrayImage::from_fn(8, 8, |x, _| image::Luma([if x < 4 { 100 } else { 120 }]));
+        assert_eq!(mean_abs_diff(&a, &a), 0.0);
+        assert_eq!(mean_abs_diff(&a, &b), 10.0);
+        assert_eq!(mean_abs_diff(&a, &image::GrayImage::new(4, 4)), 255.0);
+    }
+
+    #[test]
+    fn test_human_mouse_path() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        let (from, to) = ((100.0, 100.0), (900.0, 500.0));
+        let path = human_mouse_path(from, to, &mut rng);
+
+        assert!((8..=40).contains(&path.len()));
+        let last = path.last().unwrap();
+        assert_eq!((last.x, last.y), to);
+        assert!(path.iter().all(|s| s.delay > Duration::ZERO));
+
+        // Bowed off the straight line, but not wildly
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let off_line =
+            |s: &MouseStep| ((s.x - from.0) * dy - (s.y - from.1) * dx).abs() / dx.hypot(dy);
+        let max_off = path.iter().map(off_line).fold(0.0, f64::max);
+        assert!(max_off > 5.0 && max_off < 200.0, "max_off={max_off}");
+
+        // Slow start and finish: first and last moves are shorter than the middle
+        let gaps: Vec<f64> = std::iter::once(from)
+            .chain(path.iter().map(|s| (s.x, s.y)))
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1))
+            .collect();
+        let mid = gaps[gaps.len() / 2];
+        assert!(gaps[0] < mid && gaps[gaps.len() - 1] < mid);
+
+        let jittered = jitter_target(to, &mut rng);
+        assert!((jittered.0 - to.0).hypot(jittered.1 - to.1) <= CLICK_JITTER_PX);
     }
 }
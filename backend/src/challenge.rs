@@ -0,0 +1,151 @@
+//! Captcha / anti-bot challenge detection by screenshot.
+//!
+//! Complements the DOM check in [`crate::browser::GameBrowser::detect_challenge`]
+//! for challenges drawn inside the game canvas, which have no DOM to query.
+//! Templates are `challenge_*.png` crops in the assets directory (e.g. the
+//! header of the game's "confirm you are human" dialog); none ship by default.
+//!
+//! The dialog matching here is shared with [`crate::disconnect`].
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+use crate::detector::{asset_search_dirs, zero_mean_ncc};
+
+/// Minimum zero-mean NCC for a challenge template to count as present.
+const CHALLENGE_THRESHOLD: f32 = 0.9;
+
+/// Screenshot and templates are downscaled by this factor before matching:
+/// dialogs are large and distinctive, and this runs on every scan step.
+const CHALLENGE_SCALE: u32 = 4;
+
+/// A downscaled dialog template.
+pub struct DialogTemplate {
+    pub name: String,
+    pub image: GrayImage,
+}
+
+/// A dialog template found in a screenshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DialogMatch {
+    pub name: String,
+    pub score: f32,
+    /// Centre of the match, in screenshot pixels.
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Load the `challenge_*.png` templates from the first asset directory that
+/// has any.
+pub fn load_challenge_templates() -> Result<Vec<DialogTemplate>> {
+    load_dialog_templates("challenge_")
+}
+
+/// Load the `<prefix>*.png` templates from the first asset directory that
+/// has any.
+pub fn load_dialog_templates(prefix: &str) -> Result<Vec<DialogTemplate>> {
+    for dir in asset_search_dirs() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(prefix) && n.ends_with(".png"))
+            })
+            .collect();
+        if paths.is_empty() {
+            continue;
+        }
+        paths.sort();
+
+        let mut templates = Vec::new();
+        for path in paths {
+            match image::open(&path) {
+                Ok(img) => templates.push(DialogTemplate {
+                    name: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    image: downscale(&img),
+                }),
+                Err(e) => {
+                    tracing::warn!("failed to load dialog template {}: {e}", path.display())
+                }
+            }
+        }
+        tracing::info!(
+            "loaded {} {prefix}* template(s) from {}",
+            templates.len(),
+            dir.display()
+        );
+        return Ok(templates);
+    }
+    Ok(Vec::new())
+}
+
+pub(crate) fn downscale(image: &DynamicImage) -> GrayImage {
+    let w = (image.width() / CHALLENGE_SCALE).max(1);
+    let h = (image.height() / CHALLENGE_SCALE).max(1);
+    image.resize_exact(w, h, FilterType::Triangle).to_luma8()
+}
+
+/// The first of `templates` found in the screenshot.
+pub fn find_dialog(screenshot: &DynamicImage, templates: &[DialogTemplate]) -> Option<DialogMatch> {
+    if templates.is_empty() {
+        return None;
+    }
+    let frame = downscale(screenshot);
+    templates.iter().find_map(|t| {
+        if t.image.width() >= frame.width() || t.image.height() >= frame.height() {
+            return None;
+        }
+        // Zero-mean, so flat map areas don't score high against a textured dialog
+        let (mut best, mut at) = (f32::NEG_INFINITY, (0, 0));
+        for (x, y, p) in zero_mean_ncc(&frame, &t.image).enumerate_pixels() {
+            if p.0[0] > best {
+                (best, at) = (p.0[0], (x, y));
+            }
+        }
+        (best >= CHALLENGE_THRESHOLD).then(|| DialogMatch {
+            name: t.name.clone(),
+            score: best,
+            x: ((at.0 * CHALLENGE_SCALE) as f64 + (t.image.width() * CHALLENGE_SCALE) as f64 / 2.0),
+            y: ((at.1 * CHALLENGE_SCALE) as f64
+                + (t.image.height() * CHALLENGE_SCALE) as f64 / 2.0),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_find_challenge_template() {
+        let dialog = GrayImage::from_fn(80, 40, |x, y| Luma([((x * 7 + y * 13) % 200) as u8]));
+        let mut frame = RgbImage::from_pixel(640, 360, image::Rgb([90, 120, 60]));
+        let dialog_rgb = DynamicImage::ImageLuma8(dialog.clone()).to_rgb8();
+        image::imageops::replace(&mut frame, &dialog_rgb, 280, 160);
+        let frame = DynamicImage::ImageRgb8(frame);
+
+        let templates = [DialogTemplate {
+            name: "challenge_dialog.png".into(),
+            image: downscale(&DynamicImage::ImageLuma8(dialog)),
+        }];
+        let found = find_dialog(&frame, &templates).unwrap();
+        assert_eq!(found.name, "challenge_dialog.png");
+        // Centred on (320, 180), to within the downscale factor
+        assert!((found.x - 320.0).abs() <= CHALLENGE_SCALE as f64);
+        assert!((found.y - 180.0).abs() <= CHALLENGE_SCALE as f64);
+
+        let clean =
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(640, 360, image::Rgb([90, 120, 60])));
+        assert!(find_dialog(&clean, &templates).is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! `--check-config`: load the config, check what would otherwise only fail
+//! minutes into a run, print a summary and exit non-zero on problems.
+//!
+//! Covers the scan patterns and kingdom sections, the known-locations files,
+//! the reference images, the browser executable and the files the config
+//! points at. Nothing is launched or written.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, KingdomConfig, SCAN_PATTERNS};
+use crate::detector::MatchBackend;
+use crate::driver::BrowserKind;
+use crate::scanner;
+use crate::target::TargetProfile;
+
+/// Executables chromiumoxide looks for on `PATH` when no path is given.
+const CHROMIUM_NAMES: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "chrome",
+];
+
+/// Problems found in a config. Errors fail the check; warnings don't.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Check `config`, printing a summary of it and of the problems found.
+/// Returns the number of errors.
+pub fn run(config: &Config) -> usize {
+    let report = check(config);
+    println!("kingdoms:  {}", join(&config.kingdoms));
+    println!("pattern:   {}", config.scan_pattern);
+    for (kingdom, settings) in &config.kingdom_settings {
+        println!("  kingdom {kingdom}: {}", describe(settings));
+    }
+    println!(
+        "browser:   {:?}, headless: {}",
+        config.browser, config.headless
+    );
+    println!("target:    {}", config.search_target);
+    println!("listen:    {}", config.listen_addr);
+    let storage = match (&config.database_url, &config.db_path) {
+        (Some(_), _) => "PostgreSQL".to_string(),
+        (None, Some(path)) => format!("SQLite {}", path.display()),
+        (None, None) => "memory".to_string(),
+    };
+    println!("storage:   {storage}");
+    for warning in &report.warnings {
+        println!("warning: {warning}");
+    }
+    for error in &report.errors {
+        println!("error: {error}");
+    }
+    if report.errors.is_empty() {
+        println!("config ok");
+    }
+    report.errors.len()
+}
+
+pub fn check(config: &Config) -> Report {
+    let mut report = Report::default();
+    check_kingdoms(config, &mut report);
+    check_known_locations(config, &mut report);
+
+    match TargetProfile::load(&config.search_target) {
+        Ok((_, refs)) => {
+            if refs.is_empty() {
+                report.errors.push(format!(
+                    "no reference images for {:?}",
+                    config.search_target
+                ));
+            }
+        }
+        Err(e) => report.errors.push(format!("search target: {e:#}")),
+    }
+
+    check_browser(config, &mut report);
+
+    if !(0.0..=1.0).contains(&config.verify_min_score) {
+        report.errors.push(format!(
+            "MERCY_VERIFY_MIN_SCORE: {} is not a score (0-1)",
+            config.verify_min_score
+        ));
+    }
+
+    let files = [
+        ("MERCY_COOKIES_FILE", config.cookies_file.as_deref()),
+        ("MERCY_ONNX_MODEL", config.onnx_model.as_deref()),
+    ];
+    for (name, path) in files {
+        if let Some(path) = path
+            && !path.is_file()
+        {
+            report
+                .errors
+                .push(format!("{name}: {} not found", path.display()));
+        }
+    }
+    if config.phash_max_distance.is_some() && config.coarse_factor.is_some_and(|f| f > 1) {
+        report
+            .warnings
+            .push("MERCY_COARSE_FACTOR is ignored while MERCY_PHASH_MAX_DISTANCE is set".into());
+    }
+    if config.match_backend == MatchBackend::Onnx && config.onnx_model.is_none() {
+        report
+            .errors
+            .push("the onnx detector needs MERCY_ONNX_MODEL".into());
+    }
+    if let Some(ref path) = config.db_path
+        && let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty())
+        && !dir.is_dir()
+    {
+        report.errors.push(format!(
+            "MERCY_DB_PATH: directory {} not found",
+            dir.display()
+        ));
+    }
+    report
+}
+
+fn check_kingdoms(config: &Config, report: &mut Report) {
+    let mut seen = Vec::new();
+    for &kingdom in &config.kingdoms {
+        if seen.contains(&kingdom) {
+            report
+                .warnings
+                .push(format!("kingdom {kingdom} is listed twice"));
+        }
+        seen.push(kingdom);
+    }
+
+    for kingdom in config.kingdom_settings.keys() {
+        if !config.kingdoms.contains(kingdom) {
+            report.warnings.push(format!(
+                "kingdom {kingdom} has settings but isn't in MERCY_KINGDOMS"
+            ));
+        }
+    }
+
+    let mut patterns = vec![("MERCY_SCAN_PATTERN".to_string(), &config.scan_pattern)];
+    for (kingdom, settings) in &config.kingdom_settings {
+        if let Some(ref pattern) = settings.scan_pattern {
+            patterns.push((format!("kingdom {kingdom} SCAN_PATTERN"), pattern));
+        }
+    }
+    for (name, pattern) in patterns {
+        if !SCAN_PATTERNS.contains(&pattern.as_str()) {
+            report.errors.push(format!(
+                "{name}: unknown pattern {pattern:?} (one of {})",
+                SCAN_PATTERNS.join(", ")
+            ));
+        }
+    }
+
+    for &kingdom in &config.kingdoms {
+        let config = config.for_kingdom(kingdom);
+        if let Some(bounds) = config.scan_bounds
+            && bounds.x_max.max(bounds.y_max) > 1023
+        {
+            report.warnings.push(format!(
+                "kingdom {kingdom}: scan bounds {bounds} reach past the map (0-1023)"
+            ));
+        }
+    }
+}
+
+/// Each kingdom scanned with the "known" pattern needs spawn data: from its
+/// known-locations file, which must parse, or else compiled in.
+fn check_known_locations(config: &Config, report: &mut Report) {
+    for &kingdom in &config.kingdoms {
+        let config = config.for_kingdom(kingdom);
+        let spawns = match config.known_locations {
+            Some(ref path) => match scanner::load_known_locations(path, kingdom) {
+                Ok(cells) => cells.len(),
+                Err(e) => {
+                    report.errors.push(format!(
+                        "kingdom {kingdom}: known locations {}: {e}",
+                        path.display()
+                    ));
+                    continue;
+                }
+            },
+            None => crate::known_locations::positions_for_kingdom(kingdom).len(),
+        };
+        if config.scan_pattern == "known" && spawns == 0 {
+            report.warnings.push(format!(
+                "kingdom {kingdom}: no historical spawns, the known pattern scans the grid"
+            ));
+        }
+    }
+}
+
+fn check_browser(config: &Config, report: &mut Report) {
+    match config.browser {
+        BrowserKind::Chromium if config.cdp_url.is_some() => {}
+        BrowserKind::Chromium => match config.chromium_path {
+            Some(ref path) => {
+                if find_executable(path).is_none() {
+                    report
+                        .errors
+                        .push(format!("MERCY_CHROMIUM_PATH: {path} is not an executable"));
+                }
+            }
+            None => {
+                if !CHROMIUM_NAMES.iter().any(|n| find_executable(n).is_some()) {
+                    report.warnings.push(
+                        "no Chromium on PATH; set MERCY_CHROMIUM_PATH if the launch fails".into(),
+                    );
+                }
+            }
+        },
+        BrowserKind::Firefox => {
+            let path = config.firefox_path.as_deref().unwrap_or("firefox");
+            if find_executable(path).is_none() {
+                report
+                    .errors
+                    .push(format!("Firefox: {path} is not an executable"));
+            }
+        }
+    }
+}
+
+/// `name` as given if it's a path, else looked up on `PATH`, if executable.
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let executable = |p: &Path| {
+        p.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if name.contains('/') {
+        return executable(Path::new(name)).then(|| name.into());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|p| executable(p))
+}
+
+fn describe(settings: &KingdomConfig) -> String {
+    let mut parts = Vec::new();
+    if let Some(ref pattern) = settings.scan_pattern {
+        parts.push(format!("pattern {pattern}"));
+    }
+    if let Some(rings) = settings.scan_rings {
+        parts.push(format!("{rings} rings"));
+    }
+    if let Some(mins) = settings.scan_cooldown_mins {
+        parts.push(format!("cooldown {mins} min"));
+    }
+    if let Some(bounds) = settings.scan_bounds {
+        parts.push(format!("bounds {bounds}"));
+    }
+    if let Some(ref path) = settings.known_locations {
+        parts.push(format!("known locations {}", path.display()));
+    }
+    parts.join(", ")
+}
+
+fn join(kingdoms: &[u32]) -> String {
+    kingdoms
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_executable() {
+        assert!(find_executable("sh").is_some());
+        assert!(find_executable("/bin/sh").is_some());
+        assert!(find_executable("/etc/hostname-not-a-binary").is_none());
+        assert!(find_executable("mercy-no-such-binary").is_none());
+    }
+}
@@ -0,0 +1,438 @@
+//! Chromium over the DevTools protocol, via chromiumoxide: launched locally
+//! with a fresh profile, or attached to a running Chrome at `MERCY_CDP_URL`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+use chromiumoxide::Page;
+use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::browser::{BrowserContextId, CloseParams};
+use chromiumoxide::cdp::browser_protocol::emulation::{
+    SetTimezoneOverrideParams, SetUserAgentOverrideParams,
+};
+use chromiumoxide::cdp::browser_protocol::input::{
+    DispatchKeyEventParams, DispatchKeyEventType, DispatchMouseEventParams, DispatchMouseEventType,
+    MouseButton,
+};
+use chromiumoxide::cdp::browser_protocol::network::{CookieParam, SetCookiesParams};
+use chromiumoxide::cdp::browser_protocol::page::{
+    AddScriptToEvaluateOnNewDocumentParams, CaptureScreenshotFormat, Viewport as ClipRect,
+};
+use chromiumoxide::cdp::browser_protocol::target::{
+    CreateBrowserContextParams, CreateTargetParams,
+};
+use chromiumoxide::cdp::js_protocol::runtime::{
+    EventConsoleApiCalled, EventExceptionThrown, RemoteObject,
+};
+use chromiumoxide::handler::viewport::Viewport;
+use chromiumoxide::handler::{Handler, HandlerConfig};
+use chromiumoxide::page::ScreenshotParams;
+use chrono::Utc;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+
+use crate::browser::{BrowserError, ScreenshotFormat};
+use crate::config::Config;
+use crate::console::{ConsoleEntry, ConsoleLog};
+use crate::driver::{Capture, Driver, Evaluation, Key, KeyAction, MouseAction, Tab};
+use crate::stealth::Fingerprint;
+
+impl From<ScreenshotFormat> for CaptureScreenshotFormat {
+    fn from(format: ScreenshotFormat) -> Self {
+        match format {
+            ScreenshotFormat::Png => CaptureScreenshotFormat::Png,
+            ScreenshotFormat::Jpeg => CaptureScreenshotFormat::Jpeg,
+            ScreenshotFormat::Webp => CaptureScreenshotFormat::Webp,
+        }
+    }
+}
+
+fn window_viewport() -> Viewport {
+    Viewport {
+        width: 1920,
+        height: 1080,
+        device_scale_factor: Some(1.0),
+        ..Default::default()
+    }
+}
+
+pub struct Chromium {
+    browser: Browser,
+    /// Temp profile of a locally launched Chromium; `None` when attached over CDP.
+    _profile_dir: Option<tempfile::TempDir>,
+    /// Context our tabs live in when attached over CDP; `None` for the
+    /// default context of a local launch.
+    context: Option<BrowserContextId>,
+    /// Cleared when the CDP event stream ends (Chromium exited or the
+    /// connection dropped).
+    connected: Arc<AtomicBool>,
+    fingerprint: Fingerprint,
+    /// Where each tab's console output goes
+    console: Arc<ConsoleLog>,
+    pid: Option<u32>,
+}
+
+impl Chromium {
+    /// Launch Chromium locally, or attach to `config.cdp_url` if set.
+    pub async fn launch(config: &Config, console: Arc<ConsoleLog>) -> Result<Self> {
+        let (mut browser, mut handler, profile_dir) = match config.cdp_url {
+            Some(ref url) => {
+                let (browser, handler) = connect(url).await?;
+                (browser, handler, None)
+            }
+            None => {
+                let (browser, handler, dir) = launch_local(config).await?;
+                (browser, handler, Some(dir))
+            }
+        };
+
+        // Spawn the browser event handler; the stream ends when the
+        // connection to the browser is lost
+        let connected = Arc::new(AtomicBool::new(true));
+        let handler_connected = connected.clone();
+        tokio::spawn(async move {
+            while let Some(event) = handler.next().await {
+                let _ = event;
+            }
+            tracing::warn!("browser connection closed");
+            handler_connected.store(false, Ordering::Relaxed);
+        });
+
+        let pid = browser.get_mut_child().map(|child| child.inner.id());
+
+        let context = if config.cdp_url.is_some() {
+            // The remote browser may be shared and outlive us: work in a fresh
+            // context (no cookies/state from earlier sessions) that Chrome
+            // disposes, with its pages, when our connection closes.
+            Some(
+                browser
+                    .create_browser_context(CreateBrowserContextParams {
+                        dispose_on_detach: Some(true),
+                        ..Default::default()
+                    })
+                    .await
+                    .context("failed to create browser context")?,
+            )
+        } else {
+            None
+        };
+        Ok(Chromium {
+            browser,
+            _profile_dir: profile_dir,
+            context,
+            connected,
+            fingerprint: Fingerprint::from_config(config),
+            console,
+            pid,
+        })
+    }
+
+    async fn open_tab(&self, new_window: bool) -> Result<Box<dyn Tab>> {
+        let page = self
+            .browser
+            .new_page(CreateTargetParams {
+                browser_context_id: self.context.clone(),
+                new_window: new_window.then_some(true),
+                ..CreateTargetParams::new("about:blank")
+            })
+            .await
+            .context("failed to create new page")?;
+
+        // Also covers a remote Chrome, which doesn't get our launch flags
+        let fp = &self.fingerprint;
+        page.execute(SetUserAgentOverrideParams {
+            accept_language: fp.accept_language(),
+            ..SetUserAgentOverrideParams::new(fp.user_agent.clone())
+        })
+        .await
+        .context("failed to set user agent")?;
+        if let Some(ref timezone) = fp.timezone {
+            page.execute(SetTimezoneOverrideParams::new(timezone.clone()))
+                .await
+                .with_context(|| format!("failed to set timezone {timezone}"))?;
+        }
+
+        page.execute(AddScriptToEvaluateOnNewDocumentParams::new(
+            fp.init_script(),
+        ))
+        .await
+        .context("failed to inject init script")?;
+        self.capture_console(&page).await?;
+        Ok(Box::new(ChromiumTab(page)))
+    }
+
+    /// Copy the tab's console messages and uncaught exceptions into the
+    /// console log, until the tab closes.
+    async fn capture_console(&self, page: &Page) -> Result<()> {
+        let calls = page
+            .event_listener::<EventConsoleApiCalled>()
+            .await
+            .context("failed to listen for console messages")?
+            .map(|call| ConsoleEntry {
+                time: Utc::now(),
+                level: call.r#type.as_ref().to_owned(),
+                text: call
+                    .args
+                    .iter()
+                    .map(remote_object_text)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            });
+        let exceptions = page
+            .event_listener::<EventExceptionThrown>()
+            .await
+            .context("failed to listen for exceptions")?
+            .map(|thrown| {
+                let details = &thrown.exception_details;
+                let mut text = details
+                    .exception
+                    .as_ref()
+                    .and_then(|e| e.description.clone())
+                    .unwrap_or_else(|| details.text.clone());
+                if let Some(ref url) = details.url {
+                    text.push_str(&format!(" ({url}:{})", details.line_number + 1));
+                }
+                ConsoleEntry {
+                    time: Utc::now(),
+                    level: "exception".into(),
+                    text,
+                }
+            });
+
+        let console = self.console.clone();
+        let mut entries = futures::stream::select(calls, exceptions);
+        tokio::spawn(async move {
+            while let Some(entry) = entries.next().await {
+                console.push(entry);
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Driver for Chromium {
+    fn new_tab(&self, new_window: bool) -> BoxFuture<'_, Result<Box<dyn Tab>>> {
+        Box::pin(self.open_tab(new_window))
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    fn close(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // Over CDP our context is disposed when the connection drops
+            if self.context.is_none() {
+                self.browser
+                    .execute(CloseParams::default())
+                    .await
+                    .context("Browser.close failed")?;
+            }
+            Ok(())
+        })
+    }
+}
+
+async fn launch_local(config: &Config) -> Result<(Browser, Handler, tempfile::TempDir)> {
+    let chromium_path = config.chromium_path.clone();
+
+    // Use a fresh temp profile each launch so no cookies/state persist between runs
+    let user_data_dir = tempfile::tempdir().context("failed to create temp profile dir")?;
+
+    let mut builder = BrowserConfig::builder()
+        .no_sandbox()
+        .window_size(1920, 1080)
+        .viewport(window_viewport())
+        .arg("--disable-dev-shm-usage")
+        .arg("--force-device-scale-factor=1")
+        // Keep every game tab rendering at full speed, not just the
+        // focused one (see `GameBrowser::open_tab`)
+        .arg("--disable-background-timer-throttling")
+        .arg("--disable-renderer-backgrounding")
+        .arg("--disable-backgrounding-occluded-windows")
+        .arg(format!("--user-agent={}", config.user_agent))
+        // Use the tempdir via the builder method (not .arg()) so chromiumoxide
+        // doesn't silently override it with /tmp/chromiumoxide-runner.
+        .user_data_dir(user_data_dir.path())
+        .args(&config.chromium_args);
+    if let Some(ref lang) = config.browser_lang {
+        builder = builder.arg(format!("--lang={lang}"));
+    }
+    if let Some(port) = config.debug_port {
+        builder = builder.port(port).arg(format!(
+            "--remote-debugging-address={}",
+            config.debug_address
+        ));
+    }
+
+    if config.headless {
+        // Use new headless mode which supports WebGL (unlike old --headless).
+        // .with_head() prevents chromiumoxide from adding the old --headless flag,
+        // then we add --headless=new ourselves.
+        builder = builder.with_head().arg("--headless=new");
+    } else {
+        // Non-headless: use xvfb-run on servers for a virtual display.
+        builder = builder.with_head();
+    }
+
+    if let Some(ref path) = chromium_path {
+        builder = builder.chrome_executable(path);
+    }
+
+    let browser_config = builder
+        .build()
+        .map_err(|e| BrowserError::LaunchFailed(e.to_string()))?;
+
+    let (browser, handler) = Browser::launch(browser_config)
+        .await
+        .map_err(|e| BrowserError::LaunchFailed(e.to_string()))?;
+    Ok((browser, handler, user_data_dir))
+}
+
+/// Attach to an already running Chrome. `url` is the DevTools websocket
+/// URL, or its `http://host:port` endpoint (resolved via `/json/version`).
+async fn connect(url: &str) -> Result<(Browser, Handler)> {
+    tracing::info!("connecting to Chrome at {url}");
+    let handler_config = HandlerConfig {
+        viewport: Some(window_viewport()),
+        ..Default::default()
+    };
+    let (browser, handler) = Browser::connect_with_config(url, handler_config)
+        .await
+        .map_err(|e| BrowserError::ConnectFailed(format!("{url}: {e}")))?;
+    Ok((browser, handler))
+}
+
+/// A console argument as text: strings as they are, other values as JSON,
+/// objects by their description.
+fn remote_object_text(arg: &RemoteObject) -> String {
+    match (&arg.value, &arg.description) {
+        (Some(serde_json::Value::String(s)), _) => s.clone(),
+        (Some(value), _) => value.to_string(),
+        (None, Some(description)) => description.clone(),
+        (None, None) => arg
+            .unserializable_value
+            .as_ref()
+            .map_or_else(|| arg.r#type.as_ref().to_owned(), |v| v.inner().clone()),
+    }
+}
+
+struct ChromiumTab(Page);
+
+impl Tab for ChromiumTab {
+    fn goto<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.0.goto(url).await?;
+            Ok(())
+        })
+    }
+
+    fn reload(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.0.reload().await?;
+            Ok(())
+        })
+    }
+
+    fn url(&self) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move { Ok(self.0.url().await?) })
+    }
+
+    fn evaluate<'a>(&'a self, expression: &'a str) -> BoxFuture<'a, Result<Evaluation>> {
+        Box::pin(async move {
+            let result = self.0.evaluate(expression).await?;
+            Ok(Evaluation(result.value().cloned().unwrap_or_default()))
+        })
+    }
+
+    fn screenshot(&self, capture: Capture) -> BoxFuture<'_, Result<Vec<u8>>> {
+        let mut params = ScreenshotParams::builder().format(capture.format);
+        if let Some(quality) = capture.quality {
+            params = params.quality(quality);
+        }
+        if let Some(clip) = capture.clip {
+            params = params.clip(ClipRect {
+                x: clip.x,
+                y: clip.y,
+                width: clip.width,
+                height: clip.height,
+                scale: clip.scale,
+            });
+        }
+        Box::pin(async move { Ok(self.0.screenshot(params.build()).await?) })
+    }
+
+    fn mouse(&self, action: MouseAction, x: f64, y: f64) -> BoxFuture<'_, Result<()>> {
+        let builder = DispatchMouseEventParams::builder().x(x).y(y);
+        let builder = match action {
+            MouseAction::Move => builder.r#type(DispatchMouseEventType::MouseMoved),
+            MouseAction::Drag => builder
+                .r#type(DispatchMouseEventType::MouseMoved)
+                .button(MouseButton::Left)
+                .buttons(1_i64),
+            MouseAction::Press => builder
+                .r#type(DispatchMouseEventType::MousePressed)
+                .button(MouseButton::Left)
+                .click_count(1),
+            MouseAction::Release => builder
+                .r#type(DispatchMouseEventType::MouseReleased)
+                .button(MouseButton::Left)
+                .click_count(1),
+        };
+        Box::pin(async move {
+            let params = builder.build().map_err(anyhow::Error::msg)?;
+            self.0.execute(params).await?;
+            Ok(())
+        })
+    }
+
+    fn key(&self, action: KeyAction, key: Key) -> BoxFuture<'_, Result<()>> {
+        let builder = DispatchKeyEventParams::builder().r#type(match action {
+            KeyAction::Down => DispatchKeyEventType::KeyDown,
+            KeyAction::Up => DispatchKeyEventType::KeyUp,
+        });
+        let builder = match key {
+            Key::Named(name) => builder.key(name).code(name),
+            // Only the key down types the character
+            Key::Char(ch) if action == KeyAction::Down => {
+                builder.key(ch.to_string()).text(ch.to_string())
+            }
+            Key::Char(ch) => builder.key(ch.to_string()),
+            Key::Ctrl(ch) => builder
+                .key(ch.to_string())
+                .code(format!("Key{}", ch.to_ascii_uppercase()))
+                .modifiers(2), // 2 = Ctrl modifier
+        };
+        Box::pin(async move {
+            let params = builder.build().map_err(anyhow::Error::msg)?;
+            self.0.execute(params).await?;
+            Ok(())
+        })
+    }
+
+    fn wheel(&self, x: f64, y: f64, dx: f64, dy: f64) -> BoxFuture<'_, Result<()>> {
+        let builder = DispatchMouseEventParams::builder()
+            .r#type(DispatchMouseEventType::MouseWheel)
+            .x(x)
+            .y(y)
+            .delta_x(dx)
+            .delta_y(dy);
+        Box::pin(async move {
+            let params = builder.build().map_err(anyhow::Error::msg)?;
+            self.0.execute(params).await?;
+            Ok(())
+        })
+    }
+
+    fn set_cookies(&self, cookies: Vec<CookieParam>) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.0.execute(SetCookiesParams::new(cookies)).await?;
+            Ok(())
+        })
+    }
+}
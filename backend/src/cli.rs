@@ -0,0 +1,110 @@
+//! Command-line flags for the common settings, so local runs don't need a
+//! long `env MERCY_...=` prefix.
+//!
+//! Flags are turned into the `MERCY_*` variables they stand for before the
+//! config is loaded, so the environment stays the single source of
+//! configuration: a flag wins over the environment, which wins over the
+//! `--config` file (see [`ConfigSource`]).
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::config::ConfigSource;
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Scans Total Battle kingdoms for Mercenary Exchanges",
+    after_help = "Anything not given here is read from the MERCY_* environment variables \
+                  (see .env.example)."
+)]
+pub struct Cli {
+    /// Address the API listens on [env: MERCY_LISTEN_ADDR]
+    #[arg(short, long, value_name = "ADDR")]
+    listen: Option<String>,
+
+    /// Kingdoms to scan, comma-separated [env: MERCY_KINGDOMS]
+    #[arg(short, long, value_name = "IDS", value_delimiter = ',', num_args = 1..)]
+    kingdoms: Vec<u32>,
+
+    /// Scan pattern: single, multi, wide, grid or known [env: MERCY_SCAN_PATTERN]
+    #[arg(short, long)]
+    pattern: Option<String>,
+
+    /// Run Chromium without a window [env: MERCY_HEADLESS]
+    #[arg(long)]
+    headless: bool,
+
+    /// File of MERCY_*=value lines (the .env.example format) for variables
+    /// not already set
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Load and check the config, print a summary and exit (non-zero on
+    /// problems) without starting
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+impl Cli {
+    /// Export the flags to the environment and return where the config is
+    /// read from. Must run before any other thread exists, as it calls
+    /// `std::env::set_var`.
+    pub fn apply(self) -> Result<ConfigSource> {
+        let mut vars = Vec::new();
+        if let Some(listen) = self.listen {
+            vars.push(("MERCY_LISTEN_ADDR".to_string(), listen));
+        }
+        if !self.kingdoms.is_empty() {
+            let kingdoms: Vec<String> = self.kingdoms.iter().map(u32::to_string).collect();
+            vars.push(("MERCY_KINGDOMS".to_string(), kingdoms.join(",")));
+        }
+        if let Some(pattern) = self.pattern {
+            vars.push(("MERCY_SCAN_PATTERN".to_string(), pattern));
+        }
+        if self.headless {
+            vars.push(("MERCY_HEADLESS".to_string(), "1".to_string()));
+        }
+        export(vars, self.config)
+    }
+}
+
+/// Export `vars`, then the `config` file's variables that aren't set, and
+/// return where the config is read from. Shared with the tools in `src/bin`.
+/// Must run before any other thread exists, as it calls `std::env::set_var`.
+pub fn export(vars: Vec<(String, String)>, config: Option<PathBuf>) -> Result<ConfigSource> {
+    for (key, value) in vars {
+        // SAFETY: called from `main` before the runtime starts, so no
+        // other thread can be reading the environment.
+        unsafe { std::env::set_var(key, value) };
+    }
+
+    let source = ConfigSource::new(config);
+    // Also export the file, for the few settings read outside `Config`
+    // (`MERCY_ASSETS_DIR`)
+    for (key, value) in source.file_vars()? {
+        if std::env::var_os(&key).is_none() {
+            // SAFETY: as above
+            unsafe { std::env::set_var(key, value) };
+        }
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_flags() {
+        let cli =
+            Cli::try_parse_from(["mercy", "-k", "110,111", "--headless", "-p", "known"]).unwrap();
+        assert_eq!(cli.kingdoms, [110, 111]);
+        assert!(cli.headless);
+        assert_eq!(cli.pattern.as_deref(), Some("known"));
+        assert!(cli.listen.is_none());
+        assert!(Cli::try_parse_from(["mercy", "--check-login"]).is_err());
+    }
+}
@@ -1,5 +1,25 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::accounts::{Account, AccountRotation, load_accounts_file};
+use crate::browser::{NavigationMode, ScreenshotFormat};
+use crate::detector::{
+    Aggregation, ChannelWeights, ColorSpace, MatchBackend, MatchMethod, MatchOptions,
+};
+use crate::driver::BrowserKind;
+use crate::locate::{MapTransform, VerifyCriteria};
+use crate::logfile::Rotation;
+use crate::logging::LogFormat;
+use crate::mqtt::MqttConfig;
+use crate::notify::PushService;
+use crate::stealth::DEFAULT_USER_AGENT;
+use crate::target::{PopupLocale, TargetProfile};
+use crate::ui::{UiElement, UiPoints};
+use crate::viewport::Viewport;
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("missing environment variable: {0}")]
@@ -7,40 +27,219 @@ pub enum ConfigError {
 
     #[error("invalid kingdoms list: {0}")]
     InvalidKingdoms(String),
+
+    #[error("invalid accounts file: {0}")]
+    InvalidAccounts(String),
+
+    #[error("invalid {0}: {1}")]
+    InvalidValue(String, String),
+
+    #[error("invalid config file {}: {}", .0.display(), .1)]
+    ConfigFile(PathBuf, String),
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub kingdoms: Vec<u32>,
     pub auth_token: String,
-    pub tb_email: String,
-    pub tb_password: String,
+    /// Bearer token for `POST /control` (manual browser control); the
+    /// endpoint is disabled without one
+    pub admin_token: Option<String>,
+    /// Game accounts to log in with: the `MERCY_TB_EMAIL` one first, then
+    /// those from `MERCY_ACCOUNTS_FILE`. May be empty when session cookies
+    /// are configured
+    pub accounts: Vec<Account>,
+    /// When to switch to the next account (default per session)
+    pub account_rotation: AccountRotation,
+    /// How long an account rests after its session before it's used again
+    /// (`MERCY_ACCOUNT_COOLDOWN_MINS`, default 30 minutes)
+    pub account_cooldown: chrono::TimeDelta,
+    /// Exported cookies (JSON or Netscape cookies.txt) injected before login
+    pub cookies_file: Option<PathBuf>,
+    /// Raw `Cookie` header value injected before login
+    pub session_cookie: Option<String>,
     pub listen_addr: String,
+    /// Browser engine to drive (default Chromium)
+    pub browser: BrowserKind,
     pub chromium_path: Option<String>,
+    /// Firefox executable when `browser` is Firefox (default `firefox` on PATH)
+    pub firefox_path: Option<String>,
+    /// DevTools URL of an already running Chrome to attach to instead of
+    /// launching Chromium (`ws://...` or `http://host:port`)
+    pub cdp_url: Option<String>,
+    /// File the game tabs' console output is appended to (None = memory only)
+    pub console_log: Option<PathBuf>,
+    /// Text or JSON lines (`MERCY_LOG_FORMAT`, default text)
+    pub log_format: LogFormat,
+    /// Shell command run to notify the operator (see `notify.rs`)
+    pub notify_command: Option<String>,
+    /// Push services notifications also go to: `MERCY_NTFY_TOPIC`, and
+    /// Pushover with `MERCY_PUSHOVER_TOKEN` and `MERCY_PUSHOVER_USER`
+    pub push_services: Vec<PushService>,
+    /// MQTT broker for the Home Assistant integration (`MERCY_MQTT_HOST`,
+    /// see `mqtt.rs`)
+    pub mqtt: Option<MqttConfig>,
+    /// Shell command errors and panics are reported through (see `report.rs`)
+    pub error_command: Option<String>,
+    /// Where the per-account instance locks go (default the temp directory)
+    pub lock_dir: PathBuf,
+    /// Seconds between browser health checks (default 60, 0 = off)
+    pub health_interval_secs: u64,
+    /// Failed scan runs in a row the supervisor restarts before giving up
+    /// (default 5, 0 = never restart)
+    pub scanner_max_restarts: u32,
+    /// Seconds a SIGINT/SIGTERM shutdown waits for the scanner to stop and
+    /// for open HTTP requests to finish (default 10)
+    pub shutdown_grace_secs: u64,
+    /// Restart the browser when Chromium's resident memory exceeds this (MB)
+    pub browser_max_rss_mb: Option<u64>,
+    /// Restart the browser once its session is this old (minutes)
+    pub browser_max_age_mins: Option<u64>,
+    /// Serve `/goto`, `/verify` and `/screenshot` from a second game tab, so
+    /// they don't move the scanner's map (default false)
+    pub api_tab: bool,
     /// Run browser in headless mode (default false; use xvfb-run on servers)
     pub headless: bool,
-    /// Name of the tile to search for in popup confirmation (e.g. "Taotie", "Mercenary Exchange")
+    /// Fixed DevTools port of a locally launched Chromium, for attaching
+    /// Chrome DevTools to the live session (None = a private random port)
+    pub debug_port: Option<u16>,
+    /// Address the DevTools port binds to (default "127.0.0.1")
+    pub debug_address: String,
+    /// User agent presented to the game
+    pub user_agent: String,
+    /// Browser language, e.g. "de-DE" (None = Chromium's default)
+    pub browser_lang: Option<String>,
+    /// IANA timezone the game sees, e.g. "Europe/Berlin" (None = the host's)
+    pub timezone: Option<String>,
+    /// Extra Chromium command-line flags (local launch only)
+    pub chromium_args: Vec<String>,
+    /// Inject fingerprint patches beyond the webdriver override (see `stealth.rs`)
+    pub stealth: bool,
+    /// Click points of the MAP, zoom-out and coordinate search buttons
+    pub ui_points: UiPoints,
+    /// Map area of the screenshot before UI anchor detection (default: the
+    /// 1920×1080 bounds)
+    pub viewport: Viewport,
+    /// Window region of the game's coordinate display, read after each
+    /// navigation to check where the map landed (`MERCY_COORDS_REGION`,
+    /// `left,top,right,bottom`; None = not checked)
+    pub coords_region: Option<Viewport>,
+    /// Target profile to search for (e.g. "Taotie", "Mercenary Exchange"):
+    /// names its templates and manifest, see `target.rs`
     pub search_target: String,
+    /// Encoding of scan-step captures: "png", "jpeg" or "webp" (default "png")
+    pub screenshot_format: ScreenshotFormat,
+    /// JPEG/WebP quality of scan-step captures, 1-100 (default 80)
+    pub screenshot_quality: u8,
     /// Write debug screenshots to disk every scan step (default false)
     pub debug_screenshots: bool,
+    /// Directory exchange, challenge and debug screenshots are saved to
+    /// (default "screenshots")
+    pub screenshot_dir: PathBuf,
+    /// Prune the least recently used screenshots past this size (MB, default
+    /// 500, None = unlimited)
+    pub screenshot_dir_max_mb: Option<u64>,
+    /// ... and past this many files (default 1000, None = unlimited)
+    pub screenshot_dir_max_files: Option<usize>,
+    /// Directory to save a GIF recording of each kingdom pass to (None = off)
+    pub recording_dir: Option<PathBuf>,
+    /// Write a score heatmap of each calibration screenshot to disk (default false)
+    pub debug_heatmap: bool,
     /// Fly-animation wait after navigate_to_coords, in milliseconds (default 2000)
     pub navigate_delay_ms: u64,
     /// Scan pattern: "single", "multi", "wide", "grid" (default "grid")
     pub scan_pattern: String,
+    /// How scan steps move the map: "search" (default) or "drag"
+    pub navigation: NavigationMode,
     /// Override ring count per pattern (None = use pattern default)
     pub scan_rings: Option<u32>,
+    /// Minutes after a kingdom's scan before it's scanned again; until then
+    /// its known exchange is only re-verified (default 2)
+    pub scan_cooldown_mins: u64,
+    /// Only scan positions inside this box (None = the whole map)
+    pub scan_bounds: Option<ScanBounds>,
+    /// CSV of historical spawns (`kingdom,x,y`) for the "known" pattern, used
+    /// instead of the compiled-in data
+    pub known_locations: Option<PathBuf>,
+    /// Settings of single kingdoms, see [`Config::for_kingdom`]
+    pub kingdom_settings: BTreeMap<u32, KingdomConfig>,
+    /// SQLite database the exchanges and scan history persist in (None =
+    /// memory only)
+    pub db_path: Option<PathBuf>,
+    /// PostgreSQL connection string, used instead of `db_path` (needs the
+    /// `postgres` feature)
+    pub database_url: Option<String>,
     /// Path to exchange JSONL log file (default "exchanges.jsonl")
     pub exchange_log: String,
+    /// Append-only JSONL file of control calls (default "audit.jsonl")
+    pub audit_log: PathBuf,
+    /// Rotation and retention of the exchange, audit and console logs
+    /// (`MERCY_LOG_MAX_MB`, `MERCY_LOG_MAX_AGE_DAYS`, `MERCY_LOG_KEEP`)
+    pub log_rotation: Rotation,
+    /// JSON file the last scan time of each kingdom is kept in when there is
+    /// no database (default "last_scans.json")
+    pub scan_times_path: PathBuf,
+    /// Exchanges not seen for this many minutes expire (default 60, None =
+    /// never)
+    pub exchange_expire_mins: Option<u64>,
+    /// Exchanges kept in memory, the oldest ended ones dropped first
+    /// (default 1000, None = all). The database keeps every one.
+    pub max_exchanges: Option<usize>,
+    /// Scanner events kept for `/events/recent` (default 1000)
+    pub max_events: usize,
+    /// Screenshots kept for `/screenshots/recent` (default 30, 0 = none)
+    pub screenshot_history: usize,
+    /// Minimum score of a re-verification or calibration match (default 0.90)
+    pub verify_min_score: f32,
+    /// Max offset of that match from screen center, in pixels (default 80)
+    pub verify_max_offset_px: u32,
+    /// Passes of a kingdom without a detection, each with a near miss,
+    /// after which its near misses are clicked to confirm (None = never)
+    pub relax_after_passes: Option<u32>,
+    /// Lowest best score of a step that counts as a near miss (default 0.95)
+    pub relax_floor: f32,
+    /// Client language whose coordinate labels the popup is read by
+    /// (`MERCY_POPUP_LOCALE`, default: any, see `target.rs`)
+    pub popup_locale: Option<PopupLocale>,
+    /// Screen center and pixel-to-game scale of the map (default: measured
+    /// on the 1920×1080 canvas at 25% zoom)
+    pub map_transform: MapTransform,
     /// Coverage percentage for "known" scan pattern (1-100, default 80).
     /// Lower values scan fewer positions (faster) but may miss exchanges
     /// in historically rare spawn locations.
     pub known_coverage: u32,
     /// Max concurrent detection tasks (default 4)
     pub max_detect_tasks: usize,
+    /// Max Hamming distance for the perceptual-hash prefilter (None = disabled)
+    pub phash_max_distance: Option<u32>,
+    /// Downscale factor for the coarse matching pass (None = single-stage)
+    pub coarse_factor: Option<u32>,
+    /// Colour planes to match on: "rgb", "hsv" or "both" (default "rgb")
+    pub color_space: ColorSpace,
+    /// Enabled channels and weights, e.g. "r,g,b,edge:0.5" (default: all, equal)
+    pub channel_weights: ChannelWeights,
+    /// How channel scores combine: "min" or "mean" (default "min")
+    pub aggregation: Aggregation,
+    /// Correlation method: "ncc" or "sse" (default "ncc")
+    pub match_method: MatchMethod,
+    /// Log every match's score under both correlation methods
+    pub match_ab_log: bool,
+    /// Detection backend: "template", "features" or "onnx" (default "template")
+    pub match_backend: MatchBackend,
+    /// ONNX model file for the "onnx" backend
+    pub onnx_model: Option<PathBuf>,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, ConfigError> {
+    /// Build the config from `MERCY_*` variables.
+    fn from_vars(vars: &HashMap<String, String>) -> Result<Self, ConfigError> {
+        let var = |name: &str| {
+            vars.get(name)
+                .cloned()
+                .ok_or(std::env::VarError::NotPresent)
+        };
+        let required_env = |name: &str| var(name).map_err(|_| ConfigError::MissingEnv(name.into()));
+
         let kingdoms_str = required_env("MERCY_KINGDOMS")?;
         let kingdoms: Vec<u32> = kingdoms_str
             .split(',')
@@ -58,70 +257,1129 @@ impl Config {
         }
 
         let auth_token = required_env("MERCY_AUTH_TOKEN")?;
-        let tb_email = required_env("MERCY_TB_EMAIL")?;
-        let tb_password = required_env("MERCY_TB_PASSWORD")?;
+        let admin_token = var("MERCY_ADMIN_TOKEN").ok().filter(|v| !v.is_empty());
+
+        let cookies_file = var("MERCY_COOKIES_FILE")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let session_cookie = var("MERCY_SESSION_COOKIE").ok().filter(|v| !v.is_empty());
+
+        let extra_accounts = match var("MERCY_ACCOUNTS_FILE").ok().filter(|v| !v.is_empty()) {
+            Some(path) => {
+                load_accounts_file(path.as_ref()).map_err(ConfigError::InvalidAccounts)?
+            }
+            None => Vec::new(),
+        };
+
+        // Credentials are only needed when there are no cookies or other
+        // accounts to log in with
+        let mut accounts = Vec::new();
+        let email = var("MERCY_TB_EMAIL").ok().filter(|v| !v.is_empty());
+        match email {
+            Some(email) => accounts.push(Account {
+                email,
+                password: var("MERCY_TB_PASSWORD").unwrap_or_default(),
+                totp_secret: var("MERCY_TB_TOTP_SECRET").ok().filter(|v| !v.is_empty()),
+            }),
+            None if cookies_file.is_none()
+                && session_cookie.is_none()
+                && extra_accounts.is_empty() =>
+            {
+                return Err(ConfigError::MissingEnv("MERCY_TB_EMAIL".into()));
+            }
+            None => {}
+        }
+        accounts.extend(extra_accounts);
+
+        let account_rotation = var("MERCY_ACCOUNT_ROTATION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let account_cooldown_mins: u64 = var("MERCY_ACCOUNT_COOLDOWN_MINS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let account_cooldown = i64::try_from(account_cooldown_mins)
+            .ok()
+            .and_then(chrono::TimeDelta::try_minutes)
+            .ok_or_else(|| {
+                ConfigError::InvalidValue(
+                    "MERCY_ACCOUNT_COOLDOWN_MINS".into(),
+                    format!("{account_cooldown_mins} minutes is out of range"),
+                )
+            })?;
+
+        let listen_addr = var("MERCY_LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8090".into());
+
+        let browser = var("MERCY_BROWSER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let chromium_path = var("MERCY_CHROMIUM_PATH").ok();
+
+        let firefox_path = var("MERCY_FIREFOX_PATH").ok().filter(|v| !v.is_empty());
+
+        let cdp_url = var("MERCY_CDP_URL").ok().filter(|v| !v.is_empty());
+
+        let console_log = var("MERCY_CONSOLE_LOG")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let log_format = var("MERCY_LOG_FORMAT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let notify_command = var("MERCY_NOTIFY_COMMAND").ok().filter(|v| !v.is_empty());
+
+        let mut push_services = Vec::new();
+        if let Some(topic) = var("MERCY_NTFY_TOPIC").ok().filter(|v| !v.is_empty()) {
+            push_services.push(PushService::ntfy(&topic));
+        }
+        if let Some(token) = var("MERCY_PUSHOVER_TOKEN").ok().filter(|v| !v.is_empty()) {
+            push_services.push(PushService::Pushover {
+                token,
+                user: required_env("MERCY_PUSHOVER_USER")?,
+            });
+        }
+
+        let mqtt = var("MERCY_MQTT_HOST")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|host| MqttConfig {
+                host,
+                port: var("MERCY_MQTT_PORT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1883),
+                user: var("MERCY_MQTT_USER").ok().filter(|v| !v.is_empty()),
+                password: var("MERCY_MQTT_PASSWORD").ok().filter(|v| !v.is_empty()),
+                topic: var("MERCY_MQTT_TOPIC")
+                    .ok()
+                    .map(|v| v.trim_matches('/').to_string())
+                    .filter(|v| !v.is_empty())
+                    .unwrap_or_else(|| "mercy".into()),
+            });
+
+        let error_command = var("MERCY_ERROR_COMMAND").ok().filter(|v| !v.is_empty());
+
+        let lock_dir = var("MERCY_LOCK_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map_or_else(std::env::temp_dir, PathBuf::from);
+
+        let health_interval_secs = var("MERCY_HEALTH_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+
+        let scanner_max_restarts = var("MERCY_SCANNER_MAX_RESTARTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+
+        let shutdown_grace_secs = var("MERCY_SHUTDOWN_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+
+        let browser_max_rss_mb = var("MERCY_BROWSER_MAX_RSS_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&v| v > 0);
+
+        let browser_max_age_mins = var("MERCY_BROWSER_MAX_AGE_MINS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&v| v > 0);
+
+        let api_tab = var("MERCY_API_TAB")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        let headless = var("MERCY_HEADLESS")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        let debug_port = var("MERCY_DEBUG_PORT").ok().and_then(|v| v.parse().ok());
+
+        let debug_address = var("MERCY_DEBUG_ADDRESS")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "127.0.0.1".into());
+
+        let user_agent = var("MERCY_USER_AGENT")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_owned());
+
+        let browser_lang = var("MERCY_BROWSER_LANG").ok().filter(|v| !v.is_empty());
 
-        let listen_addr =
-            std::env::var("MERCY_LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8090".into());
+        let timezone = var("MERCY_TIMEZONE").ok().filter(|v| !v.is_empty());
 
-        let chromium_path = std::env::var("MERCY_CHROMIUM_PATH").ok();
+        let chromium_args = var("MERCY_CHROMIUM_ARGS")
+            .map(|v| v.split_whitespace().map(str::to_owned).collect())
+            .unwrap_or_default();
 
-        let headless = std::env::var("MERCY_HEADLESS")
+        let stealth = var("MERCY_STEALTH")
             .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
             .unwrap_or(false);
 
-        let search_target = std::env::var("MERCY_SEARCH_TARGET")
-            .unwrap_or_else(|_| "Mercenary Exchange Core".into());
+        let viewport = var("MERCY_VIEWPORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let coords_region = var("MERCY_COORDS_REGION").ok().and_then(|v| v.parse().ok());
 
-        let debug_screenshots = std::env::var("MERCY_DEBUG_SCREENSHOTS")
+        let mut ui_points = UiPoints::default();
+        for element in UiElement::ALL {
+            if let Some(point) = var(element.env_var()).ok().and_then(|v| v.parse().ok()) {
+                ui_points.set(element, point);
+            }
+        }
+
+        let search_target =
+            var("MERCY_SEARCH_TARGET").unwrap_or_else(|_| "Mercenary Exchange Core".into());
+
+        let screenshot_format = var("MERCY_SCREENSHOT_FORMAT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let screenshot_quality = var("MERCY_SCREENSHOT_QUALITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(80u8)
+            .clamp(1, 100);
+
+        let debug_screenshots = var("MERCY_DEBUG_SCREENSHOTS")
             .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
             .unwrap_or(false);
 
-        let navigate_delay_ms = std::env::var("MERCY_NAVIGATE_DELAY_MS")
+        let screenshot_dir = var("MERCY_SCREENSHOT_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "screenshots".into())
+            .into();
+
+        let screenshot_dir_max_mb = Some(
+            var("MERCY_SCREENSHOT_DIR_MAX_MB")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(500),
+        )
+        .filter(|&v| v > 0);
+
+        let screenshot_dir_max_files = Some(
+            var("MERCY_SCREENSHOT_DIR_MAX_FILES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1000),
+        )
+        .filter(|&v| v > 0);
+
+        let recording_dir = var("MERCY_RECORDING_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let debug_heatmap = var("MERCY_DEBUG_HEATMAP")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        let navigate_delay_ms = var("MERCY_NAVIGATE_DELAY_MS")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(750);
 
-        let scan_pattern = std::env::var("MERCY_SCAN_PATTERN").unwrap_or_else(|_| "grid".into());
+        let scan_pattern = var("MERCY_SCAN_PATTERN").unwrap_or_else(|_| "grid".into());
 
-        let scan_rings = std::env::var("MERCY_SCAN_RINGS")
+        let navigation = var("MERCY_NAVIGATION")
             .ok()
-            .and_then(|v| v.parse().ok());
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
 
-        let exchange_log =
-            std::env::var("MERCY_EXCHANGE_LOG").unwrap_or_else(|_| "exchanges.jsonl".into());
+        let scan_rings = var("MERCY_SCAN_RINGS").ok().and_then(|v| v.parse().ok());
 
-        let known_coverage = std::env::var("MERCY_KNOWN_COVERAGE")
+        let scan_cooldown_mins = var("MERCY_SCAN_COOLDOWN_MINS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+
+        let scan_bounds = var("MERCY_SCAN_BOUNDS").ok().and_then(|v| v.parse().ok());
+
+        let known_locations = var("MERCY_KNOWN_LOCATIONS")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let mut kingdom_settings = BTreeMap::new();
+        for name in vars.keys() {
+            let Some((kingdom, _)) = name
+                .strip_prefix(KINGDOM_PREFIX)
+                .and_then(|rest| rest.split_once('_'))
+            else {
+                continue;
+            };
+            let Ok(kingdom) = kingdom.parse::<u32>() else {
+                continue;
+            };
+            kingdom_settings.entry(kingdom).or_insert_with(|| {
+                let prefix = format!("{KINGDOM_PREFIX}{kingdom}_");
+                KingdomConfig::from_vars(|key| vars.get(&format!("{prefix}{key}")).cloned())
+            });
+        }
+
+        let db_path = var("MERCY_DB_PATH")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+
+        let database_url = var("MERCY_DATABASE_URL").ok().filter(|v| !v.is_empty());
+
+        let exchange_log = var("MERCY_EXCHANGE_LOG").unwrap_or_else(|_| "exchanges.jsonl".into());
+        let audit_log = var("MERCY_AUDIT_LOG")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "audit.jsonl".into())
+            .into();
+        let default_rotation = Rotation::default();
+        let log_rotation = Rotation {
+            max_bytes: var("MERCY_LOG_MAX_MB")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|&mb| mb > 0)
+                .map_or(default_rotation.max_bytes, |mb| mb * 1024 * 1024),
+            max_age: var("MERCY_LOG_MAX_AGE_DAYS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|&days| days > 0)
+                .map(|days| std::time::Duration::from_secs(days * 24 * 3600)),
+            keep: var("MERCY_LOG_KEEP")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default_rotation.keep),
+        };
+
+        let scan_times_path = var("MERCY_SCAN_TIMES")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "last_scans.json".into())
+            .into();
+
+        let exchange_expire_mins = Some(
+            var("MERCY_EXCHANGE_EXPIRE_MINS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+        )
+        .filter(|&v| v > 0);
+
+        let max_exchanges = Some(
+            var("MERCY_MAX_EXCHANGES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1000),
+        )
+        .filter(|&v| v > 0);
+
+        let max_events = var("MERCY_MAX_EVENTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&v| v > 0)
+            .unwrap_or(1000);
+
+        let screenshot_history = var("MERCY_SCREENSHOT_HISTORY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+
+        let verify_min_score = var("MERCY_VERIFY_MIN_SCORE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.90);
+
+        let verify_max_offset_px = var("MERCY_VERIFY_MAX_OFFSET_PX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(80);
+
+        let relax_after_passes = var("MERCY_RELAX_AFTER_PASSES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&v| v > 0);
+
+        let relax_floor = var("MERCY_RELAX_FLOOR")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|v| (0.0..=1.0).contains(v))
+            .unwrap_or(0.95);
+
+        let popup_locale = var("MERCY_POPUP_LOCALE")
+            .ok()
+            .and_then(|v| PopupLocale::from_code(&v));
+
+        let mut map_transform = MapTransform::default();
+        if let Some((x, y)) = var("MERCY_SCREEN_CENTER").ok().and_then(|v| parse_pair(&v)) {
+            (map_transform.center_x, map_transform.center_y) = (x, y);
+        }
+        if let Some((x, y)) = var("MERCY_PX_PER_GAME")
+            .ok()
+            .and_then(|v| parse_pair(&v))
+            .filter(|&(x, y)| x > 0.0 && y > 0.0)
+        {
+            (map_transform.px_per_game_x, map_transform.px_per_game_y) = (x, y);
+        }
+        if let Some(tilt) = var("MERCY_TILT_Y").ok().and_then(|v| v.trim().parse().ok()) {
+            map_transform.tilt_y = tilt;
+        }
+
+        let known_coverage = var("MERCY_KNOWN_COVERAGE")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(80u32)
             .clamp(1, 100);
 
-        let max_detect_tasks = std::env::var("MERCY_MAX_DETECT_TASKS")
+        let max_detect_tasks = var("MERCY_MAX_DETECT_TASKS")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(4);
 
+        let phash_max_distance = var("MERCY_PHASH_MAX_DISTANCE")
+            .ok()
+            .and_then(|v| v.parse().ok());
+
+        let coarse_factor = var("MERCY_COARSE_FACTOR").ok().and_then(|v| v.parse().ok());
+
+        let color_space = var("MERCY_COLOR_SPACE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let channel_weights = var("MERCY_CHANNELS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let aggregation = var("MERCY_CHANNEL_AGGREGATION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let match_method = var("MERCY_MATCH_METHOD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let match_ab_log = var("MERCY_MATCH_AB_LOG")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        let match_backend = var("MERCY_DETECTOR")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+
+        let onnx_model = var("MERCY_ONNX_MODEL").ok().map(PathBuf::from);
+
         Ok(Config {
             kingdoms,
             auth_token,
-            tb_email,
-            tb_password,
+            admin_token,
+            accounts,
+            account_rotation,
+            account_cooldown,
+            cookies_file,
+            session_cookie,
             listen_addr,
+            browser,
             chromium_path,
+            firefox_path,
+            cdp_url,
+            console_log,
+            log_format,
+            notify_command,
+            push_services,
+            mqtt,
+            error_command,
+            lock_dir,
+            health_interval_secs,
+            scanner_max_restarts,
+            shutdown_grace_secs,
+            browser_max_rss_mb,
+            browser_max_age_mins,
+            api_tab,
             headless,
+            debug_port,
+            debug_address,
+            user_agent,
+            browser_lang,
+            timezone,
+            chromium_args,
+            stealth,
+            ui_points,
+            viewport,
             search_target,
+            screenshot_format,
+            screenshot_quality,
             debug_screenshots,
+            screenshot_dir,
+            screenshot_dir_max_mb,
+            screenshot_dir_max_files,
+            recording_dir,
+            debug_heatmap,
             navigate_delay_ms,
             scan_pattern,
+            navigation,
             scan_rings,
+            scan_cooldown_mins,
+            scan_bounds,
+            known_locations,
+            kingdom_settings,
+            db_path,
+            database_url,
             exchange_log,
+            audit_log,
+            log_rotation,
+            scan_times_path,
+            exchange_expire_mins,
+            max_exchanges,
+            max_events,
+            screenshot_history,
+            verify_min_score,
+            verify_max_offset_px,
+            relax_after_passes,
+            relax_floor,
+            popup_locale,
+            coords_region,
+            map_transform,
             known_coverage,
             max_detect_tasks,
+            phash_max_distance,
+            coarse_factor,
+            color_space,
+            channel_weights,
+            aggregation,
+            match_method,
+            match_ab_log,
+            match_backend,
+            onnx_model,
         })
     }
+
+    /// Detector options derived from this config.
+    pub fn match_options(&self) -> MatchOptions {
+        MatchOptions {
+            phash_max_distance: self.phash_max_distance,
+            coarse_factor: self.coarse_factor,
+            color_space: self.color_space,
+            onnx_model: self.onnx_model.clone(),
+            channel_weights: self.channel_weights,
+            aggregation: self.aggregation,
+            method: self.match_method,
+            ab_log: self.match_ab_log,
+        }
+    }
+
+    /// When a match near screen center is `target`: its profile's values,
+    /// else `MERCY_VERIFY_*`.
+    pub fn verify_criteria(&self, target: &TargetProfile) -> VerifyCriteria {
+        VerifyCriteria {
+            min_score: target.verify_min_score.unwrap_or(self.verify_min_score),
+            max_offset_px: target
+                .verify_max_offset_px
+                .unwrap_or(self.verify_max_offset_px),
+            center: self.map_transform.center(),
+        }
+    }
+
+    /// The config as it applies to `kingdom`: its section's settings
+    /// (`MERCY_KINGDOM_<id>_*`) in place of the global ones.
+    pub fn for_kingdom(&self, kingdom: u32) -> Config {
+        let mut config = self.clone();
+        let Some(settings) = self.kingdom_settings.get(&kingdom) else {
+            return config;
+        };
+        if let Some(ref pattern) = settings.scan_pattern {
+            config.scan_pattern = pattern.clone();
+        }
+        if settings.scan_rings.is_some() {
+            config.scan_rings = settings.scan_rings;
+        }
+        if let Some(cooldown) = settings.scan_cooldown_mins {
+            config.scan_cooldown_mins = cooldown;
+        }
+        if settings.scan_bounds.is_some() {
+            config.scan_bounds = settings.scan_bounds;
+        }
+        if settings.known_locations.is_some() {
+            config.known_locations = settings.known_locations.clone();
+        }
+        config
+    }
+}
+
+/// Prefix of per-kingdom variables: `MERCY_KINGDOM_110_SCAN_PATTERN` is
+/// `MERCY_SCAN_PATTERN` for kingdom 110 alone.
+const KINGDOM_PREFIX: &str = "MERCY_KINGDOM_";
+
+/// Settings of one kingdom, over the global ones. Set per kingdom through
+/// `MERCY_KINGDOM_<id>_<setting>` or a `[kingdom <id>]` section of the
+/// config file; each is `None` where the global setting applies.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KingdomConfig {
+    pub scan_pattern: Option<String>,
+    pub scan_rings: Option<u32>,
+    pub scan_cooldown_mins: Option<u64>,
+    pub scan_bounds: Option<ScanBounds>,
+    pub known_locations: Option<PathBuf>,
+}
+
+impl KingdomConfig {
+    /// Read the settings through `var`, which takes the name without the
+    /// `MERCY_` prefix (`SCAN_PATTERN`).
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        Self {
+            scan_pattern: var("SCAN_PATTERN").filter(|v| !v.is_empty()),
+            scan_rings: var("SCAN_RINGS").and_then(|v| v.parse().ok()),
+            scan_cooldown_mins: var("SCAN_COOLDOWN_MINS").and_then(|v| v.parse().ok()),
+            scan_bounds: var("SCAN_BOUNDS").and_then(|v| v.parse().ok()),
+            known_locations: var("KNOWN_LOCATIONS")
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from),
+        }
+    }
+}
+
+/// Map rectangle scanning is limited to, inclusive, as `x0,y0,x1,y1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanBounds {
+    pub x_min: u32,
+    pub y_min: u32,
+    pub x_max: u32,
+    pub y_max: u32,
 }
 
-fn required_env(name: &str) -> Result<String, ConfigError> {
-    std::env::var(name).map_err(|_| ConfigError::MissingEnv(name.into()))
+impl ScanBounds {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x_min..=self.x_max).contains(&x) && (self.y_min..=self.y_max).contains(&y)
+    }
+}
+
+impl std::fmt::Display for ScanBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            x_min,
+            y_min,
+            x_max,
+            y_max,
+        } = self;
+        write!(f, "{x_min},{y_min},{x_max},{y_max}")
+    }
+}
+
+impl std::str::FromStr for ScanBounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>().map_err(|e| format!("{v}: {e}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x0, y0, x1, y1] = values[..] else {
+            return Err(format!("expected x0,y0,x1,y1, got {s}"));
+        };
+        Ok(Self {
+            x_min: x0.min(x1),
+            y_min: y0.min(y1),
+            x_max: x0.max(x1),
+            y_max: y0.max(y1),
+        })
+    }
+}
+
+/// Where the config is read from: the environment as it was at startup
+/// (command-line flags included), then the `--config` file for anything the
+/// environment leaves unset. Kept so a reload reads the file again.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    env: HashMap<String, String>,
+    file: Option<PathBuf>,
+}
+
+impl ConfigSource {
+    /// Snapshot the current environment, with `file` under it.
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self {
+            env: std::env::vars().collect(),
+            file,
+        }
+    }
+
+    /// The variables set in the config file (none without one).
+    pub fn file_vars(&self) -> Result<HashMap<String, String>, ConfigError> {
+        let Some(ref path) = self.file else {
+            return Ok(HashMap::new());
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::ConfigFile(path.clone(), e.to_string()))?;
+        let vars = parse_env_file(&text).map_err(|e| ConfigError::ConfigFile(path.clone(), e))?;
+        Ok(vars.into_iter().collect())
+    }
+
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut vars = self.file_vars()?;
+        vars.extend(self.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+        Config::from_vars(&vars)
+    }
+}
+
+/// Parse an `x,y` pair of numbers.
+fn parse_pair(s: &str) -> Option<(f64, f64)> {
+    let (x, y) = s.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+/// Parse `KEY=value` lines as `source` would for the files we ship: blank
+/// lines and `#` comments are skipped, an optional `export ` prefix is
+/// dropped, values may be quoted, and unquoted values end at a ` #` comment.
+///
+/// After a `[kingdom <id>]` header, `MERCY_*` settings are that kingdom's:
+/// `MERCY_SCAN_PATTERN` becomes `MERCY_KINGDOM_<id>_SCAN_PATTERN`.
+fn parse_env_file(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    let mut kingdom = None;
+    for line in text.lines() {
+        let line = line.trim();
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            kingdom = Some(
+                section
+                    .strip_prefix("kingdom ")
+                    .and_then(|k| k.trim().parse::<u32>().ok())
+                    .ok_or_else(|| format!("unknown section [{section}]"))?,
+            );
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value
+                .split(" #")
+                .next()
+                .and_then(|v| v.split("\t#").next())
+                .unwrap_or_default()
+                .trim_end(),
+        };
+        let key = key.trim();
+        let key = match (kingdom, key.strip_prefix("MERCY_")) {
+            (Some(kingdom), Some(setting)) => format!("{KINGDOM_PREFIX}{kingdom}_{setting}"),
+            _ => key.to_string(),
+        };
+        vars.push((key, value.to_string()));
+    }
+    Ok(vars)
+}
+
+/// A re-read config compared with the running one (see [`Config::reload`]).
+#[derive(Debug)]
+pub struct ConfigReload {
+    /// The running config with the runtime-safe changes applied
+    pub config: Config,
+    /// Changed fields that took effect
+    pub applied: Vec<&'static str>,
+    /// Changed fields that need a restart
+    pub deferred: Vec<&'static str>,
+}
+
+/// Defines [`Config::reload`] over every field, as either `live` (safe to
+/// change while running) or `restart`. Destructuring without `..` makes a
+/// new field a compile error until it's sorted into one of the lists.
+macro_rules! config_reload {
+    (live: [$($live:ident),* $(,)?], restart: [$($restart:ident),* $(,)?] $(,)?) => {
+        impl Config {
+            /// Take the fields that are safe to change at runtime (the scan
+            /// settings and match options) from `new`, and list which
+            /// changed fields were applied and which wait for a restart.
+            pub fn reload(&self, new: Config) -> ConfigReload {
+                let Config { $($live,)* $($restart,)* } = new;
+                let mut config = self.clone();
+                let mut applied = Vec::new();
+                let mut deferred = Vec::new();
+                $(
+                    if config.$live != $live {
+                        config.$live = $live;
+                        applied.push(stringify!($live));
+                    }
+                )*
+                $(
+                    if self.$restart != $restart {
+                        deferred.push(stringify!($restart));
+                    }
+                )*
+                ConfigReload { config, applied, deferred }
+            }
+        }
+    };
+}
+
+config_reload! {
+    live: [
+        kingdoms,
+        scan_pattern,
+        scan_rings,
+        known_coverage,
+        navigate_delay_ms,
+        phash_max_distance,
+        coarse_factor,
+        color_space,
+        channel_weights,
+        aggregation,
+        match_method,
+        match_ab_log,
+        scan_cooldown_mins,
+        scan_bounds,
+        known_locations,
+        kingdom_settings,
+        verify_min_score,
+        verify_max_offset_px,
+        relax_after_passes,
+        relax_floor,
+        popup_locale,
+        coords_region,
+        map_transform,
+    ],
+    restart: [
+        auth_token,
+        admin_token,
+        accounts,
+        account_rotation,
+        account_cooldown,
+        cookies_file,
+        session_cookie,
+        listen_addr,
+        browser,
+        chromium_path,
+        firefox_path,
+        cdp_url,
+        console_log,
+        log_format,
+        notify_command,
+        push_services,
+        mqtt,
+        error_command,
+        lock_dir,
+        health_interval_secs,
+        scanner_max_restarts,
+        shutdown_grace_secs,
+        browser_max_rss_mb,
+        browser_max_age_mins,
+        api_tab,
+        headless,
+        debug_port,
+        debug_address,
+        user_agent,
+        browser_lang,
+        timezone,
+        chromium_args,
+        stealth,
+        ui_points,
+        viewport,
+        search_target,
+        screenshot_format,
+        screenshot_quality,
+        debug_screenshots,
+        screenshot_dir,
+        screenshot_dir_max_mb,
+        screenshot_dir_max_files,
+        recording_dir,
+        debug_heatmap,
+        navigation,
+        db_path,
+        database_url,
+        exchange_log,
+        audit_log,
+        log_rotation,
+        scan_times_path,
+        exchange_expire_mins,
+        max_exchanges,
+        max_events,
+        screenshot_history,
+        max_detect_tasks,
+        match_backend,
+        onnx_model,
+    ],
+}
+
+/// Scan patterns `MERCY_SCAN_PATTERN` understands (anything else scans the
+/// grid).
+pub const SCAN_PATTERNS: &[&str] = &["single", "multi", "wide", "known", "grid"];
+
+/// Most rings a runtime override may ask for. A spiral's positions grow with
+/// the square of its rings, and 40 already covers the map at any step.
+pub const MAX_SCAN_RINGS: u32 = 40;
+
+/// Scan settings changed at runtime, layered over the env config. Runtime
+/// overrides (`PATCH /config`) win over env, and the parameters of a
+/// `POST /start` win over both for that run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kingdoms: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_rings: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub known_coverage: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub navigate_delay_ms: Option<u64>,
+}
+
+impl ConfigOverrides {
+    /// The overridable values of `config`, all set.
+    pub fn effective(config: &Config) -> Self {
+        Self {
+            kingdoms: Some(config.kingdoms.clone()),
+            scan_pattern: Some(config.scan_pattern.clone()),
+            scan_rings: config.scan_rings,
+            known_coverage: Some(config.known_coverage),
+            navigate_delay_ms: Some(config.navigate_delay_ms),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.kingdoms.as_ref().is_some_and(|k| k.is_empty()) {
+            return Err("kingdoms must not be empty".into());
+        }
+        if let Some(ref pattern) = self.scan_pattern
+            && !SCAN_PATTERNS.contains(&pattern.as_str())
+        {
+            return Err(format!("unknown scan pattern: {pattern}"));
+        }
+        if self
+            .scan_rings
+            .is_some_and(|r| !(1..=MAX_SCAN_RINGS).contains(&r))
+        {
+            return Err(format!("scan_rings must be 1-{MAX_SCAN_RINGS}"));
+        }
+        if self.known_coverage.is_some_and(|c| !(1..=100).contains(&c)) {
+            return Err("known_coverage must be 1-100".into());
+        }
+        Ok(())
+    }
+
+    /// These overrides with the ones set in `over` replacing them.
+    pub fn merged(&self, over: &ConfigOverrides) -> Self {
+        Self {
+            kingdoms: over.kingdoms.clone().or_else(|| self.kingdoms.clone()),
+            scan_pattern: over
+                .scan_pattern
+                .clone()
+                .or_else(|| self.scan_pattern.clone()),
+            scan_rings: over.scan_rings.or(self.scan_rings),
+            known_coverage: over.known_coverage.or(self.known_coverage),
+            navigate_delay_ms: over.navigate_delay_ms.or(self.navigate_delay_ms),
+        }
+    }
+
+    /// `config` with these overrides applied, to every kingdom's settings
+    /// too.
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if let Some(ref kingdoms) = self.kingdoms {
+            config.kingdoms = kingdoms.clone();
+        }
+        if let Some(ref pattern) = self.scan_pattern {
+            config.scan_pattern = pattern.clone();
+            for settings in config.kingdom_settings.values_mut() {
+                settings.scan_pattern = None;
+            }
+        }
+        if self.scan_rings.is_some() {
+            config.scan_rings = self.scan_rings;
+            for settings in config.kingdom_settings.values_mut() {
+                settings.scan_rings = None;
+            }
+        }
+        if let Some(coverage) = self.known_coverage {
+            config.known_coverage = coverage;
+        }
+        if let Some(delay) = self.navigate_delay_ms {
+            config.navigate_delay_ms = delay;
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_overrides_precedence() {
+        let runtime: ConfigOverrides =
+            serde_json::from_str(r#"{"scan_pattern": "multi", "scan_rings": 3}"#).unwrap();
+        let request: ConfigOverrides = serde_json::from_str(r#"{"scan_rings": 5}"#).unwrap();
+        assert!(runtime.validate().is_ok());
+        assert_eq!(
+            runtime.merged(&request),
+            ConfigOverrides {
+                scan_pattern: Some("multi".into()),
+                scan_rings: Some(5),
+                ..Default::default()
+            }
+        );
+        assert!(ConfigOverrides::default().is_empty());
+
+        let bad = |json| {
+            serde_json::from_str::<ConfigOverrides>(json)
+                .unwrap()
+                .validate()
+        };
+        assert!(bad(r#"{"scan_pattern": "zigzag"}"#).is_err());
+        assert!(bad(r#"{"kingdoms": []}"#).is_err());
+        assert!(bad(r#"{"known_coverage": 0}"#).is_err());
+        assert!(bad(r#"{"scan_rings": 20000}"#).is_err());
+        assert!(serde_json::from_str::<ConfigOverrides>(r#"{"auth_token": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let text = "# === Backend ===\n\
+                    MERCY_KINGDOMS=111,112                # Kingdoms\n\
+                    export MERCY_HEADLESS=1\n\
+                    \n\
+                    MERCY_SEARCH_TARGET=\"Mercenary Exchange Core\"  # Quoted\n\
+                    MERCY_TB_PASSWORD=pa#ss\n\
+                    # MERCY_DB_PATH=mercy.db\n\
+                    MERCY_AUTH_TOKEN=\n";
+        assert_eq!(
+            parse_env_file(text).unwrap(),
+            [
+                ("MERCY_KINGDOMS", "111,112"),
+                ("MERCY_HEADLESS", "1"),
+                ("MERCY_SEARCH_TARGET", "Mercenary Exchange Core"),
+                ("MERCY_TB_PASSWORD", "pa#ss"),
+                ("MERCY_AUTH_TOKEN", ""),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_config_reload() {
+        let load = |vars: &[(&str, &str)]| {
+            let vars: HashMap<_, _> = [
+                ("MERCY_KINGDOMS", "110"),
+                ("MERCY_AUTH_TOKEN", "secret"),
+                ("MERCY_TB_EMAIL", "a@example.com"),
+            ]
+            .iter()
+            .chain(vars)
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+            Config::from_vars(&vars).unwrap()
+        };
+        let running = load(&[]);
+        let reload = running.reload(load(&[
+            ("MERCY_KINGDOMS", "110,111"),
+            ("MERCY_PHASH_MAX_DISTANCE", "12"),
+            ("MERCY_LISTEN_ADDR", "127.0.0.1:9000"),
+            ("MERCY_SCREEN_CENTER", "960, 540"),
+            ("MERCY_PX_PER_GAME", "0,30"),
+        ]));
+        assert_eq!(
+            reload.applied,
+            ["kingdoms", "phash_max_distance", "map_transform"]
+        );
+        assert_eq!(reload.deferred, ["listen_addr"]);
+        assert_eq!(reload.config.kingdoms, [110, 111]);
+        assert_eq!(reload.config.phash_max_distance, Some(12));
+        assert_eq!(reload.config.listen_addr, running.listen_addr);
+        let transform = reload.config.map_transform;
+        assert_eq!(transform.center(), (960.0, 540.0));
+        assert_eq!(
+            transform.px_per_game_x,
+            MapTransform::default().px_per_game_x
+        );
+
+        let unchanged = running.reload(load(&[]));
+        assert!(unchanged.applied.is_empty() && unchanged.deferred.is_empty());
+
+        let mut vars: HashMap<_, _> = [
+            ("MERCY_KINGDOMS", "110"),
+            ("MERCY_AUTH_TOKEN", "secret"),
+            ("MERCY_TB_EMAIL", "a@example.com"),
+            ("MERCY_ACCOUNT_COOLDOWN_MINS", "18446744073709551615"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .into();
+        assert!(matches!(
+            Config::from_vars(&vars),
+            Err(ConfigError::InvalidValue(..))
+        ));
+        vars.insert("MERCY_ACCOUNT_COOLDOWN_MINS".into(), "45".into());
+        let config = Config::from_vars(&vars).unwrap();
+        assert_eq!(config.account_cooldown, chrono::TimeDelta::minutes(45));
+    }
+
+    #[test]
+    fn test_kingdom_sections() {
+        let text = "MERCY_KINGDOMS=110,111\n\
+                    MERCY_AUTH_TOKEN=secret\n\
+                    MERCY_TB_EMAIL=a@example.com\n\
+                    MERCY_SCAN_RINGS=3\n\
+                    [kingdom 110]\n\
+                    MERCY_SCAN_PATTERN=known\n\
+                    MERCY_SCAN_BOUNDS=600,0,100,500\n\
+                    MERCY_SCAN_COOLDOWN_MINS=10\n";
+        let mut vars: HashMap<_, _> = parse_env_file(text).unwrap().into_iter().collect();
+        assert_eq!(vars["MERCY_KINGDOM_110_SCAN_PATTERN"], "known");
+        vars.insert("MERCY_KINGDOM_111_SCAN_RINGS".into(), "6".into());
+        let config = Config::from_vars(&vars).unwrap();
+
+        let k110 = config.for_kingdom(110);
+        assert_eq!(k110.scan_pattern, "known");
+        assert_eq!(k110.scan_rings, Some(3));
+        assert_eq!(k110.scan_cooldown_mins, 10);
+        let bounds = k110.scan_bounds.unwrap();
+        assert!(bounds.contains(100, 0) && bounds.contains(600, 500));
+        assert!(!bounds.contains(601, 10));
+
+        let k111 = config.for_kingdom(111);
+        assert_eq!(
+            (k111.scan_pattern.as_str(), k111.scan_rings),
+            ("grid", Some(6))
+        );
+        assert_eq!((k111.scan_cooldown_mins, k111.scan_bounds), (2, None));
+
+        // Runtime overrides win over the kingdom sections too
+        let overridden = ConfigOverrides {
+            scan_pattern: Some("wide".into()),
+            ..Default::default()
+        }
+        .apply(&config);
+        assert_eq!(overridden.for_kingdom(110).scan_pattern, "wide");
+        assert_eq!(overridden.for_kingdom(110).scan_cooldown_mins, 10);
+
+        assert!(parse_env_file("[accounts]\nMERCY_X=1\n").is_err());
+    }
 }
@@ -0,0 +1,95 @@
+//! Console output and uncaught exceptions of the game tabs.
+//!
+//! The Unity client reports trouble (a lost WebGL context, running out of
+//! memory) only on its console. Every message is kept in a ring buffer of
+//! the last [`CONSOLE_CAPACITY`] entries for `GET /console`, and appended to
+//! `MERCY_CONSOLE_LOG` when set, which is rotated like the other logs (see
+//! `logfile.rs`).
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::logfile::{self, Rotation};
+
+/// Entries kept in memory.
+const CONSOLE_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsoleEntry {
+    pub time: DateTime<Utc>,
+    /// Console method (`log`, `warning`, `error`, ...) or `exception`.
+    pub level: String,
+    pub text: String,
+}
+
+pub struct ConsoleLog {
+    entries: Mutex<VecDeque<ConsoleEntry>>,
+    file: Option<(PathBuf, Rotation)>,
+}
+
+impl ConsoleLog {
+    pub fn new(path: Option<PathBuf>, rotation: Rotation) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(CONSOLE_CAPACITY)),
+            file: path.map(|path| (path, rotation)),
+        }
+    }
+
+    pub fn push(&self, entry: ConsoleEntry) {
+        if let Some((ref path, ref rotation)) = self.file {
+            let line = format!(
+                "{} [{}] {}\n",
+                entry.time.to_rfc3339(),
+                entry.level,
+                entry.text
+            );
+            logfile::append(path, rotation, &line);
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == CONSOLE_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The last `limit` entries, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<ConsoleEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let skip = entries.len().saturating_sub(limit);
+        entries.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(text: &str) -> ConsoleEntry {
+        ConsoleEntry {
+            time: Utc::now(),
+            level: "error".into(),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn test_console_log_ring() {
+        let log = ConsoleLog::new(None, Rotation::default());
+        for i in 0..CONSOLE_CAPACITY + 5 {
+            log.push(entry(&i.to_string()));
+        }
+        let recent = log.recent(3);
+        let texts: Vec<&str> = recent.iter().map(|e| e.text.as_str()).collect();
+        let last = CONSOLE_CAPACITY + 4;
+        assert_eq!(
+            texts,
+            [last - 2, last - 1, last].map(|i| i.to_string()).as_slice()
+        );
+        assert_eq!(log.recent(usize::MAX).len(), CONSOLE_CAPACITY);
+    }
+}
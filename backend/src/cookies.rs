@@ -0,0 +1,163 @@
+//! Session cookies for skipping the form login: an exported cookie file
+//! (`MERCY_COOKIES_FILE`) and/or a raw `Cookie` header value
+//! (`MERCY_SESSION_COOKIE`), injected before the game site is opened.
+//!
+//! Cookie files can be the JSON array written by browser cookie-export
+//! extensions (and Playwright/Puppeteer), or a Netscape `cookies.txt`.
+
+use anyhow::{Context, Result};
+use chromiumoxide::cdp::browser_protocol::network::{CookieParam, CookieSameSite, TimeSinceEpoch};
+use serde::Deserialize;
+
+use crate::config::Config;
+
+/// Domain given to cookies from `MERCY_SESSION_COOKIE`, and to exported
+/// cookies that don't name one.
+const COOKIE_DOMAIN: &str = ".totalbattle.com";
+
+/// All cookies configured for injection; empty when none are.
+pub fn load_cookies(config: &Config) -> Result<Vec<CookieParam>> {
+    let mut cookies = Vec::new();
+    if let Some(ref path) = config.cookies_file {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        cookies = parse_cookie_file(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+    }
+    if let Some(ref header) = config.session_cookie {
+        cookies.extend(parse_cookie_header(header));
+    }
+    Ok(cookies)
+}
+
+/// One cookie as exported to JSON. Field names cover both the extension
+/// (`expirationDate`, `sameSite: "no_restriction"`) and Playwright (`expires`,
+/// `sameSite: "None"`) flavours.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedCookie {
+    name: String,
+    value: String,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: Option<bool>,
+    http_only: Option<bool>,
+    same_site: Option<String>,
+    #[serde(alias = "expires")]
+    expiration_date: Option<f64>,
+}
+
+/// Parse a JSON cookie export or a Netscape `cookies.txt`.
+pub fn parse_cookie_file(text: &str) -> Result<Vec<CookieParam>> {
+    if text.trim_start().starts_with('[') {
+        let exported: Vec<ExportedCookie> =
+            serde_json::from_str(text).context("invalid JSON cookie export")?;
+        return Ok(exported
+            .into_iter()
+            .map(|c| {
+                let mut cookie = CookieParam::new(c.name, c.value);
+                cookie.domain = c.domain.or_else(|| Some(COOKIE_DOMAIN.to_owned()));
+                cookie.path = c.path;
+                cookie.secure = c.secure;
+                cookie.http_only = c.http_only;
+                cookie.same_site = c.same_site.as_deref().and_then(parse_same_site);
+                // Session cookies are exported as -1 (or without an expiry)
+                cookie.expires = c
+                    .expiration_date
+                    .filter(|&t| t > 0.0)
+                    .map(TimeSinceEpoch::new);
+                cookie
+            })
+            .collect());
+    }
+
+    let mut cookies = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, _subdomains, path, secure, expires, name, value] = fields[..] else {
+            anyhow::bail!("line {}: expected 7 tab-separated fields", n + 1);
+        };
+        let mut cookie = CookieParam::new(name, value);
+        cookie.domain = Some(domain.to_owned());
+        cookie.path = Some(path.to_owned());
+        cookie.secure = Some(secure.eq_ignore_ascii_case("TRUE"));
+        cookie.http_only = Some(http_only);
+        cookie.expires = expires
+            .parse::<f64>()
+            .ok()
+            .filter(|&t| t > 0.0)
+            .map(TimeSinceEpoch::new);
+        cookies.push(cookie);
+    }
+    Ok(cookies)
+}
+
+fn parse_same_site(s: &str) -> Option<CookieSameSite> {
+    match s.to_ascii_lowercase().as_str() {
+        "strict" => Some(CookieSameSite::Strict),
+        "lax" => Some(CookieSameSite::Lax),
+        "none" | "no_restriction" => Some(CookieSameSite::None),
+        _ => None,
+    }
+}
+
+/// Parse a `Cookie` header value (`name=value; name2=value2`), as copied
+/// from the browser's dev tools, into cookies for the game domain.
+pub fn parse_cookie_header(header: &str) -> Vec<CookieParam> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            let mut cookie = CookieParam::new(name.trim(), value.trim());
+            cookie.domain = Some(COOKIE_DOMAIN.to_owned());
+            cookie.path = Some("/".to_owned());
+            cookie.secure = Some(true);
+            Some(cookie)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cookie_formats() {
+        let json = r#"[
+            {"name": "sid", "value": "abc", "domain": ".totalbattle.com", "path": "/",
+             "secure": true, "httpOnly": true, "sameSite": "no_restriction",
+             "expirationDate": 1900000000.5},
+            {"name": "lang", "value": "en", "domain": "totalbattle.com", "expires": -1}
+        ]"#;
+        let cookies = parse_cookie_file(json).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].same_site, Some(CookieSameSite::None));
+        assert_eq!(cookies[0].http_only, Some(true));
+        assert!(cookies[0].expires.is_some());
+        assert!(cookies[1].expires.is_none(), "session cookie");
+
+        let txt = "# Netscape HTTP Cookie File\n\
+                   #HttpOnly_.totalbattle.com\tTRUE\t/\tTRUE\t1900000000\tsid\tabc\n\
+                   totalbattle.com\tFALSE\t/\tFALSE\t0\tlang\ten\n";
+        let cookies = parse_cookie_file(txt).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(
+            (cookies[0].name.as_str(), cookies[0].value.as_str()),
+            ("sid", "abc")
+        );
+        assert_eq!(cookies[0].http_only, Some(true));
+        assert_eq!(cookies[1].secure, Some(false));
+        assert!(parse_cookie_file("bad line").is_err());
+
+        let header = parse_cookie_header("sid=abc; lang=en;");
+        assert_eq!(header.len(), 2);
+        assert_eq!(header[1].domain.as_deref(), Some(COOKIE_DOMAIN));
+    }
+}
@@ -0,0 +1,153 @@
+//! Reading the map position off the game's coordinate display
+//! (`MERCY_COORDS_REGION`) with the `tesseract` OCR command, so a scan step
+//! can check that navigation landed where it was sent.
+//!
+//! The region is captured on its own, scaled up in grayscale (the display's
+//! digits are small) and read as a single line of text. The text is parsed
+//! like the popup, in the labels of `MERCY_POPUP_LOCALE`. A reading that
+//! fails or doesn't parse is no reading: the scanner keeps its assumed
+//! position then. So is a reading away from where the map was sent that a
+//! second reading, at another scale, doesn't agree with: one misread digit
+//! would otherwise move the rest of the scan.
+
+use std::io::Cursor;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::browser::GameBrowser;
+use crate::target::{PopupLocale, parse_popup_coords};
+use crate::viewport::Viewport;
+
+/// How long one `tesseract` run may take.
+const OCR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Scale of the crop handed to tesseract.
+const UPSCALE: u32 = 3;
+
+/// Scale of the second reading that must agree with an unexpected first.
+const RECHECK_UPSCALE: u32 = 4;
+
+/// Highest map coordinate; a larger reading is a misread.
+const MAP_MAX: u32 = 1023;
+
+/// (kingdom, x, y) shown in `region` of the game window, if it reads. A
+/// position other than `expected` is only returned when a second reading
+/// agrees.
+pub async fn read(
+    game: &GameBrowser,
+    region: Viewport,
+    locale: Option<PopupLocale>,
+    expected: (u32, u32, u32),
+) -> Result<Option<(u32, u32, u32)>> {
+    let bytes = game
+        .take_screenshot_region(region.left, region.top, region.width(), region.height())
+        .await
+        .context("failed to capture the coordinate display")?;
+    let bytes = Arc::new(bytes);
+    let first = read_at(bytes.clone(), UPSCALE, locale).await?;
+    if first.is_none() || first == Some(expected) {
+        return Ok(first);
+    }
+    let second = read_at(bytes, RECHECK_UPSCALE, locale).await?;
+    if second != first {
+        tracing::debug!("coordinate display read as {first:?}, then as {second:?}: ignored");
+        return Ok(None);
+    }
+    Ok(first)
+}
+
+/// The position in `bytes`, read at `scale`.
+async fn read_at(
+    bytes: Arc<Vec<u8>>,
+    scale: u32,
+    locale: Option<PopupLocale>,
+) -> Result<Option<(u32, u32, u32)>> {
+    let png = tokio::task::spawn_blocking(move || prepare(&bytes, scale)).await??;
+    let text = ocr(&png).await?;
+    let position = parse(&text, locale);
+    if position.is_none() {
+        tracing::debug!("coordinate display unreadable: {:?}", text.trim());
+    }
+    Ok(position)
+}
+
+/// The capture as a grayscale PNG upscaled by `scale`.
+fn prepare(bytes: &[u8], scale: u32) -> Result<Vec<u8>> {
+    let gray = image::load_from_memory(bytes)
+        .context("failed to decode the coordinate display")?
+        .to_luma8();
+    let scaled = image::imageops::resize(
+        &gray,
+        gray.width() * scale,
+        gray.height() * scale,
+        FilterType::CatmullRom,
+    );
+    let mut png = Vec::new();
+    scaled
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("failed to encode the coordinate display")?;
+    Ok(png)
+}
+
+/// Text of `png`, read as a single line.
+async fn ocr(png: &[u8]) -> Result<String> {
+    let mut child = Command::new("tesseract")
+        .args(["stdin", "stdout", "--psm", "7"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run tesseract")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(png).await?;
+    }
+    let output = timeout(OCR_TIMEOUT, child.wait_with_output())
+        .await
+        .context("tesseract timed out")??;
+    anyhow::ensure!(
+        output.status.success(),
+        "tesseract exited with {}",
+        output.status
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The position in OCR `text`, if it has one on the map.
+fn parse(text: &str, locale: Option<PopupLocale>) -> Option<(u32, u32, u32)> {
+    let (k, x, y) = match locale {
+        Some(locale) => locale.coords(text),
+        None => parse_popup_coords(text),
+    }?;
+    (k > 0 && x <= MAP_MAX && y <= MAP_MAX).then_some((k, x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display() {
+        assert_eq!(
+            parse("K:111 X:506 Y:638\n\x0c", None),
+            Some((111, 506, 638))
+        );
+        assert_eq!(
+            parse(" K: 111  X: 506  Y: 638 ", None),
+            Some((111, 506, 638))
+        );
+        let ru = PopupLocale::from_code("ru");
+        assert_eq!(parse("К:111 Х:506 У:638", ru), Some((111, 506, 638)));
+        // Misreads
+        assert_eq!(parse("K:111 X:5066 Y:638", None), None);
+        assert_eq!(parse("K:0 X:506 Y:638", None), None);
+        assert_eq!(parse("K:111 X: Y:638", None), None);
+    }
+}
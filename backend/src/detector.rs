@@ -1,10 +1,19 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use image::imageops::FilterType;
 use image::{DynamicImage, GrayImage, RgbImage};
 use imageproc::gradients::sobel_gradients;
+use imageproc::integral_image::{integral_image, sum_image_pixels};
 use imageproc::template_matching::{MatchTemplateMethod, match_template};
+use serde::{Deserialize, Serialize};
+
+use crate::features::{FeatureDetector, Keypoint, extract_keypoints};
+use crate::target::ProfileManifest;
+use crate::viewport::Viewport;
 
 /// A detected match position in the screenshot (pixel coordinates, at original scale).
 #[derive(Debug, Clone)]
@@ -12,31 +21,522 @@ pub struct TemplateMatch {
     pub x: u32,
     pub y: u32,
     pub score: f32,
+    /// Index into the prepared reference slice of the template that produced this match.
+    pub template: usize,
+    /// Individual channel scores, when every channel was evaluated at this position.
+    pub channels: Option<ChannelScores>,
+    /// Sub-pixel offset of the correlation peak from (`x`, `y`), each in
+    /// [-0.5, 0.5]. Zero when the backend has no refinement.
+    pub subpixel: (f32, f32),
+}
+
+impl TemplateMatch {
+    /// Match centre with the sub-pixel refinement applied.
+    pub fn position(&self) -> (f64, f64) {
+        (
+            self.x as f64 + self.subpixel.0 as f64,
+            self.y as f64 + self.subpixel.1 as f64,
+        )
+    }
+}
+
+/// A grayscale plane the matchers can correlate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    Hue,
+    Sat,
+    Edge,
+}
+
+impl Channel {
+    const ALL: [Channel; 6] = [
+        Channel::R,
+        Channel::G,
+        Channel::B,
+        Channel::Hue,
+        Channel::Sat,
+        Channel::Edge,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Channel::R => "R",
+            Channel::G => "G",
+            Channel::B => "B",
+            Channel::Hue => "Hue",
+            Channel::Sat => "Sat",
+            Channel::Edge => "Edge",
+        }
+    }
+}
+
+/// Colour representation used for matching. Each pass correlates its colour
+/// planes plus the Sobel edge map and combines the scores (by default the
+/// minimum, see [`Aggregation`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// R, G, B and Edge.
+    #[default]
+    Rgb,
+    /// Hue, saturation and Edge. Value is left out: it is exactly what the
+    /// game's day/night tint shifts, while hue and saturation mostly survive.
+    Hsv,
+    /// Run both passes and keep a candidate if either one matches.
+    Both,
+}
+
+impl ColorSpace {
+    /// Channel cascades to run, in order. Cheapest-to-reject channel first.
+    pub(crate) fn passes(self) -> &'static [&'static [Channel]] {
+        const RGB: &[Channel] = &[Channel::R, Channel::G, Channel::B, Channel::Edge];
+        const HSV: &[Channel] = &[Channel::Hue, Channel::Sat, Channel::Edge];
+        match self {
+            ColorSpace::Rgb => &[RGB],
+            ColorSpace::Hsv => &[HSV],
+            ColorSpace::Both => &[RGB, HSV],
+        }
+    }
+}
+
+impl std::str::FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rgb" => Ok(ColorSpace::Rgb),
+            "hsv" => Ok(ColorSpace::Hsv),
+            "both" => Ok(ColorSpace::Both),
+            other => Err(format!("unknown color space: {other}")),
+        }
+    }
+}
+
+/// Per-channel correlation scores at a match position. The aggregate
+/// `TemplateMatch::score` combines these per [`MatchOptions::aggregation`].
+/// Channels that weren't part of the pass that produced the match are `None`.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ChannelScores {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub g: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hue: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sat: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edge: Option<f32>,
+}
+
+impl ChannelScores {
+    fn set(&mut self, channel: Channel, score: f32) {
+        let slot = match channel {
+            Channel::R => &mut self.r,
+            Channel::G => &mut self.g,
+            Channel::B => &mut self.b,
+            Channel::Hue => &mut self.hue,
+            Channel::Sat => &mut self.sat,
+            Channel::Edge => &mut self.edge,
+        };
+        *slot = Some(score);
+    }
+}
+
+/// How a pass combines its per-channel scores into the match score.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The weakest channel decides: every channel has to match.
+    #[default]
+    Min,
+    /// Weighted mean (see [`ChannelWeights`]), so one noisy channel can't
+    /// veto an otherwise good match.
+    Mean,
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "min" => Ok(Aggregation::Min),
+            "mean" => Ok(Aggregation::Mean),
+            other => Err(format!("unknown channel aggregation: {other}")),
+        }
+    }
+}
+
+/// Which channels take part in matching and how much each counts under
+/// [`Aggregation::Mean`]. A channel with weight 0 is left out of every pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelWeights([f32; Channel::ALL.len()]);
+
+impl Default for ChannelWeights {
+    /// Every channel enabled with equal weight.
+    fn default() -> Self {
+        Self([1.0; Channel::ALL.len()])
+    }
+}
+
+impl ChannelWeights {
+    pub fn get(&self, channel: Channel) -> f32 {
+        self.0[channel as usize]
+    }
+}
+
+impl std::str::FromStr for ChannelWeights {
+    type Err = String;
+
+    /// Parse a comma-separated channel list with optional weights, e.g.
+    /// `r,g,b,edge:0.5`. Channels not listed are disabled.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = [0.0; Channel::ALL.len()];
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (name, weight) = match item.split_once(':') {
+                Some((name, weight)) => {
+                    let weight: f32 = weight
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("invalid channel weight: {item}"))?;
+                    (name.trim(), weight)
+                }
+                None => (item, 1.0),
+            };
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("invalid channel weight: {item}"));
+            }
+            let channel = Channel::ALL
+                .into_iter()
+                .find(|c| c.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("unknown channel: {name}"))?;
+            weights[channel as usize] = weight;
+        }
+        if weights.iter().all(|&w| w == 0.0) {
+            return Err(format!("no channels enabled: {s}"));
+        }
+        Ok(Self(weights))
+    }
+}
+
+/// Correlation method used to score a template position.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMethod {
+    /// Normalized cross-correlation.
+    #[default]
+    Ncc,
+    /// Normalized sum of squared errors, reported as `1 - error` so that, as
+    /// with NCC, higher is better and template thresholds keep their meaning.
+    Sse,
+}
+
+impl MatchMethod {
+    fn other(self) -> Self {
+        match self {
+            MatchMethod::Ncc => MatchMethod::Sse,
+            MatchMethod::Sse => MatchMethod::Ncc,
+        }
+    }
+}
+
+impl std::str::FromStr for MatchMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ncc" => Ok(MatchMethod::Ncc),
+            "sse" => Ok(MatchMethod::Sse),
+            other => Err(format!("unknown match method: {other}")),
+        }
+    }
+}
+
+/// One channel cascade as configured: the enabled channels of a
+/// [`ColorSpace`] pass, in order, how each is scored and how the scores
+/// combine.
+#[derive(Debug, Clone)]
+pub(crate) struct Pass {
+    pub(crate) channels: Vec<Channel>,
+    weights: Vec<f32>,
+    aggregation: Aggregation,
+    method: MatchMethod,
+}
+
+impl Pass {
+    /// Score surface of `template` over `image` (higher is better).
+    fn correlate(
+        &self,
+        image: &GrayImage,
+        template: &GrayImage,
+    ) -> image::ImageBuffer<image::Luma<f32>, Vec<f32>> {
+        match self.method {
+            MatchMethod::Ncc => match_template(
+                image,
+                template,
+                MatchTemplateMethod::CrossCorrelationNormalized,
+            ),
+            MatchMethod::Sse => {
+                let mut result = match_template(
+                    image,
+                    template,
+                    MatchTemplateMethod::SumOfSquaredErrorsNormalized,
+                );
+                for p in result.pixels_mut() {
+                    p.0[0] = 1.0 - p.0[0];
+                }
+                result
+            }
+        }
+    }
+
+    /// Score of `template` with its top-left at (`x`, `y`) in `image`; one
+    /// position of [`Pass::correlate`].
+    fn score_pixel(&self, image: &GrayImage, template: &GrayImage, x: u32, y: u32) -> f32 {
+        match self.method {
+            MatchMethod::Ncc => ncc_at(image, template, x, y),
+            MatchMethod::Sse => 1.0 - sse_at(image, template, x, y),
+        }
+    }
+
+    /// The same pass scored with the other [`MatchMethod`], for A/B logging.
+    fn with_other_method(&self) -> Self {
+        Self {
+            method: self.method.other(),
+            ..self.clone()
+        }
+    }
+
+    /// Combine scores for the leading channels of the pass. Channels without
+    /// a score yet count as a perfect 1.0, so a partial result is an upper
+    /// bound on the final score (what the cascades' early exit relies on).
+    pub(crate) fn combine(&self, scores: impl IntoIterator<Item = f32>) -> f32 {
+        let mut scores = scores.into_iter();
+        match self.aggregation {
+            Aggregation::Min => scores.fold(f32::INFINITY, f32::min),
+            Aggregation::Mean => {
+                let total: f32 = self.weights.iter().sum();
+                let sum: f32 = self
+                    .weights
+                    .iter()
+                    .map(|w| w * scores.next().unwrap_or(1.0))
+                    .sum();
+                sum / total
+            }
+        }
+    }
+}
+
+/// A loaded reference image plus its per-template matching metadata.
+#[derive(Clone)]
+pub struct RefImage {
+    /// File name the image was loaded from (used in logs).
+    pub name: String,
+    pub image: Arc<DynamicImage>,
+    /// Minimum per-channel score for this template to count as a match.
+    pub threshold: f32,
+    /// Higher priority templates are matched first and win when their matches
+    /// overlap with a lower-priority template's.
+    pub priority: i32,
+    /// Negative templates describe known look-alikes: a candidate that also
+    /// scores above this template's threshold is rejected.
+    pub negative: bool,
+}
+
+impl RefImage {
+    /// Wrap an image with the default threshold and priority.
+    pub fn new(name: impl Into<String>, image: Arc<DynamicImage>) -> Self {
+        Self {
+            name: name.into(),
+            image,
+            threshold: MATCH_THRESHOLD,
+            priority: 0,
+            negative: false,
+        }
+    }
+}
+
+/// The grayscale planes of an image that the matchers correlate against.
+pub struct Planes {
+    pub rgb: [GrayImage; 3],
+    pub hue: GrayImage,
+    pub sat: GrayImage,
+    /// Sobel edge magnitude of the luminance.
+    pub edge: GrayImage,
+    /// Luminance, used by the coarse pass and the hash prefilter.
+    pub gray: GrayImage,
+}
+
+impl Planes {
+    fn from_image(image: &DynamicImage) -> Self {
+        let rgb = image.to_rgb8();
+        let [hue, sat] = hue_saturation(&rgb);
+        let gray = image.to_luma8();
+        let edge = compute_edges(&gray);
+        Self {
+            rgb: split_channels(&rgb),
+            hue,
+            sat,
+            edge,
+            gray,
+        }
+    }
+
+    pub fn get(&self, channel: Channel) -> &GrayImage {
+        match channel {
+            Channel::R => &self.rgb[0],
+            Channel::G => &self.rgb[1],
+            Channel::B => &self.rgb[2],
+            Channel::Hue => &self.hue,
+            Channel::Sat => &self.sat,
+            Channel::Edge => &self.edge,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.gray.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.gray.height()
+    }
+
+    /// Copy out a sub-rectangle of every plane.
+    fn crop(&self, x: u32, y: u32, w: u32, h: u32) -> Self {
+        let crop = |img: &GrayImage| image::imageops::crop_imm(img, x, y, w, h).to_image();
+        Self {
+            rgb: [crop(&self.rgb[0]), crop(&self.rgb[1]), crop(&self.rgb[2])],
+            hue: crop(&self.hue),
+            sat: crop(&self.sat),
+            edge: crop(&self.edge),
+            gray: crop(&self.gray),
+        }
+    }
 }
 
 /// Pre-computed reference image for template matching.
-/// Stores per-channel grayscale images for color-aware matching,
+/// Stores per-channel grayscale planes for color-aware matching,
 /// plus a Sobel edge channel for structural matching.
 pub struct PreparedRef {
-    pub channels: [GrayImage; 3], // R, G, B
-    pub edge: GrayImage,          // Sobel edge channel
+    pub name: String,
+    pub planes: Planes,
     pub width: u32,
     pub height: u32,
+    pub threshold: f32,
+    pub priority: i32,
+    pub negative: bool,
+    /// 64-bit average hash of the template's luminance (see [`average_hash`]).
+    pub hash: u64,
+    /// Corners and descriptors for the feature-based backend.
+    pub keypoints: Vec<Keypoint>,
+}
+
+/// Tunable options for [`find_matches`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchOptions {
+    /// Perceptual-hash prefilter: only correlate positions whose window hash
+    /// is within this Hamming distance of the template hash. `None` runs the
+    /// full-frame correlation.
+    pub phash_max_distance: Option<u32>,
+    /// Coarse-to-fine matching: run a grayscale pass at 1/N resolution and only
+    /// run full-resolution matching inside the regions it flags.
+    /// `None` (or 1) correlates the full frame at full resolution.
+    pub coarse_factor: Option<u32>,
+    /// Colour planes to correlate (RGB, HSV, or both).
+    pub color_space: ColorSpace,
+    /// ONNX model file for [`MatchBackend::Onnx`].
+    pub onnx_model: Option<std::path::PathBuf>,
+    /// Enabled channels and their weights.
+    pub channel_weights: ChannelWeights,
+    /// How each pass combines its channel scores.
+    pub aggregation: Aggregation,
+    /// Correlation method.
+    pub method: MatchMethod,
+    /// Also score every match (and the calibration best match) with the
+    /// other [`MatchMethod`] and log both scores, for offline comparison.
+    pub ab_log: bool,
+}
+
+impl MatchOptions {
+    /// The colour space's passes with disabled channels removed. Passes left
+    /// without channels are skipped; if that leaves none at all the weights
+    /// are ignored, so matching never silently does nothing.
+    pub(crate) fn passes(&self) -> Vec<Pass> {
+        let build = |channels: &[Channel], weights: &ChannelWeights| {
+            let channels: Vec<Channel> = channels
+                .iter()
+                .copied()
+                .filter(|&c| weights.get(c) > 0.0)
+                .collect();
+            Pass {
+                weights: channels.iter().map(|&c| weights.get(c)).collect(),
+                channels,
+                aggregation: self.aggregation,
+                method: self.method,
+            }
+        };
+        let passes: Vec<Pass> = self
+            .color_space
+            .passes()
+            .iter()
+            .map(|channels| build(channels, &self.channel_weights))
+            .filter(|p| !p.channels.is_empty())
+            .collect();
+        if !passes.is_empty() {
+            return passes;
+        }
+        tracing::warn!(
+            "no enabled channels in the {:?} colour space, using all of them",
+            self.color_space
+        );
+        self.color_space
+            .passes()
+            .iter()
+            .map(|channels| build(channels, &ChannelWeights::default()))
+            .collect()
+    }
+}
+
+/// Which [`Detector`] implementation [`new_detector`] builds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchBackend {
+    /// Rigid NCC template matching over colour and edge planes.
+    #[default]
+    Template,
+    /// Keypoint descriptors + RANSAC (see [`crate::features`]). Tolerates
+    /// slight zoom and perspective changes.
+    Features,
+    /// A trained ONNX object detector (needs the `onnx` cargo feature and
+    /// [`MatchOptions::onnx_model`]).
+    Onnx,
+}
+
+impl std::str::FromStr for MatchBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "template" => Ok(MatchBackend::Template),
+            "features" => Ok(MatchBackend::Features),
+            "onnx" => Ok(MatchBackend::Onnx),
+            other => Err(format!("unknown match backend: {other}")),
+        }
+    }
 }
 
+/// Minimum zero-mean NCC in the coarse pass for a peak to nominate a region.
+/// Deliberately loose: downscaling blurs fine detail, and the fine pass makes
+/// the real decision.
+const COARSE_THRESHOLD: f32 = 0.6;
+
 pub const MATCH_THRESHOLD: f32 = 0.98;
 
 /// Downscale factor for template matching (1 = full size, most accurate).
 /// Using 1 (no downscale) because the reference images are small (~48x36)
 /// and downscaling them further loses too much detail for reliable matching.
-const SCALE_DOWN: u32 = 1;
-
-/// Game viewport bounds (excluding UI: minimap, top bar, bottom toolbar, right panel).
-/// Matches found within the cropped region are offset back to full screenshot coordinates.
-const VIEWPORT_LEFT: u32 = 160;
-const VIEWPORT_TOP: u32 = 60;
-const VIEWPORT_RIGHT: u32 = 1860;
-const VIEWPORT_BOTTOM: u32 = 1000;
+pub const SCALE_DOWN: u32 = 1;
 
 /// Split an RGB image into 3 separate grayscale images (one per channel).
 fn split_channels(rgb: &RgbImage) -> [GrayImage; 3] {
@@ -52,6 +552,33 @@ fn split_channels(rgb: &RgbImage) -> [GrayImage; 3] {
     [r, g, b]
 }
 
+/// Hue and saturation planes of an RGB image, each scaled to 0-255.
+/// Hue is circular, so reds near 0 and 255 correlate poorly; grey pixels get
+/// hue 0 and saturation 0.
+fn hue_saturation(rgb: &RgbImage) -> [GrayImage; 2] {
+    let (w, h) = rgb.dimensions();
+    let mut hue = GrayImage::new(w, h);
+    let mut sat = GrayImage::new(w, h);
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        let [r, g, b] = pixel.0.map(|c| c as f32);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        let degrees = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+        hue.put_pixel(x, y, image::Luma([(degrees / 360.0 * 255.0) as u8]));
+        sat.put_pixel(x, y, image::Luma([(s * 255.0) as u8]));
+    }
+    [hue, sat]
+}
+
 /// Compute Sobel edge magnitude image, normalized to u8.
 fn compute_edges(gray: &GrayImage) -> GrayImage {
     let grad = sobel_gradients(gray);
@@ -66,437 +593,2586 @@ fn compute_edges(gray: &GrayImage) -> GrayImage {
     edges
 }
 
-/// Pre-compute reference images for matching.
-/// Call once at startup; the results are reused for every scan step.
-pub fn prepare_reference_images(ref_images: &[Arc<DynamicImage>]) -> Vec<PreparedRef> {
-    ref_images
-        .iter()
-        .filter_map(|img| {
-            let ref_small_w = img.width() / SCALE_DOWN;
-            let ref_small_h = img.height() / SCALE_DOWN;
+/// A rectangle in full-screenshot pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
 
-            if ref_small_w < 10 || ref_small_h < 10 {
-                tracing::warn!("reference image too small after downscale, skipping");
-                return None;
-            }
+impl Roi {
+    /// The rectangle extending `half_w`/`half_h` pixels either side of a centre.
+    pub fn around(center_x: u32, center_y: u32, half_w: u32, half_h: u32) -> Self {
+        let x = center_x.saturating_sub(half_w);
+        let y = center_y.saturating_sub(half_h);
+        Self {
+            x,
+            y,
+            width: center_x + half_w - x,
+            height: center_y + half_h - y,
+        }
+    }
+}
 
-            let ref_small = img.resize_exact(ref_small_w, ref_small_h, FilterType::Triangle);
-            let rgb = ref_small.to_rgb8();
-            let channels = split_channels(&rgb);
-            let gray = ref_small.to_luma8();
-            let edge = compute_edges(&gray);
-            Some(PreparedRef {
-                width: ref_small_w,
-                height: ref_small_h,
-                channels,
-                edge,
-            })
-        })
-        .collect()
+/// A screenshot cropped to the game viewport and split into the planes the
+/// matchers correlate against. Build once per screenshot and pass it to every
+/// detector call so the crop, channel split and Sobel pass aren't repeated.
+/// Matches found within the cropped region are offset back to full screenshot
+/// coordinates using `viewport`.
+pub struct PreparedScreenshot {
+    pub planes: Planes,
+    pub viewport: Viewport,
+    /// Hash of the encoded bytes this was decoded from, if any. Lets
+    /// [`CachingDetector`] recognise a repeated frame.
+    pub source_hash: Option<u64>,
 }
 
-/// Find all locations in the screenshot that match any of the reference images
-/// above the confidence threshold.
-/// Only searches within the game viewport area (excluding UI elements).
-pub fn find_matches(
-    screenshot: &DynamicImage,
-    ref_images: &[PreparedRef],
-) -> Result<Vec<TemplateMatch>> {
-    // Crop to game viewport to avoid matching on minimap/UI icons
-    let viewport = screenshot.crop_imm(
-        VIEWPORT_LEFT,
-        VIEWPORT_TOP,
-        VIEWPORT_RIGHT - VIEWPORT_LEFT,
-        VIEWPORT_BOTTOM - VIEWPORT_TOP,
-    );
+impl PreparedScreenshot {
+    pub fn new(screenshot: &DynamicImage, viewport: Viewport) -> Self {
+        // Crop to game viewport to avoid matching on minimap/UI icons
+        let viewport = viewport.clamped(screenshot.width(), screenshot.height());
+        let cropped = screenshot.crop_imm(
+            viewport.left,
+            viewport.top,
+            viewport.width(),
+            viewport.height(),
+        );
+        Self::from_cropped(&cropped, viewport)
+    }
 
-    // Downscale for faster matching
-    let small_w = viewport.width() / SCALE_DOWN;
-    let small_h = viewport.height() / SCALE_DOWN;
-    let screenshot_small = viewport.resize_exact(small_w, small_h, FilterType::Triangle);
-    let screenshot_rgb = screenshot_small.to_rgb8();
-    let screenshot_channels = split_channels(&screenshot_rgb);
-    let screenshot_gray = screenshot_small.to_luma8();
-    let screenshot_edge = compute_edges(&screenshot_gray);
+    /// Prepare an image that already covers exactly `viewport`, e.g. a CDP
+    /// capture clipped to the game viewport. The viewport still maps matches
+    /// back to full-screenshot coordinates.
+    pub fn from_cropped(cropped: &DynamicImage, viewport: Viewport) -> Self {
+        // Downscale for faster matching
+        let small_w = cropped.width() / SCALE_DOWN;
+        let small_h = cropped.height() / SCALE_DOWN;
+        let small = cropped.resize_exact(small_w, small_h, FilterType::Triangle);
+        Self {
+            planes: Planes::from_image(&small),
+            viewport: Viewport {
+                right: viewport.left + cropped.width(),
+                bottom: viewport.top + cropped.height(),
+                ..viewport
+            },
+            source_hash: None,
+        }
+    }
 
-    let mut all_matches = Vec::new();
+    /// Decode an encoded screenshot (PNG from CDP) and prepare it.
+    pub fn from_bytes(bytes: &[u8], viewport: Viewport) -> Result<Self> {
+        let image = image::load_from_memory(bytes).context("failed to decode screenshot")?;
+        Ok(Self {
+            source_hash: Some(hash_bytes(bytes)),
+            ..Self::new(&image, viewport)
+        })
+    }
 
-    for prepared in ref_images {
-        // Skip if reference is larger than screenshot
-        if prepared.width >= screenshot_rgb.width() || prepared.height >= screenshot_rgb.height() {
-            tracing::warn!(
-                "reference image {}x{} is too large for screenshot {}x{}, skipping",
-                prepared.width,
-                prepared.height,
-                screenshot_rgb.width(),
-                screenshot_rgb.height()
-            );
-            continue;
+    /// Decode a capture clipped to `viewport` and prepare it without cropping.
+    pub fn from_viewport_bytes(bytes: &[u8], viewport: Viewport) -> Result<Self> {
+        let image = image::load_from_memory(bytes).context("failed to decode screenshot")?;
+        Ok(Self {
+            source_hash: Some(hash_bytes(bytes)),
+            ..Self::from_cropped(&image, viewport)
+        })
+    }
+
+    /// Restrict detection to `roi` (clamped to the viewport), e.g. a window
+    /// around the spot a known building should be. Copies only that part of
+    /// the planes; matches are still reported in full-screenshot coordinates.
+    pub fn region(&self, roi: Roi) -> Self {
+        let (x0, y0) = self.to_planes(roi.x, roi.y);
+        let (x1, y1) = self.to_planes(roi.x + roi.width, roi.y + roi.height);
+        let (x0, y0) = (x0.min(self.width()), y0.min(self.height()));
+        let (w, h) = (x1.min(self.width()) - x0, y1.min(self.height()) - y0);
+        let (left, top) = self.to_screen(x0, y0);
+        Self {
+            planes: self.planes.crop(x0, y0, w, h),
+            viewport: Viewport {
+                left,
+                top,
+                right: left + w * SCALE_DOWN,
+                bottom: top + h * SCALE_DOWN,
+            },
+            source_hash: self.source_hash,
         }
+    }
 
-        tracing::debug!(
-            "matching {}x{} template against {}x{} screenshot (RGBE 4-channel)",
-            prepared.width,
-            prepared.height,
-            screenshot_rgb.width(),
-            screenshot_rgb.height()
-        );
+    /// Map full-screenshot coordinates into the downscaled viewport.
+    /// Positions left of or above the viewport clamp to 0.
+    pub fn to_planes(&self, x: u32, y: u32) -> (u32, u32) {
+        (
+            x.saturating_sub(self.viewport.left) / SCALE_DOWN,
+            y.saturating_sub(self.viewport.top) / SCALE_DOWN,
+        )
+    }
 
-        let matches = find_template_matches_rgbe(
-            &screenshot_channels,
-            &screenshot_edge,
-            &prepared.channels,
-            &prepared.edge,
-            prepared.width,
-            prepared.height,
-        )?;
-
-        // Scale match coordinates back to original size and offset to full screenshot
-        let scaled: Vec<TemplateMatch> = matches
-            .into_iter()
-            .map(|m| TemplateMatch {
-                x: m.x * SCALE_DOWN + VIEWPORT_LEFT,
-                y: m.y * SCALE_DOWN + VIEWPORT_TOP,
-                score: m.score,
-            })
-            .collect();
+    /// Map downscaled viewport coordinates back to the full screenshot.
+    pub fn to_screen(&self, x: u32, y: u32) -> (u32, u32) {
+        (
+            x * SCALE_DOWN + self.viewport.left,
+            y * SCALE_DOWN + self.viewport.top,
+        )
+    }
 
-        all_matches.extend(scaled);
+    pub fn width(&self) -> u32 {
+        self.planes.width()
     }
 
-    // Deduplicate nearby matches (within 40px at original scale)
-    let deduped = deduplicate_matches(&mut all_matches, 40);
+    pub fn height(&self) -> u32 {
+        self.planes.height()
+    }
+}
 
-    Ok(deduped)
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
 }
 
-/// Run template matching on 4 channels (R, G, B, Edge) with cascading early exit.
-/// Runs channels sequentially; if no pixel exceeds the threshold after a channel,
-/// skips remaining channels (~4x speedup for the common "no match" case).
-fn find_template_matches_rgbe(
-    screenshot_channels: &[GrayImage; 3],
-    screenshot_edge: &GrayImage,
-    template_channels: &[GrayImage; 3],
-    template_edge: &GrayImage,
-    template_w: u32,
-    template_h: u32,
-) -> Result<Vec<TemplateMatch>> {
-    let channel_names = ["R", "G", "B", "Edge"];
+/// A detection backend. The scanner and API only go through this trait, so
+/// alternative matchers can be developed and benchmarked side by side.
+pub trait Detector: Send + Sync {
+    /// Prepared templates; `TemplateMatch::template` indexes into this.
+    fn refs(&self) -> &[PreparedRef];
 
-    // Channel 0: R — collect all candidates above threshold
-    let r_result = match_template(
-        &screenshot_channels[0],
-        &template_channels[0],
-        MatchTemplateMethod::CrossCorrelationNormalized,
-    );
-    let (w, h) = r_result.dimensions();
+    /// All matches in the screenshot, in full-screenshot pixel coordinates.
+    fn find_matches(&self, screenshot: &PreparedScreenshot) -> Result<Vec<TemplateMatch>>;
 
-    let mut candidates: Vec<(u32, u32, f32)> = Vec::new();
-    let mut best_score: f32 = 0.0;
-    for y in 0..h {
-        for x in 0..w {
-            let score = r_result.get_pixel(x, y).0[0];
-            if score > best_score {
-                best_score = score;
-            }
-            if score >= MATCH_THRESHOLD {
-                candidates.push((x, y, score));
-            }
-        }
+    /// The single best candidate regardless of threshold (for calibration
+    /// and verification).
+    fn find_best_match(&self, screenshot: &PreparedScreenshot) -> Option<TemplateMatch>;
+
+    /// [`Detector::find_matches`], plus the strongest candidate scoring at
+    /// least `floor` when none reach the threshold (a near miss, for
+    /// `MERCY_RELAX_AFTER_PASSES`). Backends without a cutoff to lower fall
+    /// back to [`Detector::find_best_match`].
+    fn find_matches_or_near_miss(
+        &self,
+        screenshot: &PreparedScreenshot,
+        floor: f32,
+    ) -> Result<(Vec<TemplateMatch>, Option<TemplateMatch>)> {
+        let matches = self.find_matches(screenshot)?;
+        let near_miss = if matches.is_empty() {
+            self.find_best_match(screenshot)
+                .filter(|m| m.score >= floor)
+        } else {
+            None
+        };
+        Ok((matches, near_miss))
     }
 
-    if candidates.is_empty() {
-        tracing::info!(
-            "template {}x{}: early-exit after R (best={:.4}, 0 candidates)",
-            template_w,
-            template_h,
-            best_score
-        );
-        return Ok(Vec::new());
+    /// The `n` strongest distinct candidates regardless of threshold (for
+    /// threshold tuning). Backends without a candidate ranking just return
+    /// the best match.
+    fn find_top_matches(&self, screenshot: &PreparedScreenshot, n: usize) -> Vec<TemplateMatch> {
+        self.find_best_match(screenshot)
+            .into_iter()
+            .take(n)
+            .collect()
     }
 
-    tracing::info!(
-        "template {}x{}: R pass: {} candidates (best={:.4})",
-        template_w,
-        template_h,
-        candidates.len(),
-        best_score
-    );
+    /// [`Detector::find_matches`] over several screenshots, prepared and
+    /// matched in parallel. Results are in input order.
+    fn find_matches_batch(
+        &self,
+        screenshots: &[DynamicImage],
+        viewport: Viewport,
+    ) -> Vec<Result<Vec<TemplateMatch>>> {
+        parallel_map(screenshots, |image| {
+            self.find_matches(&PreparedScreenshot::new(image, viewport))
+        })
+    }
 
-    // Channels 1-3: G, B, Edge — filter candidates, early-exit if none survive
-    let channel_sources: [(Option<usize>, bool); 3] = [
-        (Some(1), false), // G channel
-        (Some(2), false), // B channel
-        (None, true),     // Edge channel
-    ];
+    /// The score map rendered over the viewport crop (see [`score_heatmap`]).
+    /// `None` for backends without a per-position score.
+    fn score_heatmap(&self, _screenshot: &PreparedScreenshot) -> Option<RgbImage> {
+        None
+    }
+}
 
-    for (ch_idx, &(rgb_idx, is_edge)) in channel_sources.iter().enumerate() {
-        let result = if is_edge {
-            match_template(
-                screenshot_edge,
-                template_edge,
-                MatchTemplateMethod::CrossCorrelationNormalized,
-            )
-        } else {
-            let i = rgb_idx.unwrap();
-            match_template(
-                &screenshot_channels[i],
-                &template_channels[i],
-                MatchTemplateMethod::CrossCorrelationNormalized,
-            )
-        };
+/// The default backend: NCC template matching over colour and edge planes.
+pub struct TemplateDetector {
+    refs: Vec<PreparedRef>,
+    opts: MatchOptions,
+}
 
-        best_score = 0.0;
-        for cand in &mut candidates {
-            let ch_score = result.get_pixel(cand.0, cand.1).0[0];
-            cand.2 = cand.2.min(ch_score); // min across all channels so far
-            if cand.2 > best_score {
-                best_score = cand.2;
-            }
-        }
+impl TemplateDetector {
+    pub fn new(refs: Vec<PreparedRef>, opts: MatchOptions) -> Self {
+        Self { refs, opts }
+    }
+}
 
-        candidates.retain(|c| c.2 >= MATCH_THRESHOLD);
+impl Detector for TemplateDetector {
+    fn refs(&self) -> &[PreparedRef] {
+        &self.refs
+    }
 
-        let ch_name = channel_names[ch_idx + 1];
-        if candidates.is_empty() {
-            tracing::info!(
-                "template {}x{}: early-exit after {} (best={:.4}, 0 candidates)",
-                template_w,
-                template_h,
-                ch_name,
-                best_score
-            );
-            return Ok(Vec::new());
-        }
+    fn find_matches(&self, screenshot: &PreparedScreenshot) -> Result<Vec<TemplateMatch>> {
+        find_matches(screenshot, &self.refs, &self.opts)
+    }
 
-        tracing::info!(
-            "template {}x{}: {} pass: {} candidates (best={:.4})",
-            template_w,
-            template_h,
-            ch_name,
-            candidates.len(),
-            best_score
-        );
+    fn find_matches_or_near_miss(
+        &self,
+        screenshot: &PreparedScreenshot,
+        floor: f32,
+    ) -> Result<(Vec<TemplateMatch>, Option<TemplateMatch>)> {
+        find_matches_or_near_miss(screenshot, &self.refs, &self.opts, floor)
     }
 
-    let mut matches: Vec<TemplateMatch> = candidates
-        .into_iter()
-        .map(|(x, y, score)| TemplateMatch {
-            x: x + template_w / 2,
-            y: y + template_h / 2,
-            score,
-        })
-        .collect();
+    fn find_best_match(&self, screenshot: &PreparedScreenshot) -> Option<TemplateMatch> {
+        find_best_match(screenshot, &self.refs, &self.opts)
+    }
 
-    tracing::info!(
-        "template {}x{}: best_score={:.4}, {} raw matches above {:.2} (RGBE 4-channel)",
-        template_w,
-        template_h,
-        best_score,
-        matches.len(),
-        MATCH_THRESHOLD
+    fn find_top_matches(&self, screenshot: &PreparedScreenshot, n: usize) -> Vec<TemplateMatch> {
+        find_top_matches(screenshot, &self.refs, n, &self.opts)
+    }
+
+    fn find_matches_batch(
+        &self,
+        screenshots: &[DynamicImage],
+        viewport: Viewport,
+    ) -> Vec<Result<Vec<TemplateMatch>>> {
+        find_matches_batch(screenshots, &self.refs, &self.opts, viewport)
+    }
+
+    fn score_heatmap(&self, screenshot: &PreparedScreenshot) -> Option<RgbImage> {
+        Some(score_heatmap(screenshot, &self.refs, &self.opts))
+    }
+}
+
+/// Build the configured detection backend around a set of prepared templates.
+pub fn new_detector(
+    backend: MatchBackend,
+    refs: Vec<PreparedRef>,
+    opts: MatchOptions,
+) -> Arc<dyn Detector> {
+    match backend {
+        MatchBackend::Template => Arc::new(TemplateDetector::new(refs, opts)),
+        MatchBackend::Features => Arc::new(FeatureDetector::new(refs, opts)),
+        MatchBackend::Onnx => new_onnx_detector(refs, opts),
+    }
+}
+
+/// The ONNX backend, falling back to template matching if the model can't
+/// be loaded (or support isn't compiled in) so the scanner keeps working.
+#[cfg(feature = "onnx")]
+fn new_onnx_detector(refs: Vec<PreparedRef>, opts: MatchOptions) -> Arc<dyn Detector> {
+    let Some(path) = opts.onnx_model.clone() else {
+        tracing::error!("MERCY_DETECTOR=onnx needs MERCY_ONNX_MODEL, using template matching");
+        return Arc::new(TemplateDetector::new(refs, opts));
+    };
+    match crate::onnx::load_model(&path) {
+        Ok(model) => Arc::new(crate::onnx::OnnxDetector::new(model, refs, opts)),
+        Err(e) => {
+            tracing::error!("{e:#}, using template matching");
+            Arc::new(TemplateDetector::new(refs, opts))
+        }
+    }
+}
+
+#[cfg(not(feature = "onnx"))]
+fn new_onnx_detector(refs: Vec<PreparedRef>, opts: MatchOptions) -> Arc<dyn Detector> {
+    let model = opts
+        .onnx_model
+        .as_deref()
+        .unwrap_or(std::path::Path::new("(none)"));
+    tracing::error!(
+        "built without the `onnx` feature, ignoring model {} and using template matching",
+        model.display()
     );
+    Arc::new(TemplateDetector::new(refs, opts))
+}
 
-    matches.sort_by(|a, b| {
-        b.score
-            .partial_cmp(&a.score)
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
+/// How long detection results for an identical screenshot are reused.
+const DETECTION_CACHE_TTL: Duration = Duration::from_secs(30);
 
-    Ok(matches)
+/// Cached screenshots kept at most; scan frames are all distinct, so this
+/// bounds the cache during a scan.
+const DETECTION_CACHE_CAPACITY: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum CachedQuery {
+    Matches,
+    Best,
+    Top(usize),
 }
 
-/// Find the single best match regardless of threshold (for calibration).
-/// Uses cascading channels: runs R first, tracks best position, then refines
-/// with G/B/Edge. Skips remaining channels if best R score < threshold
-/// (but still returns the best R-only score for diagnostic output).
-pub fn find_best_match(
-    screenshot: &DynamicImage,
+type CacheKey = (u64, Viewport, CachedQuery);
+
+/// Wraps a detector and reuses its results for screenshots decoded from
+/// identical bytes within [`DETECTION_CACHE_TTL`], so repeated `/detect`
+/// calls on the same frame don't redo the correlations. Screenshots without
+/// a [`PreparedScreenshot::source_hash`] always go to the inner detector.
+///
+/// The cache belongs to one detector instance; swapping in a new template
+/// set starts with an empty cache.
+pub struct CachingDetector {
+    inner: Arc<dyn Detector>,
+    cache: Mutex<HashMap<CacheKey, (Instant, Vec<TemplateMatch>)>>,
+}
+
+impl CachingDetector {
+    pub fn new(inner: Arc<dyn Detector>) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lookup(
+        &self,
+        screenshot: &PreparedScreenshot,
+        query: CachedQuery,
+    ) -> Option<Vec<TemplateMatch>> {
+        let key = (screenshot.source_hash?, screenshot.viewport, query);
+        // Entries are inserted whole, so a poisoned map is still consistent
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let (at, matches) = cache.get(&key)?;
+        (at.elapsed() < DETECTION_CACHE_TTL).then(|| {
+            tracing::debug!("detection cache hit ({} matches)", matches.len());
+            matches.clone()
+        })
+    }
+
+    fn store(
+        &self,
+        screenshot: &PreparedScreenshot,
+        query: CachedQuery,
+        matches: &[TemplateMatch],
+    ) {
+        let Some(hash) = screenshot.source_hash else {
+            return;
+        };
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (at, _)| at.elapsed() < DETECTION_CACHE_TTL);
+        if cache.len() >= DETECTION_CACHE_CAPACITY
+            && let Some(oldest) = cache.iter().min_by_key(|(_, (at, _))| *at).map(|(k, _)| *k)
+        {
+            cache.remove(&oldest);
+        }
+        cache.insert(
+            (hash, screenshot.viewport, query),
+            (Instant::now(), matches.to_vec()),
+        );
+    }
+}
+
+impl Detector for CachingDetector {
+    fn refs(&self) -> &[PreparedRef] {
+        self.inner.refs()
+    }
+
+    fn find_matches(&self, screenshot: &PreparedScreenshot) -> Result<Vec<TemplateMatch>> {
+        if let Some(matches) = self.lookup(screenshot, CachedQuery::Matches) {
+            return Ok(matches);
+        }
+        let matches = self.inner.find_matches(screenshot)?;
+        self.store(screenshot, CachedQuery::Matches, &matches);
+        Ok(matches)
+    }
+
+    fn find_matches_or_near_miss(
+        &self,
+        screenshot: &PreparedScreenshot,
+        floor: f32,
+    ) -> Result<(Vec<TemplateMatch>, Option<TemplateMatch>)> {
+        let (matches, near_miss) = self.inner.find_matches_or_near_miss(screenshot, floor)?;
+        self.store(screenshot, CachedQuery::Matches, &matches);
+        Ok((matches, near_miss))
+    }
+
+    fn find_best_match(&self, screenshot: &PreparedScreenshot) -> Option<TemplateMatch> {
+        if let Some(best) = self.lookup(screenshot, CachedQuery::Best) {
+            return best.into_iter().next();
+        }
+        let best = self.inner.find_best_match(screenshot);
+        self.store(screenshot, CachedQuery::Best, best.as_slice());
+        best
+    }
+
+    fn find_top_matches(&self, screenshot: &PreparedScreenshot, n: usize) -> Vec<TemplateMatch> {
+        if let Some(top) = self.lookup(screenshot, CachedQuery::Top(n)) {
+            return top;
+        }
+        let top = self.inner.find_top_matches(screenshot, n);
+        self.store(screenshot, CachedQuery::Top(n), &top);
+        top
+    }
+
+    fn find_matches_batch(
+        &self,
+        screenshots: &[DynamicImage],
+        viewport: Viewport,
+    ) -> Vec<Result<Vec<TemplateMatch>>> {
+        self.inner.find_matches_batch(screenshots, viewport)
+    }
+
+    fn score_heatmap(&self, screenshot: &PreparedScreenshot) -> Option<RgbImage> {
+        self.inner.score_heatmap(screenshot)
+    }
+}
+
+/// Pre-compute reference images for matching.
+/// Call once at startup; the results are reused for every scan step.
+/// The returned templates are ordered by descending priority.
+pub fn prepare_reference_images(ref_images: &[RefImage]) -> Vec<PreparedRef> {
+    let mut prepared: Vec<PreparedRef> = ref_images
+        .iter()
+        .filter_map(|r| {
+            let img = &r.image;
+            let ref_small_w = img.width() / SCALE_DOWN;
+            let ref_small_h = img.height() / SCALE_DOWN;
+
+            if ref_small_w < 10 || ref_small_h < 10 {
+                tracing::warn!(
+                    "reference image {} too small after downscale, skipping",
+                    r.name
+                );
+                return None;
+            }
+
+            let ref_small = img.resize_exact(ref_small_w, ref_small_h, FilterType::Triangle);
+            let planes = Planes::from_image(&ref_small);
+            let hash = average_hash(
+                &integral_image(&planes.gray),
+                0,
+                0,
+                ref_small_w,
+                ref_small_h,
+            );
+            let keypoints = extract_keypoints(&planes.gray);
+            Some(PreparedRef {
+                name: r.name.clone(),
+                width: ref_small_w,
+                height: ref_small_h,
+                planes,
+                threshold: r.threshold,
+                priority: r.priority,
+                negative: r.negative,
+                hash,
+                keypoints,
+            })
+        })
+        .collect();
+
+    // Stable sort keeps manifest order among templates of equal priority
+    prepared.sort_by_key(|p| std::cmp::Reverse(p.priority));
+    prepared
+}
+
+/// Find all locations in the screenshot that match any of the reference images
+/// above the confidence threshold.
+/// Only searches within the game viewport area (excluding UI elements).
+/// Candidates that also match a negative template are dropped.
+pub fn find_matches(
+    screenshot: &PreparedScreenshot,
     ref_images: &[PreparedRef],
-) -> Option<TemplateMatch> {
-    let viewport = screenshot.crop_imm(
-        VIEWPORT_LEFT,
-        VIEWPORT_TOP,
-        VIEWPORT_RIGHT - VIEWPORT_LEFT,
-        VIEWPORT_BOTTOM - VIEWPORT_TOP,
-    );
+    opts: &MatchOptions,
+) -> Result<Vec<TemplateMatch>> {
+    find_matches_above(screenshot, ref_images, opts, None).map(|(matches, _)| matches)
+}
+
+/// [`find_matches`] with each template's cutoff lowered to `floor`, so the
+/// same pass also yields the strongest candidate short of the threshold.
+/// That near miss is only returned when nothing reached the threshold.
+pub fn find_matches_or_near_miss(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    opts: &MatchOptions,
+    floor: f32,
+) -> Result<(Vec<TemplateMatch>, Option<TemplateMatch>)> {
+    let (matches, near_miss) = find_matches_above(screenshot, ref_images, opts, Some(floor))?;
+    let near_miss = near_miss.filter(|_| matches.is_empty());
+    Ok((matches, near_miss))
+}
 
-    let small_w = viewport.width() / SCALE_DOWN;
-    let small_h = viewport.height() / SCALE_DOWN;
-    let screenshot_small = viewport.resize_exact(small_w, small_h, FilterType::Triangle);
-    let screenshot_rgb = screenshot_small.to_rgb8();
-    let screenshot_channels = split_channels(&screenshot_rgb);
-    let screenshot_gray = screenshot_small.to_luma8();
-    let screenshot_edge = compute_edges(&screenshot_gray);
+/// The matching pass behind [`find_matches`] and
+/// [`find_matches_or_near_miss`]: candidates between `floor` and a
+/// template's threshold are left out of the matches, and the strongest of
+/// them returned beside.
+fn find_matches_above(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    opts: &MatchOptions,
+    floor: Option<f32>,
+) -> Result<(Vec<TemplateMatch>, Option<TemplateMatch>)> {
+    let planes = &screenshot.planes;
+    let screenshot_integral = opts
+        .phash_max_distance
+        .map(|_| integral_image::<_, u32>(&planes.gray));
 
-    let mut best: Option<TemplateMatch> = None;
+    let passes = opts.passes();
+    let mut all_matches = Vec::new();
+    let mut near_miss: Option<TemplateMatch> = None;
+    for (template_idx, prepared) in ref_images.iter().enumerate() {
+        if prepared.negative {
+            continue;
+        }
+        let cutoff = floor.map_or(prepared.threshold, |f| f.min(prepared.threshold));
 
-    for prepared in ref_images {
-        if prepared.width >= screenshot_rgb.width() || prepared.height >= screenshot_rgb.height() {
+        // Skip if reference is larger than screenshot
+        if prepared.width >= screenshot.width() || prepared.height >= screenshot.height() {
+            tracing::warn!(
+                "reference image {}x{} is too large for screenshot {}x{}, skipping",
+                prepared.width,
+                prepared.height,
+                screenshot.width(),
+                screenshot.height()
+            );
             continue;
         }
 
-        // Channel 0: R — find best position
-        let r_result = match_template(
-            &screenshot_channels[0],
-            &prepared.channels[0],
-            MatchTemplateMethod::CrossCorrelationNormalized,
-        );
-        let (w, h) = r_result.dimensions();
-
-        let mut best_r_x = 0u32;
-        let mut best_r_y = 0u32;
-        let mut best_r_score: f32 = f32::NEG_INFINITY;
-        for y in 0..h {
-            for x in 0..w {
-                let score = r_result.get_pixel(x, y).0[0];
-                if score > best_r_score {
-                    best_r_score = score;
-                    best_r_x = x;
-                    best_r_y = y;
+        for pass in &passes {
+            let channels = &pass.channels;
+            tracing::debug!(
+                "matching {} ({}x{}) against {}x{} screenshot ({})",
+                prepared.name,
+                prepared.width,
+                prepared.height,
+                screenshot.width(),
+                screenshot.height(),
+                channel_list(channels)
+            );
+
+            // The hash prefilter wins over coarse-to-fine when both are set
+            // (`--check-config` warns about it)
+            let coarse_factor = opts.coarse_factor.filter(|&f| f > 1);
+            let hashed = opts.phash_max_distance.zip(screenshot_integral.as_ref());
+            let matches = match (hashed, coarse_factor) {
+                (Some((max_distance, integral)), _) => find_template_matches_hashed(
+                    planes,
+                    integral,
+                    prepared,
+                    template_idx,
+                    pass,
+                    cutoff,
+                    max_distance,
+                ),
+                (None, Some(factor)) => find_template_matches_coarse(
+                    planes,
+                    prepared,
+                    template_idx,
+                    pass,
+                    cutoff,
+                    factor,
+                )?,
+                (None, None) => {
+                    find_template_matches_cascade(planes, prepared, template_idx, pass, cutoff)?
                 }
+            };
+
+            let (mut kept, short): (Vec<_>, Vec<_>) =
+                reject_negatives_and_offset(screenshot, ref_images, matches, pass)
+                    .into_iter()
+                    .partition(|m| m.score >= prepared.threshold);
+            if let Some(best) = short.into_iter().max_by(|a, b| a.score.total_cmp(&b.score))
+                && near_miss.as_ref().is_none_or(|n| best.score > n.score)
+            {
+                near_miss = Some(best);
+            }
+            for m in &mut kept {
+                refine_subpixel(screenshot, prepared, pass, m);
             }
+            if opts.ab_log {
+                log_ab_scores(screenshot, prepared, pass, &kept);
+            }
+            all_matches.extend(kept);
         }
+    }
 
-        if best_r_score < MATCH_THRESHOLD {
-            // No point running more channels; return R-only score for diagnostics
-            tracing::info!(
-                "find_best_match: early-exit after R (best={:.4})",
-                best_r_score
-            );
-            let dominated = best.as_ref().is_some_and(|b| best_r_score <= b.score);
-            if !dominated {
-                best = Some(TemplateMatch {
-                    x: (best_r_x + prepared.width / 2) * SCALE_DOWN + VIEWPORT_LEFT,
-                    y: (best_r_y + prepared.height / 2) * SCALE_DOWN + VIEWPORT_TOP,
-                    score: best_r_score,
-                });
+    // Collapse each building's score plateau to one detection
+    let matches = non_max_suppression(&all_matches, ref_images, NMS_IOU_THRESHOLD);
+    Ok((matches, near_miss))
+}
+
+/// [`find_matches`] over several screenshots. Templates are prepared once by
+/// the caller and shared; screenshots are prepared and matched in parallel,
+/// one at a time per worker so only a few sets of planes are alive at once.
+/// Results are in input order.
+pub fn find_matches_batch(
+    screenshots: &[DynamicImage],
+    ref_images: &[PreparedRef],
+    opts: &MatchOptions,
+    viewport: Viewport,
+) -> Vec<Result<Vec<TemplateMatch>>> {
+    parallel_map(screenshots, |image| {
+        find_matches(&PreparedScreenshot::new(image, viewport), ref_images, opts)
+    })
+}
+
+/// Apply `f` to every item on a pool of scoped worker threads (one per CPU),
+/// keeping results in input order.
+fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let mut indexed: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break done;
+                        };
+                        done.push((i, f(item)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            // A panicking worker re-raises its panic here, like a sequential loop would
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    indexed.sort_by_key(|(i, _)| *i);
+    indexed.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Drop candidates that also match a negative template, then scale match
+/// coordinates back to original size and offset them to the full screenshot.
+/// `matches` are in downscaled viewport coordinates.
+pub(crate) fn reject_negatives_and_offset(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    matches: Vec<TemplateMatch>,
+    pass: &Pass,
+) -> Vec<TemplateMatch> {
+    let negatives: Vec<&PreparedRef> = ref_images.iter().filter(|r| r.negative).collect();
+    matches
+        .into_iter()
+        .filter(|m| {
+            let rejected = negatives.iter().find_map(|neg| {
+                let score = negative_score(&screenshot.planes, neg, pass, m.x, m.y);
+                (score >= neg.threshold).then_some((neg, score))
+            });
+            if let Some((neg, score)) = rejected {
+                let (x, y) = screenshot.to_screen(m.x, m.y);
+                tracing::info!(
+                    "rejecting candidate at ({x}, {y}) score={:.4}: matches negative template {} (score={score:.4})",
+                    m.score,
+                    neg.name,
+                );
             }
+            rejected.is_none()
+        })
+        .map(|m| {
+            let (x, y) = screenshot.to_screen(m.x, m.y);
+            TemplateMatch { x, y, ..m }
+        })
+        .collect()
+}
+
+/// Offset of a correlation peak from a parabola through it and its two
+/// neighbours on one axis, clamped to ±0.5. Zero unless `peak` is a strict
+/// local maximum of the fit.
+fn parabolic_offset(before: f32, peak: f32, after: f32) -> f32 {
+    let curvature = before - 2.0 * peak + after;
+    if curvature >= 0.0 {
+        return 0.0;
+    }
+    ((before - after) / (2.0 * curvature)).clamp(-0.5, 0.5)
+}
+
+/// Combined NCC over the pass's channels with the template's top-left at
+/// (`x`, `y`) in downscaled viewport coordinates; `None` if the template
+/// doesn't fit there.
+fn score_at(planes: &Planes, template: &PreparedRef, pass: &Pass, x: i64, y: i64) -> Option<f32> {
+    let fits = x >= 0
+        && y >= 0
+        && x + template.width as i64 <= planes.width() as i64
+        && y + template.height as i64 <= planes.height() as i64;
+    fits.then(|| {
+        pass.combine(pass.channels.iter().map(|&ch| {
+            pass.score_pixel(planes.get(ch), template.planes.get(ch), x as u32, y as u32)
+        }))
+    })
+}
+
+/// Fit a quadratic through the score peak and its horizontal and vertical
+/// neighbours to locate `m` (in full-screenshot coordinates) to sub-pixel
+/// precision. Rounding to the integer peak is worth up to half a pixel,
+/// which on the Y axis (28 px per game unit, plus tilt) is enough to land a
+/// click on the neighbouring tile.
+fn refine_subpixel(
+    screenshot: &PreparedScreenshot,
+    template: &PreparedRef,
+    pass: &Pass,
+    m: &mut TemplateMatch,
+) {
+    let planes = &screenshot.planes;
+    let (cx, cy) = screenshot.to_planes(m.x, m.y);
+    let x = cx as i64 - (template.width / 2) as i64;
+    let y = cy as i64 - (template.height / 2) as i64;
+    let score = |dx: i64, dy: i64| score_at(planes, template, pass, x + dx, y + dy);
+    let Some(peak) = score(0, 0) else {
+        return;
+    };
+    let axis = |before: Option<f32>, after: Option<f32>| match (before, after) {
+        (Some(b), Some(a)) => parabolic_offset(b, peak, a) * SCALE_DOWN as f32,
+        _ => 0.0,
+    };
+    m.subpixel = (
+        axis(score(-1, 0), score(1, 0)),
+        axis(score(0, -1), score(0, 1)),
+    );
+}
+
+/// Log each match's score under both [`MatchMethod`]s (`matches` in
+/// full-screenshot coordinates).
+fn log_ab_scores(
+    screenshot: &PreparedScreenshot,
+    template: &PreparedRef,
+    pass: &Pass,
+    matches: &[TemplateMatch],
+) {
+    let other = pass.with_other_method();
+    for m in matches {
+        let (cx, cy) = screenshot.to_planes(m.x, m.y);
+        let x = cx as i64 - (template.width / 2) as i64;
+        let y = cy as i64 - (template.height / 2) as i64;
+        let Some(other_score) = score_at(&screenshot.planes, template, &other, x, y) else {
             continue;
+        };
+        let (ncc, sse) = match pass.method {
+            MatchMethod::Ncc => (m.score, 1.0 - other_score),
+            MatchMethod::Sse => (other_score, 1.0 - m.score),
+        };
+        tracing::info!(
+            "match A/B: {} at ({}, {}) [{}] ncc={ncc:.4} sse={sse:.4}",
+            template.name,
+            m.x,
+            m.y,
+            channel_list(&pass.channels)
+        );
+    }
+}
+
+/// Channel names joined for log output, e.g. "R/G/B/Edge".
+fn channel_list(channels: &[Channel]) -> String {
+    channels
+        .iter()
+        .map(|c| c.name())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Best combined score of a negative template within a window
+/// around a candidate center (in downscaled viewport coordinates).
+/// Only the neighbourhood is correlated, so this is cheap compared to a
+/// full-frame match.
+fn negative_score(
+    screenshot: &Planes,
+    negative: &PreparedRef,
+    pass: &Pass,
+    center_x: u32,
+    center_y: u32,
+) -> f32 {
+    let (sw, sh) = (screenshot.width(), screenshot.height());
+    if negative.width >= sw || negative.height >= sh {
+        return 0.0;
+    }
+
+    // Window big enough for the negative template to slide ±half its size
+    // around the candidate center.
+    let win_w = (negative.width * 2).min(sw);
+    let win_h = (negative.height * 2).min(sh);
+    let left = center_x.saturating_sub(negative.width).min(sw - win_w);
+    let top = center_y.saturating_sub(negative.height).min(sh - win_h);
+
+    let crop = |img: &GrayImage| image::imageops::crop_imm(img, left, top, win_w, win_h).to_image();
+
+    let results: Vec<_> = pass
+        .channels
+        .iter()
+        .map(|&ch| pass.correlate(&crop(screenshot.get(ch)), negative.planes.get(ch)))
+        .collect();
+
+    let (w, h) = results[0].dimensions();
+    let mut best: f32 = 0.0;
+    for y in 0..h {
+        for x in 0..w {
+            let score = pass.combine(results.iter().map(|r| r.get_pixel(x, y).0[0]));
+            best = best.max(score);
         }
+    }
+    best
+}
 
-        // Remaining channels: G, B, Edge — full scan, min across all
-        let g_result = match_template(
-            &screenshot_channels[1],
-            &prepared.channels[1],
-            MatchTemplateMethod::CrossCorrelationNormalized,
-        );
-        let b_result = match_template(
-            &screenshot_channels[2],
-            &prepared.channels[2],
-            MatchTemplateMethod::CrossCorrelationNormalized,
-        );
-        let e_result = match_template(
-            &screenshot_edge,
-            &prepared.edge,
-            MatchTemplateMethod::CrossCorrelationNormalized,
-        );
+/// 64-bit average hash of a window: the window is split into an 8×8 grid of
+/// cells and each bit records whether that cell's mean luminance is above the
+/// window's mean. Computed from an integral image so each window costs 64
+/// box sums regardless of template size.
+fn average_hash(
+    integral: &image::ImageBuffer<image::Luma<u32>, Vec<u32>>,
+    left: u32,
+    top: u32,
+    w: u32,
+    h: u32,
+) -> u64 {
+    let mut means = [0f32; 64];
+    for cy in 0..8u32 {
+        let y0 = top + cy * h / 8;
+        let y1 = top + (cy + 1) * h / 8;
+        for cx in 0..8u32 {
+            let x0 = left + cx * w / 8;
+            let x1 = left + (cx + 1) * w / 8;
+            let area = ((x1 - x0) * (y1 - y0)).max(1);
+            let sum = sum_image_pixels(integral, x0, y0, x1.max(x0 + 1) - 1, y1.max(y0 + 1) - 1)[0];
+            means[(cy * 8 + cx) as usize] = sum as f32 / area as f32;
+        }
+    }
+    let overall = means.iter().sum::<f32>() / 64.0;
+    means
+        .iter()
+        .enumerate()
+        .filter(|&(_, &m)| m > overall)
+        .fold(0u64, |hash, (i, _)| hash | (1 << i))
+}
 
-        for y in 0..h {
-            for x in 0..w {
-                let score = r_result.get_pixel(x, y).0[0]
-                    .min(g_result.get_pixel(x, y).0[0])
-                    .min(b_result.get_pixel(x, y).0[0])
-                    .min(e_result.get_pixel(x, y).0[0]);
+/// Normalized cross-correlation of `template` against `image` at a single
+/// top-left position. Same formula as imageproc's `CrossCorrelationNormalized`.
+fn ncc_at(image: &GrayImage, template: &GrayImage, x: u32, y: u32) -> f32 {
+    let (tw, th) = template.dimensions();
+    let mut score = 0f32;
+    let mut ii = 0f32;
+    let mut tt = 0f32;
+    for ty in 0..th {
+        for tx in 0..tw {
+            let i = image.get_pixel(x + tx, y + ty).0[0] as f32;
+            let t = template.get_pixel(tx, ty).0[0] as f32;
+            score += i * t;
+            ii += i * i;
+            tt += t * t;
+        }
+    }
+    let norm = (ii * tt).sqrt();
+    if norm > 0.0 { score / norm } else { score }
+}
 
-                let dominated = best.as_ref().is_some_and(|b| score <= b.score);
-                if !dominated {
-                    best = Some(TemplateMatch {
-                        x: (x + prepared.width / 2) * SCALE_DOWN + VIEWPORT_LEFT,
-                        y: (y + prepared.height / 2) * SCALE_DOWN + VIEWPORT_TOP,
-                        score,
-                    });
+/// Normalized sum of squared errors at one position, as imageproc's
+/// `SumOfSquaredErrorsNormalized` computes it (lower is better).
+fn sse_at(image: &GrayImage, template: &GrayImage, x: u32, y: u32) -> f32 {
+    let (tw, th) = template.dimensions();
+    let mut sse = 0f32;
+    let mut ii = 0f32;
+    let mut tt = 0f32;
+    for ty in 0..th {
+        for tx in 0..tw {
+            let i = image.get_pixel(x + tx, y + ty).0[0] as f32;
+            let t = template.get_pixel(tx, ty).0[0] as f32;
+            sse += (i - t) * (i - t);
+            ii += i * i;
+            tt += t * t;
+        }
+    }
+    let norm = (ii * tt).sqrt();
+    if norm > 0.0 { sse / norm } else { sse }
+}
+
+/// Hash-prefiltered variant of [`find_template_matches_cascade`]: slides the
+/// template-sized window computing an average hash, and only evaluates the
+/// channel NCC at positions within `max_distance` bits of the template hash.
+/// In the common no-match case almost every position is rejected by the hash
+/// alone, skipping the full-frame correlations entirely.
+fn find_template_matches_hashed(
+    screenshot: &Planes,
+    screenshot_integral: &image::ImageBuffer<image::Luma<u32>, Vec<u32>>,
+    template: &PreparedRef,
+    template_idx: usize,
+    pass: &Pass,
+    threshold: f32,
+    max_distance: u32,
+) -> Vec<TemplateMatch> {
+    let (sw, sh) = (screenshot.width(), screenshot.height());
+    let (tw, th) = (template.width, template.height);
+
+    let mut hash_hits = 0usize;
+    let mut matches = Vec::new();
+    for y in 0..=(sh - th) {
+        for x in 0..=(sw - tw) {
+            let hash = average_hash(screenshot_integral, x, y, tw, th);
+            if (hash ^ template.hash).count_ones() > max_distance {
+                continue;
+            }
+            hash_hits += 1;
+
+            let mut channel_scores = Vec::with_capacity(pass.channels.len());
+            let mut score = f32::INFINITY;
+            for &ch in &pass.channels {
+                channel_scores.push(pass.score_pixel(
+                    screenshot.get(ch),
+                    template.planes.get(ch),
+                    x,
+                    y,
+                ));
+                score = pass.combine(channel_scores.iter().copied());
+                if score < threshold {
+                    break;
                 }
             }
+            if score >= threshold {
+                matches.push(TemplateMatch {
+                    x: x + tw / 2,
+                    y: y + th / 2,
+                    score,
+                    template: template_idx,
+                    channels: None,
+                    subpixel: (0.0, 0.0),
+                });
+            }
         }
     }
 
-    best
-}
+    tracing::info!(
+        "template {}x{}: hash prefilter kept {hash_hits} positions, {} raw matches above {:.2}",
+        tw,
+        th,
+        matches.len(),
+        threshold
+    );
 
-fn deduplicate_matches(matches: &mut [TemplateMatch], min_distance: u32) -> Vec<TemplateMatch> {
-    // Sort by score descending so we keep the best matches
     matches.sort_by(|a, b| {
         b.score
             .partial_cmp(&a.score)
             .unwrap_or(std::cmp::Ordering::Equal)
     });
+    matches
+}
+
+/// Coarse-to-fine variant of [`find_template_matches_cascade`].
+///
+/// Stage 1 correlates a grayscale copy of the screenshot and template at
+/// 1/`factor` resolution and keeps every local peak above [`COARSE_THRESHOLD`].
+/// The coarse pass uses zero-mean NCC: downscaling flattens texture, and the
+/// plain NCC used by the fine pass scores any flat region highly. Stage 2
+/// expands each peak into a full-resolution window (template size plus
+/// `factor` pixels of slack), merges overlapping windows, and runs the normal
+/// channel cascade only inside them. Falls back to the full-frame cascade if
+/// the template would be too small when downscaled.
+fn find_template_matches_coarse(
+    screenshot: &Planes,
+    template: &PreparedRef,
+    template_idx: usize,
+    pass: &Pass,
+    threshold: f32,
+    factor: u32,
+) -> Result<Vec<TemplateMatch>> {
+    let (sw, sh) = (screenshot.width(), screenshot.height());
+    let (tw, th) = (template.width, template.height);
+    let (ctw, cth) = (tw / factor, th / factor);
+    if ctw < 6 || cth < 6 {
+        tracing::debug!(
+            "template {tw}x{th} too small for coarse factor {factor}, using full-frame match"
+        );
+        return find_template_matches_cascade(screenshot, template, template_idx, pass, threshold);
+    }
 
-    let mut result = Vec::new();
+    let coarse_screen = image::imageops::resize(
+        &screenshot.gray,
+        sw / factor,
+        sh / factor,
+        FilterType::Triangle,
+    );
+    let coarse_template =
+        image::imageops::resize(&template.planes.gray, ctw, cth, FilterType::Triangle);
+    let coarse = zero_mean_ncc(&coarse_screen, &coarse_template);
 
-    for m in matches.iter() {
-        let too_close = result.iter().any(|existing: &TemplateMatch| {
-            let dx = m.x.abs_diff(existing.x);
-            let dy = m.y.abs_diff(existing.y);
-            dx < min_distance && dy < min_distance
+    // Full-resolution search windows (x0, y0, x1, y1), exclusive bounds
+    let mut regions: Vec<(u32, u32, u32, u32)> = Vec::new();
+    let (cw, ch) = coarse.dimensions();
+    for (cx, cy, score) in coarse.enumerate_pixels() {
+        let score = score.0[0];
+        if score < COARSE_THRESHOLD {
+            continue;
+        }
+        // Only local maxima nominate regions; the plateau around a peak is
+        // covered by the slack added to each window anyway.
+        let is_peak = (cy.saturating_sub(1)..=(cy + 1).min(ch - 1)).all(|ny| {
+            (cx.saturating_sub(1)..=(cx + 1).min(cw - 1))
+                .all(|nx| coarse.get_pixel(nx, ny).0[0] <= score)
         });
-
-        if !too_close {
-            result.push(m.clone());
+        if !is_peak {
+            continue;
         }
+        let x0 = (cx * factor).saturating_sub(factor);
+        let y0 = (cy * factor).saturating_sub(factor);
+        let x1 = (cx * factor + tw + 2 * factor).min(sw);
+        let y1 = (cy * factor + th + 2 * factor).min(sh);
+        regions.push((x0, y0, x1, y1));
     }
+    let regions = merge_regions(regions);
 
-    result
-}
-
-/// Load reference images from the assets directory.
-/// Returns them as Arc<DynamicImage> for cheap sharing across scan iterations.
-///
-/// Search order for each image:
-/// 1. `MERCY_ASSETS_DIR` env var (if set)
-/// 2. Relative to CWD (e.g. `./assets/...`)
-/// 3. Relative to the binary's `../share/mercy/` (Nix install layout)
-pub fn load_reference_images(search_target: &str) -> Result<Vec<Arc<DynamicImage>>> {
-    let env_assets = std::env::var("MERCY_ASSETS_DIR")
-        .ok()
-        .map(std::path::PathBuf::from);
-
-    let bin_share = std::env::current_exe()
-        .ok()
-        .and_then(|p| p.parent()?.parent().map(|p| p.join("share/mercy")));
-
-    let base = search_target.to_lowercase().replace(' ', "_");
-    let filenames = [format!("{base}_ref.png")];
+    tracing::info!(
+        "template {tw}x{th}: coarse pass (1/{factor}) nominated {} region(s)",
+        regions.len()
+    );
 
-    let mut images = Vec::new();
+    let mut matches = Vec::new();
+    for (x0, y0, x1, y1) in regions {
+        let (w, h) = (x1 - x0, y1 - y0);
+        if w <= tw || h <= th {
+            continue;
+        }
+        let window = screenshot.crop(x0, y0, w, h);
+        let found =
+            find_template_matches_cascade(&window, template, template_idx, pass, threshold)?;
+        matches.extend(found.into_iter().map(|m| TemplateMatch {
+            x: m.x + x0,
+            y: m.y + y0,
+            ..m
+        }));
+    }
 
-    for filename in &filenames {
-        let asset_rel = std::path::Path::new("assets").join(filename);
+    matches.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    Ok(matches)
+}
 
-        let candidates: Vec<std::path::PathBuf> = [
-            env_assets.as_ref().map(|d| d.join(filename)),
-            Some(asset_rel),
-            bin_share.as_ref().map(|d| d.join("assets").join(filename)),
-        ]
-        .into_iter()
-        .flatten()
-        .collect();
+/// Zero-mean normalized cross-correlation surface (OpenCV's `TM_CCOEFF_NORMED`),
+/// in [-1, 1]. Only used on small downscaled images (the coarse pass and
+/// [`crate::challenge`]), so the direct O(N·M) loop is fine.
+pub fn zero_mean_ncc(
+    image: &GrayImage,
+    template: &GrayImage,
+) -> image::ImageBuffer<image::Luma<f32>, Vec<f32>> {
+    let (iw, ih) = image.dimensions();
+    let (tw, th) = template.dimensions();
+    let n = (tw * th) as f32;
+    let t_mean = template.pixels().map(|p| p.0[0] as f32).sum::<f32>() / n;
+    let t_dev: Vec<f32> = template.pixels().map(|p| p.0[0] as f32 - t_mean).collect();
+    let t_norm = t_dev.iter().map(|d| d * d).sum::<f32>().sqrt();
 
-        let mut loaded = false;
-        for path in &candidates {
-            if path.exists() {
-                match image::open(path) {
-                    Ok(img) => {
-                        tracing::info!("loaded reference image: {}", path.display());
-                        images.push(Arc::new(img));
-                        loaded = true;
-                        break;
-                    }
-                    Err(e) => {
-                        tracing::warn!("failed to decode {}: {e}", path.display());
-                    }
-                }
+    image::ImageBuffer::from_fn(iw - tw + 1, ih - th + 1, |x, y| {
+        let mut sum = 0f32;
+        for ty in 0..th {
+            for tx in 0..tw {
+                sum += image.get_pixel(x + tx, y + ty).0[0] as f32;
+            }
+        }
+        let i_mean = sum / n;
+        let mut cross = 0f32;
+        let mut i_sq = 0f32;
+        for ty in 0..th {
+            for tx in 0..tw {
+                let d = image.get_pixel(x + tx, y + ty).0[0] as f32 - i_mean;
+                cross += d * t_dev[(ty * tw + tx) as usize];
+                i_sq += d * d;
             }
         }
+        let norm = i_sq.sqrt() * t_norm;
+        image::Luma([if norm > 0.0 { cross / norm } else { 0.0 }])
+    })
+}
 
-        if !loaded {
-            tracing::warn!("reference image {filename} not found in any search path");
+/// Merge overlapping or touching rectangles (x0, y0, x1, y1) until none overlap.
+fn merge_regions(mut regions: Vec<(u32, u32, u32, u32)>) -> Vec<(u32, u32, u32, u32)> {
+    let mut merged: Vec<(u32, u32, u32, u32)> = Vec::new();
+    while let Some(mut r) = regions.pop() {
+        // Absorb every merged rectangle that overlaps r, then re-check the rest
+        let mut i = 0;
+        while i < merged.len() {
+            let m = merged[i];
+            if r.0 <= m.2 && m.0 <= r.2 && r.1 <= m.3 && m.1 <= r.3 {
+                r = (r.0.min(m.0), r.1.min(m.1), r.2.max(m.2), r.3.max(m.3));
+                merged.swap_remove(i);
+                i = 0;
+            } else {
+                i += 1;
+            }
         }
+        merged.push(r);
     }
+    merged
+}
 
-    if images.is_empty() {
-        anyhow::bail!("no reference images could be loaded");
-    }
+/// Run template matching over the pass's channels with cascading early exit.
+/// Runs channels sequentially; if no pixel's partial combined score (an upper
+/// bound on its final score) reaches `threshold` after a channel, skips
+/// remaining channels (~4x speedup for the common "no match" case).
+fn find_template_matches_cascade(
+    screenshot: &Planes,
+    template: &PreparedRef,
+    template_idx: usize,
+    pass: &Pass,
+    threshold: f32,
+) -> Result<Vec<TemplateMatch>> {
+    let template_w = template.width;
+    let template_h = template.height;
+    let Some((&first, rest)) = pass.channels.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    // First channel — collect all candidates above threshold
+    let first_result = pass.correlate(screenshot.get(first), template.planes.get(first));
+    let (w, h) = first_result.dimensions();
 
-    Ok(images)
+    // (x, y, per-channel scores so far, combined score so far)
+    let mut candidates: Vec<(u32, u32, Vec<f32>, f32)> = Vec::new();
+    let mut best_score: f32 = 0.0;
+    for y in 0..h {
+        for x in 0..w {
+            let channel_score = first_result.get_pixel(x, y).0[0];
+            let score = pass.combine([channel_score]);
+            if score > best_score {
+                best_score = score;
+            }
+            if score >= threshold {
+                candidates.push((x, y, vec![channel_score], score));
+            }
+        }
+    }
+
+    if candidates.is_empty() {
+        tracing::info!(
+            "template {}x{}: early-exit after {} (best={:.4}, 0 candidates)",
+            template_w,
+            template_h,
+            first.name(),
+            best_score
+        );
+        return Ok(Vec::new());
+    }
+
+    tracing::info!(
+        "template {}x{}: {} pass: {} candidates (best={:.4})",
+        template_w,
+        template_h,
+        first.name(),
+        candidates.len(),
+        best_score
+    );
+
+    // Remaining channels — filter candidates, early-exit if none survive
+    for &ch in rest {
+        let result = pass.correlate(screenshot.get(ch), template.planes.get(ch));
+
+        best_score = 0.0;
+        for cand in &mut candidates {
+            cand.2.push(result.get_pixel(cand.0, cand.1).0[0]);
+            cand.3 = pass.combine(cand.2.iter().copied());
+            if cand.3 > best_score {
+                best_score = cand.3;
+            }
+        }
+
+        candidates.retain(|c| c.3 >= threshold);
+
+        if candidates.is_empty() {
+            tracing::info!(
+                "template {}x{}: early-exit after {} (best={:.4}, 0 candidates)",
+                template_w,
+                template_h,
+                ch.name(),
+                best_score
+            );
+            return Ok(Vec::new());
+        }
+
+        tracing::info!(
+            "template {}x{}: {} pass: {} candidates (best={:.4})",
+            template_w,
+            template_h,
+            ch.name(),
+            candidates.len(),
+            best_score
+        );
+    }
+
+    let mut matches: Vec<TemplateMatch> = candidates
+        .into_iter()
+        .map(|(x, y, _, score)| TemplateMatch {
+            x: x + template_w / 2,
+            y: y + template_h / 2,
+            score,
+            template: template_idx,
+            channels: None,
+            subpixel: (0.0, 0.0),
+        })
+        .collect();
+
+    tracing::info!(
+        "template {}x{}: best_score={:.4}, {} raw matches above {:.2} ({})",
+        template_w,
+        template_h,
+        best_score,
+        matches.len(),
+        threshold,
+        channel_list(&pass.channels)
+    );
+
+    matches.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    Ok(matches)
+}
+
+/// Find the single best match regardless of threshold (for calibration).
+/// Uses cascading channels: runs the first channel of each pass, tracks the
+/// best position, then refines with the rest. Skips remaining channels if the
+/// best first-channel score is below the template's threshold (but still
+/// returns that score for diagnostic output).
+pub fn find_best_match(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    opts: &MatchOptions,
+) -> Option<TemplateMatch> {
+    let planes = &screenshot.planes;
+
+    let passes = opts.passes();
+    let mut best: Option<TemplateMatch> = None;
+    // Pass behind `best`'s score, for the sub-pixel fit
+    let mut best_pass: Option<Pass> = None;
+
+    for (template_idx, prepared) in ref_images.iter().enumerate() {
+        if prepared.negative
+            || prepared.width >= screenshot.width()
+            || prepared.height >= screenshot.height()
+        {
+            continue;
+        }
+
+        for pass in &passes {
+            let Some((&first, rest)) = pass.channels.split_first() else {
+                continue;
+            };
+
+            // First channel — find best position
+            let first_result = pass.correlate(planes.get(first), prepared.planes.get(first));
+            let (w, h) = first_result.dimensions();
+
+            let mut best_first_x = 0u32;
+            let mut best_first_y = 0u32;
+            let mut best_first_score: f32 = f32::NEG_INFINITY;
+            for y in 0..h {
+                for x in 0..w {
+                    let score = pass.combine([first_result.get_pixel(x, y).0[0]]);
+                    if score > best_first_score {
+                        best_first_score = score;
+                        best_first_x = x;
+                        best_first_y = y;
+                    }
+                }
+            }
+
+            if best_first_score < prepared.threshold {
+                // No point running more channels; return first-channel score for diagnostics
+                tracing::info!(
+                    "find_best_match: {} early-exit after {} (best={:.4})",
+                    prepared.name,
+                    first.name(),
+                    best_first_score
+                );
+                let dominated = best.as_ref().is_some_and(|b| best_first_score <= b.score);
+                if !dominated {
+                    let (x, y) = screenshot.to_screen(
+                        best_first_x + prepared.width / 2,
+                        best_first_y + prepared.height / 2,
+                    );
+                    best = Some(TemplateMatch {
+                        x,
+                        y,
+                        score: best_first_score,
+                        template: template_idx,
+                        channels: None,
+                        subpixel: (0.0, 0.0),
+                    });
+                    best_pass = Some(Pass {
+                        channels: vec![first],
+                        weights: vec![1.0],
+                        ..pass.clone()
+                    });
+                }
+                continue;
+            }
+
+            // Remaining channels — full scan, combine across all
+            let rest_results: Vec<_> = rest
+                .iter()
+                .map(|&ch| pass.correlate(planes.get(ch), prepared.planes.get(ch)))
+                .collect();
+
+            for y in 0..h {
+                for x in 0..w {
+                    let mut scores = ChannelScores::default();
+                    scores.set(first, first_result.get_pixel(x, y).0[0]);
+                    for (&ch, result) in rest.iter().zip(&rest_results) {
+                        scores.set(ch, result.get_pixel(x, y).0[0]);
+                    }
+                    let score = pass.combine(
+                        std::iter::once(&first_result)
+                            .chain(&rest_results)
+                            .map(|r| r.get_pixel(x, y).0[0]),
+                    );
+
+                    let dominated = best.as_ref().is_some_and(|b| score <= b.score);
+                    if !dominated {
+                        let (x, y) =
+                            screenshot.to_screen(x + prepared.width / 2, y + prepared.height / 2);
+                        best = Some(TemplateMatch {
+                            x,
+                            y,
+                            score,
+                            template: template_idx,
+                            channels: Some(scores),
+                            subpixel: (0.0, 0.0),
+                        });
+                        best_pass = Some(pass.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    if let (Some(m), Some(pass)) = (best.as_mut(), &best_pass) {
+        refine_subpixel(screenshot, &ref_images[m.template], pass, m);
+        if opts.ab_log {
+            log_ab_scores(
+                screenshot,
+                &ref_images[m.template],
+                pass,
+                std::slice::from_ref(m),
+            );
+        }
+    }
+    best
+}
+
+/// Return the `n` strongest distinct candidates regardless of threshold, each
+/// with its full per-channel score breakdown (for threshold tuning).
+///
+/// Unlike [`find_best_match`] there is no early exit: all channels of every
+/// pass are correlated for every positive template, local maxima of the
+/// combined-score surface are collected, and overlapping peaks are suppressed.
+pub fn find_top_matches(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    n: usize,
+    opts: &MatchOptions,
+) -> Vec<TemplateMatch> {
+    let planes = &screenshot.planes;
+
+    let passes = opts.passes();
+    let mut peaks = Vec::new();
+
+    for (template_idx, prepared) in ref_images.iter().enumerate() {
+        if prepared.negative
+            || prepared.width >= screenshot.width()
+            || prepared.height >= screenshot.height()
+        {
+            continue;
+        }
+
+        for pass in &passes {
+            let results: Vec<_> = pass
+                .channels
+                .iter()
+                .map(|&ch| pass.correlate(planes.get(ch), prepared.planes.get(ch)))
+                .collect();
+            let (w, h) = results[0].dimensions();
+            let score_at =
+                |x: u32, y: u32| pass.combine(results.iter().map(|r| r.get_pixel(x, y).0[0]));
+
+            for y in 0..h {
+                for x in 0..w {
+                    let score = score_at(x, y);
+                    let is_peak = (y.saturating_sub(1)..=(y + 1).min(h - 1)).all(|ny| {
+                        (x.saturating_sub(1)..=(x + 1).min(w - 1))
+                            .all(|nx| (nx, ny) == (x, y) || score_at(nx, ny) <= score)
+                    });
+                    if is_peak {
+                        let mut scores = ChannelScores::default();
+                        for (&ch, result) in pass.channels.iter().zip(&results) {
+                            scores.set(ch, result.get_pixel(x, y).0[0]);
+                        }
+                        let (x, y) =
+                            screenshot.to_screen(x + prepared.width / 2, y + prepared.height / 2);
+                        peaks.push(TemplateMatch {
+                            x,
+                            y,
+                            score,
+                            template: template_idx,
+                            channels: Some(scores),
+                            subpixel: (0.0, 0.0),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // Keep the candidate list bounded before the O(n²) overlap suppression
+    peaks.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    peaks.truncate(n.saturating_mul(20).max(100));
+
+    let mut top = non_max_suppression(&peaks, ref_images, NMS_IOU_THRESHOLD);
+    top.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    top.truncate(n);
+    top
+}
+
+/// Scores below this render as the coldest colour in [`score_heatmap`].
+const HEATMAP_FLOOR: f32 = 0.5;
+
+/// Render the correlation score map as a false-colour overlay on the viewport
+/// crop, for diagnosing near misses.
+///
+/// Each position gets the same score [`find_best_match`] would give it (combined
+/// across a pass's channels, max across passes and positive templates), drawn
+/// at the template centre. Colours run blue → green → red from
+/// [`HEATMAP_FLOOR`] to 1.0; positions at or above [`MATCH_THRESHOLD`] are white.
+pub fn score_heatmap(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    opts: &MatchOptions,
+) -> RgbImage {
+    let scores = score_map(screenshot, ref_images, opts);
+    let w = screenshot.width();
+    RgbImage::from_fn(w, screenshot.height(), |x, y| {
+        let heat = heat_color(scores[(y * w + x) as usize]);
+        // Dim the screenshot so the overlay stays readable
+        image::Rgb(std::array::from_fn(|c| {
+            let base = screenshot.planes.rgb[c].get_pixel(x, y).0[0] as f32;
+            (base * 0.4 + heat[c] as f32 * 0.6) as u8
+        }))
+    })
+}
+
+/// Row-major best score per viewport pixel for [`score_heatmap`];
+/// `NEG_INFINITY` where no template centre lands.
+fn score_map(
+    screenshot: &PreparedScreenshot,
+    ref_images: &[PreparedRef],
+    opts: &MatchOptions,
+) -> Vec<f32> {
+    let planes = &screenshot.planes;
+    let (w, h) = (screenshot.width(), screenshot.height());
+    let passes = opts.passes();
+    let mut scores = vec![f32::NEG_INFINITY; (w * h) as usize];
+
+    for prepared in ref_images {
+        if prepared.negative || prepared.width >= w || prepared.height >= h {
+            continue;
+        }
+        for pass in &passes {
+            let results: Vec<_> = pass
+                .channels
+                .iter()
+                .map(|&ch| pass.correlate(planes.get(ch), prepared.planes.get(ch)))
+                .collect();
+            let Some((rw, rh)) = results.first().map(|r| r.dimensions()) else {
+                continue;
+            };
+            for y in 0..rh {
+                for x in 0..rw {
+                    let score = pass.combine(results.iter().map(|r| r.get_pixel(x, y).0[0]));
+                    let (cx, cy) = (x + prepared.width / 2, y + prepared.height / 2);
+                    let cell = &mut scores[(cy * w + cx) as usize];
+                    *cell = cell.max(score);
+                }
+            }
+        }
+    }
+    scores
+}
+
+/// False colour for a correlation score (see [`score_heatmap`]).
+fn heat_color(score: f32) -> [u8; 3] {
+    if score >= MATCH_THRESHOLD {
+        return [255, 255, 255];
+    }
+    if !score.is_finite() {
+        return [0, 0, 0];
+    }
+    let t = ((score - HEATMAP_FLOOR) / (1.0 - HEATMAP_FLOOR)).clamp(0.0, 1.0);
+    let ramp = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+    // blue (0) → green (0.5) → red (1)
+    [
+        ramp(2.0 * t - 1.0),
+        ramp(1.0 - (2.0 * t - 1.0).abs()),
+        ramp(1.0 - 2.0 * t),
+    ]
+}
+
+/// Encode an image as PNG (for debug output and API responses).
+pub fn encode_png(image: &RgbImage) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .context("failed to encode PNG")?;
+    Ok(png)
+}
+
+/// Overlap (IoU) above which a weaker detection is treated as the same building.
+pub(crate) const NMS_IOU_THRESHOLD: f32 = 0.3;
+
+/// Intersection-over-union of two template-sized boxes centered on matches.
+fn box_iou(a: &TemplateMatch, a_size: (u32, u32), b: &TemplateMatch, b_size: (u32, u32)) -> f32 {
+    let span = |c: u32, len: u32| (c as f32 - len as f32 / 2.0, c as f32 + len as f32 / 2.0);
+    let (ax0, ax1) = span(a.x, a_size.0);
+    let (ay0, ay1) = span(a.y, a_size.1);
+    let (bx0, bx1) = span(b.x, b_size.0);
+    let (by0, by1) = span(b.y, b_size.1);
+
+    let iw = (ax1.min(bx1) - ax0.max(bx0)).max(0.0);
+    let ih = (ay1.min(by1) - ay0.max(by0)).max(0.0);
+    let inter = iw * ih;
+    let union = (a_size.0 * a_size.1 + b_size.0 * b_size.1) as f32 - inter;
+    if union > 0.0 { inter / union } else { 0.0 }
+}
+
+/// Non-maximum suppression over the thresholded correlation surface.
+///
+/// First keeps only local maxima (no higher-scoring candidate from the same
+/// template in the 8-neighbourhood), then greedily accepts detections in
+/// priority/score order, suppressing any whose template-sized box overlaps an
+/// accepted one by more than `iou_threshold`. Unlike a fixed pixel radius this
+/// scales with template size, so adjacent buildings stay separate while the
+/// plateau of near-identical scores around one building collapses to a
+/// single detection.
+pub(crate) fn non_max_suppression(
+    matches: &[TemplateMatch],
+    ref_images: &[PreparedRef],
+    iou_threshold: f32,
+) -> Vec<TemplateMatch> {
+    let size = |m: &TemplateMatch| {
+        ref_images
+            .get(m.template)
+            .map_or((1, 1), |r| (r.width * SCALE_DOWN, r.height * SCALE_DOWN))
+    };
+    let priority = |m: &TemplateMatch| ref_images.get(m.template).map_or(0, |r| r.priority);
+
+    let scores: std::collections::HashMap<(usize, u32, u32), f32> = matches
+        .iter()
+        .map(|m| ((m.template, m.x, m.y), m.score))
+        .collect();
+    let is_local_max = |m: &TemplateMatch| {
+        (-1i64..=1).all(|dy| {
+            (-1i64..=1).all(|dx| {
+                let (nx, ny) = (m.x as i64 + dx, m.y as i64 + dy);
+                if (dx == 0 && dy == 0) || nx < 0 || ny < 0 {
+                    return true;
+                }
+                scores
+                    .get(&(m.template, nx as u32, ny as u32))
+                    .is_none_or(|&s| s <= m.score)
+            })
+        })
+    };
+
+    let mut peaks: Vec<&TemplateMatch> = matches.iter().filter(|m| is_local_max(m)).collect();
+    // Sort by template priority, then score descending, so we keep the best matches
+    peaks.sort_by(|a, b| {
+        priority(b).cmp(&priority(a)).then_with(|| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+    });
+
+    let mut result: Vec<TemplateMatch> = Vec::new();
+    for m in peaks {
+        let overlaps = result
+            .iter()
+            .any(|kept| box_iou(m, size(m), kept, size(kept)) > iou_threshold);
+        if !overlaps {
+            result.push(m.clone());
+        }
+    }
+
+    result
+}
+
+/// Per-target template manifest, stored as `<target>_refs.json` next to the
+/// reference images. Lets a target use several templates (lighting or
+/// decoration variants), each with its own threshold and priority.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct TemplateManifest {
+    /// The target's settings besides its templates (see [`crate::target`])
+    #[serde(flatten)]
+    profile: ProfileManifest,
+    templates: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ManifestEntry {
+    /// Image file name, relative to the manifest's directory.
+    file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    threshold: Option<f32>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    negative: bool,
+}
+
+/// Directories searched for reference assets, in order:
+/// 1. `MERCY_ASSETS_DIR` env var (if set)
+/// 2. `./assets` relative to CWD
+/// 3. Relative to the binary's `../share/mercy/assets` (Nix install layout)
+pub(crate) fn asset_search_dirs() -> Vec<std::path::PathBuf> {
+    let env_assets = std::env::var("MERCY_ASSETS_DIR")
+        .ok()
+        .map(std::path::PathBuf::from);
+
+    let bin_share = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent()?.parent().map(|p| p.join("share/mercy/assets")));
+
+    [
+        env_assets,
+        Some(std::path::PathBuf::from("assets")),
+        bin_share,
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+fn open_reference(path: &std::path::Path) -> Option<Arc<DynamicImage>> {
+    if !path.exists() {
+        return None;
+    }
+    match image::open(path) {
+        Ok(img) => {
+            tracing::info!("loaded reference image: {}", path.display());
+            Some(Arc::new(img))
+        }
+        Err(e) => {
+            tracing::warn!("failed to decode {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Read a manifest. Its template files must be plain paths inside its
+/// directory: no `..` and not absolute.
+fn read_manifest(path: &std::path::Path) -> Result<TemplateManifest> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let manifest: TemplateManifest = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    if let Some(entry) = manifest.templates.iter().find(|entry| {
+        !std::path::Path::new(&entry.file)
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+    }) {
+        anyhow::bail!(
+            "manifest {} lists {:?} outside its directory",
+            path.display(),
+            entry.file
+        );
+    }
+    Ok(manifest)
+}
+
+/// The file base name of a search target (lowercased, spaces → `_`). Only
+/// letters, digits, `_` and spaces are accepted, so a name can't reach
+/// outside the assets directories.
+fn target_base(search_target: &str) -> Result<String> {
+    let base = search_target.to_lowercase();
+    if base.is_empty()
+        || !base
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == ' ')
+    {
+        anyhow::bail!("invalid search target name {search_target:?}");
+    }
+    Ok(base.replace(' ', "_"))
+}
+
+/// Load every template listed in a manifest, and the manifest's target
+/// settings. Entries that fail to load are skipped with a warning.
+fn load_manifest(path: &std::path::Path) -> Result<(Vec<RefImage>, ProfileManifest)> {
+    let manifest = read_manifest(path)?;
+    let dir = path.parent().unwrap_or(std::path::Path::new("."));
+    let threshold = manifest.profile.threshold.unwrap_or(MATCH_THRESHOLD);
+
+    let mut images = Vec::new();
+    for entry in manifest.templates {
+        let img_path = dir.join(&entry.file);
+        match open_reference(&img_path) {
+            Some(image) => images.push(RefImage {
+                name: entry.file,
+                image,
+                threshold: entry.threshold.unwrap_or(threshold),
+                priority: entry.priority,
+                negative: entry.negative,
+            }),
+            None => tracing::warn!("manifest template {} not found", img_path.display()),
+        }
+    }
+    Ok((images, manifest.profile))
+}
+
+/// Load the reference images and target settings of a search target from
+/// the assets directory. Images are returned as `Arc<DynamicImage>` for
+/// cheap sharing across scan iterations.
+///
+/// The target name maps to a base name (see [`target_base`]). If a
+/// `<base>_refs.json` manifest exists in a search directory, all templates it
+/// lists are loaded; otherwise the single `<base>_ref.png` is used with the
+/// default threshold and settings. See [`asset_search_dirs`] for the search
+/// order, and [`crate::target::TargetProfile::load`] for the settings.
+pub(crate) fn load_target(search_target: &str) -> Result<(Vec<RefImage>, ProfileManifest)> {
+    let dirs = asset_search_dirs();
+    let base = target_base(search_target)?;
+
+    let manifest_name = format!("{base}_refs.json");
+    if let Some(manifest_path) = dirs
+        .iter()
+        .map(|d| d.join(&manifest_name))
+        .find(|p| p.exists())
+    {
+        tracing::info!("loading template manifest: {}", manifest_path.display());
+        let (images, profile) = load_manifest(&manifest_path)?;
+        if images.iter().all(|r| r.negative) {
+            anyhow::bail!(
+                "manifest {} lists no loadable positive templates",
+                manifest_path.display()
+            );
+        }
+        return Ok((images, profile));
+    }
+
+    let filename = format!("{base}_ref.png");
+    let image = dirs
+        .iter()
+        .find_map(|d| open_reference(&d.join(&filename)))
+        .with_context(|| format!("reference image {filename} not found in any search path"))?;
+
+    Ok((
+        vec![RefImage::new(filename, image)],
+        ProfileManifest::default(),
+    ))
+}
+
+/// Save a new template for `search_target` and register it in the target's
+/// manifest, so it is loaded again on restart.
+///
+/// Writes go to `MERCY_ASSETS_DIR` (or `./assets`). If that directory has no
+/// manifest yet, one is created from the templates currently in use (the
+/// first manifest found on the search path, or the single `<base>_ref.png`),
+/// copying their files over so the new manifest is self-contained.
+pub fn save_reference(search_target: &str, image: DynamicImage) -> Result<RefImage> {
+    let dir = std::env::var("MERCY_ASSETS_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|_| std::path::PathBuf::from("assets"));
+    save_reference_in(&dir, search_target, image)
+}
+
+fn save_reference_in(
+    dir: &std::path::Path,
+    search_target: &str,
+    image: DynamicImage,
+) -> Result<RefImage> {
+    // Read, extend and write the manifest as one step, so two saves at once
+    // don't drop each other's entry
+    static MANIFEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+    let base = target_base(search_target)?;
+    let manifest_path = dir.join(format!("{base}_refs.json"));
+    let mut manifest = if manifest_path.exists() {
+        read_manifest(&manifest_path)?
+    } else {
+        seed_manifest(&base, dir)?
+    };
+
+    let file = format!(
+        "{base}_ref_{}.png",
+        chrono::Utc::now().format("%Y%m%d%H%M%S%3f")
+    );
+    let path = dir.join(&file);
+    image
+        .save(&path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    manifest.templates.push(ManifestEntry {
+        file: file.clone(),
+        threshold: None,
+        priority: 0,
+        negative: false,
+    });
+    let json = serde_json::to_string_pretty(&manifest).context("failed to encode manifest")?;
+    std::fs::write(&manifest_path, json)
+        .with_context(|| format!("failed to write {}", manifest_path.display()))?;
+    tracing::info!(
+        "saved reference {} ({}x{}), manifest {} now lists {} template(s)",
+        path.display(),
+        image.width(),
+        image.height(),
+        manifest_path.display(),
+        manifest.templates.len()
+    );
+
+    Ok(RefImage::new(file, Arc::new(image)))
+}
+
+/// Manifest describing the templates currently in use for `base`, with their
+/// image files copied into `dir`.
+fn seed_manifest(base: &str, dir: &std::path::Path) -> Result<TemplateManifest> {
+    let dirs = asset_search_dirs();
+    let copy_into_dir = |src: &std::path::Path, file: &str| -> Result<()> {
+        let dest = dir.join(file);
+        if !dest.exists() {
+            std::fs::copy(src, &dest).with_context(|| {
+                format!("failed to copy {} to {}", src.display(), dest.display())
+            })?;
+        }
+        Ok(())
+    };
+
+    let manifest_name = format!("{base}_refs.json");
+    if let Some(existing) = dirs
+        .iter()
+        .map(|d| d.join(&manifest_name))
+        .find(|p| p.exists())
+    {
+        let manifest = read_manifest(&existing)?;
+        let src_dir = existing.parent().unwrap_or(std::path::Path::new("."));
+        for entry in &manifest.templates {
+            copy_into_dir(&src_dir.join(&entry.file), &entry.file)?;
+        }
+        return Ok(manifest);
+    }
+
+    let legacy = format!("{base}_ref.png");
+    let mut manifest = TemplateManifest::default();
+    if let Some(src) = dirs.iter().map(|d| d.join(&legacy)).find(|p| p.exists()) {
+        copy_into_dir(&src, &legacy)?;
+        manifest.templates.push(ManifestEntry {
+            file: legacy,
+            threshold: None,
+            priority: 0,
+            negative: false,
+        });
+    }
+    Ok(manifest)
+}
+
+/// The active detector, plus the raw templates and settings it was built
+/// from, so templates can be added and settings reloaded while the service
+/// runs.
+///
+/// Callers take a snapshot with [`DetectorHandle::current`]; adding a
+/// template or changing the options builds a fresh detector and swaps it in,
+/// leaving in-flight detections on the old one.
+pub struct DetectorHandle {
+    backend: MatchBackend,
+    inner: std::sync::RwLock<(Vec<RefImage>, MatchOptions, Arc<dyn Detector>)>,
+    /// Held from reading the current refs and options to swapping in the
+    /// detector built from them, so concurrent updates don't lose each other
+    update: std::sync::Mutex<()>,
+}
+
+impl DetectorHandle {
+    pub fn new(backend: MatchBackend, refs: Vec<RefImage>, opts: MatchOptions) -> Self {
+        let detector = Self::build(backend, &refs, &opts);
+        Self {
+            backend,
+            inner: std::sync::RwLock::new((refs, opts, detector)),
+            update: std::sync::Mutex::new(()),
+        }
+    }
+
+    fn build(backend: MatchBackend, refs: &[RefImage], opts: &MatchOptions) -> Arc<dyn Detector> {
+        let detector = new_detector(backend, prepare_reference_images(refs), opts.clone());
+        Arc::new(CachingDetector::new(detector))
+    }
+
+    pub fn current(&self) -> Arc<dyn Detector> {
+        // A poisoned lock still holds a consistent (refs, opts, detector)
+        // triple: all are replaced together in a single assignment.
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        inner.2.clone()
+    }
+
+    /// Prepare `reference` and make it part of the active template set.
+    /// Returns the number of templates now active.
+    pub fn add_reference(&self, reference: RefImage) -> usize {
+        let _update = self.update.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut refs, opts) = {
+            let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+            (inner.0.clone(), inner.1.clone())
+        };
+        refs.push(reference);
+        // Preparing is the slow part; do it without holding the read lock
+        let detector = Self::build(self.backend, &refs, &opts);
+        let count = detector.refs().len();
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = (refs, opts, detector);
+        count
+    }
+
+    /// A detector for other templates (another target's), with the same
+    /// backend and match options as the current one. Not made active.
+    pub fn with_refs(&self, refs: &[RefImage]) -> Arc<dyn Detector> {
+        let opts = {
+            let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+            inner.1.clone()
+        };
+        Self::build(self.backend, refs, &opts)
+    }
+
+    /// Rebuild the detector with new match options (a config reload).
+    pub fn set_options(&self, opts: MatchOptions) {
+        let _update = self.update.lock().unwrap_or_else(|e| e.into_inner());
+        let refs = {
+            let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+            inner.0.clone()
+        };
+        let detector = Self::build(self.backend, &refs, &opts);
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = (refs, opts, detector);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_manifest_applies_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let img = DynamicImage::new_rgb8(20, 20);
+        img.save(dir.path().join("day_ref.png")).unwrap();
+        img.save(dir.path().join("night_ref.png")).unwrap();
+        let manifest = dir.path().join("target_refs.json");
+        std::fs::write(
+            &manifest,
+            r#"{"threshold": 0.97, "templates": [
+                {"file": "day_ref.png"},
+                {"file": "night_ref.png", "threshold": 0.95, "priority": 2},
+                {"file": "missing_ref.png"}
+            ]}"#,
+        )
+        .unwrap();
+        let outside = dir.path().join("outside_refs.json");
+        std::fs::write(&outside, r#"{"templates": [{"file": "../day_ref.png"}]}"#).unwrap();
+        assert!(load_manifest(&outside).is_err());
+        assert!(target_base("../target").is_err());
+        assert_eq!(
+            target_base("Mercenary Exchange 2").unwrap(),
+            "mercenary_exchange_2"
+        );
+
+        let (refs, _) = load_manifest(&manifest).unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].threshold, 0.97, "the manifest's default");
+        assert_eq!(refs[0].priority, 0);
+        assert_eq!(refs[1].threshold, 0.95);
+
+        let prepared = prepare_reference_images(&refs);
+        assert_eq!(prepared[0].name, "night_ref.png", "higher priority first");
+    }
+
+    #[test]
+    fn test_save_reference_appends_to_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        DynamicImage::new_rgb8(20, 20)
+            .save(dir.path().join("target_ref.png"))
+            .unwrap();
+        let manifest = dir.path().join("target_refs.json");
+        std::fs::write(
+            &manifest,
+            r#"{"popup_keywords": ["Exchange"], "templates": [{"file": "target_ref.png"}]}"#,
+        )
+        .unwrap();
+
+        let saved =
+            save_reference_in(dir.path(), "Target", DynamicImage::new_rgb8(24, 18)).unwrap();
+        assert!(dir.path().join(&saved.name).exists());
+
+        let (refs, profile) = load_manifest(&manifest).unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[1].name, saved.name);
+        assert_eq!(profile.popup_keywords, ["Exchange"]);
+        assert_eq!((refs[1].image.width(), refs[1].image.height()), (24, 18));
+    }
+
+    #[test]
+    fn test_concurrent_add_reference_keeps_every_template() {
+        let handle =
+            DetectorHandle::new(MatchBackend::Template, Vec::new(), MatchOptions::default());
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let handle = &handle;
+                s.spawn(move || {
+                    let image = Arc::new(DynamicImage::new_rgb8(16 + i, 16));
+                    handle.add_reference(RefImage::new(format!("ref_{i}.png"), image));
+                });
+            }
+        });
+        assert_eq!(handle.current().refs().len(), 4);
+    }
+
+    /// Deterministic textured patch so NCC has structure to lock onto.
+    fn patch(seed: u32) -> RgbImage {
+        RgbImage::from_fn(16, 16, |x, y| {
+            let v = ((x * 37 + y * 91 + seed * 53) % 251) as u8;
+            image::Rgb([v, v.wrapping_mul(3), 255 - v])
+        })
+    }
+
+    #[test]
+    fn test_negative_score_detects_lookalike() {
+        let mut frame = RgbImage::from_pixel(80, 60, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 30, 20);
+        let planes = Planes::from_image(&DynamicImage::ImageRgb8(frame));
+
+        let refs = [
+            RefImage {
+                negative: true,
+                ..RefImage::new("same", Arc::new(DynamicImage::ImageRgb8(patch(1))))
+            },
+            RefImage {
+                negative: true,
+                ..RefImage::new("other", Arc::new(DynamicImage::ImageRgb8(patch(7))))
+            },
+        ];
+        let prepared = prepare_reference_images(&refs);
+
+        // Candidate center of the embedded patch
+        let rgbe = &MatchOptions::default().passes()[0];
+        let same = negative_score(&planes, &prepared[0], rgbe, 38, 28);
+        let other = negative_score(&planes, &prepared[1], rgbe, 38, 28);
+        // Edge maps differ slightly at the patch border, so not exactly 1.0
+        assert!(
+            same > 0.9,
+            "identical negative should score high, got {same}"
+        );
+        assert!(other < same, "different negative should score lower");
+    }
+
+    #[test]
+    fn test_ncc_at_matches_imageproc() {
+        let frame = DynamicImage::ImageRgb8(patch(3)).to_luma8();
+        let template = image::imageops::crop_imm(&frame, 4, 4, 8, 8).to_image();
+        let full = match_template(
+            &frame,
+            &template,
+            MatchTemplateMethod::CrossCorrelationNormalized,
+        );
+        for (x, y) in [(0, 0), (4, 4), (7, 2)] {
+            let expected = full.get_pixel(x, y).0[0];
+            assert!((ncc_at(&frame, &template, x, y) - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_sse_method_finds_embedded_template() {
+        let frame = DynamicImage::ImageRgb8(patch(3)).to_luma8();
+        let template = image::imageops::crop_imm(&frame, 4, 4, 8, 8).to_image();
+        let full = match_template(
+            &frame,
+            &template,
+            MatchTemplateMethod::SumOfSquaredErrorsNormalized,
+        );
+        for (x, y) in [(0, 0), (4, 4), (7, 2)] {
+            let expected = full.get_pixel(x, y).0[0];
+            assert!((sse_at(&frame, &template, x, y) - expected).abs() < 1e-4);
+        }
+
+        let mut frame = RgbImage::from_pixel(80, 60, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 30, 20);
+        let planes = Planes::from_image(&DynamicImage::ImageRgb8(frame));
+        let refs = [RefImage {
+            threshold: 0.9,
+            ..RefImage::new("t", Arc::new(DynamicImage::ImageRgb8(patch(1))))
+        }];
+        let prepared = prepare_reference_images(&refs);
+        // The edge map differs at the patch border, which SSE punishes harder
+        let opts = MatchOptions {
+            method: "SSE".parse().unwrap(),
+            channel_weights: "r,g,b".parse().unwrap(),
+            ..Default::default()
+        };
+        let matches =
+            find_template_matches_cascade(&planes, &prepared[0], 0, &opts.passes()[0], 0.9)
+                .unwrap();
+        assert_eq!(matches.first().map(|m| (m.x, m.y)), Some((38, 28)));
+        assert!(matches[0].score > 0.99, "1 - SSE of an exact match");
+    }
+
+    #[test]
+    fn test_hash_prefilter_finds_embedded_template() {
+        let mut frame = RgbImage::from_pixel(80, 60, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 30, 20);
+        let planes = Planes::from_image(&DynamicImage::ImageRgb8(frame));
+
+        let refs = [RefImage {
+            threshold: 0.9,
+            ..RefImage::new("t", Arc::new(DynamicImage::ImageRgb8(patch(1))))
+        }];
+        let prepared = prepare_reference_images(&refs);
+        let matches = find_template_matches_hashed(
+            &planes,
+            &integral_image(&planes.gray),
+            &prepared[0],
+            0,
+            &MatchOptions::default().passes()[0],
+            0.9,
+            4,
+        );
+        assert!(!matches.is_empty(), "embedded template should be found");
+        assert_eq!((matches[0].x, matches[0].y), (38, 28));
+    }
+
+    #[test]
+    fn test_prepared_screenshot_crops_to_viewport() {
+        let mut frame = RgbImage::from_pixel(1920, 1080, image::Rgb([40, 40, 40]));
+        let viewport = Viewport::default();
+        image::imageops::replace(
+            &mut frame,
+            &patch(1),
+            viewport.left as i64,
+            viewport.top as i64,
+        );
+        let prepared = PreparedScreenshot::new(&DynamicImage::ImageRgb8(frame), viewport);
+
+        assert_eq!(
+            (prepared.width(), prepared.height()),
+            (viewport.width(), viewport.height())
+        );
+        assert_eq!(prepared.to_screen(0, 0), (viewport.left, viewport.top));
+        // Viewport origin lands at plane origin
+        assert_eq!(
+            prepared.planes.rgb[0].get_pixel(0, 0).0[0],
+            patch(1).get_pixel(0, 0).0[0]
+        );
+    }
+
+    #[test]
+    fn test_prepared_screenshot_from_clipped_capture() {
+        let frame = RgbImage::from_fn(1920, 1080, |x, y| {
+            image::Rgb([(x % 251) as u8, (y % 241) as u8, ((x + y) % 7) as u8])
+        });
+        let viewport = Viewport::default();
+        let clipped = image::imageops::crop_imm(
+            &frame,
+            viewport.left,
+            viewport.top,
+            viewport.width(),
+            viewport.height(),
+        )
+        .to_image();
+
+        let full = PreparedScreenshot::new(&DynamicImage::ImageRgb8(frame), viewport);
+        let region = PreparedScreenshot::from_cropped(&DynamicImage::ImageRgb8(clipped), viewport);
+        assert_eq!(region.viewport, full.viewport);
+        assert_eq!(region.planes.gray, full.planes.gray);
+        assert_eq!(region.to_screen(3, 4), full.to_screen(3, 4));
+    }
+
+    #[test]
+    fn test_hue_saturation() {
+        let rgb = RgbImage::from_fn(4, 1, |x, _| {
+            image::Rgb(match x {
+                0 => [255, 0, 0],
+                1 => [0, 255, 0],
+                2 => [0, 0, 128],
+                _ => [90, 90, 90],
+            })
+        });
+        let [hue, sat] = hue_saturation(&rgb);
+        let hs: Vec<(u8, u8)> = (0..4)
+            .map(|x| (hue.get_pixel(x, 0).0[0], sat.get_pixel(x, 0).0[0]))
+            .collect();
+        assert_eq!(hs, [(0, 255), (85, 255), (170, 255), (0, 0)]);
+    }
+
+    #[test]
+    fn test_hsv_pass_finds_embedded_template() {
+        let mut frame = RgbImage::from_pixel(80, 60, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 30, 20);
+        let planes = Planes::from_image(&DynamicImage::ImageRgb8(frame));
+
+        let refs = [RefImage {
+            threshold: 0.9,
+            ..RefImage::new("t", Arc::new(DynamicImage::ImageRgb8(patch(1))))
+        }];
+        let prepared = prepare_reference_images(&refs);
+        let opts = MatchOptions {
+            color_space: ColorSpace::Hsv,
+            ..Default::default()
+        };
+        let matches =
+            find_template_matches_cascade(&planes, &prepared[0], 0, &opts.passes()[0], 0.9)
+                .unwrap();
+        assert_eq!(
+            matches.first().map(|m| (m.x, m.y)),
+            Some((38, 28)),
+            "embedded template should be found on hue/sat/edge"
+        );
+        assert_eq!("HSV".parse::<ColorSpace>(), Ok(ColorSpace::Hsv));
+    }
+
+    #[test]
+    fn test_channel_weights_filter_passes() {
+        let weights: ChannelWeights = "r, g,B,edge:0.5".parse().unwrap();
+        assert_eq!(weights.get(Channel::Edge), 0.5);
+        assert_eq!(weights.get(Channel::Hue), 0.0);
+        assert!("r,foo".parse::<ChannelWeights>().is_err());
+        assert!("r:-1".parse::<ChannelWeights>().is_err());
+        assert!("edge:0".parse::<ChannelWeights>().is_err());
+
+        let opts = MatchOptions {
+            color_space: ColorSpace::Both,
+            channel_weights: "r,g,b".parse().unwrap(),
+            ..Default::default()
+        };
+        let passes = opts.passes();
+        assert_eq!(passes.len(), 1, "HSV pass has no enabled channels left");
+        assert_eq!(passes[0].channels, [Channel::R, Channel::G, Channel::B]);
+    }
+
+    #[test]
+    fn test_pass_combine() {
+        let opts = MatchOptions {
+            channel_weights: "r,g,b,edge:0.5".parse().unwrap(),
+            aggregation: Aggregation::Mean,
+            ..Default::default()
+        };
+        let pass = &opts.passes()[0];
+        let mean = pass.combine([1.0, 1.0, 1.0, 0.3]);
+        assert!((mean - 3.15 / 3.5).abs() < 1e-6, "got {mean}");
+        // Unscored channels count as perfect, bounding the final score
+        assert!(pass.combine([0.9]) >= pass.combine([0.9, 1.0, 1.0, 0.0]));
+
+        let min = &MatchOptions::default().passes()[0];
+        assert_eq!(min.combine([1.0, 0.95, 0.99, 0.3]), 0.3);
+    }
+
+    #[test]
+    fn test_near_miss_from_matching_pass() {
+        let mut frame = RgbImage::from_pixel(80, 60, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 30, 20);
+        let viewport = Viewport {
+            left: 0,
+            top: 0,
+            right: 80,
+            bottom: 60,
+        };
+        let screenshot = PreparedScreenshot::new(&DynamicImage::ImageRgb8(frame), viewport);
+        let opts = MatchOptions::default();
+        let prepare = |threshold| {
+            prepare_reference_images(&[RefImage {
+                threshold,
+                ..RefImage::new("t", Arc::new(DynamicImage::ImageRgb8(patch(1))))
+            }])
+        };
+
+        // Out of reach: no matches, but the same pass keeps the near miss
+        let out_of_reach = prepare(1.5);
+        assert!(
+            find_matches(&screenshot, &out_of_reach, &opts)
+                .unwrap()
+                .is_empty()
+        );
+        let (matches, near_miss) =
+            find_matches_or_near_miss(&screenshot, &out_of_reach, &opts, 0.9).unwrap();
+        assert!(matches.is_empty());
+        let near_miss = near_miss.expect("near miss above the floor");
+        assert_eq!((near_miss.x, near_miss.y), (38, 28));
+
+        let (matches, near_miss) =
+            find_matches_or_near_miss(&screenshot, &prepare(0.9), &opts, 0.5).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(near_miss.is_none(), "only reported without matches");
+    }
+
+    #[test]
+    fn test_score_heatmap_marks_match() {
+        let mut frame = RgbImage::from_pixel(80, 60, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 30, 20);
+        let viewport = Viewport {
+            left: 0,
+            top: 0,
+            right: 80,
+            bottom: 60,
+        };
+        let screenshot = PreparedScreenshot::new(&DynamicImage::ImageRgb8(frame), viewport);
+        let refs = [RefImage::new(
+            "t",
+            Arc::new(DynamicImage::ImageRgb8(patch(1))),
+        )];
+        let prepared = prepare_reference_images(&refs);
+
+        let opts = MatchOptions::default();
+        let scores = score_map(&screenshot, &prepared, &opts);
+        let hottest = (0..scores.len()).max_by(|&a, &b| scores[a].total_cmp(&scores[b]));
+        assert_eq!(
+            hottest,
+            Some(28 * 80 + 38),
+            "match centre should be hottest"
+        );
+        assert!(scores[28 * 80 + 38] > 0.9);
+        assert_eq!(
+            scores[0],
+            f32::NEG_INFINITY,
+            "no template centre lands on the corner"
+        );
+
+        let heatmap = score_heatmap(&screenshot, &prepared, &opts);
+        assert_eq!(heatmap.dimensions(), (80, 60));
+        assert_eq!(heat_color(f32::NEG_INFINITY), [0, 0, 0]);
+        assert_eq!(heat_color(0.0), [0, 0, 255]);
+    }
+
+    fn prepared_of_size(w: u32, h: u32) -> Vec<PreparedRef> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+            image::Rgb([(x * 5) as u8, (y * 7) as u8, 90])
+        }));
+        prepare_reference_images(&[RefImage::new("t", Arc::new(img))])
+    }
+
+    fn m(x: u32, y: u32, score: f32) -> TemplateMatch {
+        TemplateMatch {
+            x,
+            y,
+            score,
+            template: 0,
+            channels: None,
+            subpixel: (0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_nms_collapses_plateau() {
+        let refs = prepared_of_size(48, 36);
+        let plateau = [m(100, 100, 0.985), m(101, 100, 0.99), m(102, 101, 0.986)];
+        let kept = non_max_suppression(&plateau, &refs, NMS_IOU_THRESHOLD);
+        assert_eq!(kept.len(), 1);
+        assert_eq!((kept[0].x, kept[0].y), (101, 100));
+    }
+
+    #[test]
+    fn test_nms_keeps_adjacent_buildings() {
+        // 39px apart horizontally: the old 40px box dedup merged these
+        let refs = prepared_of_size(48, 36);
+        let kept = non_max_suppression(
+            &[m(100, 100, 0.99), m(139, 100, 0.985)],
+            &refs,
+            NMS_IOU_THRESHOLD,
+        );
+        assert_eq!(kept.len(), 2);
+    }
+
+    #[test]
+    fn test_nms_suppresses_offset_duplicate() {
+        // Same building detected twice a few pixels apart (not neighbours)
+        let refs = prepared_of_size(48, 36);
+        let kept = non_max_suppression(
+            &[m(100, 100, 0.99), m(106, 104, 0.982)],
+            &refs,
+            NMS_IOU_THRESHOLD,
+        );
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].score, 0.99);
+    }
+
+    #[test]
+    fn test_merge_regions() {
+        let merged = merge_regions(vec![
+            (0, 0, 10, 10),
+            (100, 100, 110, 110),
+            (8, 8, 20, 20),
+            (19, 0, 30, 5),
+        ]);
+        assert_eq!(merged.len(), 2);
+        assert!(merged.contains(&(0, 0, 30, 20)));
+        assert!(merged.contains(&(100, 100, 110, 110)));
+    }
+
+    /// Counts calls and reports one fixed match.
+    struct CountingDetector(std::sync::atomic::AtomicUsize);
+
+    impl Detector for CountingDetector {
+        fn refs(&self) -> &[PreparedRef] {
+            &[]
+        }
+
+        fn find_matches(&self, screenshot: &PreparedScreenshot) -> Result<Vec<TemplateMatch>> {
+            Ok(self.find_best_match(screenshot).into_iter().collect())
+        }
+
+        fn find_best_match(&self, _: &PreparedScreenshot) -> Option<TemplateMatch> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Some(m(5, 5, 0.99))
+        }
+    }
+
+    #[test]
+    fn test_caching_detector_reuses_identical_frames() {
+        let encode = |seed| {
+            let mut png = Vec::new();
+            DynamicImage::ImageRgb8(patch(seed))
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            png
+        };
+        let viewport = Viewport {
+            left: 0,
+            top: 0,
+            right: 12,
+            bottom: 12,
+        };
+        let inner = Arc::new(CountingDetector(Default::default()));
+        let detector = CachingDetector::new(inner.clone());
+        let calls = || inner.0.load(std::sync::atomic::Ordering::Relaxed);
+
+        let frame = encode(1);
+        let first = PreparedScreenshot::from_bytes(&frame, viewport).unwrap();
+        let again = PreparedScreenshot::from_bytes(&frame, viewport).unwrap();
+        assert!(detector.find_best_match(&first).is_some());
+        assert!(detector.find_best_match(&again).is_some());
+        assert_eq!(calls(), 1, "identical bytes hit the cache");
+
+        detector.find_matches(&again).unwrap();
+        assert_eq!(calls(), 2, "a different query is computed separately");
+
+        let other = PreparedScreenshot::from_bytes(&encode(2), viewport).unwrap();
+        detector.find_best_match(&other);
+        assert_eq!(calls(), 3, "a different frame misses");
+
+        let fresh = PreparedScreenshot::new(&image::load_from_memory(&frame).unwrap(), viewport);
+        detector.find_best_match(&fresh);
+        assert_eq!(
+            calls(),
+            4,
+            "screenshots without a source hash bypass the cache"
+        );
+    }
+
+    #[test]
+    fn test_region_search_reports_full_screenshot_coordinates() {
+        let mut frame = RgbImage::from_pixel(200, 120, image::Rgb([40, 40, 40]));
+        image::imageops::replace(&mut frame, &patch(1), 110, 60);
+        // A decoy outside the region of interest
+        image::imageops::replace(&mut frame, &patch(1), 20, 20);
+        let viewport = Viewport {
+            left: 10,
+            top: 5,
+            right: 190,
+            bottom: 115,
+        };
+        let screenshot = PreparedScreenshot::new(&DynamicImage::ImageRgb8(frame), viewport);
+        let refs = [RefImage::new(
+            "t",
+            Arc::new(DynamicImage::ImageRgb8(patch(1))),
+        )];
+        let prepared = prepare_reference_images(&refs);
+
+        let roi = screenshot.region(Roi::around(120, 70, 30, 30));
+        assert_eq!((roi.width(), roi.height()), (60, 60));
+        let best = find_best_match(&roi, &prepared, &MatchOptions::default()).unwrap();
+        assert_eq!((best.x, best.y), (118, 68));
+
+        // Clamped to the viewport rather than running off the planes
+        let edge = screenshot.region(Roi::around(185, 110, 30, 30));
+        assert_eq!((edge.viewport.right, edge.viewport.bottom), (190, 115));
+    }
+
+    #[test]
+    fn test_parabolic_offset() {
+        assert_eq!(parabolic_offset(0.9, 1.0, 0.9), 0.0);
+        assert!(
+            parabolic_offset(0.8, 1.0, 0.9) > 0.0,
+            "leans toward the higher side"
+        );
+        assert!(parabolic_offset(0.9, 1.0, 0.8) < 0.0);
+        assert_eq!(parabolic_offset(1.0, 0.9, 1.0), 0.0, "not a maximum");
+        assert_eq!(
+            parabolic_offset(0.0, 1.0, 1.0),
+            0.5,
+            "clamped to half a pixel"
+        );
+    }
+
+    #[test]
+    fn test_best_match_is_refined_to_subpixel() {
+        let blob = |cx: f32, cy: f32| {
+            move |x: u32, y: u32| {
+                let d2 = (x as f32 - cx).powi(2) + (y as f32 - cy).powi(2);
+                let v = (40.0 + 200.0 * (-d2 / 18.0).exp()) as u8;
+                image::Rgb([v, v, v])
+            }
+        };
+        let template = RgbImage::from_fn(17, 17, blob(8.0, 8.0));
+        let frame = RgbImage::from_fn(80, 60, blob(40.3, 30.0));
+        let viewport = Viewport {
+            left: 0,
+            top: 0,
+            right: 80,
+            bottom: 60,
+        };
+        let screenshot = PreparedScreenshot::new(&DynamicImage::ImageRgb8(frame), viewport);
+        let refs = [RefImage::new(
+            "blob",
+            Arc::new(DynamicImage::ImageRgb8(template)),
+        )];
+        let prepared = prepare_reference_images(&refs);
+
+        let best = find_best_match(&screenshot, &prepared, &MatchOptions::default()).unwrap();
+        assert_eq!((best.x, best.y), (40, 30));
+        let (x, y) = best.position();
+        assert!((x - 40.3).abs() < 0.15, "x refined to {x}");
+        assert!((y - 30.0).abs() < 0.15, "y refined to {y}");
+    }
+
+    #[test]
+    fn test_parallel_map_keeps_input_order() {
+        let items: Vec<u32> = (0..100).collect();
+        let squared = parallel_map(&items, |&i| i * i);
+        assert_eq!(squared, items.iter().map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_coarse_to_fine_finds_embedded_template() {
+        let big_patch = RgbImage::from_fn(48, 36, |x, y| {
+            let v = ((x / 4 * 37 + y / 4 * 91) % 251) as u8;
+            image::Rgb([v, v.wrapping_mul(3), 255 - v])
+        });
+        let mut frame = RgbImage::from_fn(200, 120, |x, y| {
+            let v = ((x * 7919 + y * 104729) % 241) as u8;
+            image::Rgb([v, 255 - v, v / 2])
+        });
+        image::imageops::replace(&mut frame, &big_patch, 120, 60);
+        let planes = Planes::from_image(&DynamicImage::ImageRgb8(frame));
+
+        let refs = [RefImage {
+            threshold: 0.9,
+            ..RefImage::new("t", Arc::new(DynamicImage::ImageRgb8(big_patch)))
+        }];
+        let prepared = prepare_reference_images(&refs);
+        let pass = &MatchOptions::default().passes()[0];
+        let matches = find_template_matches_coarse(&planes, &prepared[0], 0, pass, 0.9, 4).unwrap();
+        assert!(!matches.is_empty(), "embedded template should be found");
+        assert_eq!((matches[0].x, matches[0].y), (144, 78));
+    }
 }
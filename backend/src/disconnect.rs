@@ -0,0 +1,59 @@
+//! Detection of the game's "connection lost" dialog by screenshot.
+//!
+//! Complements the DOM check in [`crate::browser::GameBrowser::detect_disconnect`]
+//! for the dialog the game draws inside its canvas. Templates are
+//! `disconnect_*.png` crops of the dialog in the assets directory, plus an
+//! optional `reload_button.png` crop of its reload button; none ship by
+//! default. Without a button crop, recovery reloads the page instead.
+
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::challenge::{self, DialogMatch, DialogTemplate};
+use crate::detector::asset_search_dirs;
+
+pub struct DisconnectTemplates {
+    pub dialogs: Vec<DialogTemplate>,
+    pub reload_button: Option<DialogTemplate>,
+}
+
+/// Load the `disconnect_*.png` dialog templates and `reload_button.png`.
+pub fn load_disconnect_templates() -> Result<DisconnectTemplates> {
+    let dialogs = challenge::load_dialog_templates("disconnect_")?;
+    let reload_button = asset_search_dirs()
+        .into_iter()
+        .map(|d| d.join("reload_button.png"))
+        .find(|p| p.exists())
+        .and_then(|path| match image::open(&path) {
+            Ok(img) => Some(DialogTemplate {
+                name: "reload_button.png".into(),
+                image: challenge::downscale(&img),
+            }),
+            Err(e) => {
+                tracing::warn!("failed to load {}: {e}", path.display());
+                None
+            }
+        });
+    Ok(DisconnectTemplates {
+        dialogs,
+        reload_button,
+    })
+}
+
+impl DisconnectTemplates {
+    pub fn is_empty(&self) -> bool {
+        self.dialogs.is_empty()
+    }
+
+    /// The disconnect dialog, if it's in the screenshot.
+    pub fn find_dialog(&self, screenshot: &DynamicImage) -> Option<DialogMatch> {
+        challenge::find_dialog(screenshot, &self.dialogs)
+    }
+
+    /// Where to click the reload button in a full screenshot, if there's a
+    /// button template and it's showing.
+    pub fn find_reload_button(&self, screenshot: &DynamicImage) -> Option<(f64, f64)> {
+        let button = self.reload_button.as_ref()?;
+        challenge::find_dialog(screenshot, std::slice::from_ref(button)).map(|m| (m.x, m.y))
+    }
+}
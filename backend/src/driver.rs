@@ -0,0 +1,134 @@
+//! The browser primitives the game automation is built on, behind traits so
+//! the engine can be swapped (`MERCY_BROWSER`): Chromium over CDP
+//! (`chromium.rs`) or Firefox over WebDriver BiDi (`bidi.rs`).
+//!
+//! Everything here works in page (CSS) pixels. Mapping from the reference
+//! frame, human-like pointer paths and the game's own quirks stay in
+//! `browser.rs`, shared by both engines.
+
+use anyhow::Result;
+use chromiumoxide::cdp::browser_protocol::network::CookieParam;
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+
+use crate::browser::ScreenshotFormat;
+use crate::frame::CaptureClip;
+
+/// Which browser engine to drive (`MERCY_BROWSER`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BrowserKind {
+    #[default]
+    Chromium,
+    Firefox,
+}
+
+impl std::str::FromStr for BrowserKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chromium" | "chrome" => Ok(BrowserKind::Chromium),
+            "firefox" => Ok(BrowserKind::Firefox),
+            other => Err(format!("unknown browser: {other}")),
+        }
+    }
+}
+
+/// A running browser (or a connection to one), shared by all of its tabs.
+pub trait Driver: Send + Sync {
+    /// Open a tab with the configured fingerprint applied and its console
+    /// captured. Tabs after the first go in their own window so none of them
+    /// is a background tab (which browsers stop rendering).
+    fn new_tab(&self, new_window: bool) -> BoxFuture<'_, Result<Box<dyn Tab>>>;
+
+    /// False once the browser exited or the connection to it dropped.
+    fn is_connected(&self) -> bool;
+
+    /// The browser's process id, for a locally launched browser.
+    fn pid(&self) -> Option<u32>;
+
+    /// Ask a locally launched browser to exit. A browser attached to is left
+    /// running; our tabs go with the connection.
+    fn close(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// One tab.
+pub trait Tab: Send + Sync {
+    fn goto<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    fn reload(&self) -> BoxFuture<'_, Result<()>>;
+
+    fn url(&self) -> BoxFuture<'_, Result<Option<String>>>;
+
+    /// Evaluate a JS expression (awaiting it if it's a promise).
+    fn evaluate<'a>(&'a self, expression: &'a str) -> BoxFuture<'a, Result<Evaluation>>;
+
+    fn screenshot(&self, capture: Capture) -> BoxFuture<'_, Result<Vec<u8>>>;
+
+    /// Dispatch a left-button pointer event at page pixel (`x`, `y`).
+    fn mouse(&self, action: MouseAction, x: f64, y: f64) -> BoxFuture<'_, Result<()>>;
+
+    fn key(&self, action: KeyAction, key: Key) -> BoxFuture<'_, Result<()>>;
+
+    /// Scroll the mouse wheel at page pixel (`x`, `y`) by (`dx`, `dy`) pixels.
+    fn wheel(&self, x: f64, y: f64, dx: f64, dy: f64) -> BoxFuture<'_, Result<()>>;
+
+    fn set_cookies(&self, cookies: Vec<CookieParam>) -> BoxFuture<'_, Result<()>>;
+}
+
+/// The value of an evaluated expression as JSON: `null` for `undefined` and
+/// values that don't serialize.
+#[derive(Debug, Clone)]
+pub struct Evaluation(pub serde_json::Value);
+
+impl Evaluation {
+    pub fn into_value<T: DeserializeOwned>(self) -> serde_json::Result<T> {
+        serde_json::from_value(self.0)
+    }
+}
+
+/// A screenshot request.
+#[derive(Debug, Clone, Copy)]
+pub struct Capture {
+    pub format: ScreenshotFormat,
+    /// 1-100, lossy formats only
+    pub quality: Option<u8>,
+    /// The whole viewport when `None`
+    pub clip: Option<CaptureClip>,
+}
+
+impl Capture {
+    pub fn png() -> Self {
+        Self {
+            format: ScreenshotFormat::Png,
+            quality: None,
+            clip: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Move,
+    /// A move with the left button held
+    Drag,
+    Press,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Down,
+    Up,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A named key whose DOM `key` and `code` are the same: `Escape`,
+    /// `Enter`, `Tab`, `Backspace`
+    Named(&'static str),
+    /// A character that types itself
+    Char(char),
+    /// A letter with Ctrl held
+    Ctrl(char),
+}
@@ -0,0 +1,134 @@
+//! What the scanner did recently, for `GET /events/recent`: phase changes,
+//! detections, confirmations and errors, in a ring buffer of the last
+//! `MERCY_MAX_EVENTS` events.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::state::ScannerPhase;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    Phase {
+        from: ScannerPhase,
+        to: ScannerPhase,
+    },
+    /// A scan step's best match, about to be clicked for confirmation
+    Detection {
+        kingdom: u32,
+        step: usize,
+        score: f32,
+    },
+    /// The outcome of clicking a detection
+    Confirmation {
+        kingdom: u32,
+        x: u32,
+        y: u32,
+        /// Coordinates read from the popup rather than estimated
+        confirmed: bool,
+        /// Added to the exchanges, if only in memory (false for duplicates)
+        stored: bool,
+    },
+    Error {
+        kingdom: Option<u32>,
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    /// Events kept in memory; the oldest are dropped past it
+    capacity: usize,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn push(&mut self, kind: EventKind) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            time: Utc::now(),
+            kind,
+        });
+    }
+
+    /// The last `limit` events no older than `since`, oldest first.
+    pub fn recent(&self, limit: usize, since: Option<DateTime<Utc>>) -> Vec<Event> {
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .filter(|e| since.is_none_or(|since| e.time >= since))
+            .collect();
+        let skip = events.len().saturating_sub(limit);
+        events.into_iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_recent() {
+        let mut log = EventLog::new(100);
+        for kingdom in 0..105 {
+            log.push(EventKind::Error {
+                kingdom: Some(kingdom),
+                message: String::new(),
+            });
+        }
+        let kingdoms = |events: Vec<Event>| -> Vec<_> {
+            events
+                .into_iter()
+                .map(|e| match e.kind {
+                    EventKind::Error { kingdom, .. } => kingdom.unwrap(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        let all = log.recent(usize::MAX, None);
+        assert_eq!(all.len(), 100);
+        assert_eq!(log.len(), 100);
+        assert_eq!(kingdoms(all)[0], 5);
+        assert_eq!(kingdoms(log.recent(2, None)), [103, 104]);
+        assert!(
+            log.recent(10, Some(Utc::now() + chrono::Duration::seconds(1)))
+                .is_empty()
+        );
+
+        let json = serde_json::to_value(&log.recent(1, None)[0]).unwrap();
+        assert_eq!(json["kind"], "error");
+        assert_eq!(json["kingdom"], 104);
+    }
+}
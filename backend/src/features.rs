@@ -0,0 +1,396 @@
+//! Keypoint-based detection, an alternative to rigid template matching.
+//!
+//! FAST corners are described with BRIEF-style binary descriptors (256
+//! intensity comparisons on a box-smoothed patch), matched by Hamming
+//! distance, and grouped by RANSAC into scale + rotation + translation
+//! transforms from template to screenshot. Unlike NCC this tolerates slight
+//! zoom and perspective differences, at the cost of needing templates with
+//! enough texture to produce corners.
+
+use std::sync::OnceLock;
+
+use image::GrayImage;
+use imageproc::corners::corners_fast9;
+use imageproc::integral_image::{integral_image, sum_image_pixels};
+use imageproc::suppress::local_maxima;
+
+use anyhow::Result;
+
+use crate::detector::{
+    self, Detector, MatchOptions, NMS_IOU_THRESHOLD, PreparedRef, PreparedScreenshot,
+    TemplateMatch, non_max_suppression, reject_negatives_and_offset,
+};
+
+/// Half-size of the square patch the descriptor tests sample from.
+const PATCH_RADIUS: i32 = 7;
+/// Radius of the box filter applied before each intensity test.
+const SMOOTH_RADIUS: u32 = 2;
+/// Minimum FAST-9 contrast for a pixel to count as a corner.
+const FAST_THRESHOLD: u8 = 20;
+/// Corners closer than this to a stronger corner are dropped.
+const CORNER_SUPPRESS_RADIUS: u32 = 2;
+/// 4 × 64 = 256 descriptor bits.
+const DESCRIPTOR_WORDS: usize = 4;
+/// Maximum differing bits for two descriptors to be considered a match.
+const MAX_HAMMING: u32 = 64;
+/// Screenshot keypoints kept per template keypoint. More than one so that
+/// several instances of the building can all be found.
+const CANDIDATES_PER_KEYPOINT: usize = 8;
+/// Distance (px) within which a transformed template keypoint counts as an inlier.
+const INLIER_TOLERANCE: f32 = 3.0;
+const RANSAC_ITERATIONS: usize = 500;
+/// Minimum distinct template keypoints agreeing on one transform.
+const MIN_INLIERS: usize = 6;
+/// Minimum fraction of template keypoints that must be inliers. This is also
+/// the reported match score.
+pub const MIN_INLIER_RATIO: f32 = 0.3;
+/// Accepted zoom range relative to the reference.
+const SCALE_RANGE: (f32, f32) = (0.75, 1.33);
+/// Accepted rotation (radians); the map view itself never rotates.
+const MAX_ROTATION: f32 = 0.2;
+/// Upper bound on instances of one template reported per screenshot.
+const MAX_INSTANCES: usize = 10;
+
+/// A corner plus its binary descriptor.
+#[derive(Debug, Clone)]
+pub struct Keypoint {
+    pub x: u32,
+    pub y: u32,
+    bits: [u64; DESCRIPTOR_WORDS],
+}
+
+impl Keypoint {
+    fn hamming(&self, other: &Keypoint) -> u32 {
+        self.bits
+            .iter()
+            .zip(&other.bits)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+/// Small deterministic PRNG so test pairs and RANSAC samples are reproducible.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Fixed set of (dx0, dy0, dx1, dy1) offsets compared by every descriptor.
+/// Offsets are the sum of two uniform draws, biasing them towards the patch
+/// centre like BRIEF's Gaussian sampling.
+fn test_pairs() -> &'static [(i32, i32, i32, i32)] {
+    static PAIRS: OnceLock<Vec<(i32, i32, i32, i32)>> = OnceLock::new();
+    PAIRS.get_or_init(|| {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        let span = (PATCH_RADIUS + 1) as usize;
+        let mut offset = || (rng.below(span) + rng.below(span)) as i32 - PATCH_RADIUS;
+        (0..DESCRIPTOR_WORDS * 64)
+            .map(|_| (offset(), offset(), offset(), offset()))
+            .collect()
+    })
+}
+
+/// Detect corners in `gray` and compute a descriptor for each one far enough
+/// from the border for its whole patch to fit.
+pub fn extract_keypoints(gray: &GrayImage) -> Vec<Keypoint> {
+    let (w, h) = gray.dimensions();
+    let margin = PATCH_RADIUS as u32 + SMOOTH_RADIUS;
+    if w <= 2 * margin || h <= 2 * margin {
+        return Vec::new();
+    }
+
+    let integral = integral_image::<_, u32>(gray);
+    // Mean over a (2r+1)² box, so single-pixel noise doesn't flip bits
+    let smoothed = |x: i32, y: i32| {
+        let (x, y) = (x as u32, y as u32);
+        let sum = sum_image_pixels(
+            &integral,
+            x - SMOOTH_RADIUS,
+            y - SMOOTH_RADIUS,
+            x + SMOOTH_RADIUS,
+            y + SMOOTH_RADIUS,
+        )[0];
+        sum / (2 * SMOOTH_RADIUS + 1).pow(2)
+    };
+
+    let corners = local_maxima(&corners_fast9(gray, FAST_THRESHOLD), CORNER_SUPPRESS_RADIUS);
+    corners
+        .into_iter()
+        .filter(|c| c.x >= margin && c.y >= margin && c.x < w - margin && c.y < h - margin)
+        .map(|c| {
+            let (cx, cy) = (c.x as i32, c.y as i32);
+            let mut bits = [0u64; DESCRIPTOR_WORDS];
+            for (i, &(dx0, dy0, dx1, dy1)) in test_pairs().iter().enumerate() {
+                if smoothed(cx + dx0, cy + dy0) < smoothed(cx + dx1, cy + dy1) {
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+            Keypoint {
+                x: c.x,
+                y: c.y,
+                bits,
+            }
+        })
+        .collect()
+}
+
+/// Keypoint-based [`Detector`].
+///
+/// Only `find_matches` uses keypoints. The best-match queries used for
+/// calibration and re-verification fall back to NCC, whose score scale the
+/// scanner's acceptance thresholds are tuned for.
+pub struct FeatureDetector {
+    refs: Vec<PreparedRef>,
+    opts: MatchOptions,
+}
+
+impl FeatureDetector {
+    pub fn new(refs: Vec<PreparedRef>, opts: MatchOptions) -> Self {
+        Self { refs, opts }
+    }
+}
+
+impl Detector for FeatureDetector {
+    fn refs(&self) -> &[PreparedRef] {
+        &self.refs
+    }
+
+    fn find_matches(&self, screenshot: &PreparedScreenshot) -> Result<Vec<TemplateMatch>> {
+        let planes = &screenshot.planes;
+        let keypoints = extract_keypoints(&planes.gray);
+        // Negative templates are still checked with NCC on the first pass's channels
+        let passes = self.opts.passes();
+
+        let mut all_matches = Vec::new();
+        for (template_idx, prepared) in self.refs.iter().enumerate() {
+            if prepared.negative {
+                continue;
+            }
+            let matches = match_keypoints(&keypoints, prepared, template_idx);
+            all_matches.extend(reject_negatives_and_offset(
+                screenshot, &self.refs, matches, &passes[0],
+            ));
+        }
+        Ok(non_max_suppression(
+            &all_matches,
+            &self.refs,
+            NMS_IOU_THRESHOLD,
+        ))
+    }
+
+    fn find_best_match(&self, screenshot: &PreparedScreenshot) -> Option<TemplateMatch> {
+        detector::find_best_match(screenshot, &self.refs, &self.opts)
+    }
+}
+
+/// Template → screenshot mapping: scale, rotation, then translation.
+#[derive(Debug, Clone, Copy)]
+struct Similarity {
+    /// scale·cos θ and scale·sin θ
+    a: f32,
+    b: f32,
+    tx: f32,
+    ty: f32,
+}
+
+impl Similarity {
+    /// Fit the transform taking template points `p1`, `p2` to screenshot
+    /// points `q1`, `q2`. `None` if it falls outside the accepted zoom or
+    /// rotation range.
+    fn from_pairs(p1: (f32, f32), p2: (f32, f32), q1: (f32, f32), q2: (f32, f32)) -> Option<Self> {
+        let (px, py) = (p2.0 - p1.0, p2.1 - p1.1);
+        let (qx, qy) = (q2.0 - q1.0, q2.1 - q1.1);
+        let p_len = px.hypot(py);
+        if p_len < 4.0 {
+            return None;
+        }
+        let scale = qx.hypot(qy) / p_len;
+        let rotation = qy.atan2(qx) - py.atan2(px);
+        let rotation = (rotation + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU)
+            - std::f32::consts::PI;
+        if !(SCALE_RANGE.0..=SCALE_RANGE.1).contains(&scale) || rotation.abs() > MAX_ROTATION {
+            return None;
+        }
+        let (a, b) = (scale * rotation.cos(), scale * rotation.sin());
+        Some(Self {
+            a,
+            b,
+            tx: q1.0 - (a * p1.0 - b * p1.1),
+            ty: q1.1 - (b * p1.0 + a * p1.1),
+        })
+    }
+
+    fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            self.a * x - self.b * y + self.tx,
+            self.b * x + self.a * y + self.ty,
+        )
+    }
+}
+
+/// Putative correspondence: template keypoint index, template point, screenshot point.
+type Correspondence = (usize, (f32, f32), (f32, f32));
+
+/// Find every instance of `template` among the screenshot keypoints.
+///
+/// Repeatedly fits a transform with RANSAC, reports it if enough distinct
+/// template keypoints agree, then drops the correspondences inside the found
+/// instance and searches again. Coordinates are in the same space as the
+/// screenshot keypoints; the score is the inlier fraction.
+pub fn match_keypoints(
+    screenshot: &[Keypoint],
+    template: &PreparedRef,
+    template_idx: usize,
+) -> Vec<TemplateMatch> {
+    let template_kps = &template.keypoints;
+    if template_kps.len() < MIN_INLIERS {
+        tracing::debug!(
+            "template {}: only {} keypoints, too few for feature matching",
+            template.name,
+            template_kps.len()
+        );
+        return Vec::new();
+    }
+
+    let mut corrs: Vec<Correspondence> = Vec::new();
+    for (ti, tk) in template_kps.iter().enumerate() {
+        let mut nearest: Vec<(u32, &Keypoint)> = screenshot
+            .iter()
+            .map(|sk| (tk.hamming(sk), sk))
+            .filter(|&(d, _)| d <= MAX_HAMMING)
+            .collect();
+        nearest.sort_by_key(|&(d, _)| d);
+        corrs.extend(
+            nearest
+                .into_iter()
+                .take(CANDIDATES_PER_KEYPOINT)
+                .map(|(_, sk)| (ti, (tk.x as f32, tk.y as f32), (sk.x as f32, sk.y as f32))),
+        );
+    }
+
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut matches = Vec::new();
+    while matches.len() < MAX_INSTANCES && corrs.len() >= MIN_INLIERS {
+        let mut best: Option<(Similarity, usize)> = None;
+        for _ in 0..RANSAC_ITERATIONS {
+            let (i, j) = (rng.below(corrs.len()), rng.below(corrs.len()));
+            if corrs[i].0 == corrs[j].0 {
+                continue;
+            }
+            let Some(model) =
+                Similarity::from_pairs(corrs[i].1, corrs[j].1, corrs[i].2, corrs[j].2)
+            else {
+                continue;
+            };
+            let inliers = count_inliers(&corrs, &model, template_kps.len());
+            if best.is_none_or(|(_, n)| inliers > n) {
+                best = Some((model, inliers));
+            }
+        }
+
+        let Some((model, inliers)) = best else {
+            break;
+        };
+        let ratio = inliers as f32 / template_kps.len() as f32;
+        if inliers < MIN_INLIERS || ratio < MIN_INLIER_RATIO {
+            break;
+        }
+
+        let (cx, cy) = model.apply((template.width as f32 / 2.0, template.height as f32 / 2.0));
+        tracing::debug!(
+            "template {}: feature match at ({cx:.0}, {cy:.0}) with {inliers}/{} inliers",
+            template.name,
+            template_kps.len()
+        );
+        matches.push(TemplateMatch {
+            x: cx.round().max(0.0) as u32,
+            y: cy.round().max(0.0) as u32,
+            score: ratio,
+            template: template_idx,
+            channels: None,
+            subpixel: (0.0, 0.0),
+        });
+
+        // Forget everything inside this instance before looking for the next
+        let (half_w, half_h) = (template.width as f32 / 2.0, template.height as f32 / 2.0);
+        corrs.retain(|&(_, _, (sx, sy))| (sx - cx).abs() > half_w || (sy - cy).abs() > half_h);
+    }
+    matches
+}
+
+/// Number of distinct template keypoints with a correspondence that `model`
+/// maps to within [`INLIER_TOLERANCE`].
+fn count_inliers(corrs: &[Correspondence], model: &Similarity, template_kps: usize) -> usize {
+    let mut hit = vec![false; template_kps];
+    for &(ti, p, q) in corrs {
+        let (x, y) = model.apply(p);
+        if (x - q.0).hypot(y - q.1) <= INLIER_TOLERANCE {
+            hit[ti] = true;
+        }
+    }
+    hit.into_iter().filter(|&h| h).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use image::{DynamicImage, RgbImage};
+
+    use super::*;
+    use crate::detector::{RefImage, prepare_reference_images};
+
+    /// Blocky pseudo-random texture: plenty of FAST corners.
+    fn blocks(w: u32, h: u32) -> RgbImage {
+        RgbImage::from_fn(w, h, |x, y| {
+            let v = (((x / 6) * 7919 + (y / 6) * 104729) % 211) as u8 + 20;
+            image::Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn test_finds_slightly_zoomed_instance() {
+        let template = blocks(60, 48);
+        // Reference taken at a slightly different zoom than the screenshot
+        let zoomed =
+            image::imageops::resize(&template, 66, 53, image::imageops::FilterType::Nearest);
+        let mut frame = RgbImage::from_pixel(220, 160, image::Rgb([128, 128, 128]));
+        image::imageops::replace(&mut frame, &zoomed, 100, 60);
+
+        let refs = prepare_reference_images(&[RefImage::new(
+            "t",
+            Arc::new(DynamicImage::ImageRgb8(template)),
+        )]);
+        let screenshot = extract_keypoints(&DynamicImage::ImageRgb8(frame).to_luma8());
+        let matches = match_keypoints(&screenshot, &refs[0], 0);
+
+        assert_eq!(matches.len(), 1, "one instance expected, got {matches:?}");
+        let (x, y) = (matches[0].x as i32, matches[0].y as i32);
+        assert!(
+            (x - 133).abs() <= 3 && (y - 86).abs() <= 3,
+            "center at ({x}, {y})"
+        );
+    }
+
+    #[test]
+    fn test_similarity_round_trip() {
+        let model = Similarity::from_pairs((0.0, 0.0), (10.0, 0.0), (5.0, 5.0), (16.0, 5.0))
+            .expect("1.1x zoom is in range");
+        let (x, y) = model.apply((10.0, 10.0));
+        assert!((x - 16.0).abs() < 1e-3 && (y - 16.0).abs() < 1e-3);
+
+        // 2x zoom and 90° rotation are both rejected
+        assert!(Similarity::from_pairs((0.0, 0.0), (10.0, 0.0), (0.0, 0.0), (20.0, 0.0)).is_none());
+        assert!(Similarity::from_pairs((0.0, 0.0), (10.0, 0.0), (0.0, 0.0), (0.0, 10.0)).is_none());
+    }
+}
@@ -0,0 +1,138 @@
+//! The reference frame: every pixel constant in the bot (UI click points,
+//! viewport bounds, the screen center, the pixel-per-tile calibration and
+//! the reference templates) was measured on a 1920×1080 game canvas.
+//!
+//! At runtime the canvas can be a different size or sit at an offset (a
+//! smaller window, a remote Chrome). [`CanvasFrame`] records where it
+//! actually is, so screenshots can be captured rescaled into reference
+//! pixels and click points mapped back out to page pixels. Everything
+//! between the two works in reference pixels only.
+
+use serde::Serialize;
+
+pub const REFERENCE_WIDTH: f64 = 1920.0;
+pub const REFERENCE_HEIGHT: f64 = 1080.0;
+
+/// Size difference (page px) still treated as the reference canvas.
+const SIZE_TOLERANCE: f64 = 0.5;
+
+/// Relative aspect-ratio difference above which scaling is only approximate.
+const ASPECT_TOLERANCE: f64 = 0.01;
+
+/// The game canvas's rectangle on the page, in CSS pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CanvasFrame {
+    pub left: f64,
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Default for CanvasFrame {
+    /// The reference canvas, filling a 1920×1080 window.
+    fn default() -> Self {
+        Self {
+            left: 0.0,
+            top: 0.0,
+            width: REFERENCE_WIDTH,
+            height: REFERENCE_HEIGHT,
+        }
+    }
+}
+
+/// A capture rectangle in page pixels, and the factor that brings the
+/// captured image back to reference size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureClip {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub scale: f64,
+}
+
+impl CanvasFrame {
+    pub fn scale_x(self) -> f64 {
+        self.width / REFERENCE_WIDTH
+    }
+
+    pub fn scale_y(self) -> f64 {
+        self.height / REFERENCE_HEIGHT
+    }
+
+    /// Whether this is the reference canvas, so no mapping is needed.
+    pub fn is_reference(self) -> bool {
+        let reference = Self::default();
+        [
+            self.left - reference.left,
+            self.top - reference.top,
+            self.width - reference.width,
+            self.height - reference.height,
+        ]
+        .iter()
+        .all(|d| d.abs() <= SIZE_TOLERANCE)
+    }
+
+    /// Whether the canvas is 16:9 like the reference. Otherwise the game
+    /// lays its UI out differently and linear scaling is only approximate.
+    pub fn aspect_matches(self) -> bool {
+        (self.scale_x() / self.scale_y() - 1.0).abs() <= ASPECT_TOLERANCE
+    }
+
+    /// Map a reference-frame point to page pixels.
+    pub fn to_page(self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.left + x * self.scale_x(),
+            self.top + y * self.scale_y(),
+        )
+    }
+
+    /// Page clip for the reference rectangle (`x`, `y`, `w`, `h`). Captured
+    /// at `scale` it comes out reference-sized (exactly so for a 16:9
+    /// canvas; CDP scales both axes alike, so otherwise the height matches).
+    pub fn capture_clip(self, x: f64, y: f64, w: f64, h: f64) -> CaptureClip {
+        let (px, py) = self.to_page(x, y);
+        CaptureClip {
+            x: px,
+            y: py,
+            width: w * self.scale_x(),
+            height: h * self.scale_y(),
+            scale: 1.0 / self.scale_y(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canvas_frame_mapping() {
+        assert!(CanvasFrame::default().is_reference());
+
+        // 1280×720 canvas below a 40 px page header
+        let frame = CanvasFrame {
+            left: 0.0,
+            top: 40.0,
+            width: 1280.0,
+            height: 720.0,
+        };
+        assert!(!frame.is_reference());
+        assert!(frame.aspect_matches());
+        assert_eq!(frame.to_page(960.0, 540.0), (640.0, 400.0));
+
+        let clip = frame.capture_clip(150.0, 60.0, 1710.0, 960.0);
+        assert_eq!((clip.x, clip.y), (100.0, 80.0));
+        assert_eq!((clip.width, clip.height), (1140.0, 640.0));
+        assert_eq!(
+            (clip.width * clip.scale, clip.height * clip.scale),
+            (1710.0, 960.0)
+        );
+
+        let square = CanvasFrame {
+            width: 1080.0,
+            ..CanvasFrame::default()
+        };
+        assert!(!square.aspect_matches());
+    }
+}
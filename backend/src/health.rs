@@ -0,0 +1,173 @@
+//! Periodic browser health checks (`MERCY_HEALTH_INTERVAL_SECS`), so a hung
+//! page or a dead WebGL canvas is noticed even when no scan step happens to
+//! hit it.
+//!
+//! Each check evaluates `1+1` in the game tab and screenshots it to make sure
+//! the canvas isn't all black. After [`MAX_HEALTH_FAILURES`] failed checks in
+//! a row the browser is written off: a running scan relaunches it at its next
+//! step, and an idle one is dropped so the next scan starts fresh.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use image::DynamicImage;
+use serde::Serialize;
+use tokio::time::{Duration, timeout};
+
+use crate::browser::GameBrowser;
+use crate::notify::notify;
+use crate::state::{AppState, ScannerPhase};
+
+/// Failed checks in a row after which the browser is written off.
+const MAX_HEALTH_FAILURES: u32 = 3;
+
+/// How long each probe (evaluate, screenshot) may take.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A frame whose mean luminance and spread are both under these is blank.
+const BLANK_MAX_MEAN: f64 = 8.0;
+const BLANK_MAX_STDDEV: f64 = 4.0;
+
+/// Outcome of the latest health check, reported in `/status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub checked_at: DateTime<Utc>,
+    pub healthy: bool,
+    /// Failed checks in a row.
+    pub consecutive_failures: u32,
+    /// What the last failed check found.
+    pub error: Option<String>,
+}
+
+/// Run health checks every `interval` for as long as the process lives.
+pub fn spawn_health_monitor(state: AppState, interval: Duration) {
+    tracing::info!("browser health checks every {interval:?}");
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            check(&state).await;
+        }
+    });
+}
+
+async fn check(state: &AppState) {
+    let (game, phase, failures) = {
+        let s = state.read().await;
+        let failures = s.health.as_ref().map_or(0, |h| h.consecutive_failures);
+        (s.browser.clone(), s.phase, failures)
+    };
+    // Nothing to check, or logging in (the canvas is legitimately blank)
+    let Some(game) = game.filter(|_| {
+        matches!(
+            phase,
+            ScannerPhase::Ready | ScannerPhase::Scanning | ScannerPhase::Paused
+        )
+    }) else {
+        return;
+    };
+
+    let error = probe(&game).await.err();
+    let failures = if error.is_some() { failures + 1 } else { 0 };
+    let mut s = state.write().await;
+    if let Some(ref e) = error {
+        tracing::warn!("browser health check failed ({failures}/{MAX_HEALTH_FAILURES}): {e}");
+        s.errors.record("health", e.clone(), s.current_kingdom);
+    }
+    s.health = Some(Health {
+        checked_at: Utc::now(),
+        healthy: error.is_none(),
+        consecutive_failures: failures,
+        error: error.clone(),
+    });
+    if failures < MAX_HEALTH_FAILURES {
+        return;
+    }
+    // The browser may have been replaced while the check ran
+    if !s.browser.as_ref().is_some_and(|b| Arc::ptr_eq(b, &game)) {
+        return;
+    }
+
+    let error = error.unwrap_or_default();
+    game.mark_dead();
+    if s.phase == ScannerPhase::Ready {
+        s.browser = None;
+        s.api_tab = None;
+        s.set_phase(ScannerPhase::Idle);
+    }
+    s.health = None;
+    notify(
+        &s.config,
+        "unhealthy",
+        format!("Browser failed {failures} health checks in a row ({error}); replacing it"),
+    );
+}
+
+async fn probe(game: &GameBrowser) -> Result<(), String> {
+    match timeout(PROBE_TIMEOUT, game.ping()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return Err(format!("page not responding: {e:#}")),
+        Err(_) => return Err(format!("page did not respond within {PROBE_TIMEOUT:?}")),
+    }
+
+    let bytes = match timeout(PROBE_TIMEOUT, game.take_screenshot()).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => return Err(format!("screenshot failed: {e:#}")),
+        Err(_) => return Err(format!("screenshot took longer than {PROBE_TIMEOUT:?}")),
+    };
+    let blank = tokio::task::spawn_blocking(move || {
+        image::load_from_memory(&bytes)
+            .map(|img| is_blank_frame(&img))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| format!("screenshot check panicked: {e}"))?
+    .map_err(|e| format!("screenshot undecodable: {e}"))?;
+    if blank {
+        return Err("game canvas is blank".into());
+    }
+    Ok(())
+}
+
+/// Whether a screenshot is (nearly) uniformly black, as a lost WebGL
+/// context or a hung renderer leaves it.
+pub fn is_blank_frame(image: &DynamicImage) -> bool {
+    // Every 4th pixel each way is plenty to tell a rendered map from black
+    let luma = image.to_luma8();
+    let samples: Vec<f64> = luma
+        .enumerate_pixels()
+        .filter(|(x, y, _)| x % 4 == 0 && y % 4 == 0)
+        .map(|(_, _, p)| p.0[0] as f64)
+        .collect();
+    if samples.is_empty() {
+        return true;
+    }
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let variance = samples.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    mean < BLANK_MAX_MEAN && variance.sqrt() < BLANK_MAX_STDDEV
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_is_blank_frame() {
+        let black = DynamicImage::ImageRgb8(RgbImage::from_pixel(320, 180, Rgb([2, 2, 2])));
+        assert!(is_blank_frame(&black));
+
+        // A dark night-time map still has texture
+        let dark = DynamicImage::ImageRgb8(RgbImage::from_fn(320, 180, |x, y| {
+            let v = ((x / 8 + y / 8) % 2 * 30) as u8;
+            Rgb([v, v, v])
+        }));
+        assert!(!is_blank_frame(&dark));
+
+        let grey = DynamicImage::ImageRgb8(RgbImage::from_pixel(320, 180, Rgb([90, 90, 90])));
+        assert!(!is_blank_frame(&grey));
+    }
+}
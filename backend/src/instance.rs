@@ -0,0 +1,104 @@
+//! Single-instance lock: one lock file per game account, held for as long as
+//! the process runs, so two mercy instances can't drive the same account at
+//! once (they'd move each other's map and risk the account).
+//!
+//! The files are `mercy-<hash of the email>.lock` in `MERCY_LOCK_DIR` (the
+//! temp directory by default) and hold the owner's pid. The OS releases the
+//! locks when the process exits, however it exits, so a stale file never
+//! blocks a start.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha1::{Digest, Sha1};
+
+use crate::config::Config;
+
+/// Lock name when no account is configured (session cookies only).
+const COOKIES_ONLY: &str = "session cookies";
+
+/// Held locks; dropping it releases them.
+pub struct InstanceLock {
+    _files: Vec<File>,
+}
+
+/// Lock every configured account, failing if another process holds one.
+pub fn acquire(config: &Config) -> Result<InstanceLock> {
+    let mut accounts: Vec<&str> = config.accounts.iter().map(|a| a.email.as_str()).collect();
+    if accounts.is_empty() {
+        accounts.push(COOKIES_ONLY);
+    }
+    acquire_in(&config.lock_dir, &accounts)
+}
+
+fn acquire_in(dir: &Path, accounts: &[&str]) -> Result<InstanceLock> {
+    let files = accounts
+        .iter()
+        .map(|account| lock(dir, account))
+        .collect::<Result<_>>()?;
+    Ok(InstanceLock { _files: files })
+}
+
+fn lock(dir: &Path, account: &str) -> Result<File> {
+    let digest = Sha1::digest(account.to_lowercase().as_bytes());
+    let path = dir.join(format!(
+        "mercy-{}.lock",
+        &data_encoding::HEXLOWER.encode(&digest)[..16]
+    ));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file {}", path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {
+            file.set_len(0)?;
+            write!(file, "{}", std::process::id())?;
+            Ok(file)
+        }
+        Err(TryLockError::WouldBlock) => {
+            let mut owner = String::new();
+            let _ = file.read_to_string(&mut owner);
+            let owner = match owner.trim() {
+                "" => String::new(),
+                pid => format!(" (pid {pid})"),
+            };
+            anyhow::bail!(
+                "{account} is in use by another mercy instance{owner}; lock file {}",
+                path.display()
+            )
+        }
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("failed to lock {}", path.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_instance_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let held = acquire_in(dir.path(), &["a@example.com"]).unwrap();
+
+        let err = acquire_in(dir.path(), &["b@example.com", "A@example.com"])
+            .err()
+            .expect("the account is locked");
+        let message = err.to_string();
+        assert!(message.contains("A@example.com is in use"), "{message}");
+        assert!(
+            message.contains(&format!("pid {}", std::process::id())),
+            "{message}"
+        );
+
+        drop(held);
+        acquire_in(dir.path(), &["a@example.com", "b@example.com"]).unwrap();
+    }
+}
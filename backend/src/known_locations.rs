@@ -71668,7 +71668,6 @@ pub fn positions_for_kingdom(kingdom: u32) -> &'static [(u32, u32, u16)] {
 }
 
 /// Number of scan positions per known kingdom (for diagnostics).
-#[allow(dead_code)]
 pub const KINGDOM_STATS: &[(u32, u16, u16)] = &[
     // (kingdom, historical_spawns, scan_positions)
     (2, 428, 297),
@@ -1,2 +1,52 @@
+pub mod accounts;
+pub mod annotate;
+pub mod api;
+pub mod audit;
+pub mod bidi;
+pub mod browser;
+pub mod challenge;
+pub mod check;
+pub mod chromium;
+pub mod cli;
+pub mod config;
+pub mod console;
+pub mod cookies;
+pub mod coords_display;
 pub mod detector;
+pub mod disconnect;
+pub mod driver;
+pub mod events;
+pub mod features;
+pub mod frame;
+pub mod health;
+pub mod instance;
 pub mod known_locations;
+pub mod locate;
+pub mod logfile;
+pub mod logging;
+pub mod login_check;
+pub mod metrics;
+pub mod mqtt;
+pub mod notify;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+#[cfg(feature = "postgres")]
+pub mod postgres_store;
+pub mod recent_errors;
+pub mod recorder;
+pub mod reload;
+pub mod report;
+pub mod resources;
+pub mod scanner;
+pub mod screenshot_history;
+pub mod screenshots;
+pub mod shutdown;
+pub mod state;
+pub mod stats;
+pub mod stealth;
+pub mod store;
+pub mod supervisor;
+pub mod target;
+pub mod totp;
+pub mod ui;
+pub mod viewport;
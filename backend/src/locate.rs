@@ -0,0 +1,342 @@
+//! Turning a template match into game coordinates: the pixel-to-game
+//! transform, the estimate from a scan screenshot, its refinement from the
+//! goto screenshot, and whether the popup or calibration confirms it. Kept
+//! free of the browser so recorded sessions can be replayed offline (see the
+//! `replay` binary).
+
+use crate::detector::{self, Roi};
+use crate::target::PopupMatcher;
+
+/// Where navigated game coordinates appear on screen and how pixels map to
+/// game units around them (`MERCY_SCREEN_CENTER`, `MERCY_PX_PER_GAME`,
+/// `MERCY_TILT_Y`). The defaults were measured on the 1920×1080 reference
+/// canvas at 25% zoom; other layouts and zooms need their own.
+///
+/// Forward: pixel_dx = px_per_game_x * game_dx
+///          pixel_dy = tilt_y * game_dx + px_per_game_y * game_dy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapTransform {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub px_per_game_x: f64,
+    pub px_per_game_y: f64,
+    /// Vertical pixel shift per game X unit
+    pub tilt_y: f64,
+}
+
+impl Default for MapTransform {
+    /// The center was measured from the yellow crosshair square after goto
+    /// in the headless viewport: the minimap, top bar, bottom toolbar and
+    /// right-side icons shift it well away from (960, 540). The scale was
+    /// calibrated from K:111 buildings at (502,512) and (528,524).
+    fn default() -> Self {
+        Self {
+            center_x: 760.0,
+            center_y: 400.0,
+            px_per_game_x: 49.40,
+            px_per_game_y: 28.32,
+            tilt_y: -1.50,
+        }
+    }
+}
+
+impl MapTransform {
+    pub fn center(&self) -> (f64, f64) {
+        (self.center_x, self.center_y)
+    }
+
+    /// Convert a pixel position to approximate game coordinate offset from
+    /// the screen center. Takes sub-pixel positions (see
+    /// [`detector::TemplateMatch::position`]) so rounding only happens once,
+    /// in game units.
+    /// Returns (delta_x, delta_y) in game coordinate units.
+    pub fn pixel_to_game_offset(&self, pixel_x: f64, pixel_y: f64) -> (i32, i32) {
+        let screen_dx = pixel_x - self.center_x;
+        let screen_dy = pixel_y - self.center_y;
+
+        let game_dx = screen_dx / self.px_per_game_x;
+        let game_dy = (screen_dy - self.tilt_y * game_dx) / self.px_per_game_y;
+
+        (game_dx.round() as i32, game_dy.round() as i32)
+    }
+
+    /// Convert a game coordinate offset to the pixel offset it moves the view by.
+    pub fn game_to_pixel_offset(&self, game_dx: i32, game_dy: i32) -> (f64, f64) {
+        let (dx, dy) = (game_dx as f64, game_dy as f64);
+        (
+            self.px_per_game_x * dx,
+            self.tilt_y * dx + self.px_per_game_y * dy,
+        )
+    }
+
+    /// Search window centred on the screen center (where navigation puts the target).
+    pub fn center_roi(&self, (half_w, half_h): (u32, u32)) -> Roi {
+        Roi::around(self.center_x as u32, self.center_y as u32, half_w, half_h)
+    }
+}
+
+/// When a match near screen center counts as the exchange: on re-verifying a
+/// known one, and on confirming a detection without popup coordinates.
+/// Tuned for the current template and zoom (`MERCY_VERIFY_MIN_SCORE`,
+/// `MERCY_VERIFY_MAX_OFFSET_PX`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifyCriteria {
+    pub min_score: f32,
+    /// Max distance from screen center along either axis, in pixels
+    pub max_offset_px: u32,
+    /// The screen center, see [`MapTransform`]
+    pub center: (f64, f64),
+}
+
+impl VerifyCriteria {
+    /// Distance of a match from screen center along each axis, in pixels.
+    pub fn center_offset(&self, m: &detector::TemplateMatch) -> (f64, f64) {
+        (
+            (m.x as f64 - self.center.0).abs(),
+            (m.y as f64 - self.center.1).abs(),
+        )
+    }
+
+    pub fn near_center(&self, m: &detector::TemplateMatch) -> bool {
+        let (err_x, err_y) = self.center_offset(m);
+        let max = self.max_offset_px as f64;
+        err_x < max && err_y < max
+    }
+
+    pub fn accepts(&self, m: &detector::TemplateMatch) -> bool {
+        m.score >= self.min_score && self.near_center(m)
+    }
+
+    /// Half-size of the window around screen center searched when
+    /// re-verifying: the acceptance radius plus room for the template.
+    pub fn roi_half(&self) -> (u32, u32) {
+        (self.max_offset_px + 80, self.max_offset_px + 60)
+    }
+}
+
+/// Half-size of the calibration search window. Wider than verification since
+/// the estimate from the scan screenshot can be off by a couple of tiles.
+pub const CALIBRATION_ROI_HALF: (u32, u32) = (320, 240);
+
+/// Game coordinates of a match at `pixel` in a screenshot taken at
+/// `nav` (see [`MapTransform::pixel_to_game_offset`]), clamped to the map.
+pub fn estimate(transform: &MapTransform, nav: (u32, u32), pixel: (f64, f64)) -> (u32, u32) {
+    let (dx, dy) = transform.pixel_to_game_offset(pixel.0, pixel.1);
+    offset_coords(nav, (dx, dy))
+}
+
+fn offset_coords((x, y): (u32, u32), (dx, dy): (i32, i32)) -> (u32, u32) {
+    (
+        (x as i32 + dx).clamp(0, 1023) as u32,
+        (y as i32 + dy).clamp(0, 1023) as u32,
+    )
+}
+
+/// The estimate corrected by where the target shows up after navigating to
+/// it, and where to click to open its popup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Refined {
+    pub x: u32,
+    pub y: u32,
+    /// The calibration match, or the screen center without one
+    pub click: (f64, f64),
+    /// Correction applied to the estimate, in game units
+    pub correction: (i32, i32),
+}
+
+/// Refine `estimate` with the best match in the screenshot taken after
+/// navigating to it. The match's offset from screen center (sprite height,
+/// rounding in the estimate) becomes a game-unit correction.
+pub fn refine(
+    transform: &MapTransform,
+    estimate: (u32, u32),
+    calibration: Option<&detector::TemplateMatch>,
+) -> Refined {
+    let Some(m) = calibration else {
+        return Refined {
+            x: estimate.0,
+            y: estimate.1,
+            click: transform.center(),
+            correction: (0, 0),
+        };
+    };
+    let (gx, gy) = m.position();
+    let correction = transform.pixel_to_game_offset(gx, gy);
+    let (x, y) = offset_coords(estimate, correction);
+    Refined {
+        x,
+        y,
+        click: (gx, gy),
+        correction,
+    }
+}
+
+/// What the popup and calibration match say about a detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// The popup is the target's and gives its coordinates
+    Popup { kingdom: u32, x: u32, y: u32 },
+    /// No popup, but a strong calibration match near screen center: the
+    /// refined estimate stands
+    Calibrated,
+    /// The popup is the target's but has no coordinates
+    NoCoords,
+    /// The popup is some other building's
+    OtherBuilding,
+    /// No popup and a weak or missing calibration match
+    Unconfirmed,
+}
+
+impl Confirmation {
+    /// Whether the detection is stored as an exchange.
+    pub fn is_found(self) -> bool {
+        matches!(self, Self::Popup { .. } | Self::Calibrated)
+    }
+}
+
+/// Decide a detection from the popup text read after clicking it, or from
+/// the calibration match when no popup opened.
+pub fn confirm(
+    popup_text: Option<&str>,
+    popup: &PopupMatcher,
+    criteria: &VerifyCriteria,
+    calibration: Option<&detector::TemplateMatch>,
+) -> Confirmation {
+    match popup_text {
+        Some(text) => match popup.coords(text) {
+            Some((kingdom, x, y)) => Confirmation::Popup { kingdom, x, y },
+            None if popup.names_target(text) => Confirmation::NoCoords,
+            None => Confirmation::OtherBuilding,
+        },
+        None if calibration.is_some_and(|m| criteria.accepts(m)) => Confirmation::Calibrated,
+        None => Confirmation::Unconfirmed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f64, y: f64, score: f32) -> detector::TemplateMatch {
+        detector::TemplateMatch {
+            x: x as u32,
+            y: y as u32,
+            score,
+            template: 0,
+            channels: None,
+            subpixel: (0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn test_map_transform_round_trip() {
+        // A pan by the forward transform lands back on the same offset
+        let default = MapTransform::default();
+        let other = MapTransform {
+            center_x: 960.0,
+            center_y: 540.0,
+            px_per_game_x: 62.0,
+            px_per_game_y: 35.5,
+            tilt_y: 0.8,
+        };
+        for transform in [default, other] {
+            let (px, py) = transform.game_to_pixel_offset(25, -7);
+            let (cx, cy) = transform.center();
+            assert_eq!(transform.pixel_to_game_offset(cx + px, cy + py), (25, -7));
+        }
+    }
+
+    #[test]
+    fn test_verify_criteria() {
+        let default = VerifyCriteria {
+            min_score: 0.90,
+            max_offset_px: 80,
+            center: MapTransform::default().center(),
+        };
+        assert!(default.accepts(&at(810.0, 400.0, 0.92)));
+        assert!(!default.accepts(&at(860.0, 400.0, 0.92)));
+        assert!(!default.accepts(&at(760.0, 400.0, 0.85)));
+
+        let loose = VerifyCriteria {
+            min_score: 0.80,
+            max_offset_px: 120,
+            ..default
+        };
+        assert!(loose.accepts(&at(860.0, 400.0, 0.85)));
+        assert_eq!(loose.roi_half(), (200, 180));
+
+        // Measured from a moved screen center
+        let shifted = VerifyCriteria {
+            center: (860.0, 400.0),
+            ..default
+        };
+        assert!(shifted.accepts(&at(860.0, 400.0, 0.92)));
+        assert!(!shifted.accepts(&at(760.0, 400.0, 0.92)));
+    }
+
+    #[test]
+    fn test_estimate_and_refine() {
+        let transform = MapTransform::default();
+        let (cx, cy) = transform.center();
+        let (px, py) = transform.game_to_pixel_offset(-6, 4);
+        assert_eq!(
+            estimate(&transform, (512, 512), (cx + px, cy + py)),
+            (506, 516)
+        );
+        assert_eq!(
+            estimate(&transform, (2, 1020), (cx + px, cy + py)),
+            (0, 1023)
+        );
+
+        // Found a tile off after the goto
+        let (px, py) = transform.game_to_pixel_offset(1, 0);
+        let calibration = at(cx + px, cy + py, 0.95);
+        let refined = refine(&transform, (506, 516), Some(&calibration));
+        assert_eq!((refined.x, refined.y), (507, 516));
+        assert_eq!(refined.correction, (1, 0));
+        assert_eq!(refined.click, calibration.position());
+
+        let blind = refine(&transform, (506, 516), None);
+        assert_eq!((blind.x, blind.y, blind.click), (506, 516, (cx, cy)));
+    }
+
+    #[test]
+    fn test_confirm() {
+        let popup = PopupMatcher::default();
+        let criteria = VerifyCriteria {
+            min_score: 0.90,
+            max_offset_px: 80,
+            center: MapTransform::default().center(),
+        };
+        let strong = at(770.0, 405.0, 0.95);
+        let weak = at(770.0, 405.0, 0.70);
+
+        assert_eq!(
+            confirm(
+                Some("Exchange (K:111 X:506 Y:638)"),
+                &popup,
+                &criteria,
+                None
+            ),
+            Confirmation::Popup {
+                kingdom: 111,
+                x: 506,
+                y: 638
+            }
+        );
+        // A popup without coordinates isn't rescued by the calibration match
+        assert_eq!(
+            confirm(Some("Exchange"), &popup, &criteria, Some(&strong)),
+            Confirmation::NoCoords
+        );
+        assert_eq!(
+            confirm(None, &popup, &criteria, Some(&strong)),
+            Confirmation::Calibrated
+        );
+        assert_eq!(
+            confirm(None, &popup, &criteria, Some(&weak)),
+            Confirmation::Unconfirmed
+        );
+        assert!(!Confirmation::Unconfirmed.is_found());
+    }
+}
@@ -0,0 +1,263 @@
+//! Append-only log files (the exchange log, the audit log and the console
+//! log) with rotation and retention.
+//!
+//! A file is rotated once it reaches `MERCY_LOG_MAX_MB` or, with
+//! `MERCY_LOG_MAX_AGE_DAYS`, once it was started that long ago. The old
+//! segment is gzipped to `<file>.1.gz`, older ones shift up to
+//! `<file>.<MERCY_LOG_KEEP>.gz` and anything beyond is deleted. A line cut
+//! short by a crash is closed off before the next one is appended, so it
+//! costs that one line and not the next.
+//!
+//! Lines are written by a thread of their own, in the order they were
+//! appended, so gzipping a segment holds up neither the async handlers nor
+//! the scanner. [`flush`] waits for the queued ones at shutdown.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, SystemTime};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// When a log file is rotated and how many old segments are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_age: Option<Duration>,
+    /// Gzipped segments kept (0 = old segments are deleted)
+    pub keep: u32,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10 * 1024 * 1024,
+            max_age: None,
+            keep: 5,
+        }
+    }
+}
+
+/// Work for the writer thread.
+enum Job {
+    Append {
+        path: PathBuf,
+        rotation: Rotation,
+        line: String,
+    },
+    /// Answered once every line queued before it is written
+    Flush(Sender<()>),
+}
+
+/// The writer thread's queue. It is the only one writing the log files, so
+/// two appends can't both rotate the same file.
+static WRITER: LazyLock<Sender<Job>> = LazyLock::new(|| {
+    let (jobs, queue) = std::sync::mpsc::channel();
+    std::thread::spawn(move || Writer::default().run(queue));
+    jobs
+});
+
+/// Queue `line` (ending in a newline) for appending to `path` in a single
+/// write, rotating the file first if it is due. Failures are logged.
+pub fn append(path: &Path, rotation: &Rotation, line: &str) {
+    let job = Job::Append {
+        path: path.to_path_buf(),
+        rotation: *rotation,
+        line: line.to_string(),
+    };
+    if WRITER.send(job).is_err() {
+        tracing::warn!("failed to write {}: log writer stopped", path.display());
+    }
+}
+
+/// Wait until the lines appended so far are written.
+pub fn flush() {
+    let (done, written) = std::sync::mpsc::channel();
+    if WRITER.send(Job::Flush(done)).is_ok() {
+        let _ = written.recv();
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    /// Files whose age couldn't be read, warned about once
+    ageless: HashSet<PathBuf>,
+}
+
+impl Writer {
+    fn run(mut self, queue: Receiver<Job>) {
+        for job in queue {
+            match job {
+                Job::Append {
+                    path,
+                    rotation,
+                    line,
+                } => {
+                    if let Err(e) = self.write(&path, &rotation, &line) {
+                        tracing::warn!("failed to write {}: {e}", path.display());
+                    }
+                }
+                Job::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, path: &Path, rotation: &Rotation, line: &str) -> std::io::Result<()> {
+        let mut file = open(path)?;
+        let meta = file.metadata()?;
+        let too_old = match rotation.max_age {
+            Some(max_age) if meta.len() > 0 => match meta.created() {
+                Ok(created) => SystemTime::now()
+                    .duration_since(created)
+                    .is_ok_and(|age| age >= max_age),
+                Err(e) => {
+                    if self.ageless.insert(path.to_path_buf()) {
+                        tracing::warn!(
+                            "can't tell how old {} is ({e}), so MERCY_LOG_MAX_AGE_DAYS doesn't \
+                             rotate it; only MERCY_LOG_MAX_MB does",
+                            path.display()
+                        );
+                    }
+                    false
+                }
+            },
+            _ => false,
+        };
+        if meta.len() > 0 && (meta.len() + line.len() as u64 > rotation.max_bytes || too_old) {
+            drop(file);
+            rotate(path, rotation.keep)?;
+            file = open(path)?;
+        } else if meta.len() > 0 {
+            // Close off a line a crash cut short
+            let mut last = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+            }
+        }
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// The previous segment (`<file>.1.gz`) followed by the current file, for
+/// reading a log back at startup. Missing files read as empty.
+pub fn read_recent(path: &Path) -> std::io::Result<String> {
+    let mut text = String::new();
+    match File::open(segment(path, 1)) {
+        Ok(file) => {
+            GzDecoder::new(file).read_to_string(&mut text)?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    match std::fs::read_to_string(path) {
+        Ok(current) => text.push_str(&current),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(text)
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .create(true)
+        .append(true)
+        .open(path)
+}
+
+/// `<path>.<n>.gz`
+fn segment(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}.gz"));
+    PathBuf::from(name)
+}
+
+/// Shift the kept segments up by one, dropping the oldest, and gzip `path`
+/// to `<path>.1.gz`.
+fn rotate(path: &Path, keep: u32) -> std::io::Result<()> {
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    for n in (1..keep).rev() {
+        let from = segment(path, n);
+        if from.exists() {
+            std::fs::rename(&from, segment(path, n + 1))?;
+        }
+    }
+    // Written aside first, so a crash mid-way leaves the log uncompressed
+    // rather than half a segment
+    let target = segment(path, 1);
+    let mut partial = target.as_os_str().to_owned();
+    partial.push(".tmp");
+    let partial = PathBuf::from(partial);
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    std::io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::rename(&partial, &target)?;
+    std::fs::remove_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_and_truncated_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("exchanges.jsonl");
+        let rotation = Rotation {
+            max_bytes: 12,
+            max_age: None,
+            keep: 2,
+        };
+
+        let mut writer = Writer::default();
+        let mut append = |line| writer.write(&path, &rotation, line).unwrap();
+
+        // A crash left half a line behind
+        std::fs::write(&path, "{\"kin").unwrap();
+        append("one\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"kin\none\n");
+
+        append("two\n");
+        append("three\n");
+        append("four\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "four\n");
+        assert_eq!(read_recent(&path).unwrap(), "two\nthree\nfour\n");
+        assert!(segment(&path, 2).exists());
+        assert!(!segment(&path, 3).exists());
+
+        append("five long line\n");
+        // Only two segments are kept: the first one is gone
+        let mut oldest = String::new();
+        GzDecoder::new(File::open(segment(&path, 2)).unwrap())
+            .read_to_string(&mut oldest)
+            .unwrap();
+        assert_eq!(oldest, "two\nthree\n");
+        assert_eq!(read_recent(&path).unwrap(), "four\nfive long line\n");
+    }
+
+    #[test]
+    fn test_append_in_order_after_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        for n in 0..100 {
+            super::append(&path, &Rotation::default(), &format!("{n}\n"));
+        }
+        flush();
+        let lines: Vec<String> = (0..100).map(|n| n.to_string()).collect();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            lines.join("\n") + "\n"
+        );
+    }
+}
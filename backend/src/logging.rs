@@ -0,0 +1,207 @@
+//! Log output (`MERCY_LOG_FORMAT`): tracing-subscriber's text format, or one
+//! JSON object per line for log aggregation.
+//!
+//! A JSON line carries `timestamp`, `level`, `target` and `message`, the
+//! event's own fields, and the fields of the spans it happened in, flattened
+//! so the scanner's `kingdom`, `step`, `score` and `phase` are always
+//! top-level keys. `spans` names those spans, outermost first.
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format: {other}")),
+        }
+    }
+}
+
+/// Install the global subscriber. `RUST_LOG` overrides `default_filter`.
+pub fn init(format: LogFormat, default_filter: &str) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    match format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::registry()
+            .with(filter)
+            .with(JsonLayer::new(std::io::stdout))
+            .init(),
+    }
+}
+
+/// Writes each event as a JSON line to `W`.
+struct JsonLayer<W> {
+    writer: W,
+}
+
+impl<W> JsonLayer<W> {
+    fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+/// A span's fields as recorded so far, kept in its extensions.
+struct SpanFields(Map<String, Value>);
+
+/// Records fields as JSON values: numbers and booleans as such, anything
+/// else as its `Debug` text (a `message` reads as it would in text).
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<SpanFields>()
+        {
+            values.record(&mut JsonVisitor(&mut fields.0));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        line.insert("level".into(), meta.level().as_str().into());
+        line.insert("target".into(), meta.target().into());
+
+        // Inner spans' fields win over outer ones, the event's over both
+        let mut spans = Vec::new();
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|s| s.from_root())
+        {
+            spans.push(span.name());
+            if let Some(fields) = span.extensions().get::<SpanFields>() {
+                line.extend(fields.0.clone());
+            }
+        }
+        if !spans.is_empty() {
+            line.insert("spans".into(), spans.into());
+        }
+        event.record(&mut JsonVisitor(&mut line));
+
+        let mut text = Value::Object(line).to_string();
+        text.push('\n');
+        let _ = self.writer.make_writer().write_all(text.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = buffer.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(JsonLayer::new(move || Buffer(writer.clone())));
+        tracing::subscriber::with_default(subscriber, || {
+            let scan =
+                tracing::info_span!("scan_kingdom", kingdom = 111, step = tracing::field::Empty);
+            let _scan = scan.enter();
+            scan.record("step", 12);
+            let confirm = tracing::info_span!("confirm_match", score = 0.97);
+            let _confirm = confirm.enter();
+            tracing::warn!(x = 506, "popup read {}", "K:111");
+        });
+
+        let text = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(text.trim_end()).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "popup read K:111");
+        assert_eq!(line["kingdom"], 111);
+        assert_eq!(line["step"], 12);
+        assert_eq!(line["score"], 0.97);
+        assert_eq!(line["x"], 506);
+        assert_eq!(
+            line["spans"],
+            serde_json::json!(["scan_kingdom", "confirm_match"])
+        );
+
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
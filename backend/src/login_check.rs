@@ -0,0 +1,127 @@
+//! The `login_check` binary (`src/bin/login_check.rs`): launch the browser,
+//! log in, and check the map renders and can be navigated, printing a
+//! pass/fail line per step. Catches broken selectors, credentials or cookies
+//! before a scan runs into them.
+//!
+//! Uses the primary account (or the session cookies) like the first scan
+//! would. A 2FA prompt can only be answered with `MERCY_TB_TOTP_SECRET`, as
+//! `POST /login/2fa` isn't served.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use tokio::sync::RwLock;
+
+use crate::browser::GameBrowser;
+use crate::config::Config;
+use crate::state::{AppState, AppStateInner};
+use crate::{cookies, health, logging, scanner};
+
+/// Where the navigation step goes, in the first kingdom.
+const CHECK_COORDS: (u32, u32) = (512, 512);
+
+/// Run the steps, stopping at the first failure. Returns whether all passed.
+#[tokio::main]
+pub async fn run(config: Config) -> bool {
+    logging::init(
+        config.log_format,
+        "warn,chromiumoxide::conn=off,chromiumoxide::handler=off",
+    );
+
+    let state: AppState = Arc::new(RwLock::new(AppStateInner::new(config.clone())));
+    let (console, counters) = {
+        let s = state.read().await;
+        (s.console.clone(), s.counters.clone())
+    };
+
+    let Some(game) = step(
+        "launch",
+        GameBrowser::launch(&config, console, counters),
+        |_| format!("{:?}", config.browser),
+    )
+    .await
+    else {
+        return false;
+    };
+    let game = Arc::new(game);
+
+    if step("login", log_in(&game, &state, &config), |how| how.clone())
+        .await
+        .is_none()
+    {
+        return false;
+    }
+    if step("screenshot", rendered_frame(&game), |size| size.clone())
+        .await
+        .is_none()
+    {
+        return false;
+    }
+
+    let kingdom = config.kingdoms[0];
+    let (x, y) = CHECK_COORDS;
+    let navigate = async {
+        game.navigate_to_coords(kingdom, x, y).await?;
+        rendered_frame(&game).await
+    };
+    step("navigate", navigate, |_| format!("K:{kingdom} X:{x} Y:{y}"))
+        .await
+        .is_some()
+}
+
+/// Run one step and print its outcome: `describe` of its value on success.
+async fn step<T>(
+    name: &str,
+    action: impl Future<Output = Result<T>>,
+    describe: impl FnOnce(&T) -> String,
+) -> Option<T> {
+    let started = Instant::now();
+    let result = action.await;
+    let elapsed = started.elapsed().as_secs_f64();
+    match result {
+        Ok(value) => {
+            println!("PASS {name:<10} {elapsed:>5.1}s  {}", describe(&value));
+            Some(value)
+        }
+        Err(e) => {
+            println!("FAIL {name:<10} {elapsed:>5.1}s  {e:#}");
+            None
+        }
+    }
+}
+
+/// Log in with the session cookies if any, else the primary account's
+/// credentials. Returns how.
+async fn log_in(game: &GameBrowser, state: &AppState, config: &Config) -> Result<String> {
+    let cookies = cookies::load_cookies(config).context("failed to load session cookies")?;
+    if !cookies.is_empty() && game.login_with_cookies(cookies).await? {
+        return Ok("session cookies".into());
+    }
+
+    let account = config
+        .accounts
+        .first()
+        .context("no session cookies accepted and no MERCY_TB_EMAIL")?;
+    let secret = account.totp_secret.as_deref();
+    game.login(&account.email, &account.password, || async move {
+        match secret {
+            Some(_) => scanner::two_factor_code(state, config, secret).await,
+            None => anyhow::bail!("2FA prompt needs MERCY_TB_TOTP_SECRET for the check"),
+        }
+    })
+    .await?;
+    Ok(format!("as {}", account.email))
+}
+
+/// Take a screenshot and make sure the game drew something. Returns its size.
+async fn rendered_frame(game: &GameBrowser) -> Result<String> {
+    let bytes = game.take_screenshot().await?;
+    let image = tokio::task::spawn_blocking(move || image::load_from_memory(&bytes))
+        .await?
+        .context("screenshot undecodable")?;
+    if health::is_blank_frame(&image) {
+        anyhow::bail!("game canvas is blank");
+    }
+    Ok(format!("{}x{}", image.width(), image.height()))
+}
@@ -1,31 +1,46 @@
-mod api;
-mod browser;
-mod config;
-mod detector;
-mod known_locations;
-mod scanner;
-mod state;
-
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
+use clap::Parser;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::sync::{Notify, RwLock};
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::EnvFilter;
 
-use crate::config::Config;
-use crate::state::AppStateInner;
+// Everything but the wiring lives in the library, shared with the tools in
+// src/bin
+use mercy::audit::AuditLog;
+use mercy::config::ConfigSource;
+#[cfg(feature = "postgres")]
+use mercy::postgres_store;
+use mercy::state::AppStateInner;
+use mercy::{
+    api, check, cli, detector, health, instance, logging, mqtt, reload, report, resources, scanner,
+    shutdown, store, target,
+};
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-            EnvFilter::new("info,chromiumoxide::conn=off,chromiumoxide::handler=off")
-        }))
-        .init();
+fn main() -> Result<()> {
+    let cli = cli::Cli::parse();
+    let check_config = cli.check_config;
+    let source = cli.apply()?;
+    if check_config {
+        let config = source.load().context("failed to load configuration")?;
+        if check::run(&config) > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    run(source)
+}
 
-    let config = Config::from_env().context("failed to load configuration")?;
+#[tokio::main]
+async fn run(source: ConfigSource) -> Result<()> {
+    let config = source.load().context("failed to load configuration")?;
+    logging::init(
+        config.log_format,
+        "info,chromiumoxide::conn=off,chromiumoxide::handler=off",
+    );
+    report::init(&config);
+    let _instance = instance::acquire(&config)?;
 
     tracing::info!(
         "mercy starting, kingdoms: {:?}, listen: {}, target: {}",
@@ -34,16 +49,85 @@ async fn main() -> Result<()> {
         config.search_target,
     );
 
-    // Load reference images once at startup and pre-compute downscaled grayscale versions
-    let raw_ref_images = detector::load_reference_images(&config.search_target)
-        .context("failed to load reference images")?;
-    tracing::info!("loaded {} reference image(s)", raw_ref_images.len());
+    // Load the target and its reference images once at startup
+    let (target, raw_ref_images) = target::TargetProfile::load(&config.search_target)
+        .context("failed to load the search target")?;
+    tracing::info!(
+        "loaded target {:?} with {} reference image(s)",
+        target.name,
+        raw_ref_images.len()
+    );
+
+    let detector = Arc::new(detector::DetectorHandle::new(
+        config.match_backend,
+        raw_ref_images,
+        config.match_options(),
+    ));
 
-    let ref_images = Arc::new(detector::prepare_reference_images(&raw_ref_images));
+    let mut inner = AppStateInner::new(config.clone());
+    inner.config_source = source;
+    inner.target = Arc::new(target);
+    let store: Option<(Box<dyn store::Storage>, String)> =
+        match (&config.database_url, &config.db_path) {
+            (Some(url), _) => Some((open_postgres(url)?, "PostgreSQL".into())),
+            (None, Some(path)) => Some((
+                Box::new(
+                    store::SqliteStore::open(path)
+                        .with_context(|| format!("failed to open database {}", path.display()))?,
+                ),
+                path.display().to_string(),
+            )),
+            (None, None) => None,
+        };
+    if let Some((store, name)) = store {
+        inner
+            .attach_store(store)
+            .context("failed to load state from the database")?;
+        tracing::info!("loaded {} exchange(s) from {name}", inner.exchanges.len());
+    } else {
+        inner.persist_scan_times(config.scan_times_path.clone());
+        let exchanges = scanner::load_exchange_log(&config);
+        if !exchanges.is_empty() {
+            tracing::info!(
+                "restored {} exchange(s) from {}",
+                exchanges.len(),
+                config.exchange_log
+            );
+        }
+        inner.restore_exchanges(exchanges);
+    }
+    // Shared with the API outside the state lock
+    let config = inner.config.clone();
+    let exchanges = inner.exchanges.clone();
+    let state: mercy::state::AppState = Arc::new(RwLock::new(inner));
 
-    let state: crate::state::AppState = Arc::new(Mutex::new(AppStateInner::new(config.clone())));
+    if config.health_interval_secs > 0 {
+        health::spawn_health_monitor(
+            state.clone(),
+            std::time::Duration::from_secs(config.health_interval_secs),
+        );
+    }
 
-    let app = api::router(state, ref_images).layer(TraceLayer::new_for_http());
+    resources::spawn_resource_monitor(state.clone());
+    reload::spawn_sighup_handler(state.clone(), detector.clone());
+    let audit = Arc::new(AuditLog::open(
+        config.audit_log.clone(),
+        config.log_rotation,
+    ));
+    if let Some(ref mqtt) = config.mqtt {
+        mqtt::spawn(state.clone(), detector.clone(), audit.clone(), mqtt.clone());
+    }
+
+    // Spans name the path without the query, so a `?token=` isn't logged
+    let app = api::router(state.clone(), config.clone(), exchanges, detector, audit).layer(
+        TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<_>| {
+            tracing::debug_span!(
+                "request",
+                method = %request.method(),
+                path = request.uri().path(),
+            )
+        }),
+    );
 
     let listener = TcpListener::bind(&config.listen_addr)
         .await
@@ -51,7 +135,49 @@ async fn main() -> Result<()> {
 
     tracing::info!("listening on {}", config.listen_addr);
 
-    axum::serve(listener, app).await.context("server error")?;
+    // Stop accepting connections once a shutdown signal arrives; open
+    // requests get the grace period below to finish
+    let draining = Arc::new(Notify::new());
+    let mut server = tokio::spawn(
+        axum::serve(listener, app)
+            .with_graceful_shutdown({
+                let draining = draining.clone();
+                async move { draining.notified().await }
+            })
+            .into_future(),
+    );
+    let signal = tokio::select! {
+        result = &mut server => {
+            return result.context("server task failed")?.context("server error");
+        }
+        signal = shutdown::signal_received() => signal,
+    };
+
+    tracing::info!("{signal} received, shutting down");
+    draining.notify_one();
+    let grace = std::time::Duration::from_secs(config.shutdown_grace_secs);
+    shutdown::stop(&state, grace).await;
+    match tokio::time::timeout(grace, server).await {
+        Ok(result) => result
+            .context("server task failed")?
+            .context("server error")?,
+        Err(_) => tracing::warn!(
+            "requests still open after {}s, closing them",
+            grace.as_secs()
+        ),
+    }
+    tracing::info!("shut down");
 
     Ok(())
 }
+
+#[cfg(feature = "postgres")]
+fn open_postgres(url: &str) -> Result<Box<dyn store::Storage>> {
+    let store = postgres_store::PgStore::connect(url).context("failed to connect to PostgreSQL")?;
+    Ok(Box::new(store))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn open_postgres(_url: &str) -> Result<Box<dyn store::Storage>> {
+    anyhow::bail!("MERCY_DATABASE_URL needs mercy built with the `postgres` feature")
+}
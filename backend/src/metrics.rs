@@ -0,0 +1,80 @@
+//! Operational counters since startup, reported as `counters` in `/status`.
+//!
+//! Shared as an `Arc` by the state and every browser, and bumped without
+//! taking the state lock. Also the gauges of the capped stores, reported as
+//! `storage`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub screenshots: AtomicU64,
+    /// Moves of the map: search-dialog gotos and drag pans
+    pub navigations: AtomicU64,
+    pub detections_spawned: AtomicU64,
+    pub detections_completed: AtomicU64,
+    pub popup_reads: AtomicU64,
+    /// Browsers replaced after a crash or for a scheduled restart
+    pub browser_restarts: AtomicU64,
+}
+
+/// The counters at one point in time.
+#[derive(Debug, Clone, Serialize)]
+pub struct CounterValues {
+    pub screenshots: u64,
+    pub navigations: u64,
+    pub detections_spawned: u64,
+    pub detections_completed: u64,
+    pub popup_reads: u64,
+    pub browser_restarts: u64,
+}
+
+/// Add one to `counter`.
+pub fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Counters {
+    pub fn values(&self) -> CounterValues {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        CounterValues {
+            screenshots: get(&self.screenshots),
+            navigations: get(&self.navigations),
+            detections_spawned: get(&self.detections_spawned),
+            detections_completed: get(&self.detections_completed),
+            popup_reads: get(&self.popup_reads),
+            browser_restarts: get(&self.browser_restarts),
+        }
+    }
+}
+
+/// How full a capped store is.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Gauge {
+    pub used: u64,
+    /// None = no cap
+    pub max: Option<u64>,
+}
+
+impl Gauge {
+    pub fn new(used: usize, max: Option<usize>) -> Self {
+        Self {
+            used: used as u64,
+            max: max.map(|m| m as u64),
+        }
+    }
+}
+
+/// The stores that would otherwise grow for as long as the service runs
+/// (`MERCY_MAX_EXCHANGES`, `MERCY_MAX_EVENTS`, `MERCY_SCREENSHOT_DIR_MAX_*`).
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageUsage {
+    /// Exchanges in memory
+    pub exchanges: Gauge,
+    pub events: Gauge,
+    /// Files in the screenshot directory
+    pub screenshots: Gauge,
+    pub screenshot_bytes: Gauge,
+}
@@ -0,0 +1,501 @@
+//! Home Assistant integration over MQTT (`MERCY_MQTT_HOST`), through the
+//! `mosquitto_pub` and `mosquitto_sub` clients.
+//!
+//! The scanner's state goes to `<topic>/state` as one retained JSON object
+//! whenever it changes, and the latest live exchange to `<topic>/exchange`.
+//! Retained discovery configs under `homeassistant/` make Home Assistant
+//! show these as a device with sensors for the phase, the current kingdom
+//! and the latest exchange, and two switches: `Scanning` (on starts a scan,
+//! off stops it) and `Paused` (on pauses, off resumes). Switch commands
+//! arrive on `<topic>/<switch>/set` and act like `POST /start`, `/stop` and
+//! `/pause`. They go to the audit log with the status those calls would
+//! have returned.
+//!
+//! The broker password never goes on the clients' command line, where any
+//! local user could read it: they read it from their config files
+//! (`$XDG_CONFIG_HOME/mosquitto_pub`, `mosquitto_sub`), written to a private
+//! temp directory for as long as the process runs.
+
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::time::{sleep, timeout};
+
+use crate::audit::{AuditEntry, AuditLog};
+use crate::config::ConfigOverrides;
+use crate::detector::DetectorHandle;
+use crate::state::{AppState, AppStateInner, MercExchange, ScannerPhase};
+use crate::supervisor;
+
+/// Home Assistant's default discovery prefix.
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// How often the state is compared with what was last published.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long one `mosquitto_pub` may take.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Wait before subscribing again after `mosquitto_sub` exits.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(30);
+
+/// The broker and the topic everything is published under.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Base topic (`MERCY_MQTT_TOPIC`, default `mercy`)
+    pub topic: String,
+}
+
+impl std::fmt::Debug for MqttConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl MqttConfig {
+    /// Connection arguments shared by `mosquitto_pub` and `mosquitto_sub`,
+    /// all but the password.
+    fn connection_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-h".into(),
+            self.host.clone(),
+            "-p".into(),
+            self.port.to_string(),
+        ];
+        if let Some(ref user) = self.user {
+            args.extend(["-u".into(), user.clone()]);
+        }
+        args
+    }
+}
+
+/// Runs `mosquitto_pub` and `mosquitto_sub` against the broker.
+struct Clients {
+    config: MqttConfig,
+    /// Holds the clients' config files with the password (None without one)
+    options_dir: Option<tempfile::TempDir>,
+}
+
+impl Clients {
+    fn new(config: MqttConfig) -> Result<Self> {
+        let options_dir = match config.password {
+            Some(ref password) => {
+                // Created 0700, so only this user can list it
+                let dir = tempfile::Builder::new()
+                    .prefix("mercy-mqtt")
+                    .tempdir()
+                    .context("failed to create the MQTT options directory")?;
+                for client in ["mosquitto_pub", "mosquitto_sub"] {
+                    write_private(&dir.path().join(client), &format!("-P {password}\n"))?;
+                }
+                Some(dir)
+            }
+            None => None,
+        };
+        Ok(Self {
+            config,
+            options_dir,
+        })
+    }
+
+    /// A command running `client` connected to the broker.
+    fn command(&self, client: &str) -> Command {
+        let mut command = Command::new(client);
+        command.args(self.config.connection_args());
+        if let Some(ref dir) = self.options_dir {
+            command.env("XDG_CONFIG_HOME", dir.path());
+        }
+        command
+    }
+}
+
+/// Write `contents` to a new file only its owner can read.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// A switch command from Home Assistant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SwitchCommand {
+    Start,
+    Stop,
+    Pause,
+    Resume,
+}
+
+/// Publish the state and take switch commands for as long as the process
+/// lives.
+pub fn spawn(
+    state: AppState,
+    detectors: Arc<DetectorHandle>,
+    audit: Arc<AuditLog>,
+    config: MqttConfig,
+) {
+    tracing::info!(
+        "publishing to MQTT at {}:{} under {}",
+        config.host,
+        config.port,
+        config.topic
+    );
+    let clients = match Clients::new(config) {
+        Ok(clients) => Arc::new(clients),
+        Err(e) => {
+            tracing::error!("not publishing to MQTT: {e:#}");
+            return;
+        }
+    };
+    tokio::spawn(publish_state(state.clone(), clients.clone()));
+    tokio::spawn(take_commands(state, detectors, audit, clients));
+}
+
+async fn publish_state(state: AppState, clients: Arc<Clients>) {
+    let config = &clients.config;
+    let mut discovered = false;
+    let mut last_state = String::new();
+    let mut last_exchange = String::new();
+    let mut ticker = tokio::time::interval(PUBLISH_INTERVAL);
+    loop {
+        ticker.tick().await;
+        // Until the broker takes them; they're retained after that
+        if !discovered {
+            let mut published = Ok(());
+            for (topic, payload) in discovery_configs(&config.topic) {
+                published = publish(&clients, &topic, &payload.to_string()).await;
+                if published.is_err() {
+                    break;
+                }
+            }
+            match published {
+                Ok(()) => discovered = true,
+                Err(e) => {
+                    tracing::warn!("failed to publish Home Assistant discovery: {e:#}");
+                    continue;
+                }
+            }
+        }
+
+        let (current, exchange) = {
+            let s = state.read().await;
+            (state_payload(&s).to_string(), latest_exchange(&s))
+        };
+        if current != last_state {
+            match publish(&clients, &format!("{}/state", config.topic), &current).await {
+                Ok(()) => last_state = current,
+                Err(e) => tracing::warn!("failed to publish the state to MQTT: {e:#}"),
+            }
+        }
+        if let Some(exchange) = exchange.map(|e| json!(e).to_string())
+            && exchange != last_exchange
+        {
+            match publish(&clients, &format!("{}/exchange", config.topic), &exchange).await {
+                Ok(()) => last_exchange = exchange,
+                Err(e) => tracing::warn!("failed to publish the exchange to MQTT: {e:#}"),
+            }
+        }
+    }
+}
+
+/// Publish `payload` to `topic`, retained.
+async fn publish(clients: &Clients, topic: &str, payload: &str) -> Result<()> {
+    let mut child = clients
+        .command("mosquitto_pub")
+        .args(["-r", "-t", topic, "-s"])
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run mosquitto_pub")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload.as_bytes()).await?;
+    }
+    let status = timeout(PUBLISH_TIMEOUT, child.wait())
+        .await
+        .context("mosquitto_pub timed out")??;
+    anyhow::ensure!(status.success(), "mosquitto_pub exited with {status}");
+    Ok(())
+}
+
+async fn take_commands(
+    state: AppState,
+    detectors: Arc<DetectorHandle>,
+    audit: Arc<AuditLog>,
+    clients: Arc<Clients>,
+) {
+    loop {
+        if let Err(e) = listen(&state, &detectors, &audit, &clients).await {
+            tracing::warn!(
+                "MQTT subscription failed, retrying in {}s: {e:#}",
+                RESUBSCRIBE_DELAY.as_secs()
+            );
+        }
+        sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+/// Run the switch commands read from `mosquitto_sub` until it exits.
+async fn listen(
+    state: &AppState,
+    detectors: &Arc<DetectorHandle>,
+    audit: &AuditLog,
+    clients: &Clients,
+) -> Result<()> {
+    let config = &clients.config;
+    let mut child = clients
+        .command("mosquitto_sub")
+        .args(["-v", "-t", &format!("{}/+/set", config.topic)])
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run mosquitto_sub")?;
+    let stdout = child.stdout.take().context("no mosquitto_sub output")?;
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        let Some(command) = parse_command(&config.topic, &line) else {
+            tracing::debug!("ignoring MQTT message: {line}");
+            continue;
+        };
+        tracing::info!("MQTT command: {command:?}");
+        let done = match command {
+            SwitchCommand::Start => {
+                let started = supervisor::start(state, detectors, ConfigOverrides::default())
+                    .await
+                    .is_some();
+                if !started {
+                    tracing::info!("scan already running");
+                }
+                started
+            }
+            SwitchCommand::Stop => {
+                supervisor::stop(state).await;
+                true
+            }
+            SwitchCommand::Pause => {
+                let paused = supervisor::pause(state).await;
+                if !paused {
+                    tracing::info!("no scan to pause");
+                }
+                paused
+            }
+            SwitchCommand::Resume => {
+                // Only a paused scan; off on an idle switch starts nothing
+                state.read().await.phase == ScannerPhase::Paused
+                    && supervisor::start(state, detectors, ConfigOverrides::default())
+                        .await
+                        .is_some()
+            }
+        };
+        let (topic, payload) = line.split_once(' ').unwrap_or((&line, ""));
+        audit.record(AuditEntry {
+            time: chrono::Utc::now(),
+            caller: "mqtt".into(),
+            method: payload.into(),
+            path: topic.into(),
+            // As `POST /start` and `/pause` answer
+            status: if done { 200 } else { 409 },
+        });
+    }
+    let status = child.wait().await?;
+    anyhow::bail!("mosquitto_sub exited with {status}")
+}
+
+/// The command in a `mosquitto_sub -v` line (`<topic> <payload>`).
+fn parse_command(base: &str, line: &str) -> Option<SwitchCommand> {
+    let (topic, payload) = line.split_once(' ')?;
+    let switch = topic
+        .strip_prefix(base)?
+        .strip_prefix('/')?
+        .strip_suffix("/set")?;
+    match (switch, payload.trim()) {
+        ("scanning", "ON") => Some(SwitchCommand::Start),
+        ("scanning", "OFF") => Some(SwitchCommand::Stop),
+        ("paused", "ON") => Some(SwitchCommand::Pause),
+        ("paused", "OFF") => Some(SwitchCommand::Resume),
+        _ => None,
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "ON" } else { "OFF" }
+}
+
+/// What `<topic>/state` carries.
+fn state_payload(s: &AppStateInner) -> Value {
+    let running = s
+        .scanner_handle
+        .as_ref()
+        .is_some_and(|handle| !handle.is_finished());
+    json!({
+        "phase": s.phase,
+        "kingdom": s.current_kingdom,
+        "scanning": on_off(running),
+        "paused": on_off(s.phase == ScannerPhase::Paused),
+    })
+}
+
+/// The most recently found exchange still on the map.
+fn latest_exchange(s: &AppStateInner) -> Option<MercExchange> {
+    s.exchanges
+        .list()
+        .into_iter()
+        .filter(|e| e.status.is_live())
+        .max_by_key(|e| e.found_at)
+}
+
+/// Discovery topics and configs for the device's entities.
+fn discovery_configs(base: &str) -> Vec<(String, Value)> {
+    // Node and object ids may only hold letters, digits, `_` and `-`
+    let node: String = base
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let device = json!({ "identifiers": [node], "name": "Mercy" });
+    let state_topic = format!("{base}/state");
+    let entity = |component: &str, object: &str, mut config: Value| {
+        config["unique_id"] = json!(format!("{node}_{object}"));
+        config["device"] = device.clone();
+        (
+            format!("{DISCOVERY_PREFIX}/{component}/{node}/{object}/config"),
+            config,
+        )
+    };
+    let switch = |object: &str, name: &str, icon: &str| {
+        entity(
+            "switch",
+            object,
+            json!({
+                "name": name,
+                "icon": icon,
+                "state_topic": state_topic,
+                "value_template": format!("{{{{ value_json.{object} }}}}"),
+                "command_topic": format!("{base}/{object}/set"),
+                "payload_on": "ON",
+                "payload_off": "OFF",
+            }),
+        )
+    };
+    let exchange_topic = format!("{base}/exchange");
+    vec![
+        entity(
+            "sensor",
+            "phase",
+            json!({
+                "name": "Phase",
+                "icon": "mdi:radar",
+                "state_topic": state_topic,
+                "value_template": "{{ value_json.phase }}",
+            }),
+        ),
+        entity(
+            "sensor",
+            "kingdom",
+            json!({
+                "name": "Kingdom",
+                "icon": "mdi:map",
+                "state_topic": state_topic,
+                "value_template": "{{ value_json.kingdom }}",
+            }),
+        ),
+        entity(
+            "sensor",
+            "exchange",
+            json!({
+                "name": "Latest exchange",
+                "icon": "mdi:map-marker",
+                "state_topic": exchange_topic,
+                "value_template":
+                    "K:{{ value_json.kingdom }} X:{{ value_json.x }} Y:{{ value_json.y }}",
+                "json_attributes_topic": exchange_topic,
+            }),
+        ),
+        switch("scanning", "Scanning", "mdi:play"),
+        switch("paused", "Paused", "mdi:pause"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_switch_commands_and_discovery() {
+        assert_eq!(
+            parse_command("home/mercy", "home/mercy/scanning/set ON"),
+            Some(SwitchCommand::Start)
+        );
+        assert_eq!(
+            parse_command("home/mercy", "home/mercy/paused/set OFF"),
+            Some(SwitchCommand::Resume)
+        );
+        assert_eq!(parse_command("home/mercy", "home/mercy/state ON"), None);
+        assert_eq!(parse_command("home/mercy", "other/scanning/set ON"), None);
+
+        let configs = discovery_configs("home/mercy");
+        let (topic, scanning) = configs
+            .iter()
+            .find(|(_, c)| c["unique_id"] == "home_mercy_scanning")
+            .unwrap();
+        assert_eq!(topic, "homeassistant/switch/home_mercy/scanning/config");
+        assert_eq!(scanning["command_topic"], "home/mercy/scanning/set");
+        assert_eq!(scanning["value_template"], "{{ value_json.scanning }}");
+        assert_eq!(scanning["state_topic"], "home/mercy/state");
+    }
+
+    #[test]
+    fn test_password_stays_off_the_command_line() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let config = MqttConfig {
+            host: "broker".into(),
+            port: 1883,
+            user: Some("mercy".into()),
+            password: Some("hunter 2".into()),
+            topic: "mercy".into(),
+        };
+        assert!(!format!("{config:?}").contains("hunter"));
+        let clients = Clients::new(config).unwrap();
+        assert!(
+            clients
+                .config
+                .connection_args()
+                .iter()
+                .all(|arg| !arg.contains("hunter"))
+        );
+
+        let dir = clients.options_dir.as_ref().unwrap().path();
+        for client in ["mosquitto_pub", "mosquitto_sub"] {
+            let path = dir.join(client);
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "-P hunter 2\n");
+            let mode = path.metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
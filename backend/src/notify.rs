@@ -0,0 +1,233 @@
+//! Operator notifications through a user-supplied shell command
+//! (`MERCY_NOTIFY_COMMAND`), e.g. `curl -s -d @- https://ntfy.sh/my-topic`,
+//! and to the push services built in: an ntfy topic (`MERCY_NTFY_TOPIC`)
+//! and Pushover (`MERCY_PUSHOVER_TOKEN` and `MERCY_PUSHOVER_USER`), sent with
+//! `curl`.
+//!
+//! The command runs via `sh -c` with `MERCY_EVENT` and `MERCY_MESSAGE` set and
+//! a JSON object (`event`, `message`, `timestamp`) on stdin, so any push
+//! service reachable from a shell one-liner works without a client here.
+//! Events needing a human (everything but a found `exchange`) go to the push
+//! services at high priority.
+
+use std::process::Stdio;
+
+use chrono::Utc;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{Duration, timeout};
+
+use crate::config::Config;
+
+/// How long the notification command may run before it is abandoned.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The event of a found exchange; the others need attention.
+pub const EXCHANGE_EVENT: &str = "exchange";
+
+/// A push service notifications are sent to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushService {
+    /// Topic URL, e.g. `https://ntfy.sh/my-topic`
+    Ntfy(String),
+    Pushover {
+        token: String,
+        user: String,
+    },
+}
+
+impl PushService {
+    /// An ntfy topic: a URL, or a bare topic name on ntfy.sh.
+    pub fn ntfy(topic: &str) -> Self {
+        if topic.starts_with("http://") || topic.starts_with("https://") {
+            Self::Ntfy(topic.to_string())
+        } else {
+            Self::Ntfy(format!("https://ntfy.sh/{topic}"))
+        }
+    }
+
+    /// `curl` arguments and stdin sending `message` as `event`. Secrets go
+    /// on stdin, as a curl config (`-K -`), never on the command line where
+    /// any local user can read them.
+    fn curl_request(&self, event: &str, urgent: bool, message: &str) -> (Vec<String>, String) {
+        let mut args: Vec<String> = ["-fsS", "-o", "/dev/null"].map(String::from).into();
+        match self {
+            Self::Ntfy(url) => {
+                args.extend([
+                    "-H".into(),
+                    format!("Title: mercy: {event}"),
+                    "-H".into(),
+                    format!("Priority: {}", if urgent { "high" } else { "default" }),
+                    "--data-binary".into(),
+                    "@-".into(),
+                    url.clone(),
+                ]);
+                (args, message.to_string())
+            }
+            Self::Pushover { token, user } => {
+                args.extend([
+                    "-K".into(),
+                    "-".into(),
+                    "https://api.pushover.net/1/messages.json".into(),
+                ]);
+                let fields = [
+                    format!("token={token}"),
+                    format!("user={user}"),
+                    format!("title=mercy: {event}"),
+                    format!("priority={}", if urgent { 1 } else { 0 }),
+                    format!("message={message}"),
+                ];
+                let config = fields
+                    .iter()
+                    .map(|field| format!("form-string = {}\n", curl_quote(field)))
+                    .collect();
+                (args, config)
+            }
+        }
+    }
+}
+
+/// `value` as a double-quoted curl config string.
+fn curl_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Log `message` and, if configured, run the notification command and send
+/// it to the push services in the background. Never blocks the caller and
+/// never fails: problems are logged.
+pub fn notify(config: &Config, event: &str, message: String) {
+    let urgent = event != EXCHANGE_EVENT;
+    if urgent {
+        tracing::warn!("notification [{event}]: {message}");
+    } else {
+        tracing::info!("notification [{event}]: {message}");
+    }
+    for service in &config.push_services {
+        let (args, input) = service.curl_request(event, urgent, &message);
+        spawn_curl(args, event.to_string(), input);
+    }
+    let Some(command) = config.notify_command.clone() else {
+        return;
+    };
+    let payload = json!({
+        "event": event,
+        "message": message,
+        "timestamp": Utc::now().to_rfc3339(),
+    })
+    .to_string();
+    spawn_command(command, event.to_string(), message, payload);
+}
+
+/// Run `command` in the background with `event` and `message` in its
+/// environment and `payload` on stdin. Needs a Tokio runtime; without one
+/// (e.g. a panic on a plain thread) it's only logged.
+pub fn spawn_command(command: String, event: String, message: String, payload: String) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        tracing::warn!("no runtime to run the notification command for [{event}]");
+        return;
+    };
+    runtime.spawn(async move {
+        match timeout(NOTIFY_TIMEOUT, run(&command, &event, &message, &payload)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("notification command failed: {e:#}"),
+            Err(_) => tracing::warn!("notification command timed out after {NOTIFY_TIMEOUT:?}"),
+        }
+    });
+}
+
+/// Run `curl` with `args` in the background, `input` on its stdin.
+fn spawn_curl(args: Vec<String>, event: String, input: String) {
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    runtime.spawn(async move {
+        let sent = async {
+            let mut child = tokio::process::Command::new("curl")
+                .args(&args)
+                .stdin(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input.as_bytes()).await?;
+            }
+            let status = child.wait().await?;
+            anyhow::ensure!(status.success(), "curl exited with {status}");
+            anyhow::Ok(())
+        };
+        match timeout(NOTIFY_TIMEOUT, sent).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("push notification for [{event}] failed: {e:#}"),
+            Err(_) => tracing::warn!("push notification for [{event}] timed out"),
+        }
+    });
+}
+
+async fn run(command: &str, event: &str, message: &str, payload: &str) -> anyhow::Result<()> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("MERCY_EVENT", event)
+        .env("MERCY_MESSAGE", message)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(payload.as_bytes()).await?;
+    }
+    let status = child.wait().await?;
+    anyhow::ensure!(status.success(), "exited with {status}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_curl_args() {
+        assert_eq!(
+            PushService::ntfy("mercy-alerts"),
+            PushService::Ntfy("https://ntfy.sh/mercy-alerts".into())
+        );
+        let (ntfy, input) = PushService::ntfy("https://ntfy.example.com/mercy").curl_request(
+            "challenge",
+            true,
+            "solve it",
+        );
+        assert!(ntfy.contains(&"Priority: high".to_string()));
+        assert_eq!(ntfy.last().unwrap(), "https://ntfy.example.com/mercy");
+        assert_eq!(input, "solve it");
+
+        let (args, config) = PushService::Pushover {
+            token: "app-secret".into(),
+            user: "me".into(),
+        }
+        .curl_request(EXCHANGE_EVENT, false, "K:110 \"X\"\nnext");
+        assert!(
+            args.iter().all(|arg| !arg.contains("app-secret")),
+            "token on the command line: {args:?}"
+        );
+        for line in [
+            r#"form-string = "token=app-secret""#,
+            r#"form-string = "user=me""#,
+            r#"form-string = "priority=0""#,
+            r#"form-string = "message=K:110 \"X\"\nnext""#,
+        ] {
+            assert!(config.lines().any(|l| l == line), "{line} in {config:?}");
+        }
+    }
+}
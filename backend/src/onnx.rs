@@ -0,0 +1,211 @@
+//! ONNX object-detection backend (`MERCY_DETECTOR=onnx`, built with the
+//! `onnx` cargo feature).
+//!
+//! Runs a single-class detector on the viewport crop instead of correlating
+//! templates. The model is expected to take one `[1, 3, 640, 640]` input
+//! (RGB, scaled to 0-1) and produce one `[N, 6]` (or `[1, N, 6]`) output of
+//! `(x1, y1, x2, y2, score, class)` rows in input pixels — the usual
+//! end-to-end export with NMS built in. The class column is ignored.
+//!
+//! Reference templates are still loaded: negative templates reject
+//! detections the same way they do for template matching, and reported
+//! matches point at the first positive template so callers can name them.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use tract_onnx::prelude::*;
+
+use crate::detector::{
+    Detector, MatchOptions, PreparedRef, PreparedScreenshot, TemplateMatch,
+    reject_negatives_and_offset,
+};
+
+/// Model input edge length, in pixels.
+const INPUT_SIZE: u32 = 640;
+
+/// Minimum model confidence for a detection to count as a match.
+pub const ONNX_SCORE_THRESHOLD: f32 = 0.5;
+
+pub type Model = TypedRunnableModel<TypedModel>;
+
+/// A detection box in model input pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Detection {
+    x1: f32,
+    y1: f32,
+    x2: f32,
+    y2: f32,
+    score: f32,
+}
+
+pub struct OnnxDetector {
+    refs: Vec<PreparedRef>,
+    opts: MatchOptions,
+    model: Model,
+}
+
+/// Load and optimise a model for the fixed input size.
+pub fn load_model(path: &Path) -> Result<Model> {
+    let model = tract_onnx::onnx()
+        .model_for_path(path)
+        .and_then(|m| {
+            m.with_input_fact(
+                0,
+                f32::fact([1, 3, INPUT_SIZE as usize, INPUT_SIZE as usize]).into(),
+            )
+        })
+        .and_then(|m| m.into_optimized())
+        .and_then(|m| m.into_runnable())
+        .with_context(|| format!("failed to load ONNX model {}", path.display()))?;
+    tracing::info!("loaded ONNX model {}", path.display());
+    Ok(model)
+}
+
+impl OnnxDetector {
+    pub fn new(model: Model, refs: Vec<PreparedRef>, opts: MatchOptions) -> Self {
+        Self { refs, opts, model }
+    }
+
+    /// Run the model on the viewport crop. Boxes come back in downscaled
+    /// viewport coordinates, strongest first.
+    fn detect(&self, screenshot: &PreparedScreenshot) -> Result<Vec<Detection>> {
+        let [r, g, b] = &screenshot.planes.rgb;
+        let planes = [r, g, b]
+            .map(|p| image::imageops::resize(p, INPUT_SIZE, INPUT_SIZE, FilterType::Triangle));
+        let size = INPUT_SIZE as usize;
+        let input: Tensor =
+            tract_ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| {
+                planes[c].get_pixel(x as u32, y as u32).0[0] as f32 / 255.0
+            })
+            .into();
+
+        let outputs = self
+            .model
+            .run(tvec!(input.into()))
+            .context("ONNX inference failed")?;
+        let output = outputs[0]
+            .to_array_view::<f32>()
+            .context("ONNX output is not f32")?;
+        let rows = output.as_slice().context("ONNX output is not contiguous")?;
+
+        let sx = screenshot.width() as f32 / INPUT_SIZE as f32;
+        let sy = screenshot.height() as f32 / INPUT_SIZE as f32;
+        let mut detections = parse_detections(rows)?;
+        for d in &mut detections {
+            d.x1 *= sx;
+            d.x2 *= sx;
+            d.y1 *= sy;
+            d.y2 *= sy;
+        }
+        detections.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Ok(detections)
+    }
+
+    /// Index reported as `TemplateMatch::template` for model detections.
+    fn template_index(&self) -> usize {
+        self.refs.iter().position(|r| !r.negative).unwrap_or(0)
+    }
+
+    fn to_match(&self, d: &Detection) -> TemplateMatch {
+        TemplateMatch {
+            x: ((d.x1 + d.x2) / 2.0).max(0.0) as u32,
+            y: ((d.y1 + d.y2) / 2.0).max(0.0) as u32,
+            score: d.score,
+            template: self.template_index(),
+            channels: None,
+            subpixel: (0.0, 0.0),
+        }
+    }
+}
+
+/// Split a flat `(x1, y1, x2, y2, score, class)` output into detections.
+fn parse_detections(rows: &[f32]) -> Result<Vec<Detection>> {
+    anyhow::ensure!(
+        rows.len().is_multiple_of(6),
+        "ONNX output has {} values, expected rows of 6",
+        rows.len()
+    );
+    Ok(rows
+        .chunks_exact(6)
+        .map(|row| Detection {
+            x1: row[0],
+            y1: row[1],
+            x2: row[2],
+            y2: row[3],
+            score: row[4],
+        })
+        .collect())
+}
+
+impl Detector for OnnxDetector {
+    fn refs(&self) -> &[PreparedRef] {
+        &self.refs
+    }
+
+    fn find_matches(&self, screenshot: &PreparedScreenshot) -> Result<Vec<TemplateMatch>> {
+        let matches = self
+            .detect(screenshot)?
+            .iter()
+            .filter(|d| d.score >= ONNX_SCORE_THRESHOLD)
+            .map(|d| self.to_match(d))
+            .collect();
+        let passes = self.opts.passes();
+        Ok(reject_negatives_and_offset(
+            screenshot, &self.refs, matches, &passes[0],
+        ))
+    }
+
+    fn find_best_match(&self, screenshot: &PreparedScreenshot) -> Option<TemplateMatch> {
+        self.find_top_matches(screenshot, 1).into_iter().next()
+    }
+
+    fn find_top_matches(&self, screenshot: &PreparedScreenshot, n: usize) -> Vec<TemplateMatch> {
+        match self.detect(screenshot) {
+            Ok(detections) => detections
+                .iter()
+                .take(n)
+                .map(|d| {
+                    let m = self.to_match(d);
+                    let (x, y) = screenshot.to_screen(m.x, m.y);
+                    TemplateMatch { x, y, ..m }
+                })
+                .collect(),
+            Err(e) => {
+                tracing::warn!("ONNX detection failed: {e:#}");
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_detections() {
+        let rows = [
+            10.0, 20.0, 30.0, 40.0, 0.9, 0.0, //
+            1.0, 2.0, 3.0, 4.0, 0.2, 0.0,
+        ];
+        let detections = parse_detections(&rows).unwrap();
+        assert_eq!(detections.len(), 2);
+        assert_eq!(
+            detections[0],
+            Detection {
+                x1: 10.0,
+                y1: 20.0,
+                x2: 30.0,
+                y2: 40.0,
+                score: 0.9,
+            }
+        );
+        assert!(parse_detections(&rows[..7]).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! PostgreSQL [`Storage`] (`MERCY_DATABASE_URL`, `postgres` feature), with
+//! the same tables as [`SqliteStore`](crate::store::SqliteStore), so several
+//! instances can write to one database.
+//!
+//! The `postgres` client blocks on a runtime of its own, which can't run
+//! inside the async one: the exchange book makes every call on its store
+//! thread ([`StoreThread`](crate::store::StoreThread)), and connecting runs
+//! in `block_in_place`.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use postgres::{Client, NoTls};
+
+use crate::state::MercExchange;
+use crate::store::{Result, ScanSummary, Storage, StoreError};
+use crate::target::PopupInfo;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS exchanges (
+    id BIGSERIAL PRIMARY KEY,
+    kingdom INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    found_at TIMESTAMPTZ NOT NULL,
+    scan_duration_secs DOUBLE PRECISION,
+    confirmed BOOLEAN NOT NULL,
+    status TEXT NOT NULL,
+    confirmed_at TIMESTAMPTZ,
+    verified_at TIMESTAMPTZ,
+    ended_at TIMESTAMPTZ
+);
+ALTER TABLE exchanges ADD COLUMN IF NOT EXISTS name TEXT;
+ALTER TABLE exchanges ADD COLUMN IF NOT EXISTS level INTEGER;
+ALTER TABLE exchanges ADD COLUMN IF NOT EXISTS timer TEXT;
+CREATE TABLE IF NOT EXISTS verifications (
+    id BIGSERIAL PRIMARY KEY,
+    kingdom INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    verified_at TIMESTAMPTZ NOT NULL,
+    present BOOLEAN NOT NULL
+);
+CREATE TABLE IF NOT EXISTS scans (
+    id BIGSERIAL PRIMARY KEY,
+    kingdom INTEGER NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ NOT NULL,
+    exchanges_found INTEGER NOT NULL,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS scans_kingdom ON scans (kingdom, finished_at);
+";
+
+pub struct PgStore {
+    client: Client,
+}
+
+impl PgStore {
+    /// Connect and create the tables. Called from the multi-threaded
+    /// runtime at startup; the client is dropped here too if that fails.
+    pub fn connect(url: &str) -> Result<Self> {
+        tokio::task::block_in_place(|| {
+            let mut client = Client::connect(url, NoTls)?;
+            client.batch_execute(SCHEMA)?;
+            Ok(Self { client })
+        })
+    }
+
+    fn with<T>(
+        &mut self,
+        f: impl FnOnce(&mut Client) -> std::result::Result<T, postgres::Error>,
+    ) -> Result<T> {
+        Ok(f(&mut self.client)?)
+    }
+}
+
+impl Storage for PgStore {
+    fn exchanges(&mut self) -> Result<Vec<MercExchange>> {
+        let rows = self.with(|client| {
+            client.query(
+                "SELECT id, kingdom, x, y, status, found_at, confirmed_at, verified_at,
+                        ended_at, scan_duration_secs, name, level, timer
+                 FROM exchanges ORDER BY id",
+                &[],
+            )
+        })?;
+        rows.iter()
+            .map(|row| {
+                let status: String = row.get(4);
+                Ok(MercExchange {
+                    id: row.get::<_, i64>(0) as u64,
+                    kingdom: row.get::<_, i32>(1) as u32,
+                    x: row.get::<_, i32>(2) as u32,
+                    y: row.get::<_, i32>(3) as u32,
+                    status: status.parse().map_err(StoreError::Invalid)?,
+                    found_at: row.get(5),
+                    confirmed_at: row.get(6),
+                    verified_at: row.get(7),
+                    ended_at: row.get(8),
+                    scan_duration_secs: row.get(9),
+                    popup: PopupInfo {
+                        name: row.get(10),
+                        level: row.get::<_, Option<i32>>(11).map(|l| l as u32),
+                        timer: row.get(12),
+                    },
+                    screenshot: None,
+                    details: Default::default(),
+                })
+            })
+            .collect()
+    }
+
+    fn last_scan_times(&mut self) -> Result<HashMap<u32, DateTime<Utc>>> {
+        let rows = self.with(|client| {
+            client.query(
+                "SELECT kingdom, MAX(finished_at) FROM scans GROUP BY kingdom",
+                &[],
+            )
+        })?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get::<_, i32>(0) as u32, row.get(1)))
+            .collect())
+    }
+
+    fn insert_exchange(&mut self, exchange: &MercExchange) -> Result<u64> {
+        let row = self.with(|client| {
+            client.query_one(
+                "INSERT INTO exchanges (kingdom, x, y, status, found_at, confirmed_at,
+                                        verified_at, ended_at, scan_duration_secs, confirmed,
+                                        name, level, timer)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                 RETURNING id",
+                &[
+                    &(exchange.kingdom as i32),
+                    &(exchange.x as i32),
+                    &(exchange.y as i32),
+                    &exchange.status.as_str(),
+                    &exchange.found_at,
+                    &exchange.confirmed_at,
+                    &exchange.verified_at,
+                    &exchange.ended_at,
+                    &exchange.scan_duration_secs,
+                    &exchange.confirmed_at.is_some(),
+                    &exchange.popup.name,
+                    &exchange.popup.level.map(|l| l as i32),
+                    &exchange.popup.timer,
+                ],
+            )
+        })?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    fn update_exchange(&mut self, exchange: &MercExchange) -> Result<()> {
+        self.with(|client| {
+            client.execute(
+                "UPDATE exchanges SET status = $2, verified_at = $3, ended_at = $4 WHERE id = $1",
+                &[
+                    &(exchange.id as i64),
+                    &exchange.status.as_str(),
+                    &exchange.verified_at,
+                    &exchange.ended_at,
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    fn clear_exchanges(&mut self) -> Result<()> {
+        self.with(|client| client.execute("DELETE FROM exchanges", &[]))?;
+        Ok(())
+    }
+
+    fn insert_verification(
+        &mut self,
+        kingdom: u32,
+        x: u32,
+        y: u32,
+        present: bool,
+        verified_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.with(|client| {
+            client.execute(
+                "INSERT INTO verifications (kingdom, x, y, verified_at, present)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &(kingdom as i32),
+                    &(x as i32),
+                    &(y as i32),
+                    &verified_at,
+                    &present,
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    fn insert_scan(&mut self, scan: &ScanSummary) -> Result<()> {
+        self.with(|client| {
+            client.execute(
+                "INSERT INTO scans (kingdom, started_at, finished_at, exchanges_found, error)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &(scan.kingdom as i32),
+                    &scan.started_at,
+                    &scan.finished_at,
+                    &(scan.exchanges_found as i32),
+                    &scan.error,
+                ],
+            )
+        })?;
+        Ok(())
+    }
+}
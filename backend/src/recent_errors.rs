@@ -0,0 +1,69 @@
+//! The last [`CAPACITY`] scanner and browser errors, for `recent_errors` in
+//! `GET /status`: a scan that is `running` can still have failed every
+//! navigation for an hour. Each is kept with when it happened and in which
+//! stage (`browser_launch`, `login`, `navigation`, `confirmation`, `health`
+//! or `scanner`).
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Errors kept; the oldest are dropped past it.
+const CAPACITY: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentError {
+    pub time: DateTime<Utc>,
+    pub stage: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kingdom: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+    errors: Mutex<VecDeque<RecentError>>,
+}
+
+impl ErrorLog {
+    pub fn record(&self, stage: &'static str, message: String, kingdom: Option<u32>) {
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        if errors.len() == CAPACITY {
+            errors.pop_front();
+        }
+        errors.push_back(RecentError {
+            time: Utc::now(),
+            stage,
+            message,
+            kingdom,
+        });
+    }
+
+    /// The errors kept, oldest first.
+    pub fn recent(&self) -> Vec<RecentError> {
+        let errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        errors.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_log_is_bounded() {
+        let log = ErrorLog::default();
+        for i in 0..CAPACITY + 5 {
+            log.record("navigation", format!("failed {i}"), Some(111));
+        }
+        let recent = log.recent();
+        assert_eq!(recent.len(), CAPACITY);
+        assert_eq!(recent[0].message, "failed 5");
+        assert_eq!(
+            recent.last().unwrap().message,
+            format!("failed {}", CAPACITY + 4)
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Scan recordings: every screenshot a kingdom pass takes (scan steps, goto
+//! and popup frames, with a marker where the bot clicked) assembled into an
+//! animated GIF, so a bad click can be replayed after the fact.
+//!
+//! Frames are queued from the scanner and encoded in order on a blocking
+//! worker, so recording doesn't hold up navigation.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::FilterType;
+use image::{Delay, Frame, Rgba, RgbaImage};
+use imageproc::drawing::draw_filled_rect_mut;
+use imageproc::rect::Rect;
+use tokio::task::JoinHandle;
+
+/// Frames are drawn onto the 1920×1080 browser window scaled down by this
+/// factor, so viewport-only and full-page captures line up.
+const RECORDING_SCALE: u32 = 3;
+const CANVAS_WIDTH: u32 = 1920 / RECORDING_SCALE;
+const CANVAS_HEIGHT: u32 = 1080 / RECORDING_SCALE;
+
+/// How long each frame is shown on playback.
+const FRAME_DELAY_MS: u32 = 500;
+
+/// NeuQuant speed for GIF palette quantization (1 best, 30 fastest).
+const GIF_SPEED: i32 = 20;
+
+const CLICK_COLOR: Rgba<u8> = Rgba([255, 40, 40, 255]);
+
+struct RecordedFrame {
+    bytes: Arc<Vec<u8>>,
+    origin: (u32, u32),
+    click: Option<(f64, f64)>,
+}
+
+/// GIF recording of one kingdom pass. Dropping it without [`finish`]
+/// still flushes the file once the worker drains its queue.
+///
+/// [`finish`]: Recorder::finish
+pub struct Recorder {
+    tx: mpsc::Sender<RecordedFrame>,
+    worker: JoinHandle<Result<usize>>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Start `scan_k<K>_<timestamp>.gif` in `dir`.
+    pub fn start(dir: &Path, kingdom: u32) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(format!(
+            "scan_k{kingdom}_{}.gif",
+            Utc::now().format("%Y%m%d_%H%M%S")
+        ));
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+
+        let (tx, rx) = mpsc::channel::<RecordedFrame>();
+        let worker = tokio::task::spawn_blocking(move || {
+            let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), GIF_SPEED);
+            encoder.set_repeat(Repeat::Infinite)?;
+            let mut frames = 0;
+            for frame in rx {
+                match compose(&frame) {
+                    Ok(canvas) => {
+                        encoder.encode_frame(Frame::from_parts(
+                            canvas,
+                            0,
+                            0,
+                            Delay::from_numer_denom_ms(FRAME_DELAY_MS, 1),
+                        ))?;
+                        frames += 1;
+                    }
+                    Err(e) => tracing::warn!("skipping recording frame: {e:#}"),
+                }
+            }
+            Ok(frames)
+        });
+
+        tracing::info!("recording kingdom {kingdom} pass to {}", path.display());
+        Ok(Self { tx, worker, path })
+    }
+
+    /// Queue an encoded screenshot whose top-left pixel sits at `origin` in
+    /// the browser window, optionally marking a click position.
+    pub fn record(&self, bytes: Arc<Vec<u8>>, origin: (u32, u32), click: Option<(f64, f64)>) {
+        // Only fails once the worker has died, which it already logged
+        let _ = self.tx.send(RecordedFrame {
+            bytes,
+            origin,
+            click,
+        });
+    }
+
+    /// Encode the queued frames and close the file.
+    pub async fn finish(self) {
+        let Recorder { tx, worker, path } = self;
+        drop(tx);
+        match worker.await {
+            Ok(Ok(frames)) => {
+                tracing::info!("saved recording {} ({frames} frames)", path.display())
+            }
+            Ok(Err(e)) => tracing::warn!("recording {} failed: {e:#}", path.display()),
+            Err(e) => tracing::warn!("recording worker panicked: {e}"),
+        }
+    }
+}
+
+/// Draw a frame onto a black window-sized canvas at its scaled position.
+fn compose(frame: &RecordedFrame) -> Result<RgbaImage> {
+    let image = image::load_from_memory(&frame.bytes).context("failed to decode frame")?;
+    let small = image
+        .resize_exact(
+            (image.width() / RECORDING_SCALE).max(1),
+            (image.height() / RECORDING_SCALE).max(1),
+            FilterType::Triangle,
+        )
+        .into_rgba8();
+
+    let mut canvas = RgbaImage::from_pixel(CANVAS_WIDTH, CANVAS_HEIGHT, Rgba([0, 0, 0, 255]));
+    image::imageops::replace(
+        &mut canvas,
+        &small,
+        (frame.origin.0 / RECORDING_SCALE) as i64,
+        (frame.origin.1 / RECORDING_SCALE) as i64,
+    );
+
+    if let Some((x, y)) = frame.click {
+        let (cx, cy) = (
+            (x / RECORDING_SCALE as f64) as i32,
+            (y / RECORDING_SCALE as f64) as i32,
+        );
+        // Crosshair with a gap in the middle, so the clicked pixel stays visible
+        for (dx, dy, w, h) in [(-9, -1, 6, 3), (4, -1, 6, 3), (-1, -9, 3, 6), (-1, 4, 3, 6)] {
+            draw_filled_rect_mut(
+                &mut canvas,
+                Rect::at(cx + dx, cy + dy).of_size(w, h),
+                CLICK_COLOR,
+            );
+        }
+    }
+    Ok(canvas)
+}
+
+/// Recordings in `dir`, newest first, as `(file name, size in bytes)`.
+pub fn list_recordings(dir: &Path) -> Vec<(String, u64)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut recordings: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let size = e.metadata().ok()?.len();
+            is_recording_name(&name).then_some((name, size))
+        })
+        .collect();
+    // Timestamped names: reverse order puts each kingdom's newest first
+    recordings.sort_by(|a, b| b.0.cmp(&a.0));
+    recordings
+}
+
+/// Whether `name` is a bare recording file name (no path components), so
+/// it's safe to join onto the recording directory.
+pub fn is_recording_name(name: &str) -> bool {
+    name.ends_with(".gif")
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\'])
+        && !name.contains("..")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_compose_places_frame_and_click() {
+        let capture = RgbImage::from_pixel(300, 150, Rgb([0, 200, 0]));
+        let bytes = crate::detector::encode_png(&capture).unwrap();
+        let canvas = compose(&RecordedFrame {
+            bytes: Arc::new(bytes),
+            origin: (300, 150),
+            click: Some((900.0, 540.0)),
+        })
+        .unwrap();
+
+        assert_eq!(canvas.dimensions(), (CANVAS_WIDTH, CANVAS_HEIGHT));
+        assert_eq!(*canvas.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*canvas.get_pixel(150, 80), Rgba([0, 200, 0, 255]));
+        assert_eq!(*canvas.get_pixel(300 - 7, 180), CLICK_COLOR);
+        assert_ne!(*canvas.get_pixel(300, 180), CLICK_COLOR, "crosshair centre");
+    }
+
+    #[test]
+    fn test_is_recording_name() {
+        assert!(is_recording_name("scan_k111_20260101_120000.gif"));
+        assert!(!is_recording_name("../exchanges.jsonl"));
+        assert!(!is_recording_name("../../etc/x.gif"));
+        assert!(!is_recording_name("notes.txt"));
+    }
+}
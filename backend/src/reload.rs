@@ -0,0 +1,69 @@
+//! Config reload on SIGHUP or `POST /config/reload`.
+//!
+//! The config is read again from the startup environment and the
+//! `--config` file (see [`ConfigSource`](crate::config::ConfigSource)). The
+//! scan settings and match options take effect at once: the scanner picks
+//! them up at its next kingdom, and detections get a rebuilt detector.
+//! Anything else that changed is logged and waits for a restart.
+
+use std::sync::Arc;
+
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::config::{ConfigError, ConfigReload};
+use crate::detector::DetectorHandle;
+use crate::state::AppState;
+
+/// Reload the config into `state` and `detectors`, returning what changed.
+pub async fn reload(
+    state: &AppState,
+    detectors: &Arc<DetectorHandle>,
+) -> Result<ConfigReload, ConfigError> {
+    let (reload, match_options) = {
+        let mut s = state.write().await;
+        let reload = s.config.reload(s.config_source.load()?);
+        let options = reload.config.match_options();
+        let changed = options != s.config.match_options();
+        s.config = Arc::new(reload.config.clone());
+        (reload, changed.then_some(options))
+    };
+    // Rebuilding prepares every template: off the runtime, and not under
+    // the state lock
+    if let Some(options) = match_options {
+        let detectors = detectors.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || detectors.set_options(options)).await {
+            tracing::error!("detector rebuild failed: {e}");
+        }
+    }
+
+    if reload.applied.is_empty() && reload.deferred.is_empty() {
+        tracing::info!("config reloaded, nothing changed");
+    } else if !reload.applied.is_empty() {
+        tracing::info!("config reloaded, applied: {}", reload.applied.join(", "));
+    }
+    if !reload.deferred.is_empty() {
+        tracing::warn!(
+            "config reloaded, changes waiting for a restart: {}",
+            reload.deferred.join(", ")
+        );
+    }
+    Ok(reload)
+}
+
+/// Reload the config on every SIGHUP for as long as the process lives.
+pub fn spawn_sighup_handler(state: AppState, detectors: Arc<DetectorHandle>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!("SIGHUP config reload unavailable: {e}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            if let Err(e) = reload(&state, &detectors).await {
+                tracing::error!("config reload failed, keeping the running config: {e}");
+            }
+        }
+    });
+}
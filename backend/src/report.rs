@@ -0,0 +1,99 @@
+//! Error reports through a user-supplied shell command
+//! (`MERCY_ERROR_COMMAND`), for failures nobody is watching the log for:
+//! scanner task errors, browser launch errors and panics, including those in
+//! detection tasks whose handles nobody joins.
+//!
+//! Like `MERCY_NOTIFY_COMMAND` the command runs via `sh -c`, with
+//! `MERCY_EVENT` (the kind: `scanner`, `browser_launch` or `panic`) and
+//! `MERCY_MESSAGE` set and a JSON object (`event`, `message`, `kingdom`,
+//! `step`, `timestamp`) on stdin. That reaches Sentry with e.g.
+//! `sentry-cli send-event -m "$MERCY_MESSAGE" -t kind:$MERCY_EVENT`, or any
+//! webhook with `curl -s -d @- <url>`.
+
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+use chrono::Utc;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::notify;
+
+static COMMAND: OnceLock<String> = OnceLock::new();
+
+thread_local! {
+    /// (kingdom, step) of the work running on this thread, for panics.
+    static CONTEXT: Cell<Option<(u32, usize)>> = const { Cell::new(None) };
+}
+
+/// Take the command from `config` and report panics from here on, after the
+/// default hook has printed them.
+pub fn init(config: &Config) {
+    let Some(ref command) = config.error_command else {
+        return;
+    };
+    let _ = COMMAND.set(command.clone());
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let message = info.payload_as_str().unwrap_or("Box<dyn Any>");
+        let message = match info.location() {
+            Some(location) => format!("{message} at {location}"),
+            None => message.to_string(),
+        };
+        let (kingdom, step) = CONTEXT.get().unzip();
+        error("panic", message, kingdom, step);
+    }));
+}
+
+/// Run `f` with a panic in it reported against `kingdom` and scan `step`.
+pub fn with_context<T>(kingdom: u32, step: usize, f: impl FnOnce() -> T) -> T {
+    /// Puts the outer context back, also when `f` unwinds.
+    struct Restore(Option<(u32, usize)>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CONTEXT.set(self.0);
+        }
+    }
+
+    let _restore = Restore(CONTEXT.replace(Some((kingdom, step))));
+    f()
+}
+
+/// Report an error of `kind` in the background, if a command is configured.
+pub fn error(kind: &str, message: String, kingdom: Option<u32>, step: Option<usize>) {
+    let Some(command) = COMMAND.get() else {
+        return;
+    };
+    let payload = json!({
+        "event": kind,
+        "message": message,
+        "kingdom": kingdom,
+        "step": step,
+        "timestamp": Utc::now().to_rfc3339(),
+    })
+    .to_string();
+    let message = match (kingdom, step) {
+        (Some(kingdom), Some(step)) => format!("K:{kingdom} step {step}: {message}"),
+        (Some(kingdom), None) => format!("K:{kingdom}: {message}"),
+        _ => message,
+    };
+    notify::spawn_command(command.clone(), kind.to_string(), message, payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_restored_after_panic() {
+        let panicked = std::panic::catch_unwind(|| {
+            with_context(111, 12, || {
+                assert_eq!(CONTEXT.get(), Some((111, 12)));
+                panic!("detector failed");
+            })
+        });
+        assert!(panicked.is_err());
+        assert_eq!(CONTEXT.get(), None);
+    }
+}
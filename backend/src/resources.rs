@@ -0,0 +1,190 @@
+//! Browser memory use and session age, and the restarts they trigger
+//! (`MERCY_BROWSER_MAX_RSS_MB`, `MERCY_BROWSER_MAX_AGE_MINS`).
+//!
+//! The game client leaks: a session left running for a day grows to several
+//! GB and gets OOM-killed mid-scan. Every [`SAMPLE_INTERVAL`] the monitor
+//! sums the resident memory of the browser's process tree (from `/proc`, so
+//! Linux only, and only for a locally launched browser) and checks the
+//! session age. Past either limit the browser is flagged for a restart. The
+//! scanner picks that up before its next step and relaunches, logs in again
+//! and resumes the step. A browser waiting in `Ready` is relaunched right away.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::time::Duration;
+
+use crate::config::Config;
+use crate::metrics;
+use crate::scanner;
+use crate::state::{AppState, ScannerPhase};
+
+/// How often memory use and session age are checked.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Latest sample, reported in `/status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    pub sampled_at: DateTime<Utc>,
+    /// Resident memory of Chromium and its child processes; `None` for a
+    /// remote browser or off Linux.
+    pub rss_mb: Option<u64>,
+    pub session_started: DateTime<Utc>,
+    /// Why the browser is due a restart, once it is.
+    pub restart_due: Option<String>,
+}
+
+/// Sample the browser's resources for as long as the process lives.
+pub fn spawn_resource_monitor(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            sample(&state).await;
+        }
+    });
+}
+
+async fn sample(state: &AppState) {
+    let (game, phase, config) = {
+        let s = state.read().await;
+        (s.browser.clone(), s.phase, s.config.clone())
+    };
+    let Some(game) = game else {
+        state.write().await.resources = None;
+        return;
+    };
+
+    let rss_kb = match game.pid() {
+        Some(pid) => tokio::task::spawn_blocking(move || process_tree_rss_kb(pid))
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let rss_mb = rss_kb.map(|kb| kb / 1024);
+    let session_started = game.launched_at();
+    let restart_due = restart_reason(&config, rss_mb, session_started);
+    if let Some(ref reason) = restart_due
+        && game.request_restart(reason)
+    {
+        tracing::warn!("browser restart due: {reason}");
+    }
+
+    let mut s = state.write().await;
+    // The browser may have been replaced while sampling
+    if !s.browser.as_ref().is_some_and(|b| Arc::ptr_eq(b, &game)) {
+        return;
+    }
+    s.resources = Some(ResourceUsage {
+        sampled_at: Utc::now(),
+        rss_mb,
+        session_started,
+        restart_due: restart_due.clone(),
+    });
+    if restart_due.is_none() || phase != ScannerPhase::Ready || s.phase != ScannerPhase::Ready {
+        return;
+    }
+
+    // Nothing is using it: relaunch now rather than at the next scan
+    metrics::bump(&s.counters.browser_restarts);
+    s.browser = None;
+    s.api_tab = None;
+    s.resources = None;
+    s.set_phase(ScannerPhase::Idle);
+    drop(s);
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = scanner::prepare_browser(&state).await {
+            tracing::error!("browser restart failed: {e:#}");
+            let mut s = state.write().await;
+            s.set_phase(ScannerPhase::Idle);
+            s.browser = None;
+        }
+    });
+}
+
+/// Why the browser should be restarted, if it should.
+fn restart_reason(
+    config: &Config,
+    rss_mb: Option<u64>,
+    session_started: DateTime<Utc>,
+) -> Option<String> {
+    if let (Some(limit), Some(rss)) = (config.browser_max_rss_mb, rss_mb)
+        && rss > limit
+    {
+        return Some(format!("Chromium uses {rss} MB (limit {limit} MB)"));
+    }
+    let age_mins = (Utc::now() - session_started).num_minutes().max(0) as u64;
+    if let Some(limit) = config.browser_max_age_mins
+        && age_mins >= limit
+    {
+        return Some(format!("session is {age_mins} minutes old (limit {limit})"));
+    }
+    None
+}
+
+/// Resident memory (kB) of `pid` and all of its descendants.
+fn process_tree_rss_kb(pid: u32) -> Option<u64> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(child) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        if let Some(ppid) = std::fs::read_to_string(entry.path().join("stat"))
+            .ok()
+            .and_then(|stat| parse_stat_ppid(&stat))
+        {
+            children.entry(ppid).or_default().push(child);
+        }
+    }
+
+    let mut total = 0;
+    let mut stack = vec![pid];
+    while let Some(p) = stack.pop() {
+        // A process that exited meanwhile just doesn't count
+        total += std::fs::read_to_string(format!("/proc/{p}/status"))
+            .ok()
+            .and_then(|status| parse_vm_rss_kb(&status))
+            .unwrap_or(0);
+        stack.extend(children.get(&p).into_iter().flatten());
+    }
+    (total > 0).then_some(total)
+}
+
+/// Parent pid from `/proc/<pid>/stat`. The command name can contain spaces
+/// and parentheses, so fields are counted from its closing one.
+fn parse_stat_ppid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// `VmRSS` (kB) from `/proc/<pid>/status`.
+fn parse_vm_rss_kb(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_files() {
+        let stat = "4242 (chrome (renderer)) S 4100 4100 4100 0 -1 4194560 12345";
+        assert_eq!(parse_stat_ppid(stat), Some(4100));
+        assert_eq!(parse_stat_ppid("garbage"), None);
+
+        let status = "Name:\tchrome\nVmPeak:\t 2000000 kB\nVmRSS:\t  812340 kB\nThreads:\t30\n";
+        assert_eq!(parse_vm_rss_kb(status), Some(812_340));
+        // Kernel threads have no VmRSS line
+        assert_eq!(parse_vm_rss_kb("Name:\tkthreadd\n"), None);
+    }
+}
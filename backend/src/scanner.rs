@@ -1,18 +1,41 @@
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use anyhow::{Context, Result};
-use chrono::Utc;
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tokio::time::{Duration, sleep};
 
-use crate::browser::{self, GameBrowser};
+use crate::accounts::AccountRotation;
+use crate::annotate;
+use crate::browser::{BrowserError, GameBrowser, NavigationMode};
+use crate::challenge::{self, DialogTemplate};
 use crate::config::Config;
-use crate::detector::{self, PreparedRef};
-use crate::state::{AppState, MercExchange, ScannerPhase};
-
-#[derive(Debug, Serialize)]
+use crate::cookies;
+use crate::coords_display;
+use crate::detector::{self, Detector, DetectorHandle, PreparedScreenshot};
+use crate::disconnect::{self, DisconnectTemplates};
+use crate::events::EventKind;
+use crate::locate::{self, CALIBRATION_ROI_HALF, Confirmation, MapTransform};
+use crate::logfile;
+use crate::metrics;
+use crate::notify::{EXCHANGE_EVENT, notify};
+use crate::recorder::Recorder;
+use crate::report;
+use crate::screenshot_history::ShotSource;
+use crate::screenshots::Screenshots;
+use crate::state::{
+    Added, AppState, Challenge, ConfirmedBy, ExchangeDetails, MercExchange, ScannerPhase,
+};
+use crate::stats::KingdomStats;
+use crate::target::{PopupInfo, parse_popup_info};
+use crate::totp;
+use crate::viewport::{self, Viewport};
+
+#[derive(Debug, Serialize, Deserialize)]
 struct ExchangeLogEntry {
     timestamp: String,
     kingdom: u32,
@@ -24,93 +47,469 @@ struct ExchangeLogEntry {
     calibration_score: Option<f32>,
     scan_pattern: String,
     scan_duration_secs: Option<f64>,
+    #[serde(flatten)]
+    popup: PopupInfo,
+    /// Clicked as a near miss, below the threshold (`MERCY_RELAX_AFTER_PASSES`)
+    #[serde(default)]
+    near_miss: bool,
+    /// The kingdom being scanned, when the popup reported another one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scanned_kingdom: Option<u32>,
 }
 
 fn log_exchange(config: &Config, entry: &ExchangeLogEntry) {
-    use std::fs::OpenOptions;
-    use std::io::Write;
-
+    // One write per line, so a stop between entries can't leave half a line
     let line = match serde_json::to_string(entry) {
-        Ok(l) => l,
+        Ok(l) => l + "\n",
         Err(e) => {
             tracing::warn!("failed to serialize exchange log entry: {e}");
             return;
         }
     };
 
-    match OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&config.exchange_log)
-    {
-        Ok(mut f) => {
-            if let Err(e) = writeln!(f, "{line}") {
-                tracing::warn!("failed to write to {}: {e}", config.exchange_log);
-            }
-        }
+    logfile::append(Path::new(&config.exchange_log), &config.log_rotation, &line);
+}
+
+/// The notification for a stored exchange, with what its popup said:
+/// `Mercenary Exchange (level 3) found at K:111 X:506 Y:638, 02:13:45 left`.
+fn exchange_message(
+    target_name: &str,
+    k: u32,
+    x: u32,
+    y: u32,
+    from_popup: bool,
+    popup: &PopupInfo,
+) -> String {
+    let mut message = popup.name.as_deref().unwrap_or(target_name).to_string();
+    if let Some(level) = popup.level {
+        message.push_str(&format!(" (level {level})"));
+    }
+    message.push_str(&format!(" found at K:{k} X:{x} Y:{y}"));
+    if let Some(ref timer) = popup.timer {
+        message.push_str(&format!(", {timer} left"));
+    }
+    if !from_popup {
+        message.push_str(" (estimate)");
+    }
+    message
+}
+
+/// Exchanges to start from after a restart: the most recent confirmed entry
+/// of each kingdom in the exchange log and its last rotated segment. A
+/// missing log is an empty one.
+pub fn load_exchange_log(config: &Config) -> Vec<MercExchange> {
+    match logfile::read_recent(Path::new(&config.exchange_log)) {
+        Ok(text) => latest_confirmed_exchanges(&text),
         Err(e) => {
-            tracing::warn!("failed to open {}: {e}", config.exchange_log);
+            tracing::warn!("failed to read {}: {e}", config.exchange_log);
+            Vec::new()
         }
     }
 }
 
+fn latest_confirmed_exchanges(log: &str) -> Vec<MercExchange> {
+    let mut latest: HashMap<u32, MercExchange> = HashMap::new();
+    let mut invalid = 0;
+    for line in log.lines().filter(|l| !l.trim().is_empty()) {
+        let Some((entry, found_at)) = serde_json::from_str::<ExchangeLogEntry>(line)
+            .ok()
+            .and_then(|e| {
+                let found_at = DateTime::parse_from_rfc3339(&e.timestamp).ok()?;
+                Some((e, found_at.with_timezone(&Utc)))
+            })
+        else {
+            invalid += 1;
+            continue;
+        };
+        if !entry.confirmed
+            || latest
+                .get(&entry.kingdom)
+                .is_some_and(|e| e.found_at > found_at)
+        {
+            continue;
+        }
+        latest.insert(
+            entry.kingdom,
+            MercExchange {
+                found_at,
+                confirmed_at: Some(found_at),
+                scan_duration_secs: entry.scan_duration_secs,
+                popup: entry.popup,
+                details: ExchangeDetails {
+                    initial_score: Some(entry.initial_score),
+                    calibration_score: entry.calibration_score,
+                    scan_pattern: Some(entry.scan_pattern),
+                    confirmed_by: Some(ConfirmedBy::Popup),
+                    scanned_kingdom: entry.scanned_kingdom,
+                    verifications: Vec::new(),
+                },
+                ..MercExchange::found(entry.kingdom, entry.x, entry.y, true)
+            },
+        );
+    }
+    if invalid > 0 {
+        tracing::warn!("skipped {invalid} unreadable exchange log line(s)");
+    }
+    let mut exchanges: Vec<_> = latest.into_values().collect();
+    exchanges.sort_by_key(|e| e.found_at);
+    exchanges
+}
+
 /// Game-coordinate step between scan positions.
 /// Viewport covers ~34×33 game units (usable area at 25% zoom),
 /// so step=25 gives ~25% overlap for reliable detection.
 const SCAN_STEP: u32 = 25;
 
+/// Strongest candidates (regardless of threshold) drawn on debug screenshots.
+const DEBUG_CANDIDATES: usize = 10;
+
+/// How long login waits for a manually entered 2FA code.
+const TWO_FACTOR_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A TOTP code with less validity left than this is skipped for the next one.
+const TOTP_MIN_REMAINING_SECS: u64 = 5;
+
 /// Launch browser and log in if not already done. Sets phase Idle → Preparing → Ready.
 /// If a browser already exists, returns it without relaunching.
+#[tracing::instrument(skip_all, fields(phase = "preparing"))]
 pub async fn prepare_browser(state: &AppState) -> Result<Arc<GameBrowser>> {
     // Fast path: browser already exists
     {
-        let s = state.lock().await;
+        let s = state.read().await;
         if let Some(ref browser) = s.browser {
             return Ok(browser.clone());
         }
     }
 
     // Set phase to Preparing
-    let config = {
-        let mut s = state.lock().await;
-        s.phase = ScannerPhase::Preparing;
-        s.config.clone()
+    let (config, console, counters, selected) = {
+        let mut s = state.write().await;
+        s.set_phase(ScannerPhase::Preparing);
+        let selected = s.accounts.select(Utc::now());
+        (
+            s.effective_config(),
+            s.console.clone(),
+            s.counters.clone(),
+            selected,
+        )
     };
+    let account = selected.map(|(index, wait)| (index, &config.accounts[index], wait));
+    if let Some((_, account, wait)) = account
+        && let Ok(wait) = wait.to_std()
+        && !wait.is_zero()
+    {
+        tracing::info!(
+            "all accounts cooling down, waiting {}s for {}",
+            wait.as_secs(),
+            account.email
+        );
+        sleep(wait).await;
+    }
 
     tracing::info!("launching browser");
-    let game = Arc::new(
-        GameBrowser::launch(&config)
-            .await
-            .context("failed to launch browser")?,
-    );
+    let game = match GameBrowser::launch(&config, console, counters).await {
+        Ok(game) => Arc::new(game),
+        Err(e) => {
+            let e = e.context("failed to launch browser");
+            report::error("browser_launch", format!("{e:#}"), None, None);
+            let errors = state.read().await.errors.clone();
+            errors.record("browser_launch", format!("{e:#}"), None);
+            return Err(e);
+        }
+    };
 
     // Store browser in state so the API can take screenshots
     {
-        let mut s = state.lock().await;
+        let mut s = state.write().await;
         s.browser = Some(game.clone());
+        s.api_tab = None;
+        s.health = None;
+        s.resources = None;
     }
 
-    tracing::info!("logging in");
-    game.login(&config.tb_email, &config.tb_password)
-        .await
-        .context("login failed")?;
+    let login = async {
+        // Session cookies belong to the primary account
+        let cookies = match account {
+            Some((index, ..)) if index > 0 => Vec::new(),
+            _ => cookies::load_cookies(&config).context("failed to load session cookies")?,
+        };
+        let cookie_login = if cookies.is_empty() {
+            false
+        } else {
+            tracing::info!("logging in with session cookies");
+            game.login_with_cookies(cookies)
+                .await
+                .context("cookie login failed")?
+        };
+        if !cookie_login {
+            let Some((_, account, _)) = account else {
+                anyhow::bail!("session cookies rejected and no MERCY_TB_EMAIL to fall back to");
+            };
+            tracing::info!("logging in as {}", account.email);
+            game.login(&account.email, &account.password, || {
+                two_factor_code(state, &config, account.totp_secret.as_deref())
+            })
+            .await?;
+        }
+        anyhow::Ok(())
+    };
+    if let Err(e) = login.await {
+        notify(&config, "login_failed", format!("login failed: {e:#}"));
+        let errors = state.read().await.errors.clone();
+        errors.record("login", format!("{e:#}"), None);
+        return Err(e);
+    }
+
+    verify_zoom(&game, config.kingdoms[0], config.map_transform).await;
+    let viewport = detect_session_viewport(&game, config.viewport).await;
 
     // Set phase to Ready
     {
-        let mut s = state.lock().await;
-        s.phase = ScannerPhase::Ready;
+        let mut s = state.write().await;
+        s.viewport = viewport;
+        s.set_phase(ScannerPhase::Ready);
     }
 
     tracing::info!("browser ready");
     Ok(game)
 }
 
+/// Code for the login 2FA prompt: computed from the account's TOTP secret if
+/// it has one, otherwise waited for from `POST /login/2fa` in `WaitingFor2fa`.
+#[tracing::instrument(skip_all, fields(phase = "waiting_for_2fa"))]
+pub async fn two_factor_code(
+    state: &AppState,
+    config: &Config,
+    totp_secret: Option<&str>,
+) -> Result<String> {
+    if let Some(secret) = totp_secret {
+        let key = totp::decode_secret(secret).map_err(anyhow::Error::msg)?;
+        let mut now = Utc::now().timestamp() as u64;
+        // A code about to roll over may be stale by the time it's submitted
+        let remaining = totp::seconds_remaining(now);
+        if remaining < TOTP_MIN_REMAINING_SECS {
+            sleep(Duration::from_secs(remaining)).await;
+            now = Utc::now().timestamp() as u64;
+        }
+        tracing::info!("answering 2FA prompt with TOTP code");
+        return Ok(totp::totp(&key, now));
+    }
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    {
+        let mut s = state.write().await;
+        s.set_phase(ScannerPhase::WaitingFor2fa);
+        s.two_factor_tx = Some(tx);
+    }
+    notify(
+        config,
+        "two_factor",
+        "login is waiting for a 2FA code (POST /login/2fa)".into(),
+    );
+    let code = tokio::time::timeout(TWO_FACTOR_TIMEOUT, rx).await;
+    {
+        let mut s = state.write().await;
+        s.two_factor_tx = None;
+        if s.phase == ScannerPhase::WaitingFor2fa {
+            s.set_phase(ScannerPhase::Preparing);
+        }
+    }
+    match code {
+        Ok(Ok(code)) => Ok(code),
+        Ok(Err(_)) => anyhow::bail!("2FA entry cancelled"),
+        Err(_) => anyhow::bail!("no 2FA code entered within {TWO_FACTOR_TIMEOUT:?}"),
+    }
+}
+
+/// Locate the game viewport from UI anchors on a post-login screenshot.
+/// Any failure falls back to the `default` bounds.
+async fn detect_session_viewport(game: &GameBrowser, default: Viewport) -> Viewport {
+    let bytes = match game.take_screenshot().await {
+        Ok(b) => b,
+        Err(e) => {
+            tracing::warn!("viewport detection skipped, screenshot failed: {e:#}");
+            return default;
+        }
+    };
+
+    let detected = tokio::task::spawn_blocking(move || -> Result<Viewport> {
+        let anchors = viewport::load_ui_anchors()?;
+        let image = image::load_from_memory(&bytes).context("failed to decode screenshot")?;
+        Ok(viewport::detect_viewport(&image, &anchors, default))
+    })
+    .await;
+
+    match detected {
+        Ok(Ok(v)) => {
+            tracing::info!("game viewport: {v:?}");
+            v
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("viewport detection failed, using default: {e:#}");
+            default
+        }
+        Err(e) => {
+            tracing::warn!("viewport detection task panicked, using default: {e}");
+            default
+        }
+    }
+}
+
+/// Game units the zoom probe moves along X between its two screenshots.
+const ZOOM_PROBE_STEP: u32 = 4;
+
+/// Accepted deviation of the measured map scale from the calibrated one.
+/// One zoom step changes the scale by well over this.
+const ZOOM_TOLERANCE: f64 = 0.15;
+
+/// Zoom clicks tried before giving up on reaching the calibrated scale.
+const MAX_ZOOM_CORRECTIONS: u32 = 4;
+
+/// Downscale factor for the zoom probe screenshots.
+const ZOOM_PROBE_SCALE: u32 = 4;
+
+/// Side of the square map patch tracked by the zoom probe, in downscaled pixels.
+const ZOOM_PROBE_PATCH: u32 = 48;
+
+/// Minimum zero-mean NCC for the probe patch to count as found again.
+const ZOOM_PROBE_MIN_SCORE: f32 = 0.6;
+
+/// Check the map is at the zoom the pixel-to-game transform was calibrated
+/// for, and correct it with extra zoom clicks if not. A missed zoom-out click
+/// at login would otherwise skew every coordinate conversion all session.
+/// Navigates `ZOOM_PROBE_STEP` units along X near the kingdom center and
+/// compares how far the map moved with the [`MapTransform`].
+async fn verify_zoom(game: &GameBrowser, kingdom: u32, transform: MapTransform) {
+    for attempt in 0..=MAX_ZOOM_CORRECTIONS {
+        let scale = match probe_zoom(game, kingdom, transform).await {
+            Ok(Some(scale)) => scale,
+            Ok(None) => {
+                tracing::warn!(
+                    "zoom check inconclusive (map shift not found), assuming zoom is right"
+                );
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("zoom check failed: {e:#}");
+                return;
+            }
+        };
+        if (scale - 1.0).abs() <= ZOOM_TOLERANCE {
+            tracing::info!("zoom verified: map scale {scale:.2}× calibrated");
+            return;
+        }
+        if attempt == MAX_ZOOM_CORRECTIONS {
+            tracing::error!(
+                "map scale still {scale:.2}× calibrated after {attempt} zoom corrections; coordinates will be off"
+            );
+            return;
+        }
+        if scale > 1.0 {
+            tracing::warn!("map scale {scale:.2}× calibrated (zoomed in), zooming out");
+            game.zoom_out().await.ok();
+        } else {
+            tracing::warn!("map scale {scale:.2}× calibrated (zoomed out), zooming in");
+            game.zoom_in().await;
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Screenshot the map before and after a `ZOOM_PROBE_STEP` move and measure
+/// the scale from the shift.
+async fn probe_zoom(
+    game: &GameBrowser,
+    kingdom: u32,
+    transform: MapTransform,
+) -> Result<Option<f64>> {
+    game.navigate_to_coords(kingdom, 512, 512).await?;
+    let before = game.take_screenshot().await?;
+    game.navigate_to_coords(kingdom, 512 + ZOOM_PROBE_STEP, 512)
+        .await?;
+    let after = game.take_screenshot().await?;
+
+    tokio::task::spawn_blocking(move || {
+        let before = image::load_from_memory(&before).context("failed to decode screenshot")?;
+        let after = image::load_from_memory(&after).context("failed to decode screenshot")?;
+        Ok(measure_zoom(&before, &after, ZOOM_PROBE_STEP, transform))
+    })
+    .await
+    .context("zoom probe task panicked")?
+}
+
+/// Map scale relative to the calibrated transform (1.0 = calibrated, >1 =
+/// zoomed in), from screenshots taken before and after moving `step` game
+/// units along X: a patch at the screen center in `before` is found in
+/// `after`, and its displacement compared with the calibrated one.
+fn measure_zoom(
+    before: &image::DynamicImage,
+    after: &image::DynamicImage,
+    step: u32,
+    transform: MapTransform,
+) -> Option<f64> {
+    let small = |img: &image::DynamicImage| {
+        img.resize_exact(
+            img.width() / ZOOM_PROBE_SCALE,
+            img.height() / ZOOM_PROBE_SCALE,
+            image::imageops::FilterType::Triangle,
+        )
+        .to_luma8()
+    };
+    let (before, after) = (small(before), small(after));
+
+    // Moving the camera right slides the map left (and, by the tilt, down)
+    let expected = (
+        -transform.px_per_game_x * step as f64 / ZOOM_PROBE_SCALE as f64,
+        -transform.tilt_y * step as f64 / ZOOM_PROBE_SCALE as f64,
+    );
+    let patch = ZOOM_PROBE_PATCH;
+    let cx = (transform.center_x / ZOOM_PROBE_SCALE as f64) as u32;
+    let cy = (transform.center_y / ZOOM_PROBE_SCALE as f64) as u32;
+    let (px, py) = (cx.checked_sub(patch / 2)?, cy.checked_sub(patch / 2)?);
+    if px + patch > before.width() || py + patch > before.height() {
+        return None;
+    }
+    let template = image::imageops::crop_imm(&before, px, py, patch, patch).to_image();
+
+    // Only search where the patch can have gone: left of where it was, up to
+    // 3× the calibrated shift, and a few rows either way (the tilt is small)
+    let reach = (3.0 * expected.0.abs()) as u32;
+    let band_x = px.saturating_sub(reach);
+    let band_y = py.saturating_sub(patch / 4);
+    let band_w = (px + patch + patch / 4).min(after.width()) - band_x;
+    let band_h = (patch + patch / 2).min(after.height() - band_y);
+    if band_w < patch || band_h < patch {
+        return None;
+    }
+    let band = image::imageops::crop_imm(&after, band_x, band_y, band_w, band_h).to_image();
+    let scores = detector::zero_mean_ncc(&band, &template);
+    let (bx, by, best) = scores
+        .enumerate_pixels()
+        .map(|(x, y, p)| (x, y, p.0[0]))
+        .fold(
+            (0, 0, f32::NEG_INFINITY),
+            |a, b| if b.2 > a.2 { b } else { a },
+        );
+    if best < ZOOM_PROBE_MIN_SCORE {
+        return None;
+    }
+
+    let shift = (
+        (bx + band_x) as f64 - px as f64,
+        (by + band_y) as f64 - py as f64,
+    );
+    let expected_len = expected.0.hypot(expected.1);
+    // Project onto the expected direction so a little vertical noise doesn't count
+    let along = (shift.0 * expected.0 + shift.1 * expected.1) / expected_len;
+    Some(along / expected_len)
+}
+
 /// Check whether the scan loop should continue. If paused, blocks until resumed.
 /// Returns `true` for Scanning, `false` for anything else (stopped, idle, etc.).
 async fn check_should_continue(state: &AppState) -> bool {
     loop {
         let (phase, notify) = {
-            let s = state.lock().await;
+            let s = state.read().await;
             (s.phase, s.pause_notify.clone())
         };
         match phase {
@@ -125,44 +524,56 @@ async fn check_should_continue(state: &AppState) -> bool {
     }
 }
 
-pub async fn run_scan(state: AppState, ref_images: Arc<Vec<PreparedRef>>) -> Result<()> {
-    let config = {
-        let s = state.lock().await;
-        s.config.clone()
-    };
-
-    let game = prepare_browser(&state).await?;
+#[tracing::instrument(skip_all, fields(phase = "scanning"))]
+pub async fn run_scan(state: AppState, detectors: Arc<DetectorHandle>) -> Result<()> {
+    let mut game = prepare_browser(&state).await?;
 
     // Create priority scan channel and store sender in state
     let (priority_tx, mut priority_rx) = tokio::sync::mpsc::unbounded_channel::<u32>();
     {
-        let mut s = state.lock().await;
-        s.phase = ScannerPhase::Scanning;
+        let mut s = state.write().await;
+        s.set_phase(ScannerPhase::Scanning);
         s.priority_scan_tx = Some(priority_tx);
     }
 
     tracing::info!("starting kingdom scan loop");
 
-    let cooldown = chrono::Duration::minutes(2);
-
     loop {
+        // Overrides changed through the API apply from the next pass
+        let config = state.read().await.effective_config();
         for &kingdom in &config.kingdoms {
+            // Pick up templates added through the API since the last kingdom
+            let detector = detectors.current();
+
             // Drain priority queue: scan any manually-requested kingdoms first
             while let Ok(prio_kingdom) = priority_rx.try_recv() {
                 tracing::info!("priority scan requested for kingdom {prio_kingdom}");
                 {
-                    let mut s = state.lock().await;
+                    let mut s = state.write().await;
                     s.manual_scan_kingdom = Some(prio_kingdom);
                     s.current_kingdom = Some(prio_kingdom);
                 }
-                if let Err(e) =
-                    scan_kingdom(&game, &state, prio_kingdom, &ref_images, &config).await
-                {
+                let started_at = Utc::now();
+                let prio_config = config.for_kingdom(prio_kingdom);
+                let result = scan_kingdom_recovering(
+                    &mut game,
+                    &state,
+                    prio_kingdom,
+                    &scan_positions(&prio_config, prio_kingdom),
+                    &detector,
+                    &prio_config,
+                )
+                .await;
+                if let Err(ref e) = result {
                     tracing::error!("error in priority scan of kingdom {prio_kingdom}: {e:#}");
                 }
                 {
-                    let mut s = state.lock().await;
-                    s.set_last_scan_time(prio_kingdom);
+                    let mut s = state.write().await;
+                    s.finish_scan(
+                        prio_kingdom,
+                        started_at,
+                        result.err().map(|e| format!("{e:#}")),
+                    );
                     s.manual_scan_kingdom = None;
                 }
             }
@@ -170,20 +581,41 @@ pub async fn run_scan(state: AppState, ref_images: Arc<Vec<PreparedRef>>) -> Res
             if !check_should_continue(&state).await {
                 tracing::info!("scanner stopped");
                 // Clear priority_scan_tx on exit
-                state.lock().await.priority_scan_tx = None;
+                state.write().await.priority_scan_tx = None;
                 return Ok(());
             }
 
+            let rotate = config.account_rotation == AccountRotation::Kingdom
+                && state.read().await.accounts.rotation_due();
+            let restart = game
+                .restart_requested()
+                .or_else(|| rotate.then(|| "rotating to the next account".to_string()));
+            if let Some(reason) = restart {
+                tracing::warn!("restarting browser: {reason}");
+                match relaunch_browser(&state).await {
+                    Ok(new_game) => game = new_game,
+                    Err(e) => {
+                        state.write().await.priority_scan_tx = None;
+                        return Err(e);
+                    }
+                }
+            }
+
             // Update current kingdom
             {
-                let mut s = state.lock().await;
+                let mut s = state.write().await;
                 s.current_kingdom = Some(kingdom);
             }
 
             // Cooldown + re-verification logic
+            let config = config.for_kingdom(kingdom);
+            let cooldown = chrono::Duration::minutes(config.scan_cooldown_mins as i64);
             let (last_scan, known_exchange) = {
-                let s = state.lock().await;
-                (s.last_scan_time(kingdom), s.exchange_for_kingdom(kingdom))
+                let s = state.read().await;
+                (
+                    s.last_scan_time(kingdom),
+                    s.exchanges.latest_for_kingdom(kingdom),
+                )
             };
 
             if let Some(last) = last_scan {
@@ -192,20 +624,31 @@ pub async fn run_scan(state: AppState, ref_images: Arc<Vec<PreparedRef>>) -> Res
                     if let Some((ex, ey)) = known_exchange {
                         // Re-verify: navigate to known location, check if still there
                         tracing::info!("kingdom {kingdom}: re-verifying exchange at ({ex}, {ey})");
-                        match verify_exchange(&game, kingdom, ex, ey, &ref_images, &config).await {
-                            Ok(true) => {
+                        match verify_exchange(
+                            &game,
+                            &state,
+                            kingdom,
+                            ex,
+                            ey,
+                            detector.as_ref(),
+                            &config,
+                        )
+                        .await
+                        {
+                            Ok(outcome) if outcome.present => {
                                 tracing::info!("kingdom {kingdom}: exchange still present");
-                                let mut s = state.lock().await;
-                                s.refresh_exchange(kingdom, ex, ey);
+                                let exchanges = state.read().await.exchanges.clone();
+                                exchanges.record_verification(kingdom, ex, ey, true);
+                                exchanges.mark_verified(kingdom, ex, ey);
                                 let remaining = (cooldown - elapsed).to_std().unwrap_or_default();
-                                drop(s);
                                 sleep(remaining).await;
                                 continue;
                             }
-                            Ok(false) => {
-                                tracing::info!("kingdom {kingdom}: exchange gone, removing");
-                                let mut s = state.lock().await;
-                                s.remove_exchange(kingdom);
+                            Ok(_) => {
+                                tracing::info!("kingdom {kingdom}: exchange gone");
+                                let exchanges = state.read().await.exchanges.clone();
+                                exchanges.record_verification(kingdom, ex, ey, false);
+                                exchanges.mark_gone(kingdom);
                                 // Fall through to full scan
                             }
                             Err(e) => {
@@ -225,13 +668,23 @@ pub async fn run_scan(state: AppState, ref_images: Arc<Vec<PreparedRef>>) -> Res
 
             // Full spiral scan
             tracing::info!("scanning kingdom {kingdom}");
-            if let Err(e) = scan_kingdom(&game, &state, kingdom, &ref_images, &config).await {
+            let started_at = Utc::now();
+            let result = scan_kingdom_recovering(
+                &mut game,
+                &state,
+                kingdom,
+                &scan_positions(&config, kingdom),
+                &detector,
+                &config,
+            )
+            .await;
+            if let Err(ref e) = result {
                 tracing::error!("error scanning kingdom {kingdom}: {e:#}");
             }
 
             {
-                let mut s = state.lock().await;
-                s.set_last_scan_time(kingdom);
+                let mut s = state.write().await;
+                s.finish_scan(kingdom, started_at, result.err().map(|e| format!("{e:#}")));
             }
         }
 
@@ -239,35 +692,58 @@ pub async fn run_scan(state: AppState, ref_images: Arc<Vec<PreparedRef>>) -> Res
     }
 }
 
-/// Run a single kingdom scan when the scanner loop is not active (Ready/Idle).
+/// Run a single kingdom scan when the scanner loop is not active (Ready/Idle),
+/// of the configured pattern's positions or of `custom` ones.
+#[tracing::instrument(skip_all, fields(phase = "scanning", kingdom = kingdom))]
 pub async fn run_single_kingdom_scan(
     state: AppState,
-    ref_images: Arc<Vec<PreparedRef>>,
+    detectors: Arc<DetectorHandle>,
     kingdom: u32,
+    custom: Option<Vec<(u32, u32)>>,
 ) -> Result<()> {
-    let config = {
-        let s = state.lock().await;
-        s.config.clone()
+    let mut config = state.read().await.effective_config().for_kingdom(kingdom);
+    let positions = match custom {
+        Some(positions) => {
+            config.scan_pattern = CUSTOM_PATTERN.into();
+            positions
+        }
+        None => scan_positions(&config, kingdom),
     };
 
-    let game = prepare_browser(&state).await?;
+    let mut game = match prepare_browser(&state).await {
+        Ok(game) => game,
+        Err(e) => {
+            state.write().await.events.push(EventKind::Error {
+                kingdom: Some(kingdom),
+                message: format!("{e:#}"),
+            });
+            return Err(e);
+        }
+    };
 
     {
-        let mut s = state.lock().await;
-        s.phase = ScannerPhase::Scanning;
+        let mut s = state.write().await;
+        s.set_phase(ScannerPhase::Scanning);
         s.manual_scan_kingdom = Some(kingdom);
         s.current_kingdom = Some(kingdom);
     }
 
     tracing::info!("one-shot scan for kingdom {kingdom}");
-    let result = scan_kingdom(&game, &state, kingdom, &ref_images, &config).await;
+    let detector = detectors.current();
+    let started_at = Utc::now();
+    let result =
+        scan_kingdom_recovering(&mut game, &state, kingdom, &positions, &detector, &config).await;
 
     {
-        let mut s = state.lock().await;
-        s.set_last_scan_time(kingdom);
+        let mut s = state.write().await;
+        s.finish_scan(
+            kingdom,
+            started_at,
+            result.as_ref().err().map(|e| format!("{e:#}")),
+        );
         s.manual_scan_kingdom = None;
         s.current_kingdom = None;
-        s.phase = ScannerPhase::Ready;
+        s.set_phase(ScannerPhase::Ready);
     }
 
     if let Err(ref e) = result {
@@ -276,46 +752,367 @@ pub async fn run_single_kingdom_scan(
     result
 }
 
+/// What a re-check of an exchange location saw.
+pub struct VerifyOutcome {
+    /// Whether the exchange is still there
+    pub present: bool,
+    /// The best match near screen center, if any
+    pub best: Option<detector::TemplateMatch>,
+    /// Pixel distance of that match from where the exchange should be
+    pub pixel_error: Option<(f64, f64)>,
+    /// The verification screenshot (PNG)
+    pub screenshot: Vec<u8>,
+}
+
 /// Navigate to known exchange coordinates, screenshot, and check if the exchange
-/// is still visible near screen center (within ~80px, score >= 0.90).
-async fn verify_exchange(
+/// is still visible near screen center (see [`locate::VerifyCriteria`]).
+#[tracing::instrument(skip_all, fields(kingdom = kingdom, x = x, y = y))]
+pub async fn verify_exchange(
     game: &GameBrowser,
+    state: &AppState,
     kingdom: u32,
     x: u32,
     y: u32,
-    ref_images: &[PreparedRef],
-    _config: &Config,
-) -> Result<bool> {
-    game.navigate_to_coords(kingdom, x, y).await?;
-    sleep(Duration::from_secs(2)).await;
+    detector: &dyn Detector,
+    config: &Config,
+) -> Result<VerifyOutcome> {
+    let (viewport, target) = {
+        let s = state.read().await;
+        (s.viewport, s.target.clone())
+    };
+    let screenshot_bytes = {
+        let _view = game.lock_view().await;
+        goto(game, config, kingdom, x, y).await?;
+        sleep(Duration::from_secs(2)).await;
 
-    let screenshot_bytes = game
-        .take_screenshot()
-        .await
-        .context("failed to take verification screenshot")?;
+        game.take_screenshot()
+            .await
+            .context("failed to take verification screenshot")?
+    };
 
-    let screenshot = image::load_from_memory(&screenshot_bytes)
+    let screenshot = PreparedScreenshot::from_bytes(&screenshot_bytes, viewport)
         .context("failed to decode verification screenshot")?;
 
-    match detector::find_best_match(&screenshot, ref_images) {
-        Some(m) => {
-            let err_x = (m.x as f64 - SCREEN_CENTER_X).abs();
-            let err_y = (m.y as f64 - SCREEN_CENTER_Y).abs();
-            let near_center = err_x < 80.0 && err_y < 80.0;
-            let good_score = m.score >= 0.90;
+    let criteria = config.verify_criteria(&target);
+    let roi = config.map_transform.center_roi(criteria.roi_half());
+    let best = detector.find_best_match(&screenshot.region(roi));
+    let (present, pixel_error) = match best {
+        Some(ref m) => {
+            let (err_x, err_y) = criteria.center_offset(m);
+            let near_center = criteria.near_center(m);
+            let good_score = m.score >= criteria.min_score;
             tracing::info!(
                 "verify K:{kingdom} ({x},{y}): pixel ({},{}) score={:.4} err=({err_x:.0},{err_y:.0}) near={near_center} good={good_score}",
                 m.x,
                 m.y,
                 m.score
             );
-            Ok(near_center && good_score)
+            (near_center && good_score, Some((err_x, err_y)))
         }
         None => {
             tracing::info!("verify K:{kingdom} ({x},{y}): no match found");
-            Ok(false)
+            (false, None)
+        }
+    };
+    Ok(VerifyOutcome {
+        present,
+        best,
+        pixel_error,
+        screenshot: screenshot_bytes,
+    })
+}
+
+/// Check the page for a captcha or verification challenge: the DOM first,
+/// then the screenshot against the challenge templates. If one is showing,
+/// save the screenshot, pause the scanner and notify the operator. Nothing is
+/// clicked: solving it is left to a human. Returns whether a challenge was
+/// found, along with the screenshot.
+#[tracing::instrument(skip_all, fields(phase = tracing::field::Empty))]
+async fn pause_for_challenge(
+    game: &GameBrowser,
+    state: &AppState,
+    config: &Config,
+    kingdom: u32,
+    templates: &Arc<Vec<DialogTemplate>>,
+    screenshot: Vec<u8>,
+) -> (bool, Vec<u8>) {
+    let mut reason = match game.detect_challenge().await {
+        Ok(found) => found.map(|sel| format!("page element {sel}")),
+        Err(e) => {
+            tracing::warn!("challenge DOM check failed: {e:#}");
+            None
+        }
+    };
+
+    let mut screenshot = screenshot;
+    if reason.is_none() && !templates.is_empty() {
+        let templates = templates.clone();
+        let checked = tokio::task::spawn_blocking(move || {
+            let found = image::load_from_memory(&screenshot)
+                .ok()
+                .and_then(|img| challenge::find_dialog(&img, &templates));
+            (found, screenshot)
+        })
+        .await;
+        match checked {
+            Ok((found, bytes)) => {
+                reason = found.map(|m| format!("template {} (score={:.3})", m.name, m.score));
+                screenshot = bytes;
+            }
+            Err(e) => {
+                tracing::warn!("challenge template check panicked: {e}");
+                return (false, Vec::new());
+            }
         }
     }
+
+    let Some(reason) = reason else {
+        return (false, screenshot);
+    };
+
+    tracing::error!("kingdom {kingdom}: verification challenge detected ({reason}), pausing");
+    let name = format!(
+        "challenge_k{kingdom}_{}.png",
+        Utc::now().format("%Y%m%d_%H%M%S")
+    );
+    // The step capture only covers the viewport; the operator needs the whole page
+    let full = game.take_screenshot().await.ok();
+    let saved = match Screenshots::new(config)
+        .save(name.clone(), full.unwrap_or_else(|| screenshot.clone()))
+        .await
+    {
+        Ok(path) => Some(path.display().to_string()),
+        Err(e) => {
+            tracing::warn!("failed to save {name}: {e}");
+            None
+        }
+    };
+
+    {
+        let mut s = state.write().await;
+        if s.phase == ScannerPhase::Scanning {
+            s.set_phase(ScannerPhase::Paused);
+            tracing::Span::current().record("phase", "paused");
+        }
+        s.challenge = Some(Challenge {
+            detected_at: Utc::now(),
+            kingdom,
+            reason: reason.clone(),
+            screenshot: saved.clone(),
+        });
+    }
+
+    notify(
+        config,
+        "challenge",
+        format!(
+            "Verification challenge in kingdom {kingdom} ({reason}); scanner paused until it is solved and resumed{}",
+            saved
+                .map(|p| format!(", screenshot saved to {p}"))
+                .unwrap_or_default()
+        ),
+    );
+    (true, screenshot)
+}
+
+/// Check the page for the game's "connection lost" dialog: the DOM first,
+/// then the screenshot against the disconnect templates. If it's showing,
+/// click its reload button (or reload the page), wait for the client and
+/// redo the map and zoom setup. Returns whether the dialog was found, along
+/// with the screenshot.
+async fn recover_from_disconnect(
+    game: &GameBrowser,
+    config: &Config,
+    kingdom: u32,
+    templates: &Arc<DisconnectTemplates>,
+    screenshot: Vec<u8>,
+) -> Result<(bool, Vec<u8>)> {
+    let mut reason = match game.detect_disconnect().await {
+        Ok(found) => found.map(|text| format!("page text {text:?}")),
+        Err(e) => {
+            tracing::warn!("disconnect DOM check failed: {e:#}");
+            None
+        }
+    };
+
+    let mut screenshot = screenshot;
+    if reason.is_none() && !templates.is_empty() {
+        let templates = templates.clone();
+        let (found, bytes) = tokio::task::spawn_blocking(move || {
+            let found = image::load_from_memory(&screenshot)
+                .ok()
+                .and_then(|img| templates.find_dialog(&img));
+            (found, screenshot)
+        })
+        .await
+        .context("disconnect template check panicked")?;
+        reason = found.map(|m| format!("template {} (score={:.3})", m.name, m.score));
+        screenshot = bytes;
+    }
+
+    let Some(reason) = reason else {
+        return Ok((false, screenshot));
+    };
+
+    notify(
+        config,
+        "disconnect",
+        format!("Game disconnected in kingdom {kingdom} ({reason}); reloading the client"),
+    );
+
+    // The step capture only covers the viewport; the button can be anywhere
+    let button = if templates.reload_button.is_some() {
+        let full = game
+            .take_screenshot()
+            .await
+            .context("failed to take disconnect screenshot")?;
+        let templates = templates.clone();
+        tokio::task::spawn_blocking(move || {
+            image::load_from_memory(&full)
+                .ok()
+                .and_then(|img| templates.find_reload_button(&img))
+        })
+        .await
+        .context("reload button search panicked")?
+    } else {
+        None
+    };
+    game.reload_game(button).await?;
+    verify_zoom(game, kingdom, config.map_transform).await;
+    tracing::info!("kingdom {kingdom}: game client reloaded, redoing the step");
+    Ok((true, screenshot))
+}
+
+/// Disconnect-dialog reloads allowed per kingdom scan. Past this the
+/// browser is relaunched instead.
+const MAX_DISCONNECT_RECOVERIES: u32 = 3;
+
+/// Relaunches allowed per kingdom scan before giving up on it.
+const MAX_BROWSER_RELAUNCHES: u32 = 3;
+
+/// The browser died (crashed, hung, or lost its CDP connection) or its game
+/// client couldn't be reloaded during a kingdom scan. `step` is the scan position to resume from.
+#[derive(Debug, Error)]
+#[error("browser died at scan step {}", .step + 1)]
+struct BrowserDied {
+    step: usize,
+    #[source]
+    source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+/// The browser is due a scheduled restart (see `resources.rs`), taken
+/// between scan steps. `step` is the scan position to resume from.
+#[derive(Debug, Error)]
+#[error("scheduled browser restart at scan step {}: {reason}", .step + 1)]
+struct RestartDue {
+    step: usize,
+    reason: String,
+}
+
+impl BrowserDied {
+    fn at(step: usize, source: anyhow::Error) -> Self {
+        Self {
+            step,
+            source: source.into(),
+        }
+    }
+}
+
+/// Let in-flight detections finish, then pick the step to resume a scan at:
+/// `step`, or the earliest step whose match can no longer be confirmed.
+async fn resume_step(
+    step: usize,
+    semaphore: &tokio::sync::Semaphore,
+    config: &Config,
+    rx: &mut tokio::sync::mpsc::UnboundedReceiver<DetectionResult>,
+) -> usize {
+    let _ = semaphore.acquire_many(config.max_detect_tasks as u32).await;
+    let mut step = step;
+    while let Ok(det) = rx.try_recv() {
+        step = step.min(det.step_index);
+    }
+    step
+}
+
+/// Drop the dead browser from state, then launch and log in a new one and
+/// put the scanner back into `Scanning` (unless it was stopped meanwhile).
+async fn relaunch_browser(state: &AppState) -> Result<Arc<GameBrowser>> {
+    {
+        let mut s = state.write().await;
+        s.browser = None;
+        s.api_tab = None;
+        s.resources = None;
+        metrics::bump(&s.counters.browser_restarts);
+    }
+    tracing::warn!("relaunching browser");
+    let game = prepare_browser(state).await?;
+    let mut s = state.write().await;
+    if s.phase == ScannerPhase::Ready {
+        s.set_phase(ScannerPhase::Scanning);
+    }
+    Ok(game)
+}
+
+/// [`scan_kingdom`] with crash recovery: if the browser dies mid-scan it is
+/// relaunched (at most [`MAX_BROWSER_RELAUNCHES`] times) and the scan resumes
+/// at the step where it died. Scheduled restarts resume the same way but
+/// don't count towards that limit. `game` is replaced with the new browser.
+#[tracing::instrument(skip_all, fields(kingdom = kingdom))]
+async fn scan_kingdom_recovering(
+    game: &mut Arc<GameBrowser>,
+    state: &AppState,
+    kingdom: u32,
+    positions: &[(u32, u32)],
+    detector: &Arc<dyn Detector>,
+    config: &Config,
+) -> Result<()> {
+    let recorder = config.recording_dir.as_deref().and_then(|dir| {
+        Recorder::start(dir, kingdom)
+            .inspect_err(|e| tracing::warn!("failed to start recording: {e:#}"))
+            .ok()
+    });
+
+    let mut start_step = 0;
+    let mut relaunches = 0;
+    let result = loop {
+        let err = match scan_kingdom(
+            game,
+            state,
+            kingdom,
+            positions,
+            detector,
+            config,
+            start_step,
+            recorder.as_ref(),
+        )
+        .await
+        {
+            Ok(()) => break Ok(()),
+            Err(e) => e,
+        };
+        if let Some(due) = err.downcast_ref::<RestartDue>() {
+            tracing::warn!("kingdom {kingdom}: {err}");
+            start_step = due.step;
+        } else if let Some(died) = err.downcast_ref::<BrowserDied>() {
+            if relaunches >= MAX_BROWSER_RELAUNCHES {
+                break Err(err.context(format!("giving up after {relaunches} browser relaunches")));
+            }
+            tracing::error!("kingdom {kingdom}: {err:#}");
+            start_step = died.step;
+            relaunches += 1;
+        } else {
+            break Err(err);
+        }
+        match relaunch_browser(state).await {
+            Ok(new_game) => *game = new_game,
+            Err(e) => break Err(e),
+        }
+    };
+
+    if let Some(recorder) = recorder {
+        recorder.finish().await;
+    }
+    result
 }
 
 struct DetectionResult {
@@ -325,35 +1122,110 @@ struct DetectionResult {
     step_index: usize,
 }
 
+/// Rings of the spiral patterns (`MERCY_SCAN_RINGS`, or the pattern's
+/// default); None for the patterns without rings.
+pub fn scan_rings(config: &Config) -> Option<u32> {
+    match config.scan_pattern.as_str() {
+        "single" | "multi" => Some(config.scan_rings.unwrap_or(4)),
+        "wide" => Some(config.scan_rings.unwrap_or(9)),
+        _ => None,
+    }
+}
+
+/// `scan_pattern` of a scan of positions given through `POST /scan/custom`.
+pub const CUSTOM_PATTERN: &str = "custom";
+
+/// The map positions a scan of `kingdom` visits, in order.
+pub fn scan_positions(config: &Config, kingdom: u32) -> Vec<(u32, u32)> {
+    let rings = scan_rings(config).unwrap_or_default();
+    let mut positions = match config.scan_pattern.as_str() {
+        "single" => spiral_scan_positions(512, 512, SCAN_STEP, rings),
+        "multi" => multi_spiral_positions(SCAN_STEP, rings),
+        "wide" => wide_spiral_positions(rings),
+        "known" => known_positions(
+            kingdom,
+            config.known_coverage,
+            config.known_locations.as_deref(),
+        ),
+        // Panning back to the start of every row would cost ~20 drags each
+        _ if config.navigation == NavigationMode::Drag => snake_rows(grid_scan_positions()),
+        _ => grid_scan_positions(),
+    };
+    if let Some(bounds) = config.scan_bounds {
+        positions.retain(|&(x, y)| bounds.contains(x, y));
+    }
+    positions
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(step = tracing::field::Empty))]
 async fn scan_kingdom(
     game: &GameBrowser,
     state: &AppState,
     kingdom: u32,
-    ref_images: &Arc<Vec<PreparedRef>>,
+    positions: &[(u32, u32)],
+    detector: &Arc<dyn Detector>,
     config: &Config,
+    start_step: usize,
+    recorder: Option<&Recorder>,
 ) -> Result<()> {
-    let positions = match config.scan_pattern.as_str() {
-        "single" => spiral_scan_positions(512, 512, SCAN_STEP, config.scan_rings.unwrap_or(4)),
-        "multi" => multi_spiral_positions(SCAN_STEP, config.scan_rings.unwrap_or(4)),
-        "wide" => wide_spiral_positions(config.scan_rings.unwrap_or(9)),
-        "grid" => grid_scan_positions(),
-        "known" => known_positions(kingdom, config.known_coverage),
-        _ => grid_scan_positions(),
-    };
+    if let Some(bounds) = config.scan_bounds
+        && positions.is_empty()
+    {
+        tracing::warn!("kingdom {kingdom}: no scan positions inside {bounds}");
+    }
     let total = positions.len();
+    let viewport = state.read().await.viewport;
     tracing::info!(
         "scanning {total} positions in kingdom {kingdom} (pattern={})",
         config.scan_pattern
     );
 
+    let challenge_templates = Arc::new(challenge::load_challenge_templates().unwrap_or_else(|e| {
+        tracing::warn!("failed to load challenge templates: {e:#}");
+        Vec::new()
+    }));
+
+    let disconnect_templates =
+        Arc::new(disconnect::load_disconnect_templates().unwrap_or_else(|e| {
+            tracing::warn!("failed to load disconnect templates: {e:#}");
+            DisconnectTemplates {
+                dialogs: Vec::new(),
+                reload_button: None,
+            }
+        }));
+    let mut disconnects = 0;
+
     let scan_start = Instant::now();
+    let (counters, history, errors) = {
+        let s = state.read().await;
+        (
+            s.counters.clone(),
+            s.screenshot_history.clone(),
+            s.errors.clone(),
+        )
+    };
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<DetectionResult>();
     let semaphore = Arc::new(tokio::sync::Semaphore::new(config.max_detect_tasks));
     tracing::info!("max concurrent detections: {}", config.max_detect_tasks);
-
-    for (i, &(gx, gy)) in positions.iter().enumerate() {
+    // Steps' best candidates short of the threshold, kept while relaxing
+    // after dry passes is on
+    let near_misses = config
+        .relax_after_passes
+        .map(|_| Arc::new(Mutex::new(Vec::new())));
+    let mut detected = false;
+
+    if start_step > 0 {
+        tracing::info!(
+            "resuming kingdom {kingdom} scan at step {}/{total}",
+            start_step + 1
+        );
+    }
+    'steps: for (i, &(gx, gy)) in positions.iter().enumerate().skip(start_step) {
+        tracing::Span::current().record("step", i + 1);
         // Check for detection result from previous step (non-blocking)
         if let Ok(det) = rx.try_recv() {
+            detected = true;
             let m = &det.matches[0];
             let scan_secs = scan_start.elapsed().as_secs_f64();
             tracing::info!(
@@ -365,18 +1237,23 @@ async fn scan_kingdom(
                 m.y,
                 m.score
             );
+            state
+                .write()
+                .await
+                .record_detection(kingdom, det.step_index + 1, m.score);
             match confirm_match(
                 game,
                 state,
                 kingdom,
-                m.x,
-                m.y,
+                m.position(),
                 det.nav_x,
                 det.nav_y,
                 m.score,
                 Some(scan_secs),
                 config,
-                ref_images,
+                detector.as_ref(),
+                recorder,
+                false,
             )
             .await
             {
@@ -400,6 +1277,7 @@ async fn scan_kingdom(
                 }
                 Err(e) => {
                     tracing::warn!("failed to confirm match at pixel ({}, {}): {e:#}", m.x, m.y);
+                    errors.record("confirmation", format!("{e:#}"), Some(kingdom));
                     while rx.try_recv().is_ok() {}
                 }
             }
@@ -409,25 +1287,162 @@ async fn scan_kingdom(
             return Ok(());
         }
 
-        // Dismiss store popup that may have appeared while idle
-        game.send_canvas_escape().await;
+        if let Some(reason) = game.restart_requested() {
+            let step = resume_step(i, &semaphore, config, &mut rx).await;
+            return Err(RestartDue { step, reason }.into());
+        }
 
-        tracing::info!("step {}/{}: goto ({gx}, {gy})", i + 1, total);
-        game.navigate_to_coords(kingdom, gx, gy).await?;
+        // Where the coordinate display says the map went
+        let mut landed = None;
+        let mut renavigated = false;
+        let screenshot_bytes = loop {
+            let captured = if game.is_alive() {
+                // Dismiss store popup that may have appeared while idle
+                game.send_canvas_escape().await;
+
+                tracing::info!("step {}/{}: goto ({gx}, {gy})", i + 1, total);
+                let _view = game.lock_view().await;
+                match goto(game, config, kingdom, gx, gy).await {
+                    Ok(()) => {
+                        if let Some(region) = config.coords_region {
+                            let expected = (kingdom, gx, gy);
+                            landed =
+                                coords_display::read(game, region, config.popup_locale, expected)
+                                    .await
+                                    .unwrap_or_else(|e| {
+                                        tracing::warn!("coordinate display not read: {e:#}");
+                                        None
+                                    });
+                        }
+                        // Only the viewport is matched, so skip transferring
+                        // and decoding the UI around it
+                        game.take_screenshot_region(
+                            viewport.left,
+                            viewport.top,
+                            viewport.width(),
+                            viewport.height(),
+                        )
+                        .await
+                        .context("failed to take screenshot")
+                    }
+                    Err(e) => Err(e),
+                }
+            } else {
+                Err(anyhow::anyhow!("browser connection lost"))
+            };
+            let screenshot_bytes = match captured {
+                Ok(bytes) => bytes,
+                Err(e) if !game.is_alive() => {
+                    let step = resume_step(i, &semaphore, config, &mut rx).await;
+                    return Err(BrowserDied::at(step, e).into());
+                }
+                // Retried already; one unreachable spot isn't worth the scan
+                Err(e) if matches!(e.downcast_ref(), Some(BrowserError::NavigationFailed(..))) => {
+                    tracing::warn!("skipping step {}/{}: {e}", i + 1, total);
+                    errors.record("navigation", e.to_string(), Some(kingdom));
+                    continue 'steps;
+                }
+                Err(e) => return Err(e),
+            };
 
-        // Take screenshot
-        let screenshot_bytes = game
-            .take_screenshot()
-            .await
-            .context("failed to take screenshot")?;
+            // Landed in another kingdom: navigate again, from there
+            if let Some((k, x, y)) = landed
+                && k != kingdom
+                && !renavigated
+            {
+                tracing::warn!(
+                    "step {}/{total}: navigation to K:{kingdom} landed at K:{k} X:{x} Y:{y}, navigating again",
+                    i + 1
+                );
+                game.assume_position(k, x, y);
+                renavigated = true;
+                continue;
+            }
 
-        if config.debug_screenshots {
-            let scan_path = format!("debug_scan_k{kingdom}_s{:03}.png", i + 1);
-            if let Err(e) = tokio::fs::write(&scan_path, &screenshot_bytes).await {
-                tracing::warn!("failed to save {scan_path}: {e}");
+            let reloaded = if disconnects < MAX_DISCONNECT_RECOVERIES {
+                recover_from_disconnect(
+                    game,
+                    config,
+                    kingdom,
+                    &disconnect_templates,
+                    screenshot_bytes,
+                )
+                .await
+            } else {
+                Err(anyhow::anyhow!(
+                    "gave up after {disconnects} disconnect-dialog reloads"
+                ))
+            };
+            let screenshot_bytes = match reloaded {
+                Ok((false, bytes)) => bytes,
+                Ok((true, _)) => {
+                    disconnects += 1;
+                    continue;
+                }
+                // A client that won't come back gets a fresh browser
+                Err(e) => {
+                    let step = resume_step(i, &semaphore, config, &mut rx).await;
+                    return Err(BrowserDied::at(step, e).into());
+                }
+            };
+
+            let (challenged, screenshot_bytes) = pause_for_challenge(
+                game,
+                state,
+                config,
+                kingdom,
+                &challenge_templates,
+                screenshot_bytes,
+            )
+            .await;
+            if !challenged {
+                break screenshot_bytes;
+            }
+            // Wait for a human to solve it and resume, then redo this step
+            if !check_should_continue(state).await {
+                return Ok(());
             }
+        };
+
+        // Estimates are relative to where the map is, so a navigation that
+        // landed elsewhere is taken as where the map is from here on
+        let (gx, gy) = match landed {
+            Some((k, x, y)) if (k, x, y) != (kingdom, gx, gy) => {
+                tracing::warn!(
+                    "step {}/{total}: navigation to K:{kingdom} X:{gx} Y:{gy} landed at K:{k} X:{x} Y:{y}",
+                    i + 1
+                );
+                game.assume_position(k, x, y);
+                if k != kingdom {
+                    continue 'steps;
+                }
+                (x, y)
+            }
+            _ => (gx, gy),
+        };
+
+        state.write().await.record_position_scanned(kingdom);
+        let screenshot_bytes = Arc::new(screenshot_bytes);
+        history.push(
+            ShotSource::Scan,
+            Some((kingdom, gx, gy)),
+            Some(i + 1),
+            screenshot_bytes.clone(),
+        );
+        if let Some(recorder) = recorder {
+            recorder.record(
+                screenshot_bytes.clone(),
+                (viewport.left, viewport.top),
+                None,
+            );
         }
 
+        // Saved from the detection task, annotated with its candidates
+        let scan_name = config
+            .debug_screenshots
+            .then(|| format!("debug_scan_k{kingdom}_s{:03}.png", i + 1));
+        let screenshots = Screenshots::new(config);
+
         // Acquire semaphore permit — blocks scan loop if too many detections queued
         let permit = semaphore
             .clone()
@@ -436,50 +1451,101 @@ async fn scan_kingdom(
             .expect("semaphore closed unexpectedly");
 
         // Spawn detection in background (CPU-bound work overlaps with next navigation)
-        let refs = ref_images.clone();
+        let detector = detector.clone();
         let tx = tx.clone();
+        let counters = counters.clone();
+        let near_misses = near_misses.clone();
+        let relax_floor = config.relax_floor;
+        metrics::bump(&counters.detections_spawned);
         tokio::task::spawn_blocking(move || {
             let _permit = permit; // held until closure exits
 
-            let screenshot = match image::load_from_memory(&screenshot_bytes) {
-                Ok(img) => img,
-                Err(e) => {
-                    tracing::warn!("failed to decode screenshot in background: {e}");
-                    return;
-                }
-            };
-
-            let matches = match detector::find_matches(&screenshot, &refs) {
-                Ok(m) => m,
-                Err(e) => {
-                    tracing::warn!("template matching failed in background: {e}");
-                    return;
+            // A panic in here is reported (see `report.rs`) with this step
+            report::with_context(kingdom, i + 1, || {
+                let screenshot =
+                    match PreparedScreenshot::from_viewport_bytes(&screenshot_bytes, viewport) {
+                        Ok(img) => img,
+                        Err(e) => {
+                            tracing::warn!("failed to decode screenshot in background: {e:#}");
+                            return;
+                        }
+                    };
+
+                // While relaxing, the same pass also yields the step's near miss
+                let matches = match near_misses {
+                    Some(_) => detector.find_matches_or_near_miss(&screenshot, relax_floor),
+                    None => detector.find_matches(&screenshot).map(|m| (m, None)),
+                };
+                metrics::bump(&counters.detections_completed);
+                let (matches, near_miss) = match matches {
+                    Ok(m) => m,
+                    Err(e) => {
+                        tracing::warn!("template matching failed in background: {e}");
+                        return;
+                    }
+                };
+
+                if let Some(name) = scan_name {
+                    let candidates = detector.find_top_matches(&screenshot, DEBUG_CANDIDATES);
+                    match annotate::annotate_screenshot(
+                        &screenshot_bytes,
+                        (viewport.left, viewport.top),
+                        viewport,
+                        detector.refs(),
+                        &candidates,
+                        &matches,
+                    ) {
+                        Ok(png) => {
+                            if let Err(e) = screenshots.save_blocking(&name, &png) {
+                                tracing::warn!("failed to save {name}: {e}");
+                            }
+                        }
+                        Err(e) => tracing::warn!("failed to annotate {name}: {e:#}"),
+                    }
                 }
-            };
 
-            if matches.is_empty() {
-                tracing::info!("step {}/{total}: no matches (async)", i + 1);
-                return;
-            }
+                if matches.is_empty() {
+                    tracing::info!("step {}/{total}: no matches (async)", i + 1);
+                    if let Some(near_misses) = near_misses
+                        && let Some(best) = near_miss
+                    {
+                        tracing::debug!(
+                            "step {}/{total}: near miss score={:.4}",
+                            i + 1,
+                            best.score
+                        );
+                        near_misses.lock().unwrap_or_else(|e| e.into_inner()).push(
+                            DetectionResult {
+                                matches: vec![best],
+                                nav_x: gx,
+                                nav_y: gy,
+                                step_index: i,
+                            },
+                        );
+                    }
+                    return;
+                }
 
-            tracing::info!(
-                "step {}/{total}: found {} match(es) (async)",
-                i + 1,
-                matches.len()
-            );
+                tracing::info!(
+                    "step {}/{total}: found {} match(es) (async)",
+                    i + 1,
+                    matches.len()
+                );
 
-            let _ = tx.send(DetectionResult {
-                matches,
-                nav_x: gx,
-                nav_y: gy,
-                step_index: i,
-            });
+                let _ = tx.send(DetectionResult {
+                    matches,
+                    nav_x: gx,
+                    nav_y: gy,
+                    step_index: i,
+                });
+            })
         });
     }
 
     // After loop: wait for final detection result
     drop(tx); // close sender so recv terminates
     if let Some(det) = rx.recv().await {
+        detected = true;
         let m = &det.matches[0];
         let scan_secs = scan_start.elapsed().as_secs_f64();
         tracing::info!(
@@ -490,18 +1556,23 @@ async fn scan_kingdom(
             m.y,
             m.score
         );
+        state
+            .write()
+            .await
+            .record_detection(kingdom, det.step_index + 1, m.score);
         match confirm_match(
             game,
             state,
             kingdom,
-            m.x,
-            m.y,
+            m.position(),
             det.nav_x,
             det.nav_y,
             m.score,
             Some(scan_secs),
             config,
-            ref_images,
+            detector.as_ref(),
+            recorder,
+            false,
         )
         .await
         {
@@ -523,137 +1594,325 @@ async fn scan_kingdom(
             }
             Err(e) => {
                 tracing::warn!("failed to confirm final match: {e:#}");
+                errors.record("confirmation", format!("{e:#}"), Some(kingdom));
             }
         }
     }
 
+    if let Some(near_misses) = near_misses {
+        let near_misses =
+            std::mem::take(&mut *near_misses.lock().unwrap_or_else(|e| e.into_inner()));
+        let scan_secs = scan_start.elapsed().as_secs_f64();
+        if !detected
+            && relax_near_misses(
+                game,
+                state,
+                kingdom,
+                near_misses,
+                scan_secs,
+                config,
+                detector.as_ref(),
+                recorder,
+            )
+            .await
+        {
+            let elapsed = scan_start.elapsed();
+            tracing::info!(
+                "kingdom {kingdom} scan completed in {elapsed:.1?} (near miss confirmed)"
+            );
+            return Ok(());
+        }
+    }
+
     let elapsed = scan_start.elapsed();
     tracing::info!("kingdom {kingdom} scan completed in {elapsed:.1?} (no match found)");
     Ok(())
 }
 
-/// Screen center pixel coordinates (where navigated game coords appear).
-/// Measured from the yellow crosshair square after goto in the 1920×1080
-/// headless viewport.  The minimap, top bar, bottom toolbar and right-side
-/// icons shift the center well away from (960, 540).
-pub const SCREEN_CENTER_X: f64 = 760.0;
-pub const SCREEN_CENTER_Y: f64 = 400.0;
-
-/// Calibrated pixel-to-game-coordinate transform (25% zoom).
-/// Forward: pixel_dx = PX_PER_GAME_X * game_dx
-///          pixel_dy = TILT_Y * game_dx + PX_PER_GAME_Y * game_dy
-/// Calibrated from K:111 buildings at (502,512) and (528,524).
-const PX_PER_GAME_X: f64 = 49.40;
-const PX_PER_GAME_Y: f64 = 28.32;
-const TILT_Y: f64 = -1.50; // vertical pixel shift per game X unit
-
-/// Convert a pixel offset from screen center to approximate game coordinate offset.
-/// Returns (delta_x, delta_y) in game coordinate units.
-pub fn pixel_to_game_offset(pixel_x: u32, pixel_y: u32) -> (i32, i32) {
-    let screen_dx = pixel_x as f64 - SCREEN_CENTER_X;
-    let screen_dy = pixel_y as f64 - SCREEN_CENTER_Y;
-
-    let game_dx = screen_dx / PX_PER_GAME_X;
-    let game_dy = (screen_dy - TILT_Y * game_dx) / PX_PER_GAME_Y;
-
-    (game_dx.round() as i32, game_dy.round() as i32)
+/// Near misses clicked after a dry spell, strongest first.
+const MAX_RELAXED_RETRIES: usize = 3;
+
+/// Count a pass of `kingdom` without a detection toward its dry spell, and
+/// once `MERCY_RELAX_AFTER_PASSES` passes in a row had near misses, click
+/// this pass's strongest ones as if they were detections. The outcomes go
+/// to the exchange log, marked `near_miss`, and to `/stats`. Returns
+/// whether one was confirmed.
+#[allow(clippy::too_many_arguments)]
+async fn relax_near_misses(
+    game: &GameBrowser,
+    state: &AppState,
+    kingdom: u32,
+    near_misses: Vec<DetectionResult>,
+    scan_secs: f64,
+    config: &Config,
+    detector: &dyn Detector,
+    recorder: Option<&Recorder>,
+) -> bool {
+    let Some(after) = config.relax_after_passes else {
+        return false;
+    };
+    let (passes, near_misses, errors) = {
+        let mut s = state.write().await;
+        let stats = s.kingdom_stats.entry(kingdom).or_default();
+        let (passes, due) = due_near_misses(stats, after, near_misses);
+        (passes, due, s.errors.clone())
+    };
+    if near_misses.is_empty() {
+        if passes > 0 {
+            tracing::info!("kingdom {kingdom}: {passes}/{after} dry passes with near misses");
+        }
+        return false;
+    }
+
+    tracing::info!(
+        "kingdom {kingdom}: no detection in {passes} passes, retrying {} near miss(es)",
+        near_misses.len()
+    );
+
+    let (mut retried, mut confirmed) = (0, 0);
+    for miss in &near_misses {
+        let m = &miss.matches[0];
+        retried += 1;
+        let outcome = confirm_match(
+            game,
+            state,
+            kingdom,
+            m.position(),
+            miss.nav_x,
+            miss.nav_y,
+            m.score,
+            Some(scan_secs),
+            config,
+            detector,
+            recorder,
+            true,
+        )
+        .await;
+        match outcome {
+            Ok(found) => {
+                tracing::info!(
+                    "near miss from step {} (score={:.4}) {}",
+                    miss.step_index + 1,
+                    m.score,
+                    if found { "confirmed" } else { "not confirmed" }
+                );
+                if found {
+                    confirmed += 1;
+                    break;
+                }
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "failed to confirm near miss at pixel ({}, {}): {e:#}",
+                    m.x,
+                    m.y
+                );
+                errors.record("confirmation", format!("{e:#}"), Some(kingdom));
+            }
+        }
+    }
+
+    state
+        .write()
+        .await
+        .kingdom_stats
+        .entry(kingdom)
+        .or_default()
+        .record_relaxed(retried, confirmed);
+    confirmed > 0
+}
+
+/// Count a dry pass in `stats`, and once `after` passes in a row had near
+/// misses, the strongest [`MAX_RELAXED_RETRIES`] of this pass's, strongest
+/// first, to click. Returns the dry spell's length and the near misses due
+/// (none before then).
+fn due_near_misses(
+    stats: &mut KingdomStats,
+    after: u32,
+    mut near_misses: Vec<DetectionResult>,
+) -> (u32, Vec<DetectionResult>) {
+    let passes = stats.record_dry_pass(!near_misses.is_empty());
+    if passes < after {
+        return (passes, Vec::new());
+    }
+    near_misses.sort_by(|a, b| b.matches[0].score.total_cmp(&a.matches[0].score));
+    near_misses.truncate(MAX_RELAXED_RETRIES);
+    (passes, near_misses)
+}
+
+/// Center the map on (x, y) in `kingdom`. In drag mode the map is panned
+/// from where it is when that's known and in the same kingdom. The search
+/// dialog only finds the starting point, and if even that fails the map is
+/// assumed to be there already (confirmed coordinates still come from the
+/// game's popup).
+async fn goto(game: &GameBrowser, config: &Config, kingdom: u32, x: u32, y: u32) -> Result<()> {
+    if config.navigation == NavigationMode::Search {
+        return game.navigate_to_coords(kingdom, x, y).await;
+    }
+    match game.position() {
+        Some((k, cx, cy)) if k == kingdom => {
+            let (dx, dy) = config
+                .map_transform
+                .game_to_pixel_offset(x as i32 - cx as i32, y as i32 - cy as i32);
+            game.pan_to((kingdom, x, y), dx, dy).await
+        }
+        _ => match game.navigate_to_coords(kingdom, x, y).await {
+            Err(e) if game.is_alive() => {
+                tracing::warn!(
+                    "search dialog failed ({e:#}); assuming the map is at K:{kingdom} X:{x} Y:{y}"
+                );
+                game.assume_position(kingdom, x, y);
+                Ok(())
+            }
+            result => result,
+        },
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip_all, fields(score = initial_score))]
 async fn confirm_match(
     game: &GameBrowser,
     state: &AppState,
     kingdom: u32,
-    pixel_x: u32,
-    pixel_y: u32,
+    (pixel_x, pixel_y): (f64, f64),
     nav_x: u32,
     nav_y: u32,
     initial_score: f32,
     scan_duration_secs: Option<f64>,
     config: &Config,
-    ref_images: &[PreparedRef],
+    detector: &dyn Detector,
+    recorder: Option<&Recorder>,
+    near_miss: bool,
 ) -> Result<bool> {
+    let screenshots = Screenshots::new(config);
+    // Until the popup is closed again
+    let _view = game.lock_view().await;
+
     // Step 1: Estimate game coordinates from pixel position
-    let (gdx, gdy) = pixel_to_game_offset(pixel_x, pixel_y);
-    let est_x = (nav_x as i32 + gdx).clamp(0, 1023) as u32;
-    let est_y = (nav_y as i32 + gdy).clamp(0, 1023) as u32;
+    let transform = config.map_transform;
+    let (est_x, est_y) = locate::estimate(&transform, (nav_x, nav_y), (pixel_x, pixel_y));
 
     tracing::info!(
-        "match at pixel ({pixel_x}, {pixel_y}), offset from center: ({}, {}), estimated game coords: K:{kingdom} X:{est_x} Y:{est_y}",
-        pixel_x as i32 - SCREEN_CENTER_X as i32,
-        pixel_y as i32 - SCREEN_CENTER_Y as i32,
+        "match at pixel ({pixel_x:.1}, {pixel_y:.1}), offset from center: ({:.1}, {:.1}), estimated game coords: K:{kingdom} X:{est_x} Y:{est_y}",
+        pixel_x - transform.center_x,
+        pixel_y - transform.center_y,
     );
 
     // Step 2: Navigate to the estimated coordinates (centers the target on screen)
     tracing::info!("navigating to estimated coords K:{kingdom} X:{est_x} Y:{est_y}");
-    game.navigate_to_coords(kingdom, est_x, est_y).await?;
+    goto(game, config, kingdom, est_x, est_y).await?;
     sleep(Duration::from_secs(2)).await;
 
     // Step 3: Screenshot after navigation (target should be near center)
-    let goto_bytes = game
-        .take_screenshot()
-        .await
-        .context("failed to take goto screenshot")?;
+    let goto_bytes = Arc::new(
+        game.take_screenshot()
+            .await
+            .context("failed to take goto screenshot")?,
+    );
+
+    // Calibration: re-run template matching on goto screenshot to refine position
+    let (viewport, history) = {
+        let s = state.read().await;
+        (s.viewport, s.screenshot_history.clone())
+    };
+    history.push(
+        ShotSource::Calibration,
+        Some((kingdom, est_x, est_y)),
+        None,
+        goto_bytes.clone(),
+    );
+    let goto_img = PreparedScreenshot::from_bytes(&goto_bytes, viewport)
+        .context("failed to decode goto screenshot")?;
+    let calibration =
+        detector.find_best_match(&goto_img.region(transform.center_roi(CALIBRATION_ROI_HALF)));
 
     if config.debug_screenshots {
-        let goto_path = format!("debug_goto_k{kingdom}_{est_x}_{est_y}.png");
-        if let Err(e) = tokio::fs::write(&goto_path, &goto_bytes).await {
-            tracing::warn!("failed to save {goto_path}: {e}");
-        } else {
-            tracing::info!("saved goto screenshot: {goto_path}");
+        let goto_name = format!("debug_goto_k{kingdom}_{est_x}_{est_y}.png");
+        let candidates = detector.find_top_matches(&goto_img, DEBUG_CANDIDATES);
+        match annotate::annotate_screenshot(
+            &goto_bytes,
+            (0, 0),
+            viewport,
+            detector.refs(),
+            &candidates,
+            calibration.as_slice(),
+        ) {
+            Ok(png) => match screenshots.save(goto_name.clone(), png).await {
+                Ok(path) => tracing::info!("saved goto screenshot: {}", path.display()),
+                Err(e) => tracing::warn!("failed to save {goto_name}: {e}"),
+            },
+            Err(e) => tracing::warn!("failed to annotate {goto_name}: {e:#}"),
         }
     }
 
-    // Calibration: re-run template matching on goto screenshot to refine position
-    let goto_img =
-        image::load_from_memory(&goto_bytes).context("failed to decode goto screenshot")?;
-    let calibration = detector::find_best_match(&goto_img, ref_images);
+    if config.debug_heatmap
+        && let Some(heatmap) = detector.score_heatmap(&goto_img)
+    {
+        let heatmap_name = format!("debug_heatmap_k{kingdom}_{est_x}_{est_y}.png");
+        match detector::encode_png(&heatmap) {
+            Ok(png) => match screenshots.save(heatmap_name.clone(), png).await {
+                Ok(path) => tracing::info!("saved score heatmap: {}", path.display()),
+                Err(e) => tracing::warn!("failed to save {heatmap_name}: {e}"),
+            },
+            Err(e) => tracing::warn!("failed to encode {heatmap_name}: {e:#}"),
+        }
+    }
 
     // Refine coordinates using calibration offset (accounts for sprite height)
-    let (refined_x, refined_y, click_x, click_y) = if let Some(ref gm) = calibration {
-        let err_x = gm.x as f64 - SCREEN_CENTER_X;
-        let err_y = gm.y as f64 - SCREEN_CENTER_Y;
+    let refined = locate::refine(&transform, (est_x, est_y), calibration.as_ref());
+    let (refined_x, refined_y) = (refined.x, refined.y);
+    let (click_x, click_y) = refined.click;
+    if let Some(ref gm) = calibration {
         tracing::info!(
-            "CALIBRATION: building at pixel ({}, {}), score={:.4}, error from center: ({err_x:.0}, {err_y:.0})",
-            gm.x,
-            gm.y,
-            gm.score
+            "CALIBRATION: building at pixel ({click_x:.1}, {click_y:.1}), score={:.4}, error from center: ({:.1}, {:.1})",
+            gm.score,
+            click_x - transform.center_x,
+            click_y - transform.center_y,
         );
-
-        // The calibration error tells us how far the building is from where we
-        // expected it. Convert that pixel offset to game coordinate correction.
-        let (corr_dx, corr_dy) = pixel_to_game_offset(gm.x, gm.y);
-        let rx = (est_x as i32 + corr_dx).clamp(0, 1023) as u32;
-        let ry = (est_y as i32 + corr_dy).clamp(0, 1023) as u32;
+        let (corr_dx, corr_dy) = refined.correction;
         tracing::info!(
-            "refined coords: K:{kingdom} X:{rx} Y:{ry} (correction: {corr_dx}, {corr_dy})"
+            "refined coords: K:{kingdom} X:{refined_x} Y:{refined_y} (correction: {corr_dx}, {corr_dy})"
         );
-
-        (rx, ry, gm.x as f64, gm.y as f64)
     } else {
         tracing::info!("CALIBRATION: no match in goto screenshot, using estimate");
-        (est_x, est_y, SCREEN_CENTER_X, SCREEN_CENTER_Y)
-    };
+    }
 
     let cal_score = calibration.as_ref().map(|gm| gm.score);
 
+    if let Some(recorder) = recorder {
+        recorder.record(goto_bytes, (0, 0), Some((click_x, click_y)));
+    }
+
     // Step 4: Click at the detected building position
     tracing::info!("clicking at ({click_x:.0}, {click_y:.0})");
     game.click_at_cdp_full(click_x, click_y).await?;
     sleep(Duration::from_secs(2)).await;
 
     // Step 5: Screenshot the popup
-    let popup_bytes = game
-        .take_screenshot()
-        .await
-        .context("failed to take popup screenshot")?;
+    let popup_bytes = Arc::new(
+        game.take_screenshot()
+            .await
+            .context("failed to take popup screenshot")?,
+    );
+    history.push(
+        ShotSource::Popup,
+        Some((kingdom, refined_x, refined_y)),
+        None,
+        popup_bytes.clone(),
+    );
+    if let Some(recorder) = recorder {
+        recorder.record(popup_bytes.clone(), (0, 0), None);
+    }
 
     if config.debug_screenshots {
-        let popup_path = format!("debug_popup_k{kingdom}_{refined_x}_{refined_y}.png");
-        if let Err(e) = tokio::fs::write(&popup_path, &popup_bytes).await {
-            tracing::warn!("failed to save {popup_path}: {e}");
-        } else {
-            tracing::info!("saved popup screenshot: {popup_path}");
+        let popup_name = format!("debug_popup_k{kingdom}_{refined_x}_{refined_y}.png");
+        match screenshots
+            .save(popup_name.clone(), popup_bytes.to_vec())
+            .await
+        {
+            Ok(path) => tracing::info!("saved popup screenshot: {}", path.display()),
+            Err(e) => tracing::warn!("failed to save {popup_name}: {e}"),
         }
     }
 
@@ -661,155 +1920,158 @@ async fn confirm_match(
     let popup_text = game.read_popup_text().await?;
     tracing::info!("popup text result: {:?}", popup_text);
 
-    let screenshot = Some(popup_bytes.to_vec());
-
-    let confirmed = if let Some(ref text) = popup_text {
-        if let Some((k, x, y)) = browser::parse_popup_coords(text) {
-            tracing::info!("found coordinates in popup: K:{k} X:{x} Y:{y}");
-
-            let exchange = MercExchange {
-                kingdom: k,
-                x,
-                y,
-                found_at: Utc::now(),
-                scan_duration_secs,
-                confirmed: true,
-                screenshot_png: screenshot,
-            };
-
-            let mut s = state.lock().await;
-            let stored = s.add_exchange(exchange);
-            if stored {
-                tracing::info!(
-                    "added exchange K:{k} X:{x} Y:{y} confirmed (total: {})",
-                    s.exchanges.len()
-                );
-            } else {
-                tracing::debug!("duplicate or full, skipping K:{k} X:{x} Y:{y}");
+    // Saved for `/exchanges/{id}/screenshot` once an exchange is stored
+    let save_screenshot = async |k: u32, x: u32, y: u32| {
+        let name = format!(
+            "exchange_k{k}_{x}_{y}_{}.png",
+            Utc::now().format("%Y%m%d_%H%M%S")
+        );
+        match screenshots.save(name.clone(), popup_bytes.to_vec()).await {
+            Ok(path) => Some(path),
+            Err(e) => {
+                tracing::warn!("failed to save {name}: {e}");
+                None
             }
-            drop(s);
-
-            log_exchange(
-                config,
-                &ExchangeLogEntry {
-                    timestamp: Utc::now().to_rfc3339(),
-                    kingdom: k,
-                    x,
-                    y,
-                    confirmed: true,
-                    stored,
-                    initial_score,
-                    calibration_score: cal_score,
-                    scan_pattern: config.scan_pattern.clone(),
-                    scan_duration_secs,
-                },
-            );
+        }
+    };
 
-            true
-        } else {
+    let target = state.read().await.target.clone();
+    let criteria = config.verify_criteria(&target);
+    let confirmation = locate::confirm(
+        popup_text.as_deref(),
+        &target.popup.clone().with_locale(config.popup_locale),
+        &criteria,
+        calibration.as_ref(),
+    );
+    let text = popup_text.as_deref().unwrap_or_default();
+    let (k, x, y, from_popup) = match confirmation {
+        Confirmation::Popup { kingdom, x, y } => {
+            tracing::info!("found coordinates in popup: K:{kingdom} X:{x} Y:{y}");
+            (kingdom, x, y, true)
+        }
+        Confirmation::Calibrated => {
+            tracing::info!("no popup but strong calibration match, storing refined estimate");
+            (kingdom, refined_x, refined_y, false)
+        }
+        Confirmation::NoCoords => {
             tracing::info!("popup text has no coords, not confirmed: {text}");
+            (kingdom, refined_x, refined_y, false)
+        }
+        Confirmation::OtherBuilding => {
+            tracing::info!("popup is not a {}, not confirmed: {text}", target.name);
+            (kingdom, refined_x, refined_y, false)
+        }
+        Confirmation::Unconfirmed => {
+            tracing::info!("no popup and weak/no calibration, not confirmed");
+            (kingdom, refined_x, refined_y, false)
+        }
+    };
 
-            log_exchange(
-                config,
-                &ExchangeLogEntry {
-                    timestamp: Utc::now().to_rfc3339(),
-                    kingdom,
-                    x: refined_x,
-                    y: refined_y,
-                    confirmed: false,
-                    stored: false,
-                    initial_score,
-                    calibration_score: cal_score,
-                    scan_pattern: config.scan_pattern.clone(),
-                    scan_duration_secs,
-                },
-            );
+    // Near a border, or after a navigation that went astray, the popup can
+    // be in another kingdom than the one being scanned. The exchange is
+    // stored where the popup says, but this kingdom's is still to be found,
+    // from a map position found anew
+    let scanned_kingdom = (k != kingdom).then_some(kingdom);
+    if scanned_kingdom.is_some() {
+        tracing::warn!(
+            "popup reports K:{k} while scanning K:{kingdom}; storing it under K:{k} and re-syncing navigation"
+        );
+        game.forget_position();
+    }
 
-            false
-        }
+    // Only a popup that confirmed the target says anything about it
+    let popup = if from_popup {
+        parse_popup_info(text)
     } else {
-        // No popup text — check if calibration was strong and near center
-        let cal_confirmed = calibration.as_ref().is_some_and(|gm| {
-            let err_x = (gm.x as f64 - SCREEN_CENTER_X).abs();
-            let err_y = (gm.y as f64 - SCREEN_CENTER_Y).abs();
-            gm.score >= 0.90 && err_x < 80.0 && err_y < 80.0
-        });
-
-        if cal_confirmed {
-            tracing::info!("no popup but strong calibration match, storing refined estimate");
-            let exchange = MercExchange {
-                kingdom,
-                x: refined_x,
-                y: refined_y,
-                found_at: Utc::now(),
-                scan_duration_secs,
-                confirmed: false,
-                screenshot_png: screenshot,
-            };
+        PopupInfo::default()
+    };
 
-            let mut s = state.lock().await;
-            let stored = s.add_exchange(exchange);
-            if stored {
-                tracing::info!(
-                    "added exchange K:{kingdom} X:{refined_x} Y:{refined_y} (estimate, total: {})",
-                    s.exchanges.len()
-                );
-            } else {
-                tracing::debug!(
-                    "duplicate or full, skipping K:{kingdom} X:{refined_x} Y:{refined_y}"
-                );
-            }
-            drop(s);
+    let found = confirmation.is_found();
+    let stored = if found {
+        let screenshot = save_screenshot(k, x, y).await;
+        let exchange = MercExchange {
+            scan_duration_secs,
+            popup: popup.clone(),
+            screenshot,
+            details: ExchangeDetails {
+                initial_score: Some(initial_score),
+                calibration_score: cal_score,
+                scan_pattern: Some(config.scan_pattern.clone()),
+                confirmed_by: Some(if from_popup {
+                    ConfirmedBy::Popup
+                } else {
+                    ConfirmedBy::Calibration
+                }),
+                scanned_kingdom,
+                verifications: Vec::new(),
+            },
+            ..MercExchange::found(k, x, y, from_popup)
+        };
 
-            log_exchange(
-                config,
-                &ExchangeLogEntry {
-                    timestamp: Utc::now().to_rfc3339(),
-                    kingdom,
-                    x: refined_x,
-                    y: refined_y,
-                    confirmed: false,
-                    stored,
-                    initial_score,
-                    calibration_score: cal_score,
-                    scan_pattern: config.scan_pattern.clone(),
-                    scan_duration_secs,
+        // Storing waits for the database; not under the state lock
+        let exchanges = state.read().await.exchanges.clone();
+        let added = tokio::task::spawn_blocking(move || exchanges.add(exchange))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("adding exchange K:{k} X:{x} Y:{y} failed: {e}");
+                Added::Unstored
+            });
+        let mut s = state.write().await;
+        s.record_confirmation(k, (x, y), initial_score, from_popup, added.is_kept());
+        if added.is_kept() {
+            tracing::info!(
+                "added exchange K:{k} X:{x} Y:{y} {}{} (total: {})",
+                if from_popup { "confirmed" } else { "estimate" },
+                if added == Added::Unstored {
+                    ", not stored"
+                } else {
+                    ""
                 },
+                s.exchanges.len()
             );
-
-            true
-        } else {
-            tracing::info!("no popup and weak/no calibration, not confirmed");
-
-            log_exchange(
+            notify(
                 config,
-                &ExchangeLogEntry {
-                    timestamp: Utc::now().to_rfc3339(),
-                    kingdom,
-                    x: refined_x,
-                    y: refined_y,
-                    confirmed: false,
-                    stored: false,
-                    initial_score,
-                    calibration_score: cal_score,
-                    scan_pattern: config.scan_pattern.clone(),
-                    scan_duration_secs,
-                },
+                EXCHANGE_EVENT,
+                exchange_message(&target.name, k, x, y, from_popup, &popup),
             );
-
-            false
+        } else {
+            tracing::debug!("duplicate, skipping K:{k} X:{x} Y:{y}");
         }
+        added.is_kept()
+    } else {
+        false
     };
 
+    log_exchange(
+        config,
+        &ExchangeLogEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            kingdom: k,
+            x,
+            y,
+            confirmed: from_popup,
+            stored,
+            initial_score,
+            calibration_score: cal_score,
+            scan_pattern: config.scan_pattern.clone(),
+            scan_duration_secs,
+            popup,
+            near_miss,
+            scanned_kingdom,
+        },
+    );
+
     // Close popup
     game.send_canvas_escape().await;
     sleep(Duration::from_millis(500)).await;
 
-    Ok(confirmed)
+    // Another kingdom's exchange doesn't end this kingdom's scan
+    Ok(found && scanned_kingdom.is_none())
 }
 
 /// Generate 9 interleaved spirals in a 3×3 grid covering the full map.
-/// Interleaves by ring level so broad coverage comes first.
+/// Interleaves by ring level so broad coverage comes first; within a ring
+/// level the 9 rings are visited in travel order.
 fn multi_spiral_positions(step: u32, max_rings: u32) -> Vec<(u32, u32)> {
     let centers: [(u32, u32); 9] = [
         (512, 512), // center
@@ -838,22 +2100,21 @@ fn multi_spiral_positions(step: u32, max_rings: u32) -> Vec<(u32, u32)> {
         .collect();
 
     let mut seen = HashSet::new();
-    let mut positions = Vec::new();
 
     // Interleave: for each ring level, emit all 9 centers' ring
-    for ring in 0..=max_rings as usize {
-        for center_rings in &per_center {
-            if ring < center_rings.len() {
-                for &pos in &center_rings[ring] {
-                    if seen.insert(pos) {
-                        positions.push(pos);
-                    }
-                }
-            }
-        }
-    }
+    let tiers = (0..=max_rings as usize)
+        .map(|ring| {
+            per_center
+                .iter()
+                .filter_map(|center_rings| center_rings.get(ring))
+                .flatten()
+                .copied()
+                .filter(|&pos| seen.insert(pos))
+                .collect()
+        })
+        .collect();
 
-    positions
+    order_for_travel(tiers)
 }
 
 /// Single spiral at center with step=50 for wider coverage.
@@ -862,29 +2123,54 @@ fn wide_spiral_positions(max_rings: u32) -> Vec<(u32, u32)> {
 }
 
 /// Return density-sorted scan positions for a kingdom from the compiled-in
-/// historical spawn data, truncated to the given coverage percentage.
+/// historical spawn data (or the `file` of spawns, if given), truncated to
+/// the given coverage percentage. Cells with the same spawn count are
+/// reordered for travel.
 /// A coverage of 80 means: include positions until 80% of historical spawns
 /// are covered, then stop.  Falls back to grid_scan_positions if the kingdom
 /// has no historical data.
-fn known_positions(kingdom: u32, coverage_pct: u32) -> Vec<(u32, u32)> {
-    let data = crate::known_locations::positions_for_kingdom(kingdom);
+fn known_positions(kingdom: u32, coverage_pct: u32, file: Option<&Path>) -> Vec<(u32, u32)> {
+    let loaded;
+    let data = match file.map(|path| (path, load_known_locations(path, kingdom))) {
+        Some((_, Ok(cells))) => {
+            loaded = cells;
+            &loaded[..]
+        }
+        Some((path, Err(e))) => {
+            tracing::warn!(
+                "failed to read {}: {e}, using compiled-in data",
+                path.display()
+            );
+            crate::known_locations::positions_for_kingdom(kingdom)
+        }
+        None => crate::known_locations::positions_for_kingdom(kingdom),
+    };
     if data.is_empty() {
-        tracing::warn!("no compiled-in data for kingdom {kingdom}, falling back to grid");
+        tracing::warn!("no historical data for kingdom {kingdom}, falling back to grid");
         return grid_scan_positions();
     }
 
     let total_spawns: u32 = data.iter().map(|&(_, _, c)| c as u32).sum();
     let target = (total_spawns as f64 * coverage_pct.min(100) as f64 / 100.0).ceil() as u32;
 
+    // Cells with the same spawn count are one tier, visited in travel order
     let mut cumulative = 0u32;
-    let mut positions = Vec::new();
+    let mut tiers: Vec<Vec<(u32, u32)>> = Vec::new();
+    let mut tier_count = None;
     for &(x, y, count) in data {
-        positions.push((x, y));
+        match tiers.last_mut() {
+            Some(tier) if tier_count == Some(count) => tier.push((x, y)),
+            _ => {
+                tiers.push(vec![(x, y)]);
+                tier_count = Some(count);
+            }
+        }
         cumulative += count as u32;
         if cumulative >= target {
             break;
         }
     }
+    let positions = order_for_travel(tiers);
 
     tracing::info!(
         "kingdom {kingdom}: {}/{} positions at {coverage_pct}% coverage ({cumulative}/{total_spawns} spawns)",
@@ -894,6 +2180,54 @@ fn known_positions(kingdom: u32, coverage_pct: u32) -> Vec<(u32, u32)> {
     positions
 }
 
+/// Side of the cells spawns are grouped into, as in `gen_known_locations.py`.
+const KNOWN_CELL_SIZE: u32 = 25;
+
+/// Positions from CSV `text`, in order: `x,y` lines, or `k,x,y` lines
+/// (the known-locations format) of which only `kingdom`'s are taken. Lines
+/// that are neither, such as a header, are skipped.
+pub fn parse_positions_csv(text: &str, kingdom: u32) -> Vec<(u32, u32)> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<u32> = line
+                .split(',')
+                .map(|f| f.trim().parse().ok())
+                .collect::<Option<_>>()?;
+            match fields[..] {
+                [x, y] => Some((x, y)),
+                [k, x, y] if k == kingdom => Some((x, y)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `kingdom`'s spawns from a CSV of `kingdom,x,y` records (the format of
+/// `assets/known_locations.csv`), grouped into cells the way the compiled-in
+/// data is: `(x, y, spawn_count)` per cell centre, most spawns first.
+pub fn load_known_locations(path: &Path, kingdom: u32) -> std::io::Result<Vec<(u32, u32, u16)>> {
+    let mut cells: HashMap<(u32, u32), u16> = HashMap::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let fields: Vec<u32> = line
+            .split(',')
+            .filter_map(|f| f.trim().parse().ok())
+            .collect();
+        let [k, x, y] = fields[..] else {
+            continue;
+        };
+        if k != kingdom {
+            continue;
+        }
+        let centre =
+            |v: u32| (v / KNOWN_CELL_SIZE * KNOWN_CELL_SIZE + KNOWN_CELL_SIZE / 2).min(1023);
+        let count = cells.entry((centre(x), centre(y))).or_default();
+        *count = count.saturating_add(1);
+    }
+    let mut cells: Vec<_> = cells.into_iter().map(|((x, y), n)| (x, y, n)).collect();
+    cells.sort_by_key(|&(x, y, n)| (std::cmp::Reverse(n), y, x));
+    Ok(cells)
+}
+
 /// Regular grid across the full map (30–970, step=30).
 fn grid_scan_positions() -> Vec<(u32, u32)> {
     let step = 30u32;
@@ -910,6 +2244,88 @@ fn grid_scan_positions() -> Vec<(u32, u32)> {
     positions
 }
 
+/// Reverse every other row of a row-by-row pattern, so each row starts next
+/// to where the previous one ended.
+fn snake_rows(positions: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    let mut rows: Vec<Vec<(u32, u32)>> = Vec::new();
+    for pos in positions {
+        match rows.last_mut() {
+            Some(row) if row[0].1 == pos.1 => row.push(pos),
+            _ => rows.push(vec![pos]),
+        }
+    }
+    for row in rows.iter_mut().skip(1).step_by(2) {
+        row.reverse();
+    }
+    rows.concat()
+}
+
+/// Improvement passes of 2-opt per tier in [`order_for_travel`].
+const TWO_OPT_PASSES: usize = 20;
+
+/// Flatten priority `tiers` into one path, reordering the positions within
+/// each tier to shorten the jumps between them: the map flies for longer
+/// the further it goes. Each tier starts from where the previous one ended
+/// (the first from its own first position) and is ordered by nearest
+/// neighbour, then improved with 2-opt. Tiers keep their order.
+fn order_for_travel(tiers: Vec<Vec<(u32, u32)>>) -> Vec<(u32, u32)> {
+    let dist = |a: (u32, u32), b: (u32, u32)| {
+        let dx = a.0 as f64 - b.0 as f64;
+        let dy = a.1 as f64 - b.1 as f64;
+        dx.hypot(dy)
+    };
+    let mut path: Vec<(u32, u32)> = Vec::new();
+    for mut tier in tiers {
+        let Some(&first) = tier.first() else {
+            continue;
+        };
+        // The tier's path, anchored at where the scan is when it begins
+        let anchor = path.last().copied().unwrap_or(first);
+        let mut route = vec![anchor];
+        if path.is_empty() {
+            tier.remove(0);
+        }
+        let mut here = anchor;
+        while let Some(nearest) = tier
+            .iter()
+            .enumerate()
+            .min_by(|a, b| dist(here, *a.1).total_cmp(&dist(here, *b.1)))
+            .map(|(i, _)| i)
+        {
+            here = tier.swap_remove(nearest);
+            route.push(here);
+        }
+
+        // 2-opt with the anchor fixed and the end free: reverse
+        // route[i..=j] when that shortens the path
+        for _ in 0..TWO_OPT_PASSES {
+            let mut improved = false;
+            for i in 1..route.len() {
+                for j in i + 1..route.len() {
+                    let before = dist(route[i - 1], route[i])
+                        + route.get(j + 1).map_or(0.0, |&n| dist(route[j], n));
+                    let after = dist(route[i - 1], route[j])
+                        + route.get(j + 1).map_or(0.0, |&n| dist(route[i], n));
+                    if after + 1e-9 < before {
+                        route[i..=j].reverse();
+                        improved = true;
+                    }
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+
+        if path.is_empty() {
+            path.extend(route);
+        } else {
+            path.extend(&route[1..]);
+        }
+    }
+    path
+}
+
 /// Generate positions for a single ring of a spiral (not including center).
 fn spiral_ring_positions(cx: u32, cy: u32, step: u32, ring: u32) -> Vec<(u32, u32)> {
     let s = step as i32;
@@ -976,6 +2392,36 @@ fn push_clamped(positions: &mut Vec<(u32, u32)>, x: i32, y: i32) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_measure_zoom() {
+        // Blocky texture bigger than the window, so shifted crops stay inside
+        let map = image::RgbImage::from_fn(2400, 1200, |x, y| {
+            let mut h = (x / 12).wrapping_mul(0x9e37_79b9) ^ (y / 12).wrapping_mul(0x85eb_ca6b);
+            h ^= h >> 15;
+            h = h.wrapping_mul(0x2c1b_3c6d);
+            h ^= h >> 13;
+            image::Rgb([h as u8, (h >> 8) as u8, (h >> 16) as u8])
+        });
+        let window = |x: u32, y: u32| {
+            image::DynamicImage::ImageRgb8(
+                image::imageops::crop_imm(&map, x, y, 1920, 1080).to_image(),
+            )
+        };
+        let before = window(100, 40);
+
+        // Calibrated: 4 units right moves the view ~198px right, ~6px up
+        let transform = MapTransform::default();
+        let at_scale = measure_zoom(&before, &window(100 + 198, 40 - 6), 4, transform).unwrap();
+        assert!((at_scale - 1.0).abs() < 0.05, "scale={at_scale}");
+
+        // One zoom step in: everything ~1.3× further
+        let zoomed = measure_zoom(&before, &window(100 + 257, 40 - 8), 4, transform).unwrap();
+        assert!((zoomed - 1.3).abs() < 0.05, "scale={zoomed}");
+
+        let blank = image::DynamicImage::ImageRgb8(image::RgbImage::new(1920, 1080));
+        assert_eq!(measure_zoom(&before, &blank, 4, transform), None);
+    }
+
     #[test]
     fn test_spiral_scan_positions_center_first() {
         let positions = spiral_scan_positions(512, 512, 25, 1);
@@ -1042,14 +2488,22 @@ mod tests {
         let positions = multi_spiral_positions(25, 4);
         // First 9 positions should be the 9 center points (ring 0 of each)
         assert_eq!(positions[0], (512, 512));
-        assert_eq!(positions[1], (150, 150));
-        assert_eq!(positions[2], (874, 150));
-        assert_eq!(positions[3], (150, 874));
-        assert_eq!(positions[4], (874, 874));
-        assert_eq!(positions[5], (512, 150));
-        assert_eq!(positions[6], (150, 512));
-        assert_eq!(positions[7], (874, 512));
-        assert_eq!(positions[8], (512, 874));
+        let mut centers = positions[..9].to_vec();
+        centers.sort();
+        assert_eq!(
+            centers,
+            [
+                (150, 150),
+                (150, 512),
+                (150, 874),
+                (512, 150),
+                (512, 512),
+                (512, 874),
+                (874, 150),
+                (874, 512),
+                (874, 874),
+            ]
+        );
     }
 
     #[test]
@@ -1067,15 +2521,48 @@ mod tests {
     fn test_multi_spiral_interleaving() {
         // After the first 9 (ring 0), the next batch should be ring 1 of all 9 centers
         let positions = multi_spiral_positions(25, 2);
-        // Ring 0: 9 centers = positions[0..9]
-        // Ring 1 center spiral starts at position[9], should be near (512,512)
-        let ring1_start = positions[9];
-        let dx = (ring1_start.0 as i32 - 512).abs();
-        let dy = (ring1_start.1 as i32 - 512).abs();
-        assert!(
-            dx <= 25 && dy <= 25,
-            "ring 1 should start near center spiral: {ring1_start:?}"
-        );
+        // Ring 1 starts next to the last center visited
+        let (lx, ly) = positions[8];
+        for &(x, y) in &positions[9..9 + 72] {
+            let near_center = [150, 512, 874]
+                .iter()
+                .any(|&cx| x.abs_diff(cx) == 25 || x == cx)
+                && [150, 512, 874]
+                    .iter()
+                    .any(|&cy| y.abs_diff(cy) == 25 || y == cy);
+            assert!(near_center, "ring 1 position {x},{y} not on a ring 1");
+        }
+        assert!(positions[9].0.abs_diff(lx) <= 25 && positions[9].1.abs_diff(ly) <= 25);
+    }
+
+    #[test]
+    fn test_order_for_travel() {
+        let travel = |path: &[(u32, u32)]| -> f64 {
+            path.windows(2)
+                .map(|w| (w[0].0 as f64 - w[1].0 as f64).hypot(w[0].1 as f64 - w[1].1 as f64))
+                .sum()
+        };
+        // Two tiers, each alternating between the two sides of the map
+        let tiers = vec![
+            vec![(100, 100), (900, 100), (125, 100), (900, 125)],
+            vec![(100, 900), (900, 900), (125, 900), (900, 875), (100, 875)],
+        ];
+        let ordered = order_for_travel(tiers.clone());
+        assert_eq!(ordered[0], (100, 100));
+        // Each tier keeps its positions and its place
+        for (range, tier) in [(0..4, &tiers[0]), (4..9, &tiers[1])] {
+            let mut got = ordered[range].to_vec();
+            let mut want = tier.clone();
+            got.sort();
+            want.sort();
+            assert_eq!(got, want);
+        }
+        assert!(travel(&ordered) < travel(&tiers.concat()));
+        assert_eq!(ordered[..2], [(100, 100), (125, 100)]);
+
+        // Visiting the 9 rings of a level in turn took ~46,000 map units
+        let multi = travel(&multi_spiral_positions(25, 4));
+        assert!(multi < 30_000.0, "multi travels {multi}");
     }
 
     // --- Wide spiral tests ---
@@ -1125,6 +2612,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_snake_rows_for_drag() {
+        let positions = grid_scan_positions();
+        let snaked = snake_rows(positions.clone());
+        assert_eq!(snaked.len(), positions.len());
+        assert_eq!(snaked[31], (960, 30));
+        // The second row starts above where the first ended
+        assert_eq!(snaked[32], (960, 60));
+        for pair in snaked.windows(2) {
+            let (dx, dy) = (pair[0].0.abs_diff(pair[1].0), pair[0].1.abs_diff(pair[1].1));
+            assert!(dx + dy <= 30, "jump {pair:?}");
+        }
+    }
+
     #[test]
     fn test_grid_count() {
         // (970 - 30) / 30 + 1 = 32.33 → 32 per axis → 32 × 32 = 1024
@@ -1151,7 +2652,7 @@ mod tests {
 
     #[test]
     fn test_known_positions_full_coverage() {
-        let positions = known_positions(10, 100);
+        let positions = known_positions(10, 100, None);
         assert!(!positions.is_empty(), "kingdom 10 should have data");
         assert!(
             positions.len() < 1024,
@@ -1171,10 +2672,10 @@ mod tests {
 
     #[test]
     fn test_known_positions_coverage_tiers() {
-        let p100 = known_positions(10, 100);
-        let p90 = known_positions(10, 90);
-        let p80 = known_positions(10, 80);
-        let p70 = known_positions(10, 70);
+        let p100 = known_positions(10, 100, None);
+        let p90 = known_positions(10, 90, None);
+        let p80 = known_positions(10, 80, None);
+        let p70 = known_positions(10, 70, None);
 
         assert!(
             p70.len() < p80.len(),
@@ -1192,7 +2693,7 @@ mod tests {
 
     #[test]
     fn test_known_positions_unknown_kingdom_fallback() {
-        let positions = known_positions(99999, 80);
+        let positions = known_positions(99999, 80, None);
         assert_eq!(positions.len(), grid_scan_positions().len());
     }
 
@@ -1224,4 +2725,124 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_known_positions_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spawns.csv");
+        std::fs::write(&path, "110,30,40\n110,31,45\n111,30,40\n110,500,500\nbad\n").unwrap();
+
+        assert_eq!(
+            load_known_locations(&path, 110).unwrap(),
+            [(37, 37, 2), (512, 512, 1)]
+        );
+        assert_eq!(
+            known_positions(110, 50, Some(&path)),
+            [(37, 37)],
+            "file data replaces the compiled-in data"
+        );
+        assert_eq!(
+            known_positions(10, 100, Some(&dir.path().join("missing.csv"))),
+            known_positions(10, 100, None)
+        );
+    }
+
+    #[test]
+    fn test_exchange_message() {
+        assert_eq!(
+            exchange_message("Exchange", 111, 506, 638, false, &PopupInfo::default()),
+            "Exchange found at K:111 X:506 Y:638 (estimate)"
+        );
+        let popup = parse_popup_info("Mercenary Exchange Lv. 3 (K:111 X:506 Y:638) 02:13:45");
+        assert_eq!(
+            exchange_message("Exchange", 111, 506, 638, true, &popup),
+            "Mercenary Exchange (level 3) found at K:111 X:506 Y:638, 02:13:45 left"
+        );
+    }
+
+    #[test]
+    fn test_parse_positions_csv() {
+        assert_eq!(
+            parse_positions_csv("x,y\n500, 520\n\n10,20\n", 111),
+            [(500, 520), (10, 20)]
+        );
+        assert_eq!(
+            parse_positions_csv("111,500,520\n112,1,2\n111,30,40,5\n111,60,70", 111),
+            [(500, 520), (60, 70)]
+        );
+    }
+
+    #[test]
+    fn test_latest_confirmed_exchanges() {
+        let entry = |timestamp: &str, kingdom: u32, x: u32, confirmed: bool| {
+            serde_json::to_string(&ExchangeLogEntry {
+                timestamp: timestamp.into(),
+                kingdom,
+                x,
+                y: 500,
+                confirmed,
+                stored: confirmed,
+                initial_score: 0.9,
+                calibration_score: None,
+                scan_pattern: "spiral".into(),
+                scan_duration_secs: None,
+                popup: PopupInfo::default(),
+                near_miss: false,
+                // Found from the neighbouring kingdom's scan
+                scanned_kingdom: (kingdom == 111).then_some(112),
+            })
+            .unwrap()
+        };
+        let log = [
+            entry("2025-01-01T10:00:00+00:00", 110, 1, true),
+            entry("2025-01-01T12:00:00+00:00", 110, 2, true),
+            entry("2025-01-01T13:00:00+00:00", 110, 3, false),
+            "not json".into(),
+            entry("2025-01-01T11:00:00+00:00", 111, 4, true),
+        ]
+        .join("\n");
+
+        let exchanges = latest_confirmed_exchanges(&log);
+        let found: Vec<_> = exchanges.iter().map(|e| (e.kingdom, e.x)).collect();
+        assert_eq!(found, [(111, 4), (110, 2)]);
+        assert!(exchanges.iter().all(|e| e.confirmed_at == Some(e.found_at)));
+        assert_eq!(exchanges[0].details.scanned_kingdom, Some(112));
+        assert_eq!(exchanges[1].details.scanned_kingdom, None);
+    }
+
+    #[test]
+    fn test_due_near_misses() {
+        let misses = |scores: &[f32]| -> Vec<DetectionResult> {
+            scores
+                .iter()
+                .enumerate()
+                .map(|(step_index, &score)| DetectionResult {
+                    matches: vec![detector::TemplateMatch {
+                        x: 0,
+                        y: 0,
+                        score,
+                        template: 0,
+                        channels: None,
+                        subpixel: (0.0, 0.0),
+                    }],
+                    nav_x: 0,
+                    nav_y: 0,
+                    step_index,
+                })
+                .collect()
+        };
+        let mut stats = KingdomStats::default();
+
+        let (passes, due) = due_near_misses(&mut stats, 2, misses(&[0.7]));
+        assert_eq!((passes, due.len()), (1, 0));
+        // A pass without near misses ends the dry spell
+        let (passes, due) = due_near_misses(&mut stats, 2, Vec::new());
+        assert_eq!((passes, due.len()), (0, 0));
+        due_near_misses(&mut stats, 2, misses(&[0.7]));
+
+        let (passes, due) = due_near_misses(&mut stats, 2, misses(&[0.6, 0.8, 0.5, 0.75]));
+        assert_eq!(passes, 2);
+        let steps: Vec<_> = due.iter().map(|m| m.step_index).collect();
+        assert_eq!(steps, [1, 3, 0], "strongest first, truncated");
+    }
 }
@@ -0,0 +1,157 @@
+//! The last `MERCY_SCREENSHOT_HISTORY` screenshots the scanner and the API
+//! took, for `GET /screenshots/recent`: when a confirmation goes wrong, the
+//! frames leading up to it are still there. Each is kept with when it was
+//! taken, what took it and where the map had been sent.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// What took a screenshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShotSource {
+    /// A scan step (the viewport only)
+    Scan,
+    /// After navigating to a detection, before clicking it
+    Calibration,
+    /// After clicking a detection
+    Popup,
+    /// `/screenshot`, `/goto` and the other API captures
+    Api,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Shot {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub source: ShotSource,
+    /// Where the map had been sent, when known
+    pub kingdom: Option<u32>,
+    pub x: Option<u32>,
+    pub y: Option<u32>,
+    /// Scan step, 1-based
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<usize>,
+    #[serde(skip)]
+    pub bytes: Arc<Vec<u8>>,
+}
+
+impl Shot {
+    /// MIME type of the encoded image (region captures follow
+    /// `MERCY_SCREENSHOT_FORMAT`).
+    pub fn content_type(&self) -> &'static str {
+        image::guess_format(&self.bytes).map_or("application/octet-stream", |f| f.to_mime_type())
+    }
+}
+
+/// A response body sharing `bytes`' buffer instead of copying it.
+pub fn shared_body(bytes: Arc<Vec<u8>>) -> Bytes {
+    struct Shared(Arc<Vec<u8>>);
+    impl AsRef<[u8]> for Shared {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+    Bytes::from_owner(Shared(bytes))
+}
+
+#[derive(Debug)]
+pub struct ScreenshotHistory {
+    shots: Mutex<(u64, VecDeque<Shot>)>,
+    /// Screenshots kept; 0 keeps none
+    capacity: usize,
+}
+
+impl ScreenshotHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            shots: Mutex::new((0, VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn push(
+        &self,
+        source: ShotSource,
+        position: Option<(u32, u32, u32)>,
+        step: Option<usize>,
+        bytes: Arc<Vec<u8>>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut guard = self.shots.lock().unwrap_or_else(|e| e.into_inner());
+        let (next_id, shots) = &mut *guard;
+        *next_id += 1;
+        if shots.len() == self.capacity {
+            shots.pop_front();
+        }
+        shots.push_back(Shot {
+            id: *next_id,
+            time: Utc::now(),
+            source,
+            kingdom: position.map(|p| p.0),
+            x: position.map(|p| p.1),
+            y: position.map(|p| p.2),
+            step,
+            bytes,
+        });
+    }
+
+    /// The last `limit` screenshots no older than `since`, oldest first.
+    pub fn recent(&self, limit: usize, since: Option<DateTime<Utc>>) -> Vec<Shot> {
+        let guard = self.shots.lock().unwrap_or_else(|e| e.into_inner());
+        let matching: Vec<_> = guard
+            .1
+            .iter()
+            .filter(|s| since.is_none_or(|since| s.time >= since))
+            .collect();
+        let skip = matching.len().saturating_sub(limit);
+        matching.into_iter().skip(skip).cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Shot> {
+        let guard = self.shots.lock().unwrap_or_else(|e| e.into_inner());
+        guard.1.iter().find(|s| s.id == id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_keeps_the_last() {
+        let history = ScreenshotHistory::new(2);
+        let bytes = Arc::new(vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+        history.push(
+            ShotSource::Scan,
+            Some((111, 500, 500)),
+            Some(1),
+            bytes.clone(),
+        );
+        history.push(
+            ShotSource::Calibration,
+            Some((111, 510, 490)),
+            None,
+            bytes.clone(),
+        );
+        history.push(ShotSource::Popup, None, None, bytes);
+
+        let recent = history.recent(10, None);
+        let ids: Vec<_> = recent.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [2, 3]);
+        assert_eq!(recent[0].x, Some(510));
+        assert!(history.get(1).is_none());
+        assert_eq!(history.get(3).unwrap().content_type(), "image/png");
+        assert_eq!(history.recent(1, None)[0].source, ShotSource::Popup);
+
+        let off = ScreenshotHistory::new(0);
+        off.push(ShotSource::Api, None, None, Arc::new(Vec::new()));
+        assert!(off.recent(10, None).is_empty());
+    }
+}
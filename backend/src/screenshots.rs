@@ -0,0 +1,169 @@
+//! Screenshots kept on disk (`MERCY_SCREENSHOT_DIR`): exchange popups served
+//! by `/exchanges/{id}/screenshot`, challenge pages and debug captures.
+//!
+//! Every save prunes the least recently used files until the directory fits
+//! in `MERCY_SCREENSHOT_DIR_MAX_MB` and `MERCY_SCREENSHOT_DIR_MAX_FILES`, so
+//! long runs don't fill the disk. Reading a screenshot back through [`load`]
+//! counts as a use.
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone)]
+pub struct Screenshots {
+    dir: PathBuf,
+    /// Prune down to this many bytes (None = no size cap)
+    max_bytes: Option<u64>,
+    /// Prune down to this many files (None = no count cap)
+    max_files: Option<usize>,
+}
+
+impl Screenshots {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            dir: config.screenshot_dir.clone(),
+            max_bytes: config.screenshot_dir_max_mb.map(|mb| mb * 1024 * 1024),
+            max_files: config.screenshot_dir_max_files,
+        }
+    }
+
+    /// Write `name` into the directory and prune it. Blocking; see [`save`].
+    ///
+    /// [`save`]: Screenshots::save
+    pub fn save_blocking(&self, name: &str, bytes: &[u8]) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(name);
+        std::fs::write(&path, bytes)?;
+        if (self.max_bytes.is_some() || self.max_files.is_some())
+            && let Err(e) = prune(&self.dir, self.max_bytes, self.max_files, &path)
+        {
+            tracing::warn!("failed to prune {}: {e}", self.dir.display());
+        }
+        Ok(path)
+    }
+
+    /// Write `name` into the directory and prune it, off the async runtime.
+    pub async fn save(
+        &self,
+        name: String,
+        bytes: impl AsRef<[u8]> + Send + 'static,
+    ) -> io::Result<PathBuf> {
+        let screenshots = self.clone();
+        tokio::task::spawn_blocking(move || screenshots.save_blocking(&name, bytes.as_ref()))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Read a saved screenshot, marking it as recently used.
+pub async fn load(path: PathBuf) -> io::Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || {
+        let bytes = std::fs::read(&path)?;
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now())?;
+        Ok(bytes)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Number of files in `dir` and their total size, for `/status`. A missing
+/// directory is empty.
+pub fn usage(dir: &Path) -> io::Result<(usize, u64)> {
+    match files(dir) {
+        Ok(files) => Ok((files.len(), files.iter().map(|(_, len, _)| len).sum())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok((0, 0)),
+        Err(e) => Err(e),
+    }
+}
+
+/// The files in `dir` with their modification time and size.
+fn files(dir: &Path) -> io::Result<Vec<(SystemTime, u64, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() {
+            files.push((meta.modified()?, meta.len(), entry.path()));
+        }
+    }
+    Ok(files)
+}
+
+/// Delete the least recently modified files in `dir` until the rest fit in
+/// `max_bytes` and `max_files`, never deleting `keep`.
+fn prune(
+    dir: &Path,
+    max_bytes: Option<u64>,
+    max_files: Option<usize>,
+    keep: &Path,
+) -> io::Result<()> {
+    let mut files = files(dir)?;
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    let mut count = files.len();
+    files.sort();
+    for (_, len, path) in files {
+        if max_bytes.is_none_or(|max| total <= max) && max_files.is_none_or(|max| count <= max) {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            // Pruned by a concurrent save
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        total -= len;
+        count -= 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_prune_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = SystemTime::now() - Duration::from_secs(3600);
+        // Oldest first: b, c, d, a
+        for (name, minutes) in [("a.png", 4), ("b.png", 1), ("c.png", 2), ("d.png", 3)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, [0u8; 100]).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(base + Duration::from_secs(minutes * 60))
+                .unwrap();
+        }
+
+        let left = || {
+            let mut names: Vec<_> = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // By count: c is the oldest
+        prune(dir.path(), None, Some(3), &dir.path().join("b.png")).unwrap();
+        assert_eq!(left(), ["a.png", "b.png", "d.png"]);
+        assert_eq!(usage(dir.path()).unwrap(), (3, 300));
+
+        // b is the oldest but was just written
+        prune(dir.path(), Some(250), None, &dir.path().join("b.png")).unwrap();
+        assert_eq!(left(), ["a.png", "b.png"]);
+    }
+}
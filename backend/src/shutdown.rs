@@ -0,0 +1,73 @@
+//! Graceful shutdown on SIGINT/SIGTERM.
+//!
+//! The scanner is asked to stop at its next kingdom and given
+//! `MERCY_SHUTDOWN_GRACE_SECS` before it's aborted, the browser is closed so
+//! Chromium exits and its temp profile is removed, and the HTTP server drains
+//! open requests for the same grace period. Exchange log lines are written
+//! whole, and the queued ones flushed, so stopping between them leaves the
+//! JSONL file intact.
+
+use std::time::Duration;
+
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::logfile;
+use crate::state::{AppState, ScannerPhase};
+
+/// How long a closing browser gets to exit before it's killed on drop.
+const BROWSER_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait for SIGINT or SIGTERM. Returns the signal's name.
+pub async fn signal_received() -> &'static str {
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!("graceful shutdown unavailable: {e}");
+            return std::future::pending().await;
+        }
+    };
+    tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+/// Stop the scanner, waiting up to `grace` for it to finish its kingdom,
+/// then close the browser.
+pub async fn stop(state: &AppState, grace: Duration) {
+    let handle = {
+        let mut s = state.write().await;
+        s.set_phase(ScannerPhase::Idle);
+        // Wake a paused scanner so it sees the phase
+        s.pause_notify.notify_one();
+        s.challenge = None;
+        s.scanner_handle.take()
+    };
+
+    if let Some(mut handle) = handle {
+        tracing::info!("waiting up to {}s for the scanner to stop", grace.as_secs());
+        if tokio::time::timeout(grace, &mut handle).await.is_err() {
+            tracing::warn!(
+                "scanner still running after {}s, aborting it",
+                grace.as_secs()
+            );
+            handle.abort();
+            // Dropping the task releases its browser
+            let _ = handle.await;
+        }
+    }
+
+    let (browser, api_tab) = {
+        let mut s = state.write().await;
+        (s.browser.take(), s.api_tab.take())
+    };
+    drop(api_tab);
+    if let Some(browser) = browser {
+        tracing::info!("closing the browser");
+        browser.close(BROWSER_CLOSE_TIMEOUT).await;
+    }
+    let _ = tokio::task::spawn_blocking(logfile::flush).await;
+}
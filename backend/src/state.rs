@@ -1,125 +1,1036 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, MutexGuard};
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
-use tokio::sync::{Mutex, Notify, mpsc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, RwLock, mpsc, oneshot};
 use tokio::task::JoinHandle;
 
+use crate::accounts::AccountPool;
 use crate::browser::GameBrowser;
-use crate::config::Config;
+use crate::config::{Config, ConfigOverrides, ConfigSource};
+use crate::console::ConsoleLog;
+use crate::events::{EventKind, EventLog};
+use crate::health::Health;
+use crate::metrics::Counters;
+use crate::recent_errors::ErrorLog;
+use crate::resources::ResourceUsage;
+use crate::screenshot_history::{ScreenshotHistory, ShotSource};
+use crate::stats::KingdomStats;
+use crate::store::{self, ScanSummary, Storage, StoreThread};
+use crate::supervisor::ScannerFailure;
+use crate::target::{PopupInfo, TargetProfile};
+use crate::viewport::Viewport;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ScannerPhase {
     Idle,
     Preparing,
+    /// Login is waiting for a 2FA code from `POST /login/2fa`.
+    #[serde(rename = "waiting_for_2fa")]
+    WaitingFor2fa,
     Ready,
     Scanning,
     Paused,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Where an exchange is in its life. Found as a `Candidate` (calibration
+/// estimate) or `Confirmed` (coordinates from the popup), `Verified` by a
+/// later re-check, and finally `Gone` (a re-check missed it) or `Expired`
+/// (not seen for `MERCY_EXCHANGE_EXPIRE_MINS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeStatus {
+    Candidate,
+    Confirmed,
+    Verified,
+    Gone,
+    Expired,
+}
+
+impl ExchangeStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExchangeStatus::Candidate => "candidate",
+            ExchangeStatus::Confirmed => "confirmed",
+            ExchangeStatus::Verified => "verified",
+            ExchangeStatus::Gone => "gone",
+            ExchangeStatus::Expired => "expired",
+        }
+    }
+
+    /// Still believed to be on the map.
+    pub fn is_live(self) -> bool {
+        !matches!(self, ExchangeStatus::Gone | ExchangeStatus::Expired)
+    }
+}
+
+impl std::str::FromStr for ExchangeStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "candidate" => Ok(ExchangeStatus::Candidate),
+            "confirmed" => Ok(ExchangeStatus::Confirmed),
+            "verified" => Ok(ExchangeStatus::Verified),
+            "gone" => Ok(ExchangeStatus::Gone),
+            "expired" => Ok(ExchangeStatus::Expired),
+            other => Err(format!("unknown exchange status: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MercExchange {
+    /// Given by the store when [`ExchangeBook::add`] keeps it, increasing
+    /// and never reused, so it keeps addressing the same exchange as others
+    /// come and go.
+    pub id: u64,
     pub kingdom: u32,
     pub x: u32,
     pub y: u32,
+    pub status: ExchangeStatus,
     pub found_at: DateTime<Utc>,
+    /// When the popup gave its coordinates (None = calibration estimate).
+    pub confirmed_at: Option<DateTime<Utc>>,
+    /// Last re-check that found it still there.
+    pub verified_at: Option<DateTime<Utc>>,
+    /// When it went `Gone` or `Expired`.
+    pub ended_at: Option<DateTime<Utc>>,
     /// How long the scan took to find this exchange (seconds).
     pub scan_duration_secs: Option<f64>,
-    /// true = coordinates parsed from popup, false = calibration estimate.
-    pub confirmed: bool,
-    /// Screenshot taken after clicking the match (PNG bytes).
+    /// Name, level and timer read from its popup
+    #[serde(flatten)]
+    pub popup: PopupInfo,
+    /// Screenshot taken after clicking the match, in `MERCY_SCREENSHOT_DIR`
+    /// (it may have been pruned since).
+    #[serde(skip)]
+    pub screenshot: Option<PathBuf>,
+    /// Kept in memory only, for `GET /exchanges/{id}`
     #[serde(skip)]
-    pub screenshot_png: Option<Vec<u8>>,
+    pub details: ExchangeDetails,
+}
+
+/// How a stored exchange's coordinates were established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmedBy {
+    /// Read from the popup
+    Popup,
+    /// Refined estimate from a strong calibration match
+    Calibration,
+}
+
+/// A re-check of an exchange's location.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub at: DateTime<Utc>,
+    pub present: bool,
+}
+
+/// What the scan knew when it stored an exchange, and its re-checks since.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExchangeDetails {
+    /// Detector score of the match in the scan screenshot
+    pub initial_score: Option<f32>,
+    /// Score of the calibration match after navigating to it
+    pub calibration_score: Option<f32>,
+    pub scan_pattern: Option<String>,
+    pub confirmed_by: Option<ConfirmedBy>,
+    /// The kingdom being scanned, when the popup reported another one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scanned_kingdom: Option<u32>,
+    pub verifications: Vec<Verification>,
+}
+
+impl MercExchange {
+    /// An exchange found just now, `Confirmed` if its coordinates came from
+    /// the popup, otherwise a `Candidate`.
+    pub fn found(kingdom: u32, x: u32, y: u32, from_popup: bool) -> Self {
+        let now = Utc::now();
+        Self {
+            id: 0,
+            kingdom,
+            x,
+            y,
+            status: if from_popup {
+                ExchangeStatus::Confirmed
+            } else {
+                ExchangeStatus::Candidate
+            },
+            found_at: now,
+            confirmed_at: from_popup.then_some(now),
+            verified_at: None,
+            ended_at: None,
+            scan_duration_secs: None,
+            popup: PopupInfo::default(),
+            screenshot: None,
+            details: ExchangeDetails::default(),
+        }
+    }
+
+    /// When it was last known to be there.
+    pub fn last_seen(&self) -> DateTime<Utc> {
+        self.verified_at.unwrap_or(self.found_at).max(self.found_at)
+    }
+
+    fn verify(&mut self, at: DateTime<Utc>) {
+        self.status = ExchangeStatus::Verified;
+        self.verified_at = Some(at);
+    }
+
+    fn end(&mut self, status: ExchangeStatus, at: DateTime<Utc>) {
+        self.status = status;
+        self.ended_at = Some(at);
+    }
+}
+
+/// What an instance knows, for `GET /state/export` and `POST /state/import`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub exported_at: DateTime<Utc>,
+    /// Without screenshots.
+    pub exchanges: Vec<MercExchange>,
+    pub kingdom_stats: BTreeMap<u32, KingdomStats>,
+    pub last_kingdom_scan: HashMap<u32, DateTime<Utc>>,
+}
+
+/// A captcha or verification challenge the scanner stopped at, waiting for
+/// a human to solve it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Challenge {
+    pub detected_at: DateTime<Utc>,
+    pub kingdom: u32,
+    /// What gave it away: the DOM selector or challenge template that matched.
+    pub reason: String,
+    /// Saved screenshot of the challenge, if it could be written.
+    pub screenshot: Option<String>,
 }
 
 pub struct AppStateInner {
     pub phase: ScannerPhase,
     pub current_kingdom: Option<u32>,
-    pub exchanges: Vec<MercExchange>,
+    /// Separately locked, so readers don't wait on this state.
+    pub exchanges: Arc<ExchangeBook>,
     pub scanner_handle: Option<JoinHandle<()>>,
-    pub config: Config,
+    /// The env config; a reload swaps in a copy with the runtime-safe
+    /// fields changed. The API holds the startup one, for the fields that
+    /// need a restart anyway. The scanner reads [`effective_config`]
+    /// instead.
+    ///
+    /// [`effective_config`]: AppStateInner::effective_config
+    pub config: Arc<Config>,
+    /// Where `config` was read from, for reloads
+    pub config_source: ConfigSource,
+    /// The search target's popup check and thresholds, loaded with its
+    /// templates at startup
+    pub target: Arc<TargetProfile>,
+    /// Set through `PATCH /config`, until reset.
+    pub overrides: ConfigOverrides,
+    /// Parameters of the running scan's `POST /start`.
+    pub run_overrides: ConfigOverrides,
     pub browser: Option<Arc<GameBrowser>>,
+    /// Second tab of `browser` for API requests (`MERCY_API_TAB`), opened on
+    /// first use. Cleared together with `browser`.
+    pub api_tab: Option<Arc<GameBrowser>>,
     pub pause_notify: Arc<Notify>,
     pub last_kingdom_scan: HashMap<u32, DateTime<Utc>>,
     /// Last screenshot taken (by goto or refresh), reused by detect.
-    pub last_screenshot: Option<Vec<u8>>,
+    pub last_screenshot: Option<Arc<Vec<u8>>>,
     /// Sender for priority (manual) kingdom scans; set while scanner loop runs.
     pub priority_scan_tx: Option<mpsc::UnboundedSender<u32>>,
     /// Kingdom currently being scanned manually (for status reporting).
     pub manual_scan_kingdom: Option<u32>,
+    /// Game viewport bounds, detected from UI anchors at session start.
+    pub viewport: Viewport,
+    /// Challenge the scanner paused for; cleared on resume or stop.
+    pub challenge: Option<Challenge>,
+    /// Last failure of the scan loop and the supervisor's plan for it;
+    /// cleared by `POST /start`.
+    pub scanner_failure: Option<ScannerFailure>,
+    /// Hands a manually entered 2FA code to the waiting login; set only in
+    /// `WaitingFor2fa`.
+    pub two_factor_tx: Option<oneshot::Sender<String>>,
+    /// Latest browser health check; cleared when the browser is replaced.
+    pub health: Option<Health>,
+    /// Latest browser memory/age sample; cleared when the browser is replaced.
+    pub resources: Option<ResourceUsage>,
+    /// Which game account is logged in, and each one's usage and cooldown.
+    pub accounts: AccountPool,
+    /// Console output of the game tabs, across browser relaunches.
+    pub console: Arc<ConsoleLog>,
+    /// Recent phase changes, detections, confirmations and errors.
+    pub events: EventLog,
+    /// Recent screenshots of the scanner and the API, shared with the scan.
+    pub screenshot_history: Arc<ScreenshotHistory>,
+    /// Recent scanner and browser errors, shared with the scan.
+    pub errors: Arc<ErrorLog>,
+    /// Scan statistics of each kingdom since startup.
+    pub kingdom_stats: BTreeMap<u32, KingdomStats>,
+    /// Operational counters, shared with the browsers.
+    pub counters: Arc<Counters>,
+    /// File `last_kingdom_scan` is saved to after each scan, when there's no
+    /// database to keep it in.
+    scan_times_path: Option<PathBuf>,
 }
 
-pub type AppState = Arc<Mutex<AppStateInner>>;
+/// Read-only paths (`/status`, `/stats`, ...) take the read lock. Config and
+/// exchanges sit outside it, behind their own `Arc`s.
+pub type AppState = Arc<RwLock<AppStateInner>>;
 
 impl AppStateInner {
     pub fn new(config: Config) -> Self {
+        let console = Arc::new(ConsoleLog::new(
+            config.console_log.clone(),
+            config.log_rotation,
+        ));
+        let accounts = AccountPool::new(config.accounts.len(), config.account_cooldown);
+        let viewport = config.viewport;
+        let events = EventLog::new(config.max_events);
+        let screenshot_history = Arc::new(ScreenshotHistory::new(config.screenshot_history));
+        let target = Arc::new(TargetProfile::named(config.search_target.clone()));
         Self {
             phase: ScannerPhase::Idle,
             current_kingdom: None,
-            exchanges: Vec::new(),
+            exchanges: Arc::new(ExchangeBook::new(
+                config.exchange_expire_mins,
+                config.max_exchanges,
+            )),
             scanner_handle: None,
-            config,
+            config: Arc::new(config),
+            config_source: ConfigSource::default(),
+            target,
+            overrides: ConfigOverrides::default(),
+            run_overrides: ConfigOverrides::default(),
             browser: None,
+            api_tab: None,
             pause_notify: Arc::new(Notify::new()),
             last_kingdom_scan: HashMap::new(),
             last_screenshot: None,
             priority_scan_tx: None,
             manual_scan_kingdom: None,
+            viewport,
+            challenge: None,
+            scanner_failure: None,
+            two_factor_tx: None,
+            health: None,
+            resources: None,
+            accounts,
+            console,
+            events,
+            screenshot_history,
+            errors: Arc::new(ErrorLog::default()),
+            kingdom_stats: BTreeMap::new(),
+            counters: Arc::new(Counters::default()),
+            scan_times_path: None,
+        }
+    }
+
+    /// The config the scanner runs with: env, then runtime overrides, then
+    /// the run's own parameters.
+    pub fn effective_config(&self) -> Arc<Config> {
+        let overrides = self.overrides.merged(&self.run_overrides);
+        if overrides.is_empty() {
+            self.config.clone()
+        } else {
+            Arc::new(overrides.apply(&self.config))
+        }
+    }
+
+    /// Keep a screenshot an API call took as `last_screenshot`, for
+    /// `/detect`, and in the history. `position` is where the map was.
+    pub fn keep_screenshot(&mut self, bytes: Arc<Vec<u8>>, position: Option<(u32, u32, u32)>) {
+        self.screenshot_history
+            .push(ShotSource::Api, position, None, bytes.clone());
+        self.last_screenshot = Some(bytes);
+    }
+
+    /// Change the phase, logging an event if it differs.
+    pub fn set_phase(&mut self, phase: ScannerPhase) {
+        if phase != self.phase {
+            self.events.push(EventKind::Phase {
+                from: self.phase,
+                to: phase,
+            });
+            self.phase = phase;
+        }
+    }
+
+    /// Load the exchanges and last scan times from `store` and write every
+    /// later change through to it.
+    pub fn attach_store(&mut self, store: Box<dyn Storage>) -> store::Result<()> {
+        let store = StoreThread::spawn(store);
+        self.last_kingdom_scan = store.call(|store| store.last_scan_times())?;
+        self.exchanges.attach_store(store)
+    }
+
+    /// Load the last scan times saved at `path` and save them there after
+    /// every scan, so a restart keeps honoring the cooldowns.
+    pub fn persist_scan_times(&mut self, path: PathBuf) {
+        match read_scan_times(&path) {
+            Ok(times) => self.last_kingdom_scan.extend(times),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("failed to read {}: {e}", path.display()),
+        }
+        self.scan_times_path = Some(path);
+    }
+
+    pub fn export(&self) -> StateSnapshot {
+        StateSnapshot {
+            exported_at: Utc::now(),
+            exchanges: self.exchanges.list(),
+            kingdom_stats: self.kingdom_stats.clone(),
+            last_kingdom_scan: self.last_kingdom_scan.clone(),
+        }
+    }
+
+    /// Replace the exchanges, stats and last scan times with `snapshot`'s,
+    /// writing them through to the database or scan times file.
+    pub fn import(&mut self, snapshot: StateSnapshot) {
+        self.exchanges.import(snapshot.exchanges);
+        self.kingdom_stats = snapshot.kingdom_stats;
+        self.last_kingdom_scan = snapshot.last_kingdom_scan;
+        for (&kingdom, &at) in &self.last_kingdom_scan {
+            let summary = ScanSummary {
+                kingdom,
+                started_at: at,
+                finished_at: at,
+                exchanges_found: 0,
+                error: None,
+            };
+            self.exchanges
+                .book()
+                .persist("scan summary", move |store| store.insert_scan(&summary));
+        }
+        self.save_scan_times();
+    }
+
+    /// Start from exchanges found before a restart, each also counting as
+    /// its kingdom's last scan, so the scanner re-verifies them first.
+    pub fn restore_exchanges(&mut self, exchanges: Vec<MercExchange>) {
+        for e in &exchanges {
+            let last = self
+                .last_kingdom_scan
+                .entry(e.kingdom)
+                .or_insert(e.last_seen());
+            *last = (*last).max(e.last_seen());
+        }
+        self.exchanges.restore(exchanges);
+    }
+
+    /// A scan step's screenshot was captured for detection.
+    pub fn record_position_scanned(&mut self, kingdom: u32) {
+        self.kingdom_stats
+            .entry(kingdom)
+            .or_default()
+            .positions_scanned += 1;
+    }
+
+    /// A scan step's best match is about to be clicked.
+    pub fn record_detection(&mut self, kingdom: u32, step: usize, score: f32) {
+        let stats = self.kingdom_stats.entry(kingdom).or_default();
+        stats.detections += 1;
+        stats.near_miss_passes = 0;
+        self.events.push(EventKind::Detection {
+            kingdom,
+            step,
+            score,
+        });
+    }
+
+    /// A clicked match was confirmed at (`x`, `y`), with coordinates from
+    /// the popup or estimated; `stored` unless it was a duplicate.
+    pub fn record_confirmation(
+        &mut self,
+        kingdom: u32,
+        (x, y): (u32, u32),
+        score: f32,
+        confirmed: bool,
+        stored: bool,
+    ) {
+        self.kingdom_stats
+            .entry(kingdom)
+            .or_default()
+            .record_confirmation(score);
+        self.events.push(EventKind::Confirmation {
+            kingdom,
+            x,
+            y,
+            confirmed,
+            stored,
+        });
+    }
+
+    pub fn last_scan_time(&self, kingdom: u32) -> Option<DateTime<Utc>> {
+        self.last_kingdom_scan.get(&kingdom).copied()
+    }
+
+    /// Record a finished kingdom scan started at `started_at`, with the
+    /// error it failed with.
+    pub fn finish_scan(&mut self, kingdom: u32, started_at: DateTime<Utc>, error: Option<String>) {
+        let now = Utc::now();
+        self.last_kingdom_scan.insert(kingdom, now);
+        self.accounts.kingdom_scanned(now);
+        let summary = ScanSummary {
+            kingdom,
+            started_at,
+            finished_at: now,
+            exchanges_found: self.exchanges.found_since(kingdom, started_at),
+            error,
+        };
+        let stats = self.kingdom_stats.entry(kingdom).or_default();
+        stats.record_pass(summary.error.as_deref());
+        if let Some(ref message) = summary.error {
+            self.events.push(EventKind::Error {
+                kingdom: Some(kingdom),
+                message: message.clone(),
+            });
+        }
+        self.exchanges
+            .book()
+            .persist("scan summary", move |store| store.insert_scan(&summary));
+        self.save_scan_times();
+    }
+
+    fn save_scan_times(&self) {
+        if let Some(ref path) = self.scan_times_path
+            && let Err(e) = write_scan_times(path, &self.last_kingdom_scan)
+        {
+            tracing::warn!("failed to write {}: {e}", path.display());
+        }
+    }
+}
+
+fn read_scan_times(path: &Path) -> std::io::Result<HashMap<u32, DateTime<Utc>>> {
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+/// Replace `path` with `times`, through a temporary file so a crash can't
+/// leave it half written.
+fn write_scan_times(path: &Path, times: &HashMap<u32, DateTime<Utc>>) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(times)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// The exchanges found so far, and the database (`MERCY_DB_PATH`) they and
+/// the scan history are written through to. Locked on its own and only for
+/// the bookkeeping itself, so `/exchanges` never waits on the scanner. The
+/// database runs on its own thread ([`StoreThread`]) and is never waited
+/// on under the lock.
+///
+/// Exchanges are kept after they end, as `Gone` or `Expired`. Expiry is
+/// applied whenever the book is locked, so readers always see it.
+#[derive(Default)]
+pub struct ExchangeBook {
+    inner: std::sync::Mutex<Book>,
+    /// Expire live exchanges not seen for this long (None = never)
+    expire_after: Option<chrono::Duration>,
+}
+
+#[derive(Default)]
+struct Book {
+    exchanges: Vec<MercExchange>,
+    store: StoreThread,
+    /// Exchanges kept in memory (None = all). The store keeps the rest.
+    max_len: Option<usize>,
+    /// Provisional IDs handed out so far, counting down from `u64::MAX`
+    unstored: u64,
+}
+
+/// What [`ExchangeBook::add`] did with an exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Added {
+    /// Stored, and kept under the store's ID
+    Stored,
+    /// The store failed: kept in memory only, under a provisional ID
+    Unstored,
+    /// The same exchange was found within the last 5 minutes
+    Duplicate,
+}
+
+impl Added {
+    /// Whether the exchange is in the book now.
+    pub fn is_kept(self) -> bool {
+        self != Added::Duplicate
+    }
+}
+
+impl Book {
+    /// Queue a write on the store. Failures are only logged: losing
+    /// persistence shouldn't stop a scan.
+    fn persist(
+        &self,
+        what: &'static str,
+        write: impl FnOnce(&mut dyn Storage) -> store::Result<()> + Send + 'static,
+    ) {
+        self.store.write(what, write);
+    }
+
+    /// Drop the oldest exchanges past `max_len`, ended ones first.
+    fn evict(&mut self) {
+        let Some(max_len) = self.max_len else {
+            return;
+        };
+        while self.exchanges.len() > max_len {
+            let oldest = |live: bool| {
+                self.exchanges
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| e.status.is_live() == live)
+                    .min_by_key(|(_, e)| e.found_at)
+                    .map(|(i, _)| i)
+            };
+            let Some(i) = oldest(false).or_else(|| oldest(true)) else {
+                break;
+            };
+            let e = self.exchanges.remove(i);
+            tracing::debug!(
+                "dropped exchange {} K:{} X:{} Y:{} from memory (over {max_len})",
+                e.id,
+                e.kingdom,
+                e.x,
+                e.y
+            );
         }
     }
 
-    /// Add exchange with deduplication: skip if same K/X/Y was found within last 5 minutes.
-    pub fn add_exchange(&mut self, exchange: MercExchange) -> bool {
+    /// Apply `change` to the live exchanges matching `filter` and store them.
+    fn update(
+        &mut self,
+        filter: impl Fn(&MercExchange) -> bool,
+        change: impl Fn(&mut MercExchange),
+    ) {
+        let mut changed = Vec::new();
+        for e in self
+            .exchanges
+            .iter_mut()
+            .filter(|e| e.status.is_live() && filter(e))
+        {
+            change(e);
+            changed.push(e.clone());
+        }
+        for e in changed {
+            self.persist("exchange", move |store| store.update_exchange(&e));
+        }
+    }
+}
+
+impl ExchangeBook {
+    pub fn new(expire_mins: Option<u64>, max_len: Option<usize>) -> Self {
+        Self {
+            inner: std::sync::Mutex::new(Book {
+                max_len,
+                ..Book::default()
+            }),
+            expire_after: expire_mins.map(|mins| chrono::Duration::minutes(mins as i64)),
+        }
+    }
+
+    fn book(&self) -> MutexGuard<'_, Book> {
+        let mut book = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(expire_after) = self.expire_after {
+            let now = Utc::now();
+            book.update(
+                |e| now - e.last_seen() >= expire_after,
+                |e| {
+                    let at = e.last_seen() + expire_after;
+                    e.end(ExchangeStatus::Expired, at);
+                },
+            );
+        }
+        book
+    }
+
+    fn attach_store(&self, store: StoreThread) -> store::Result<()> {
+        let exchanges = store.call(|store| store.exchanges())?;
+        let mut book = self.book();
+        book.exchanges = exchanges;
+        book.store = store;
+        book.evict();
+        Ok(())
+    }
+
+    /// Store `exchanges` and keep them under the IDs the store gives them.
+    /// One the store fails on is kept in memory only, under a provisional ID
+    /// counting down from `u64::MAX`, which the store never hands out.
+    /// Returns how many were stored. Waits for the store without holding
+    /// the book.
+    fn insert(&self, exchanges: Vec<MercExchange>) -> usize {
+        let store = self.book().store.clone();
+        // Kept for when the store thread is gone
+        let pending = exchanges.clone();
+        let stored = store.call(move |store| {
+            Ok(exchanges
+                .into_iter()
+                .map(|exchange| match store.insert_exchange(&exchange) {
+                    Ok(id) => (MercExchange { id, ..exchange }, true),
+                    Err(e) => {
+                        tracing::error!(
+                            "failed to store exchange K:{} X:{} Y:{}, keeping it in memory only: {e}",
+                            exchange.kingdom,
+                            exchange.x,
+                            exchange.y
+                        );
+                        (exchange, false)
+                    }
+                })
+                .collect::<Vec<_>>())
+        });
+        let stored = match stored {
+            Ok(stored) => stored,
+            Err(e) => {
+                tracing::error!("failed to store exchanges, keeping them in memory only: {e}");
+                pending.into_iter().map(|e| (e, false)).collect()
+            }
+        };
+        let count = stored.iter().filter(|(_, stored)| *stored).count();
+        let mut book = self.book();
+        for (mut exchange, stored) in stored {
+            if !stored {
+                exchange.id = u64::MAX - book.unstored;
+                book.unstored += 1;
+            }
+            book.exchanges.push(exchange);
+        }
+        book.evict();
+        count
+    }
+
+    /// Replace the exchanges with ones from before a restart, with new IDs.
+    fn restore(&self, exchanges: Vec<MercExchange>) {
+        self.book().exchanges.clear();
+        self.insert(exchanges);
+    }
+
+    /// Replace the exchanges with imported ones, stored under new IDs.
+    fn import(&self, exchanges: Vec<MercExchange>) {
+        {
+            let mut book = self.book();
+            book.persist("exchange removal", |store| store.clear_exchanges());
+            book.exchanges.clear();
+        }
+        self.insert(exchanges);
+    }
+
+    /// Add exchange with deduplication: skip if same K/X/Y was found within last 5 minutes
+    /// and is still live. Otherwise store it, under a new ID, or keep it
+    /// under a provisional one if the store fails.
+    ///
+    /// Waits for the database: call it off the async runtime.
+    pub fn add(&self, exchange: MercExchange) -> Added {
         let now = Utc::now();
         let five_min = chrono::Duration::minutes(5);
 
-        let is_duplicate = self.exchanges.iter().any(|e| {
-            e.kingdom == exchange.kingdom
+        let is_duplicate = self.book().exchanges.iter().any(|e| {
+            e.status.is_live()
+                && e.kingdom == exchange.kingdom
                 && e.x == exchange.x
                 && e.y == exchange.y
                 && (now - e.found_at) < five_min
         });
 
         if is_duplicate {
-            return false;
+            return Added::Duplicate;
+        }
+
+        match self.insert(vec![exchange]) {
+            1 => Added::Stored,
+            _ => Added::Unstored,
         }
+    }
 
-        self.exchanges.push(exchange);
-        true
+    pub fn len(&self) -> usize {
+        self.book().exchanges.len()
     }
 
-    pub fn last_scan_time(&self, kingdom: u32) -> Option<DateTime<Utc>> {
-        self.last_kingdom_scan.get(&kingdom).copied()
+    pub fn is_empty(&self) -> bool {
+        self.book().exchanges.is_empty()
     }
 
-    pub fn set_last_scan_time(&mut self, kingdom: u32) {
-        self.last_kingdom_scan.insert(kingdom, Utc::now());
+    /// How many exchanges are kept in memory (None = all).
+    pub fn max_len(&self) -> Option<usize> {
+        self.book().max_len
     }
 
-    /// Return (x, y) of the most recent exchange found for a given kingdom.
-    pub fn exchange_for_kingdom(&self, kingdom: u32) -> Option<(u32, u32)> {
-        self.exchanges
+    pub fn list(&self) -> Vec<MercExchange> {
+        self.book().exchanges.clone()
+    }
+
+    pub fn get(&self, id: u64) -> Option<MercExchange> {
+        self.book().exchanges.iter().find(|e| e.id == id).cloned()
+    }
+
+    /// Return (x, y) of the most recently found live exchange of a kingdom.
+    pub fn latest_for_kingdom(&self, kingdom: u32) -> Option<(u32, u32)> {
+        self.book()
+            .exchanges
             .iter()
-            .filter(|e| e.kingdom == kingdom)
+            .filter(|e| e.kingdom == kingdom && e.status.is_live())
             .max_by_key(|e| e.found_at)
             .map(|e| (e.x, e.y))
     }
 
-    /// Update `found_at` to now for the matching exchange.
-    pub fn refresh_exchange(&mut self, kingdom: u32, x: u32, y: u32) {
-        if let Some(e) = self
+    /// Exchanges of `kingdom` found since `since`.
+    fn found_since(&self, kingdom: u32, since: DateTime<Utc>) -> usize {
+        self.book()
+            .exchanges
+            .iter()
+            .filter(|e| e.kingdom == kingdom && e.found_at >= since)
+            .count()
+    }
+
+    /// A re-check found the exchange at (`x`, `y`) still there.
+    pub fn mark_verified(&self, kingdom: u32, x: u32, y: u32) {
+        let now = Utc::now();
+        self.book().update(
+            |e| e.kingdom == kingdom && e.x == x && e.y == y,
+            |e| e.verify(now),
+        );
+    }
+
+    /// A re-check found the kingdom's exchange gone: end all its live ones.
+    pub fn mark_gone(&self, kingdom: u32) {
+        let now = Utc::now();
+        self.book().update(
+            |e| e.kingdom == kingdom,
+            |e| e.end(ExchangeStatus::Gone, now),
+        );
+    }
+
+    /// Record a re-verification of a known exchange and whether it was
+    /// still there.
+    pub fn record_verification(&self, kingdom: u32, x: u32, y: u32, present: bool) {
+        let now = Utc::now();
+        let mut book = self.book();
+        for e in book
             .exchanges
             .iter_mut()
-            .find(|e| e.kingdom == kingdom && e.x == x && e.y == y)
+            .filter(|e| e.status.is_live() && e.kingdom == kingdom && e.x == x && e.y == y)
         {
-            e.found_at = Utc::now();
+            e.details
+                .verifications
+                .push(Verification { at: now, present });
         }
+        book.persist("verification", move |store| {
+            store.insert_verification(kingdom, x, y, present, now)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_exchange_book() {
+        let book = ExchangeBook::new(Some(60), None);
+        let exchange = |kingdom, x, minutes_ago| MercExchange {
+            found_at: Utc::now() - chrono::Duration::minutes(minutes_ago),
+            ..MercExchange::found(kingdom, x, 7, true)
+        };
+        assert_eq!(book.add(exchange(110, 1, 10)), Added::Stored);
+        assert_eq!(book.add(exchange(110, 1, 0)), Added::Stored);
+        // Found again within 5 minutes
+        assert_eq!(book.add(exchange(110, 1, 0)), Added::Duplicate);
+        let stale = exchange(111, 2, 90);
+        let found_at = stale.found_at;
+        assert_eq!(book.add(stale), Added::Stored);
+        assert_eq!(book.len(), 3);
+
+        let expired = book.get(2).unwrap();
+        assert_eq!(expired.status, ExchangeStatus::Expired);
+        assert_eq!(
+            expired.ended_at,
+            Some(found_at + chrono::Duration::minutes(60))
+        );
+        assert_eq!(book.latest_for_kingdom(111), None);
+
+        book.record_verification(110, 1, 7, true);
+        book.mark_verified(110, 1, 7);
+        assert_eq!(book.get(1).unwrap().status, ExchangeStatus::Verified);
+        book.record_verification(110, 1, 7, false);
+        book.mark_gone(110);
+        let history = book.get(1).unwrap().details.verifications;
+        assert_eq!(
+            history.iter().map(|v| v.present).collect::<Vec<_>>(),
+            [true, false]
+        );
+        assert_eq!(book.get(1).unwrap().status, ExchangeStatus::Gone);
+        assert_eq!(book.latest_for_kingdom(110), None);
+        // Gone, so no longer a duplicate; IDs aren't reused
+        assert_eq!(book.add(exchange(110, 1, 0)), Added::Stored);
+        assert_eq!(book.get(3).unwrap().status, ExchangeStatus::Confirmed);
+    }
+
+    #[test]
+    fn test_exchange_book_cap() {
+        let book = ExchangeBook::new(None, Some(2));
+        let exchange = |x, minutes_ago| MercExchange {
+            found_at: Utc::now() - chrono::Duration::minutes(minutes_ago),
+            ..MercExchange::found(110, x, 7, true)
+        };
+        assert_eq!(book.add(exchange(1, 30)), Added::Stored);
+        assert_eq!(book.add(exchange(2, 20)), Added::Stored);
+        book.mark_gone(110);
+        assert_eq!(book.add(exchange(3, 10)), Added::Stored);
+        // 1 and 2 are gone; the older is dropped
+        let kept = |book: &ExchangeBook| book.list().iter().map(|e| e.x).collect::<Vec<_>>();
+        assert_eq!(kept(&book), [2, 3]);
+
+        // Then the oldest live one, once no ended one is left
+        assert_eq!(book.add(exchange(4, 0)), Added::Stored);
+        assert_eq!(book.add(exchange(5, 0)), Added::Stored);
+        assert_eq!(kept(&book), [4, 5]);
+        assert_eq!(book.max_len(), Some(2));
+    }
+
+    #[test]
+    fn test_scan_times_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last_scans.json");
+        let times = HashMap::from([(110, Utc::now()), (111, Utc::now())]);
+        write_scan_times(&path, &times).unwrap();
+        write_scan_times(&path, &times).unwrap();
+        assert_eq!(read_scan_times(&path).unwrap(), times);
+    }
+
+    #[test]
+    fn test_exchange_book_import() {
+        let snapshot = StateSnapshot {
+            exported_at: Utc::now(),
+            exchanges: vec![
+                MercExchange {
+                    id: 4,
+                    ..MercExchange::found(110, 1, 7, true)
+                },
+                MercExchange {
+                    id: 7,
+                    ..MercExchange::found(111, 2, 7, false)
+                },
+            ],
+            kingdom_stats: BTreeMap::new(),
+            last_kingdom_scan: HashMap::new(),
+        };
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: StateSnapshot = serde_json::from_str(&json).unwrap();
+
+        let book = ExchangeBook::default();
+        book.import(snapshot.exchanges);
+        // Stored under new IDs
+        assert_eq!(book.get(1).unwrap().status, ExchangeStatus::Candidate);
+        assert!(book.get(7).is_none());
+        assert_eq!(
+            book.add(MercExchange::found(112, 3, 7, true)),
+            Added::Stored
+        );
+        assert_eq!(book.get(2).unwrap().kingdom, 112);
     }
 
-    /// Remove all exchanges for a given kingdom.
-    pub fn remove_exchange(&mut self, kingdom: u32) {
-        self.exchanges.retain(|e| e.kingdom != kingdom);
+    /// Hands out IDs until `fail` is set.
+    #[derive(Default)]
+    struct FlakyStore {
+        ids: MemoryStore,
+        fail: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Storage for FlakyStore {
+        fn exchanges(&mut self) -> store::Result<Vec<MercExchange>> {
+            Ok(Vec::new())
+        }
+
+        fn last_scan_times(&mut self) -> store::Result<HashMap<u32, DateTime<Utc>>> {
+            Ok(HashMap::new())
+        }
+
+        fn insert_exchange(&mut self, exchange: &MercExchange) -> store::Result<u64> {
+            if self.fail.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err(store::StoreError::Invalid("down".into()));
+            }
+            self.ids.insert_exchange(exchange)
+        }
+
+        fn update_exchange(&mut self, _exchange: &MercExchange) -> store::Result<()> {
+            Ok(())
+        }
+
+        fn clear_exchanges(&mut self) -> store::Result<()> {
+            Ok(())
+        }
+
+        fn insert_verification(
+            &mut self,
+            _kingdom: u32,
+            _x: u32,
+            _y: u32,
+            _present: bool,
+            _verified_at: DateTime<Utc>,
+        ) -> store::Result<()> {
+            Ok(())
+        }
+
+        fn insert_scan(&mut self, _scan: &ScanSummary) -> store::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_exchange_book_keeps_unstored_exchanges() {
+        let store = FlakyStore::default();
+        let fail = store.fail.clone();
+        let book = ExchangeBook::default();
+        book.attach_store(StoreThread::spawn(Box::new(store)))
+            .unwrap();
+
+        assert_eq!(
+            book.add(MercExchange::found(110, 1, 7, true)),
+            Added::Stored
+        );
+        fail.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(
+            book.add(MercExchange::found(110, 2, 7, true)),
+            Added::Unstored
+        );
+        fail.store(false, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(
+            book.add(MercExchange::found(110, 3, 7, true)),
+            Added::Stored
+        );
+
+        // Kept under a provisional ID the store never hands out
+        let ids: Vec<_> = book.list().iter().map(|e| (e.id, e.x)).collect();
+        assert_eq!(ids, [(0, 1), (u64::MAX, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn test_exchange_book_readable_while_store_busy() {
+        let store = StoreThread::default();
+        let book = Arc::new(ExchangeBook::default());
+        book.attach_store(store.clone()).unwrap();
+        assert_eq!(
+            book.add(MercExchange::found(110, 1, 7, true)),
+            Added::Stored
+        );
+
+        // Hold the store up, as a slow database would
+        let (release, gate) = std::sync::mpsc::channel::<()>();
+        store.write("nothing", move |_| {
+            let _ = gate.recv();
+            Ok(())
+        });
+        let adding = {
+            let book = book.clone();
+            std::thread::spawn(move || book.add(MercExchange::found(111, 2, 7, true)))
+        };
+        assert_eq!(book.list().len(), 1);
+        book.mark_gone(110);
+        assert_eq!(book.get(0).unwrap().status, ExchangeStatus::Gone);
+
+        release.send(()).unwrap();
+        assert_eq!(adding.join().unwrap(), Added::Stored);
+        assert_eq!(book.len(), 2);
     }
 }
@@ -0,0 +1,93 @@
+//! Per-kingdom scan statistics since startup, served at `GET /stats`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KingdomStats {
+    /// Finished scans of the kingdom (full, priority and one-shot)
+    pub passes: u32,
+    /// Scan steps captured and handed to detection
+    pub positions_scanned: u64,
+    /// Matches clicked to confirm
+    pub detections: u32,
+    /// Clicked matches that were confirmed, by popup or calibration
+    pub confirmations: u32,
+    /// Mean scan score of the confirmed matches
+    pub avg_confirm_score: Option<f32>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Passes in a row without a detection but with a near miss
+    #[serde(default)]
+    pub near_miss_passes: u32,
+    /// Near misses clicked to confirm (`MERCY_RELAX_AFTER_PASSES`)
+    #[serde(default)]
+    pub relaxed_retries: u32,
+    /// Of those, the ones confirmed
+    #[serde(default)]
+    pub relaxed_confirmations: u32,
+}
+
+impl KingdomStats {
+    pub fn record_confirmation(&mut self, score: f32) {
+        self.confirmations += 1;
+        // Running mean, so stats imported from a snapshot keep averaging
+        let avg = self.avg_confirm_score.unwrap_or(0.0);
+        self.avg_confirm_score = Some(avg + (score - avg) / self.confirmations as f32);
+    }
+
+    /// A pass ended without a detection, with near misses or without.
+    /// Returns how many passes in a row had them.
+    pub fn record_dry_pass(&mut self, near_misses: bool) -> u32 {
+        self.near_miss_passes = if near_misses {
+            self.near_miss_passes + 1
+        } else {
+            0
+        };
+        self.near_miss_passes
+    }
+
+    /// The near misses of the last passes were clicked, `confirmed` of
+    /// `retried` of them confirmed.
+    pub fn record_relaxed(&mut self, retried: u32, confirmed: u32) {
+        self.near_miss_passes = 0;
+        self.relaxed_retries += retried;
+        self.relaxed_confirmations += confirmed;
+    }
+
+    pub fn record_pass(&mut self, error: Option<&str>) {
+        self.passes += 1;
+        if let Some(error) = error {
+            self.last_error = Some(error.to_string());
+            self.last_error_at = Some(Utc::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kingdom_stats() {
+        let mut stats = KingdomStats::default();
+        assert_eq!(stats.avg_confirm_score, None);
+        stats.record_confirmation(0.8);
+        stats.record_confirmation(0.9);
+        assert_eq!(stats.confirmations, 2);
+        assert!((stats.avg_confirm_score.unwrap() - 0.85).abs() < 1e-6);
+
+        stats.record_pass(Some("browser died"));
+        stats.record_pass(None);
+        assert_eq!(stats.passes, 2);
+        assert_eq!(stats.last_error.as_deref(), Some("browser died"));
+
+        assert_eq!(stats.record_dry_pass(true), 1);
+        assert_eq!(stats.record_dry_pass(true), 2);
+        assert_eq!(stats.record_dry_pass(false), 0);
+        stats.record_dry_pass(true);
+        stats.record_relaxed(3, 1);
+        assert_eq!(stats.near_miss_passes, 0);
+        assert_eq!((stats.relaxed_retries, stats.relaxed_confirmations), (3, 1));
+    }
+}
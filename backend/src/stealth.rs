@@ -0,0 +1,139 @@
+//! How the browser presents itself to the game: user agent, language and
+//! timezone (`MERCY_USER_AGENT`, `MERCY_BROWSER_LANG`, `MERCY_TIMEZONE`), and
+//! the script injected into every page before the site's own.
+//!
+//! The script always hides `navigator.webdriver`. With `MERCY_STEALTH` it
+//! also patches the other giveaways of an automated or headless Chromium: an
+//! empty `navigator.plugins`, a missing `window.chrome`, the SwiftShader
+//! WebGL renderer, and notification permissions that contradict
+//! `Notification.permission`.
+
+use crate::config::Config;
+
+/// User agent presented to the game by default (a plain desktop Chrome, not
+/// headless).
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+
+/// WebGL vendor and renderer reported instead of SwiftShader's.
+const WEBGL_VENDOR: &str = "Google Inc. (Intel)";
+const WEBGL_RENDERER: &str = "ANGLE (Intel, Mesa Intel(R) UHD Graphics 630 (CFL GT2), OpenGL 4.6)";
+
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub user_agent: String,
+    /// BCP 47 language, e.g. `de-DE`; Chromium's default when `None`
+    pub lang: Option<String>,
+    /// IANA timezone, e.g. `Europe/Berlin`; the host's when `None`
+    pub timezone: Option<String>,
+    /// Inject the patches beyond the webdriver override
+    pub stealth: bool,
+}
+
+impl Fingerprint {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            user_agent: config.user_agent.clone(),
+            lang: config.browser_lang.clone(),
+            timezone: config.timezone.clone(),
+            stealth: config.stealth,
+        }
+    }
+
+    /// `Accept-Language` header for [`Fingerprint::lang`].
+    pub fn accept_language(&self) -> Option<String> {
+        self.lang.as_deref().map(|lang| languages(lang).join(","))
+    }
+
+    /// Script to run in every page before the site's own scripts.
+    pub fn init_script(&self) -> String {
+        let mut script =
+            String::from("Object.defineProperty(navigator, 'webdriver', { get: () => false });\n");
+        if let Some(ref lang) = self.lang {
+            // The Accept-Language override doesn't reach navigator.languages
+            let list = serde_json::to_string(&languages(lang)).unwrap_or_default();
+            script.push_str(&format!(
+                "Object.defineProperty(navigator, 'languages', {{ get: () => {list} }});\n"
+            ));
+        }
+        if self.stealth {
+            script.push_str(
+                &STEALTH_PATCHES
+                    .replace("{vendor}", WEBGL_VENDOR)
+                    .replace("{renderer}", WEBGL_RENDERER),
+            );
+        }
+        script
+    }
+}
+
+/// `navigator.languages` for a language: the tag, then its base language.
+fn languages(lang: &str) -> Vec<String> {
+    let mut list = vec![lang.to_owned()];
+    if let Some((base, _)) = lang.split_once('-') {
+        list.push(base.to_owned());
+    }
+    list
+}
+
+const STEALTH_PATCHES: &str = r#"
+(() => {
+    const fakePlugins = ['PDF Viewer', 'Chrome PDF Viewer', 'Chromium PDF Viewer'].map(name => ({
+        name, filename: 'internal-pdf-viewer', description: 'Portable Document Format', length: 1,
+    }));
+    Object.defineProperty(navigator, 'plugins', {
+        get: () => Object.assign(Object.create(PluginArray.prototype), fakePlugins, {
+            length: fakePlugins.length,
+            item: i => fakePlugins[i] || null,
+            namedItem: n => fakePlugins.find(p => p.name === n) || null,
+            refresh: () => {},
+        }),
+    });
+
+    if (!window.chrome) {
+        window.chrome = { runtime: {}, app: { isInstalled: false }, csi: () => ({}), loadTimes: () => ({}) };
+    }
+
+    const query = navigator.permissions && navigator.permissions.query.bind(navigator.permissions);
+    if (query) {
+        navigator.permissions.query = params => params && params.name === 'notifications'
+            ? Promise.resolve({ state: Notification.permission, onchange: null })
+            : query(params);
+    }
+
+    for (const ctx of [window.WebGLRenderingContext, window.WebGL2RenderingContext]) {
+        if (!ctx) continue;
+        const getParameter = ctx.prototype.getParameter;
+        ctx.prototype.getParameter = function (p) {
+            if (p === 37445) return '{vendor}';
+            if (p === 37446) return '{renderer}';
+            return getParameter.call(this, p);
+        };
+    }
+})();
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_script() {
+        let mut fp = Fingerprint {
+            user_agent: DEFAULT_USER_AGENT.into(),
+            lang: None,
+            timezone: None,
+            stealth: false,
+        };
+        assert!(fp.init_script().contains("webdriver"));
+        assert!(!fp.init_script().contains("languages"));
+        assert_eq!(fp.accept_language(), None);
+
+        fp.lang = Some("de-DE".into());
+        fp.stealth = true;
+        let script = fp.init_script();
+        assert!(script.contains(r#"["de-DE","de"]"#));
+        assert!(script.contains(WEBGL_RENDERER));
+        assert!(!script.contains("{vendor}"));
+        assert_eq!(fp.accept_language().as_deref(), Some("de-DE,de"));
+    }
+}
@@ -0,0 +1,441 @@
+//! Persistence of exchanges, verifications and scan history, behind the
+//! [`Storage`] trait: [`MemoryStore`] (the default), [`SqliteStore`]
+//! (`MERCY_DB_PATH`) or, with the `postgres` feature, `PgStore`
+//! (`MERCY_DATABASE_URL`, see `postgres_store.rs`).
+//!
+//! In memory a restart forgets the known exchange locations, so every
+//! kingdom gets a full scan instead of a quick re-verification. With a
+//! database the state methods write each change through, and startup loads
+//! the exchanges and the last scan time of each kingdom back. Databases
+//! assign the exchange IDs, so instances sharing one don't collide.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+use thiserror::Error;
+
+use crate::state::MercExchange;
+use crate::target::PopupInfo;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    Postgres(#[from] postgres::Error),
+
+    #[error("invalid stored value: {0}")]
+    Invalid(String),
+
+    #[error("store thread stopped")]
+    Stopped,
+}
+
+pub type Result<T> = std::result::Result<T, StoreError>;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS exchanges (
+    id INTEGER PRIMARY KEY,
+    kingdom INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    found_at TEXT NOT NULL,
+    scan_duration_secs REAL,
+    confirmed INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS verifications (
+    id INTEGER PRIMARY KEY,
+    kingdom INTEGER NOT NULL,
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    verified_at TEXT NOT NULL,
+    present INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    kingdom INTEGER NOT NULL,
+    started_at TEXT NOT NULL,
+    finished_at TEXT NOT NULL,
+    exchanges_found INTEGER NOT NULL,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS scans_kingdom ON scans (kingdom, finished_at);
+";
+
+/// Changes to [`SCHEMA`], applied in order to databases whose
+/// `user_version` is behind.
+const MIGRATIONS: &[&str] = &[
+    "
+ALTER TABLE exchanges ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
+ALTER TABLE exchanges ADD COLUMN confirmed_at TEXT;
+ALTER TABLE exchanges ADD COLUMN verified_at TEXT;
+ALTER TABLE exchanges ADD COLUMN ended_at TEXT;
+UPDATE exchanges SET status = 'candidate' WHERE NOT confirmed;
+UPDATE exchanges SET confirmed_at = found_at WHERE confirmed;
+",
+    "
+ALTER TABLE exchanges ADD COLUMN name TEXT;
+ALTER TABLE exchanges ADD COLUMN level INTEGER;
+ALTER TABLE exchanges ADD COLUMN timer TEXT;
+",
+];
+
+/// One finished kingdom scan (a pass of the scan loop, or a priority or
+/// one-shot scan).
+#[derive(Debug, Clone)]
+pub struct ScanSummary {
+    pub kingdom: u32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub exchanges_found: usize,
+    /// Set when the scan failed
+    pub error: Option<String>,
+}
+
+/// Where exchanges and scan history are written through to.
+pub trait Storage: Send {
+    /// All stored exchanges, oldest first (without screenshots).
+    fn exchanges(&mut self) -> Result<Vec<MercExchange>>;
+
+    /// When each kingdom's last scan finished.
+    fn last_scan_times(&mut self) -> Result<HashMap<u32, DateTime<Utc>>>;
+
+    /// Store a new exchange, returning the ID it was given (its own `id` is
+    /// ignored).
+    fn insert_exchange(&mut self, exchange: &MercExchange) -> Result<u64>;
+
+    /// Store a status transition of an exchange.
+    fn update_exchange(&mut self, exchange: &MercExchange) -> Result<()>;
+
+    fn clear_exchanges(&mut self) -> Result<()>;
+
+    fn insert_verification(
+        &mut self,
+        kingdom: u32,
+        x: u32,
+        y: u32,
+        present: bool,
+        verified_at: DateTime<Utc>,
+    ) -> Result<()>;
+
+    fn insert_scan(&mut self, scan: &ScanSummary) -> Result<()>;
+}
+
+/// A [`Storage`] on a thread of its own, so a slow or unreachable database
+/// only holds up whoever waits for an answer from it. Writes are queued in
+/// the order they are made and their failures logged; [`StoreThread::call`]
+/// waits for its result behind them.
+#[derive(Clone)]
+pub struct StoreThread {
+    jobs: std::sync::mpsc::Sender<Job>,
+}
+
+type Job = Box<dyn FnOnce(&mut dyn Storage) + Send>;
+
+impl StoreThread {
+    /// Run `store` until every handle to it is dropped.
+    pub fn spawn(mut store: Box<dyn Storage>) -> Self {
+        let (jobs, queue) = std::sync::mpsc::channel::<Job>();
+        std::thread::spawn(move || {
+            for job in queue {
+                job(store.as_mut());
+            }
+        });
+        Self { jobs }
+    }
+
+    /// Queue `write`, logging its failure to store `what`.
+    pub fn write(
+        &self,
+        what: &'static str,
+        write: impl FnOnce(&mut dyn Storage) -> Result<()> + Send + 'static,
+    ) {
+        let job = Box::new(move |store: &mut dyn Storage| {
+            if let Err(e) = write(store) {
+                tracing::warn!("failed to store {what}: {e}");
+            }
+        });
+        if self.jobs.send(job).is_err() {
+            tracing::warn!("failed to store {what}: {}", StoreError::Stopped);
+        }
+    }
+
+    /// Run `call` on the store once the queued writes are done and wait for
+    /// its result. Blocks: call it off the async runtime, or only at startup.
+    pub fn call<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(&mut dyn Storage) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (reply, result) = std::sync::mpsc::channel();
+        let job = Box::new(move |store: &mut dyn Storage| {
+            // The caller may have given up waiting
+            let _ = reply.send(call(store));
+        });
+        self.jobs.send(job).map_err(|_| StoreError::Stopped)?;
+        result.recv().map_err(|_| StoreError::Stopped)?
+    }
+}
+
+impl Default for StoreThread {
+    fn default() -> Self {
+        Self::spawn(Box::new(MemoryStore::default()))
+    }
+}
+
+/// No persistence: only hands out exchange IDs.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    next_id: u64,
+}
+
+impl Storage for MemoryStore {
+    fn exchanges(&mut self) -> Result<Vec<MercExchange>> {
+        Ok(Vec::new())
+    }
+
+    fn last_scan_times(&mut self) -> Result<HashMap<u32, DateTime<Utc>>> {
+        Ok(HashMap::new())
+    }
+
+    fn insert_exchange(&mut self, _exchange: &MercExchange) -> Result<u64> {
+        self.next_id += 1;
+        Ok(self.next_id - 1)
+    }
+
+    fn update_exchange(&mut self, _exchange: &MercExchange) -> Result<()> {
+        Ok(())
+    }
+
+    fn clear_exchanges(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn insert_verification(
+        &mut self,
+        _kingdom: u32,
+        _x: u32,
+        _y: u32,
+        _present: bool,
+        _verified_at: DateTime<Utc>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn insert_scan(&mut self, _scan: &ScanSummary) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!(
+                "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
+        }
+        Ok(Self { conn })
+    }
+}
+
+impl Storage for SqliteStore {
+    fn exchanges(&mut self) -> Result<Vec<MercExchange>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, kingdom, x, y, status, found_at, confirmed_at, verified_at, ended_at,
+                    scan_duration_secs, name, level, timer
+             FROM exchanges ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(4)?,
+                MercExchange {
+                    id: row.get(0)?,
+                    kingdom: row.get(1)?,
+                    x: row.get(2)?,
+                    y: row.get(3)?,
+                    found_at: row.get(5)?,
+                    confirmed_at: row.get(6)?,
+                    verified_at: row.get(7)?,
+                    ended_at: row.get(8)?,
+                    scan_duration_secs: row.get(9)?,
+                    popup: PopupInfo {
+                        name: row.get(10)?,
+                        level: row.get(11)?,
+                        timer: row.get(12)?,
+                    },
+                    ..MercExchange::found(0, 0, 0, false)
+                },
+            ))
+        })?;
+        rows.map(|row| {
+            let (status, exchange) = row?;
+            Ok(MercExchange {
+                status: status.parse().map_err(StoreError::Invalid)?,
+                ..exchange
+            })
+        })
+        .collect()
+    }
+
+    fn last_scan_times(&mut self) -> Result<HashMap<u32, DateTime<Utc>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT kingdom, MAX(finished_at) FROM scans GROUP BY kingdom")?;
+        let times = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(times)
+    }
+
+    fn insert_exchange(&mut self, exchange: &MercExchange) -> Result<u64> {
+        self.conn.execute(
+            "INSERT INTO exchanges (kingdom, x, y, status, found_at, confirmed_at, verified_at,
+                                    ended_at, scan_duration_secs, confirmed, name, level, timer)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                exchange.kingdom,
+                exchange.x,
+                exchange.y,
+                exchange.status.as_str(),
+                exchange.found_at,
+                exchange.confirmed_at,
+                exchange.verified_at,
+                exchange.ended_at,
+                exchange.scan_duration_secs,
+                exchange.confirmed_at.is_some(),
+                exchange.popup.name,
+                exchange.popup.level,
+                exchange.popup.timer,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    fn update_exchange(&mut self, exchange: &MercExchange) -> Result<()> {
+        self.conn.execute(
+            "UPDATE exchanges SET status = ?2, verified_at = ?3, ended_at = ?4 WHERE id = ?1",
+            params![
+                exchange.id,
+                exchange.status.as_str(),
+                exchange.verified_at,
+                exchange.ended_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn clear_exchanges(&mut self) -> Result<()> {
+        self.conn.execute("DELETE FROM exchanges", [])?;
+        Ok(())
+    }
+
+    fn insert_verification(
+        &mut self,
+        kingdom: u32,
+        x: u32,
+        y: u32,
+        present: bool,
+        verified_at: DateTime<Utc>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO verifications (kingdom, x, y, verified_at, present)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![kingdom, x, y, verified_at, present],
+        )?;
+        Ok(())
+    }
+
+    fn insert_scan(&mut self, scan: &ScanSummary) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO scans (kingdom, started_at, finished_at, exchanges_found, error)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                scan.kingdom,
+                scan.started_at,
+                scan.finished_at,
+                scan.exchanges_found,
+                scan.error,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ExchangeStatus;
+
+    #[test]
+    fn test_store_round_trip() {
+        let mut store =
+            SqliteStore::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let t0 = Utc::now();
+        let exchange = |kingdom, x| MercExchange {
+            found_at: t0,
+            confirmed_at: Some(t0),
+            scan_duration_secs: Some(12.5),
+            popup: PopupInfo {
+                name: Some("Mercenary Exchange".into()),
+                level: Some(3),
+                timer: None,
+            },
+            ..MercExchange::found(kingdom, x, 7, true)
+        };
+        let first = store.insert_exchange(&exchange(110, 1)).unwrap();
+        let second = store.insert_exchange(&exchange(111, 2)).unwrap();
+        assert_ne!(first, second);
+        let later = t0 + chrono::Duration::minutes(5);
+        store
+            .update_exchange(&MercExchange {
+                id: first,
+                status: ExchangeStatus::Verified,
+                verified_at: Some(later),
+                ..exchange(110, 1)
+            })
+            .unwrap();
+
+        let exchanges = store.exchanges().unwrap();
+        assert_eq!(exchanges.len(), 2);
+        assert_eq!(
+            (exchanges[0].id, exchanges[0].kingdom, exchanges[0].x),
+            (first, 110, 1)
+        );
+        assert_eq!(exchanges[0].status, ExchangeStatus::Verified);
+        assert_eq!(exchanges[0].verified_at, Some(later));
+        assert_eq!(exchanges[0].confirmed_at, Some(t0));
+        assert_eq!(exchanges[0].scan_duration_secs, Some(12.5));
+        assert_eq!(exchanges[0].popup, exchange(110, 1).popup);
+        assert_eq!(exchanges[1].status, ExchangeStatus::Confirmed);
+
+        for finished_at in [t0, later] {
+            store
+                .insert_scan(&ScanSummary {
+                    kingdom: 110,
+                    started_at: t0,
+                    finished_at,
+                    exchanges_found: 1,
+                    error: None,
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            store.last_scan_times().unwrap(),
+            HashMap::from([(110, later)])
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Supervision of the scan loop started by `POST /start`.
+//!
+//! The loop runs in its own task, so both an error and a panic end up here.
+//! The failure is recorded in state (`scanner_failure` in `/status`), as an
+//! event and through `MERCY_ERROR_COMMAND`, and the scan is restarted after
+//! an exponential backoff, up to `MERCY_SCANNER_MAX_RESTARTS` failures in a
+//! row. A dead browser is dropped first so the restart launches a new one.
+//! While waiting the phase is `Ready` (or `Idle` without a browser), so
+//! `POST /start` and `POST /stop` act on the waiting supervisor as they
+//! would on the scanner.
+//!
+//! [`start`], [`stop`] and [`pause`] are the controls behind the API's
+//! `POST /start`, `/stop` and `/pause`, shared with MQTT.
+
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep};
+
+use crate::config::ConfigOverrides;
+use crate::detector::DetectorHandle;
+use crate::events::EventKind;
+use crate::report;
+use crate::scanner;
+use crate::state::{AppState, ScannerPhase};
+
+/// Wait before the first restart; doubled for each failure in a row.
+const BACKOFF_BASE: Duration = Duration::from_secs(10);
+const BACKOFF_MAX: Duration = Duration::from_secs(600);
+/// A run that lasted this long counts as recovered: the next failure starts
+/// the count over.
+const HEALTHY_RUN: Duration = Duration::from_secs(15 * 60);
+
+/// The last time the scan loop failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScannerFailure {
+    pub at: DateTime<Utc>,
+    pub reason: String,
+    /// Failures in a row before this one
+    pub restarts: u32,
+    /// When the scan restarts; None once the supervisor gave up
+    pub restart_at: Option<DateTime<Utc>>,
+}
+
+/// Spawn the supervised scan loop. Aborting the handle stops the loop.
+pub fn spawn_scanner(state: AppState, detectors: Arc<DetectorHandle>) -> JoinHandle<()> {
+    tokio::spawn(supervise(state, detectors))
+}
+
+/// Start a scan with `run_overrides`, or resume a paused one. Returns
+/// `started` or `resumed`, or None while a scan is already running.
+pub async fn start(
+    state: &AppState,
+    detectors: &Arc<DetectorHandle>,
+    run_overrides: ConfigOverrides,
+) -> Option<&'static str> {
+    let mut s = state.write().await;
+    match s.phase {
+        ScannerPhase::Paused => {
+            // Resume: set phase to Scanning and wake the paused scanner
+            s.set_phase(ScannerPhase::Scanning);
+            s.challenge = None;
+            s.pause_notify.notify_one();
+            Some("resumed")
+        }
+        ScannerPhase::Idle | ScannerPhase::Ready => {
+            // Stop existing scanner handle if any
+            if let Some(handle) = s.scanner_handle.take() {
+                handle.abort();
+            }
+
+            // Exchanges are kept: known ones are re-verified first
+            s.current_kingdom = None;
+            s.run_overrides = run_overrides;
+
+            s.scanner_failure = None;
+            s.scanner_handle = Some(spawn_scanner(state.clone(), detectors.clone()));
+            Some("started")
+        }
+        ScannerPhase::Scanning | ScannerPhase::Preparing | ScannerPhase::WaitingFor2fa => None,
+    }
+}
+
+/// Stop the scan, or a supervisor waiting to restart it. The browser stays.
+pub async fn stop(state: &AppState) {
+    let mut s = state.write().await;
+
+    if let Some(handle) = s.scanner_handle.take() {
+        handle.abort();
+    }
+
+    // Wake any paused waiter so it can exit
+    s.pause_notify.notify_one();
+    s.challenge = None;
+    // A supervisor waiting to restart was aborted with the handle
+    if let Some(ref mut failure) = s.scanner_failure {
+        failure.restart_at = None;
+    }
+
+    // Keep browser alive: Ready if browser exists, Idle otherwise
+    let phase = if s.browser.is_some() {
+        ScannerPhase::Ready
+    } else {
+        ScannerPhase::Idle
+    };
+    s.set_phase(phase);
+    s.manual_scan_kingdom = None;
+    s.run_overrides = ConfigOverrides::default();
+}
+
+/// Pause a running scan; false if there is none.
+pub async fn pause(state: &AppState) -> bool {
+    let mut s = state.write().await;
+    match s.phase {
+        ScannerPhase::Scanning => {
+            s.set_phase(ScannerPhase::Paused);
+            true
+        }
+        // Idempotent
+        ScannerPhase::Paused => true,
+        _ => false,
+    }
+}
+
+/// Aborts the task when dropped, so aborting the supervisor stops its run.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn supervise(state: AppState, detectors: Arc<DetectorHandle>) {
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        let mut run = AbortOnDrop(tokio::spawn(scanner::run_scan(
+            state.clone(),
+            detectors.clone(),
+        )));
+        let reason = match (&mut run.0).await {
+            // Stopped through the API (or shutting down)
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => format!("{e:#}"),
+            Err(e) if e.is_panic() => {
+                format!("scanner panicked: {}", panic_message(e.into_panic()))
+            }
+            Err(_) => return,
+        };
+        if started.elapsed() >= HEALTHY_RUN {
+            restarts = 0;
+        }
+        tracing::error!("scanner error: {reason}");
+
+        let mut s = state.write().await;
+        report::error("scanner", reason.clone(), s.current_kingdom, None);
+        s.errors
+            .record("scanner", reason.clone(), s.current_kingdom);
+        s.events.push(EventKind::Error {
+            kingdom: None,
+            message: reason.clone(),
+        });
+        let max_restarts = s.config.scanner_max_restarts;
+        let delay = backoff(restarts);
+        let give_up = restarts >= max_restarts;
+        s.scanner_failure = Some(ScannerFailure {
+            at: Utc::now(),
+            reason,
+            restarts,
+            restart_at: (!give_up).then(|| Utc::now() + delay),
+        });
+        if s.browser.as_ref().is_some_and(|b| !b.is_alive()) {
+            s.browser = None;
+            s.api_tab = None;
+        }
+        let phase = if s.browser.is_some() {
+            ScannerPhase::Ready
+        } else {
+            ScannerPhase::Idle
+        };
+        s.set_phase(phase);
+        s.priority_scan_tx = None;
+        if give_up {
+            s.run_overrides = ConfigOverrides::default();
+            tracing::error!(
+                "scanner failed {} time(s) in a row, not restarting",
+                restarts + 1
+            );
+            return;
+        }
+        drop(s);
+
+        restarts += 1;
+        tracing::warn!(
+            "restarting the scanner in {}s ({restarts}/{max_restarts})",
+            delay.as_secs()
+        );
+        sleep(delay).await;
+    }
+}
+
+/// Wait before restart number `restarts + 1`.
+fn backoff(restarts: u32) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(restarts))
+        .min(BACKOFF_MAX)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or("Box<dyn Any>".into(), |m| m.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(10));
+        assert_eq!(backoff(1), Duration::from_secs(20));
+        assert_eq!(backoff(3), Duration::from_secs(80));
+        assert_eq!(backoff(6), BACKOFF_MAX);
+        assert_eq!(backoff(40), BACKOFF_MAX);
+    }
+}
@@ -0,0 +1,437 @@
+//! Target profiles: what the scanner looks for and how a hit is confirmed.
+//!
+//! A profile is named by `MERCY_SEARCH_TARGET` and read from the
+//! `<name>_refs.json` manifest in the assets directory (see
+//! [`detector::asset_search_dirs`]). Besides the templates, the manifest can
+//! carry the target's popup check and thresholds:
+//!
+//! ```json
+//! {
+//!     "name": "Mercenary Exchange",
+//!     "threshold": 0.97,
+//!     "popup_keywords": ["Mercenary Exchange"],
+//!     "popup_pattern": "K:(?<k>\\d+)\\s*X:(?<x>\\d+)\\s*Y:(?<y>\\d+)",
+//!     "verify_min_score": 0.9,
+//!     "verify_max_offset_px": 80,
+//!     "templates": [{"file": "mercenary_exchange_ref.png"}]
+//! }
+//! ```
+//!
+//! Every field but `templates` is optional. Without a manifest the single
+//! `<name>_ref.png` is used with the defaults.
+//!
+//! Without a `popup_pattern` the coordinates are read by the labels of the
+//! client's language: [`POPUP_LOCALES`] lists them, `MERCY_POPUP_LOCALE`
+//! picks one, and by default each is tried in turn.
+
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::detector::{self, RefImage};
+
+/// The target settings of a template manifest, next to its `templates`.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub(crate) struct ProfileManifest {
+    /// Display name (default: the `MERCY_SEARCH_TARGET` it was loaded for)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Threshold of templates without their own (default
+    /// [`detector::MATCH_THRESHOLD`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub popup_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub popup_keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_min_score: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_max_offset_px: Option<u32>,
+}
+
+/// A search target: its name, popup check and verification thresholds. The
+/// templates, with the manifest's match threshold applied, are handed to the
+/// detector separately.
+#[derive(Debug, Clone)]
+pub struct TargetProfile {
+    pub name: String,
+    pub popup: PopupMatcher,
+    /// Replaces `MERCY_VERIFY_MIN_SCORE` for this target
+    pub verify_min_score: Option<f32>,
+    /// Replaces `MERCY_VERIFY_MAX_OFFSET_PX` for this target
+    pub verify_max_offset_px: Option<u32>,
+}
+
+impl TargetProfile {
+    /// The profile with every default: any popup with coordinates confirms.
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            popup: PopupMatcher::default(),
+            verify_min_score: None,
+            verify_max_offset_px: None,
+        }
+    }
+
+    /// Load the profile and templates of `search_target` from the assets
+    /// directory.
+    pub fn load(search_target: &str) -> Result<(Self, Vec<RefImage>)> {
+        let (refs, manifest) = detector::load_target(search_target)?;
+        let profile = Self::from_manifest(search_target, manifest)?;
+        Ok((profile, refs))
+    }
+
+    fn from_manifest(search_target: &str, manifest: ProfileManifest) -> Result<Self> {
+        let pattern = manifest
+            .popup_pattern
+            .as_deref()
+            .map(PopupMatcher::compile)
+            .transpose()?;
+        Ok(Self {
+            name: manifest.name.unwrap_or_else(|| search_target.to_string()),
+            popup: PopupMatcher {
+                pattern,
+                keywords: manifest.popup_keywords,
+                locale: None,
+            },
+            verify_min_score: manifest.verify_min_score,
+            verify_max_offset_px: manifest.verify_max_offset_px,
+        })
+    }
+}
+
+/// Reads a target's coordinates from the text of the popup that opens when
+/// it's clicked.
+#[derive(Debug, Clone, Default)]
+pub struct PopupMatcher {
+    /// Regex with `k`, `x` and `y` groups (default: `K:<n> X:<n> Y:<n>`)
+    pattern: Option<Regex>,
+    /// The popup must contain one of these, ignoring case (none: any popup)
+    keywords: Vec<String>,
+    /// Labels the default pattern reads (none: any of [`POPUP_LOCALES`])
+    locale: Option<PopupLocale>,
+}
+
+impl PopupMatcher {
+    fn compile(pattern: &str) -> Result<Regex> {
+        let regex =
+            Regex::new(pattern).with_context(|| format!("bad popup_pattern {pattern:?}"))?;
+        for group in ["k", "x", "y"] {
+            if !regex.capture_names().any(|n| n == Some(group)) {
+                anyhow::bail!("popup_pattern {pattern:?} has no `{group}` group");
+            }
+        }
+        Ok(regex)
+    }
+
+    /// The matcher reading the coordinate labels of `locale` (None: any).
+    pub fn with_locale(self, locale: Option<PopupLocale>) -> Self {
+        Self { locale, ..self }
+    }
+
+    /// Whether `text` is this target's popup, by its keywords.
+    pub fn names_target(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.keywords.is_empty()
+            || self
+                .keywords
+                .iter()
+                .any(|k| text.contains(&k.to_lowercase()))
+    }
+
+    /// Kingdom and coordinates from `text`, if it's this target's popup.
+    pub fn coords(&self, text: &str) -> Option<(u32, u32, u32)> {
+        if !self.names_target(text) {
+            return None;
+        }
+        let Some(ref pattern) = self.pattern else {
+            return match self.locale {
+                Some(locale) => locale.coords(text),
+                None => parse_popup_coords(text),
+            };
+        };
+        let caps = pattern.captures(text)?;
+        let number = |group: &str| caps.name(group)?.as_str().parse().ok();
+        Some((number("k")?, number("x")?, number("y")?))
+    }
+}
+
+/// The coordinate labels of a game client language, e.g. `K:111 X:506
+/// Y:638` in English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PopupLocale {
+    /// Language code, for `MERCY_POPUP_LOCALE`
+    pub code: &'static str,
+    /// Accepted labels of the kingdom, x and y coordinates
+    kingdom: &'static [&'static str],
+    x: &'static [&'static str],
+    y: &'static [&'static str],
+}
+
+/// Known client languages, tried in this order when none is configured.
+/// Languages labelling the coordinates like English need no entry.
+pub const POPUP_LOCALES: &[PopupLocale] = &[
+    PopupLocale {
+        code: "en",
+        kingdom: &["K"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    // Королевство; X and Y may be typed as the Cyrillic look-alikes Х and У
+    PopupLocale {
+        code: "ru",
+        kingdom: &["К"],
+        x: &["X", "Х"],
+        y: &["Y", "У"],
+    },
+    // Royaume, Reino, Regno
+    PopupLocale {
+        code: "fr",
+        kingdom: &["R"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    PopupLocale {
+        code: "es",
+        kingdom: &["R"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    PopupLocale {
+        code: "pt",
+        kingdom: &["R"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    PopupLocale {
+        code: "it",
+        kingdom: &["R"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    PopupLocale {
+        code: "zh",
+        kingdom: &["王国"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    PopupLocale {
+        code: "ja",
+        kingdom: &["王国"],
+        x: &["X"],
+        y: &["Y"],
+    },
+    PopupLocale {
+        code: "ko",
+        kingdom: &["왕국"],
+        x: &["X"],
+        y: &["Y"],
+    },
+];
+
+impl PopupLocale {
+    /// The locale of a language code like `ru` or `pt-BR`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        let language = code.split(['-', '_']).next()?.to_lowercase();
+        POPUP_LOCALES.iter().find(|l| l.code == language).copied()
+    }
+
+    /// Kingdom and coordinates labelled the way this language does.
+    pub fn coords(&self, text: &str) -> Option<(u32, u32, u32)> {
+        let k = number_after_label(text, self.kingdom)?;
+        let x = number_after_label(text, self.x)?;
+        let y = number_after_label(text, self.y)?;
+        Some((k, x, y))
+    }
+}
+
+/// Extract coordinates from popup text like "(K:111 X:506 Y:638)", in any
+/// of the [`POPUP_LOCALES`].
+pub fn parse_popup_coords(text: &str) -> Option<(u32, u32, u32)> {
+    POPUP_LOCALES.iter().find_map(|locale| locale.coords(text))
+}
+
+/// What else the popup says about the building besides where it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PopupInfo {
+    /// The building's name, as the popup titles it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+    /// Countdown as the popup shows it, e.g. `02:13:45` or `1d 4h`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timer: Option<String>,
+}
+
+/// "Level 3", "Lv. 3", and the word in the other client languages.
+static LEVEL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(?:level|lvl|lv|niveau|nivel|livello|nível|stufe|уровень|ур)\.?\s*:?\s*(\d{1,3})\b",
+    )
+    .expect("valid level pattern")
+});
+
+/// `02:13:45`, `13:45`, `1d 04:13:45`, `1d 4h`, `4h 30m`, `30m 15s`.
+static TIMER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:\d+d\s*)?\d{1,2}:\d{2}(?::\d{2})?\b|\b\d+\s*[dhm](?:\s*\d+\s*[hms])*\b")
+        .expect("valid timer pattern")
+});
+
+/// Name, level and timer from the popup `text`. The name is its first line,
+/// up to the coordinates and without the level and timer.
+pub fn parse_popup_info(text: &str) -> PopupInfo {
+    let level = LEVEL.captures(text).and_then(|caps| caps[1].parse().ok());
+    let timer = TIMER.find(text).map(|m| m.as_str().trim().to_string());
+    let first_line = text.lines().map(str::trim).find(|l| !l.is_empty());
+    let name = first_line.and_then(|line| {
+        let line = line.split('(').next().unwrap_or(line);
+        let line = LEVEL.replace_all(line, "");
+        let line = TIMER.replace_all(&line, "");
+        let name = line
+            .trim()
+            .trim_matches(|c: char| c == '-' || c == ',' || c.is_whitespace());
+        (!name.is_empty()).then(|| name.to_string())
+    });
+    PopupInfo { name, level, timer }
+}
+
+/// The number after the first of `labels` followed by a colon (ASCII or
+/// full-width), with any spaces around the colon.
+fn number_after_label(text: &str, labels: &[&str]) -> Option<u32> {
+    labels.iter().find_map(|label| {
+        text.match_indices(label).find_map(|(idx, _)| {
+            let after = text[idx + label.len()..].trim_start();
+            let after = after
+                .strip_prefix(':')
+                .or_else(|| after.strip_prefix('：'))?
+                .trim_start();
+            let digits: String = after.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse().ok()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_popup_coords() {
+        assert_eq!(
+            parse_popup_coords("Mercenary Exchange (K:111 X:506 Y:638)"),
+            Some((111, 506, 638))
+        );
+        assert_eq!(
+            parse_popup_coords("(K:109 X:100 Y:200)"),
+            Some((109, 100, 200))
+        );
+        assert_eq!(parse_popup_coords("no coords here"), None);
+        assert_eq!(
+            parse_popup_coords("Mercenary Exchange (K: 111 X: 506 Y: 638)"),
+            Some((111, 506, 638))
+        );
+    }
+
+    #[test]
+    fn test_parse_popup_info() {
+        assert_eq!(
+            parse_popup_info(
+                "Mercenary Exchange Lv. 3 (K:111 X:506 Y:638)\nDisappears in 02:13:45"
+            ),
+            PopupInfo {
+                name: Some("Mercenary Exchange".into()),
+                level: Some(3),
+                timer: Some("02:13:45".into()),
+            }
+        );
+        assert_eq!(
+            parse_popup_info("\n  Mercenary Exchange\n  Level 5\n  (K:111 X:506 Y:638)\n  1d 4h"),
+            PopupInfo {
+                name: Some("Mercenary Exchange".into()),
+                level: Some(5),
+                timer: Some("1d 4h".into()),
+            }
+        );
+        assert_eq!(
+            parse_popup_info("Биржа наёмников, Уровень 2 (К:111 Х:506 У:638)"),
+            PopupInfo {
+                name: Some("Биржа наёмников".into()),
+                level: Some(2),
+                timer: None,
+            }
+        );
+        // Coordinates alone say nothing else
+        assert_eq!(
+            parse_popup_info("(K:111 X:506 Y:638)"),
+            PopupInfo::default()
+        );
+    }
+
+    #[test]
+    fn test_popup_locales() {
+        let cases = [
+            ("en", "Mercenary Exchange (K:111 X:506 Y:638)"),
+            ("ru", "Биржа наёмников (К:111 Х:506 У:638)"),
+            ("ru", "Биржа наёмников (К: 111 X: 506 Y: 638)"),
+            ("fr", "Bourse aux mercenaires (R:111 X:506 Y:638)"),
+            ("es", "Intercambio de mercenarios (R:111 X:506 Y:638)"),
+            ("pt", "Troca de mercenários (R:111 X:506 Y:638)"),
+            ("it", "Scambio di mercenari (R:111 X:506 Y:638)"),
+            ("zh", "雇佣兵交易所 (王国：111 X：506 Y：638)"),
+            ("ja", "傭兵取引所 (王国:111 X:506 Y:638)"),
+            ("ko", "용병 거래소 (왕국:111 X:506 Y:638)"),
+        ];
+        for (code, text) in cases {
+            let locale = PopupLocale::from_code(code).unwrap();
+            assert_eq!(locale.coords(text), Some((111, 506, 638)), "{code}: {text}");
+            // Auto-detection finds it without being told the language
+            assert_eq!(parse_popup_coords(text), Some((111, 506, 638)), "{text}");
+        }
+
+        assert_eq!(PopupLocale::from_code("pt-BR").unwrap().code, "pt");
+        assert_eq!(PopupLocale::from_code("xx"), None);
+        // A configured locale doesn't read other languages' labels
+        let en = PopupLocale::from_code("en").unwrap();
+        assert_eq!(
+            en.coords("Bourse aux mercenaires (R:111 X:506 Y:638)"),
+            None
+        );
+        let matcher = TargetProfile::named("Exchange").popup.with_locale(Some(en));
+        assert_eq!(matcher.coords("(R:111 X:506 Y:638)"), None);
+        assert_eq!(matcher.coords("(K:111 X:506 Y:638)"), Some((111, 506, 638)));
+    }
+
+    #[test]
+    fn test_profile_manifest() {
+        let manifest: ProfileManifest = serde_json::from_str(
+            r#"{"name": "Crypt", "threshold": 0.95,
+                "popup_keywords": ["crypt"],
+                "popup_pattern": "\\[(?<k>\\d+)/(?<x>\\d+)/(?<y>\\d+)\\]"}"#,
+        )
+        .unwrap();
+        let profile = TargetProfile::from_manifest("Epic Crypt", manifest).unwrap();
+        assert_eq!(profile.name, "Crypt");
+        assert_eq!(
+            profile.popup.coords("Epic Crypt [110/5/600]"),
+            Some((110, 5, 600))
+        );
+        assert_eq!(profile.popup.coords("Citadel [110/5/600]"), None);
+        assert_eq!(profile.popup.coords("Crypt (K:110 X:5 Y:600)"), None);
+
+        let default = TargetProfile::named("Mercenary Exchange");
+        assert_eq!(
+            default.popup.coords("Anything (K:110 X:5 Y:600)"),
+            Some((110, 5, 600))
+        );
+
+        let missing_group = ProfileManifest {
+            popup_pattern: Some(r"K:(?<k>\d+) X:(?<x>\d+)".into()),
+            ..ProfileManifest::default()
+        };
+        assert!(TargetProfile::from_manifest("Crypt", missing_group).is_err());
+    }
+}
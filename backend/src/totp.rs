@@ -0,0 +1,93 @@
+//! Time-based one-time passwords (RFC 6238) for accounts with two-factor
+//! authentication, computed from the base32 secret behind the
+//! authenticator-app QR code (`MERCY_TB_TOTP_SECRET`).
+
+use data_encoding::BASE32_NOPAD;
+use sha1::{Digest, Sha1};
+
+/// Seconds each code is valid for.
+const TIME_STEP: u64 = 30;
+
+/// Digits in a code.
+const DIGITS: u32 = 6;
+
+/// SHA-1 block size, for HMAC key padding.
+const BLOCK_SIZE: usize = 64;
+
+/// Decode a base32 secret as shown by authenticator setup pages: case,
+/// spaces, dashes and `=` padding are ignored.
+pub fn decode_secret(secret: &str) -> Result<Vec<u8>, String> {
+    let cleaned: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if cleaned.is_empty() {
+        return Err("empty TOTP secret".into());
+    }
+    BASE32_NOPAD
+        .decode(cleaned.as_bytes())
+        .map_err(|e| format!("invalid base32 TOTP secret: {e}"))
+}
+
+/// The code for `key` at `unix_time` (seconds), zero-padded.
+pub fn totp(key: &[u8], unix_time: u64) -> String {
+    let counter = unix_time / TIME_STEP;
+    let mac = hmac_sha1(key, &counter.to_be_bytes());
+    // Dynamic truncation (RFC 4226 §5.3)
+    let offset = (mac[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        mac[offset],
+        mac[offset + 1],
+        mac[offset + 2],
+        mac[offset + 3],
+    ]) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Seconds until the code at `unix_time` expires.
+pub fn seconds_remaining(unix_time: u64) -> u64 {
+    TIME_STEP - unix_time % TIME_STEP
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha1::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha1::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp_rfc6238_vectors() {
+        // RFC 6238 appendix B (SHA-1), last 6 of the 8 published digits
+        let key = b"12345678901234567890";
+        assert_eq!(totp(key, 59), "287082");
+        assert_eq!(totp(key, 1111111109), "081804");
+        assert_eq!(totp(key, 1234567890), "005924");
+        assert_eq!(totp(key, 2000000000), "279037");
+        assert_eq!(totp(key, 20000000000), "353130");
+
+        let decoded = decode_secret("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(decoded, key);
+        assert!(decode_secret("not base32!").is_err());
+        assert_eq!(seconds_remaining(59), 1);
+    }
+}
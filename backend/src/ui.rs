@@ -0,0 +1,261 @@
+//! Fixed UI click points: the MAP button, the zoom-out button and the
+//! coordinate search icon.
+//!
+//! Points default to positions measured on the 1920×1080 window and can be
+//! overridden with `MERCY_UI_*` when a game update moves them. If crops of
+//! the elements (`ui_map_button.png`, `ui_zoom_out.png`, `ui_search.png`)
+//! are in the assets directory, login checks that each one is actually
+//! under its click point and logs where it went if not.
+
+use image::{DynamicImage, GrayImage};
+use imageproc::template_matching::{MatchTemplateMethod, find_extremes, match_template};
+
+use crate::detector::asset_search_dirs;
+
+/// Minimum NCC for a UI element to count as present.
+const UI_THRESHOLD: f32 = 0.9;
+
+/// How far (px) from its click point an element is searched for.
+const SEARCH_RADIUS: u32 = 80;
+
+/// An element found more than this far (px) from its point has moved.
+const MOVED_TOLERANCE: f64 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UiElement {
+    MapButton,
+    ZoomOut,
+    Search,
+}
+
+impl UiElement {
+    pub const ALL: [UiElement; 3] = [UiElement::MapButton, UiElement::ZoomOut, UiElement::Search];
+
+    /// Template file stem and log name.
+    pub fn name(self) -> &'static str {
+        match self {
+            UiElement::MapButton => "map_button",
+            UiElement::ZoomOut => "zoom_out",
+            UiElement::Search => "search",
+        }
+    }
+
+    /// Environment variable overriding the click point.
+    pub fn env_var(self) -> &'static str {
+        match self {
+            UiElement::MapButton => "MERCY_UI_MAP_BUTTON",
+            UiElement::ZoomOut => "MERCY_UI_ZOOM_OUT",
+            UiElement::Search => "MERCY_UI_SEARCH",
+        }
+    }
+}
+
+/// A click point in window pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl std::str::FromStr for UiPoint {
+    type Err = String;
+
+    /// Parse `"x,y"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s
+            .split_once(',')
+            .ok_or_else(|| format!("expected x,y: {s}"))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| format!("invalid coordinate: {v}"))
+        };
+        Ok(UiPoint {
+            x: parse(x)?,
+            y: parse(y)?,
+        })
+    }
+}
+
+impl std::fmt::Display for UiPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+/// Click points for every [`UiElement`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiPoints {
+    pub map_button: UiPoint,
+    pub zoom_out: UiPoint,
+    pub search: UiPoint,
+}
+
+impl Default for UiPoints {
+    /// Points for the 1920×1080 window with the current game UI.
+    fn default() -> Self {
+        Self {
+            map_button: UiPoint {
+                x: 680.0,
+                y: 1045.0,
+            },
+            zoom_out: UiPoint {
+                x: 1818.0,
+                y: 1025.0,
+            },
+            search: UiPoint { x: 83.0, y: 865.0 },
+        }
+    }
+}
+
+impl UiPoints {
+    pub fn get(&self, element: UiElement) -> UiPoint {
+        match element {
+            UiElement::MapButton => self.map_button,
+            UiElement::ZoomOut => self.zoom_out,
+            UiElement::Search => self.search,
+        }
+    }
+
+    pub fn set(&mut self, element: UiElement, point: UiPoint) {
+        match element {
+            UiElement::MapButton => self.map_button = point,
+            UiElement::ZoomOut => self.zoom_out = point,
+            UiElement::Search => self.search = point,
+        }
+    }
+}
+
+/// A crop of a UI element, centred on its click point.
+pub struct UiTemplate {
+    pub element: UiElement,
+    pub image: GrayImage,
+}
+
+/// Load the `ui_<element>.png` crops from the first asset directory that
+/// has any.
+pub fn load_ui_templates() -> Vec<UiTemplate> {
+    for dir in asset_search_dirs() {
+        let templates: Vec<_> = UiElement::ALL
+            .into_iter()
+            .filter_map(|element| {
+                let path = dir.join(format!("ui_{}.png", element.name()));
+                if !path.exists() {
+                    return None;
+                }
+                match image::open(&path) {
+                    Ok(img) => Some(UiTemplate {
+                        element,
+                        image: img.to_luma8(),
+                    }),
+                    Err(e) => {
+                        tracing::warn!("failed to load UI template {}: {e}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect();
+        if !templates.is_empty() {
+            tracing::info!(
+                "loaded {} UI element template(s) from {}",
+                templates.len(),
+                dir.display()
+            );
+            return templates;
+        }
+    }
+    Vec::new()
+}
+
+/// Outcome of looking for a UI element around its click point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UiCheck {
+    /// Under the click point.
+    Found { score: f32 },
+    /// Nearby but off the click point; `at` is where its centre is now.
+    Moved { at: UiPoint, score: f32 },
+    /// Not within [`SEARCH_RADIUS`] of the click point.
+    Missing { best: f32 },
+}
+
+/// Search for `template` within [`SEARCH_RADIUS`] of `point`.
+pub fn check_ui_point(screenshot: &DynamicImage, point: UiPoint, template: &GrayImage) -> UiCheck {
+    let (tw, th) = template.dimensions();
+    let (sw, sh) = (screenshot.width(), screenshot.height());
+    let x0 = (point.x as u32).saturating_sub(tw / 2 + SEARCH_RADIUS);
+    let y0 = (point.y as u32).saturating_sub(th / 2 + SEARCH_RADIUS);
+    let x1 = (point.x as u32 + tw.div_ceil(2) + SEARCH_RADIUS).min(sw);
+    let y1 = (point.y as u32 + th.div_ceil(2) + SEARCH_RADIUS).min(sh);
+    if x1 < x0 + tw || y1 < y0 + th {
+        return UiCheck::Missing { best: 0.0 };
+    }
+
+    let window = screenshot.crop_imm(x0, y0, x1 - x0, y1 - y0).to_luma8();
+    let result = match_template(
+        &window,
+        template,
+        MatchTemplateMethod::CrossCorrelationNormalized,
+    );
+    let extremes = find_extremes(&result);
+    let score = extremes.max_value;
+    if score < UI_THRESHOLD {
+        return UiCheck::Missing { best: score };
+    }
+
+    let (mx, my) = extremes.max_value_location;
+    let at = UiPoint {
+        x: (x0 + mx) as f64 + tw as f64 / 2.0,
+        y: (y0 + my) as f64 + th as f64 / 2.0,
+    };
+    if (at.x - point.x).hypot(at.y - point.y) > MOVED_TOLERANCE {
+        UiCheck::Moved { at, score }
+    } else {
+        UiCheck::Found { score }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn test_check_ui_point() {
+        let button = GrayImage::from_fn(30, 20, |x, y| Luma([((x * 9 + y * 17) % 230) as u8]));
+        let mut frame = RgbImage::from_pixel(400, 300, Rgb([50, 50, 50]));
+        let button_rgb = DynamicImage::ImageLuma8(button.clone()).to_rgb8();
+        // Centred on (200, 150)
+        image::imageops::replace(&mut frame, &button_rgb, 185, 140);
+        let frame = DynamicImage::ImageRgb8(frame);
+
+        let at = |x, y| UiPoint { x, y };
+        assert!(matches!(
+            check_ui_point(&frame, at(200.0, 150.0), &button),
+            UiCheck::Found { .. }
+        ));
+        match check_ui_point(&frame, at(160.0, 130.0), &button) {
+            UiCheck::Moved { at, .. } => assert_eq!((at.x, at.y), (200.0, 150.0)),
+            other => panic!("expected Moved, got {other:?}"),
+        }
+        assert!(matches!(
+            check_ui_point(&frame, at(20.0, 280.0), &button),
+            UiCheck::Missing { .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_ui_point() {
+        assert_eq!(
+            "680, 1045".parse(),
+            Ok(UiPoint {
+                x: 680.0,
+                y: 1045.0
+            })
+        );
+        assert!("680".parse::<UiPoint>().is_err());
+        assert!("-1,5".parse::<UiPoint>().is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Game viewport bounds: the part of the screenshot showing the map, without
+//! the minimap, top bar, bottom toolbar and right panel.
+//!
+//! Bounds default to values measured on the 1920×1080 window, or to
+//! `MERCY_VIEWPORT` (`left,top,right,bottom`) for other sizes. At session
+//! start they can be re-detected from UI anchors: small crops of the UI
+//! listed in `ui_anchors.json` in the assets directory, e.g.
+//!
+//! ```json
+//! {"anchors": [
+//!     {"file": "ui_minimap_edge.png", "bound": "left"},
+//!     {"file": "ui_top_bar.png", "bound": "top"}
+//! ]}
+//! ```
+//!
+//! Each anchor is cropped so that its edge facing the map *is* the viewport
+//! boundary: a `left` anchor's right edge, a `top` anchor's bottom edge, a
+//! `right` anchor's left edge, a `bottom` anchor's top edge.
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, GrayImage};
+use imageproc::template_matching::{MatchTemplateMethod, find_extremes, match_template};
+use serde::{Deserialize, Serialize};
+
+use crate::detector::asset_search_dirs;
+
+/// Minimum NCC for an anchor to count as found.
+const ANCHOR_THRESHOLD: f32 = 0.9;
+
+/// Smallest plausible viewport; anything smaller means an anchor matched in
+/// the wrong place.
+const MIN_VIEWPORT_SIZE: u32 = 200;
+
+/// Viewport rectangle in screenshot pixels (`right`/`bottom` exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Viewport {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl Default for Viewport {
+    /// Bounds for the 1920×1080 window with the current game UI.
+    fn default() -> Self {
+        Self {
+            left: 160,
+            top: 60,
+            right: 1860,
+            bottom: 1000,
+        }
+    }
+}
+
+impl std::str::FromStr for Viewport {
+    type Err = String;
+
+    /// `left,top,right,bottom` in screenshot pixels.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>().map_err(|e| format!("{v}: {e}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [left, top, right, bottom] = values[..] else {
+            return Err(format!("expected left,top,right,bottom, got {s}"));
+        };
+        if right <= left || bottom <= top {
+            return Err(format!("empty viewport: {s}"));
+        }
+        Ok(Self {
+            left,
+            top,
+            right,
+            bottom,
+        })
+    }
+}
+
+impl Viewport {
+    pub fn width(&self) -> u32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> u32 {
+        self.bottom - self.top
+    }
+
+    /// Clamp to a `width`×`height` screenshot, so a smaller window never
+    /// produces an out-of-range crop.
+    pub fn clamped(self, width: u32, height: u32) -> Self {
+        let right = self.right.min(width);
+        let bottom = self.bottom.min(height);
+        Self {
+            left: self.left.min(right),
+            top: self.top.min(bottom),
+            right,
+            bottom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bound {
+    Left,
+    Top,
+    Right,
+    Bottom,
+}
+
+/// A UI crop whose position in the screenshot fixes one viewport bound.
+pub struct UiAnchor {
+    pub name: String,
+    pub image: GrayImage,
+    pub bound: Bound,
+}
+
+#[derive(Deserialize)]
+struct AnchorManifest {
+    anchors: Vec<AnchorEntry>,
+}
+
+#[derive(Deserialize)]
+struct AnchorEntry {
+    file: String,
+    bound: Bound,
+}
+
+/// Load the anchors listed in the first `ui_anchors.json` on the asset search
+/// path. No manifest means no anchors (the default viewport is used).
+pub fn load_ui_anchors() -> Result<Vec<UiAnchor>> {
+    let Some(path) = asset_search_dirs()
+        .into_iter()
+        .map(|d| d.join("ui_anchors.json"))
+        .find(|p| p.exists())
+    else {
+        return Ok(Vec::new());
+    };
+
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let manifest: AnchorManifest = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    let dir = path.parent().unwrap_or(std::path::Path::new("."));
+
+    let mut anchors = Vec::new();
+    for entry in manifest.anchors {
+        let file = dir.join(&entry.file);
+        match image::open(&file) {
+            Ok(img) => anchors.push(UiAnchor {
+                name: entry.file,
+                image: img.to_luma8(),
+                bound: entry.bound,
+            }),
+            Err(e) => tracing::warn!("failed to load UI anchor {}: {e}", file.display()),
+        }
+    }
+    tracing::info!(
+        "loaded {} UI anchor(s) from {}",
+        anchors.len(),
+        path.display()
+    );
+    Ok(anchors)
+}
+
+/// Locate the UI anchors in a screenshot and derive the viewport from them.
+/// Bounds whose anchor isn't found keep their `default` value; if the result
+/// is implausible the default viewport is returned.
+pub fn detect_viewport(
+    screenshot: &DynamicImage,
+    anchors: &[UiAnchor],
+    default: Viewport,
+) -> Viewport {
+    let (sw, sh) = (screenshot.width(), screenshot.height());
+    let default = default.clamped(sw, sh);
+    if anchors.is_empty() {
+        return default;
+    }
+
+    let gray = screenshot.to_luma8();
+    let mut viewport = default;
+    for anchor in anchors {
+        let (aw, ah) = anchor.image.dimensions();
+        // Only search the third of the screen where this bound's UI lives
+        let (rx, ry, rw, rh) = match anchor.bound {
+            Bound::Left => (0, 0, sw / 3, sh),
+            Bound::Right => (sw - sw / 3, 0, sw / 3, sh),
+            Bound::Top => (0, 0, sw, sh / 3),
+            Bound::Bottom => (0, sh - sh / 3, sw, sh / 3),
+        };
+        if aw >= rw || ah >= rh {
+            tracing::warn!("UI anchor {} larger than its search region", anchor.name);
+            continue;
+        }
+
+        let region = image::imageops::crop_imm(&gray, rx, ry, rw, rh).to_image();
+        let result = match_template(
+            &region,
+            &anchor.image,
+            MatchTemplateMethod::CrossCorrelationNormalized,
+        );
+        let extremes = find_extremes(&result);
+        let (x, y) = extremes.max_value_location;
+        let (x, y) = (x + rx, y + ry);
+        if extremes.max_value < ANCHOR_THRESHOLD {
+            tracing::warn!(
+                "UI anchor {} not found (best={:.4}), keeping default {:?} bound",
+                anchor.name,
+                extremes.max_value,
+                anchor.bound
+            );
+            continue;
+        }
+
+        tracing::info!(
+            "UI anchor {} at ({x}, {y}) score={:.4}",
+            anchor.name,
+            extremes.max_value
+        );
+        match anchor.bound {
+            Bound::Left => viewport.left = x + aw,
+            Bound::Top => viewport.top = y + ah,
+            Bound::Right => viewport.right = x,
+            Bound::Bottom => viewport.bottom = y,
+        }
+    }
+
+    if viewport.right < viewport.left + MIN_VIEWPORT_SIZE
+        || viewport.bottom < viewport.top + MIN_VIEWPORT_SIZE
+    {
+        tracing::warn!("detected viewport {viewport:?} is implausible, using default");
+        return default;
+    }
+    viewport
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, RgbImage};
+
+    use super::*;
+
+    /// Deterministic textured block standing in for a piece of UI.
+    fn ui_block(seed: u32) -> GrayImage {
+        GrayImage::from_fn(24, 16, |x, y| {
+            Luma([((x * 37 + y * 91 + seed * 53) % 251) as u8])
+        })
+    }
+
+    #[test]
+    fn test_detect_viewport_from_anchors() {
+        let mut frame = RgbImage::from_pixel(600, 400, image::Rgb([90, 120, 60]));
+        let left = ui_block(1);
+        let top = ui_block(2);
+        let place = |frame: &mut RgbImage, block: &GrayImage, x: i64, y: i64| {
+            let rgb = DynamicImage::ImageLuma8(block.clone()).to_rgb8();
+            image::imageops::replace(frame, &rgb, x, y);
+        };
+        place(&mut frame, &left, 100, 200);
+        place(&mut frame, &top, 300, 40);
+
+        let anchors = [
+            UiAnchor {
+                name: "left".into(),
+                image: left,
+                bound: Bound::Left,
+            },
+            UiAnchor {
+                name: "top".into(),
+                image: top,
+                bound: Bound::Top,
+            },
+        ];
+        let viewport = detect_viewport(
+            &DynamicImage::ImageRgb8(frame),
+            &anchors,
+            Viewport::default(),
+        );
+        assert_eq!(
+            viewport,
+            Viewport {
+                left: 124,
+                top: 56,
+                right: 600,
+                bottom: 400,
+            },
+            "found bounds move, missing ones keep the (clamped) default"
+        );
+    }
+
+    #[test]
+    fn test_parse_viewport() {
+        assert_eq!(
+            "100, 40, 1180, 680".parse::<Viewport>(),
+            Ok(Viewport {
+                left: 100,
+                top: 40,
+                right: 1180,
+                bottom: 680,
+            })
+        );
+        assert!("100,40,1180".parse::<Viewport>().is_err());
+        assert!("500,40,100,680".parse::<Viewport>().is_err());
+    }
+}
@@ -7,6 +7,7 @@ import ExchangeList from '@/components/ExchangeList';
 import ScreenshotView from '@/components/ScreenshotView';
 import GotoForm from '@/components/GotoForm';
 import ManualScanForm from '@/components/ManualScanForm';
+import TwoFactorForm from '@/components/TwoFactorForm';
 import { proxyGet } from '@/lib/api';
 import type { StatusResponse, Exchange } from '@/lib/api';
 
@@ -36,6 +37,7 @@ export default function DashboardPage() {
   return (
     <div className="space-y-6">
       <StatusPanel status={status} />
+      {status?.phase === 'waiting_for_2fa' && <TwoFactorForm onAction={fetchData} />}
       <ActionButtons status={status} onAction={fetchData} />
       <div className="grid gap-6 lg:grid-cols-2">
         <ExchangeList exchanges={exchanges} />
@@ -1,12 +1,28 @@
 'use client';
 
 import { Card, CardHeader, CardTitle, CardContent } from '@/components/ui/Card';
-import type { Exchange } from '@/lib/api';
+import type { Exchange, ExchangeStatus } from '@/lib/api';
+
+const STATUS_LABELS: Record<ExchangeStatus, string> = {
+  candidate: 'Estimate',
+  confirmed: 'Confirmed',
+  verified: 'Verified',
+  gone: 'Gone',
+  expired: 'Expired',
+};
+
+const STATUS_STYLES: Record<ExchangeStatus, string> = {
+  candidate: 'bg-yellow-900/40 text-yellow-400',
+  confirmed: 'bg-green-900/40 text-green-400',
+  verified: 'bg-green-900/40 text-green-400',
+  gone: 'bg-muted text-muted-foreground',
+  expired: 'bg-muted text-muted-foreground',
+};
 
 export default function ExchangeList({ exchanges }: { exchanges: Exchange[] }) {
-  function downloadScreenshot(index: number, ex: Exchange) {
+  function downloadScreenshot(ex: Exchange) {
     const a = document.createElement('a');
-    a.href = `/api/proxy/exchanges/${index}/screenshot`;
+    a.href = `/api/proxy/exchanges/${ex.id}/screenshot`;
     a.download = `exchange_k${ex.kingdom}_${ex.x}_${ex.y}.png`;
     a.click();
   }
@@ -36,10 +52,10 @@ export default function ExchangeList({ exchanges }: { exchanges: Exchange[] }) {
                 </tr>
               </thead>
               <tbody>
-                {exchanges.map((ex, i) => (
+                {exchanges.map((ex) => (
                   <tr
-                    key={i}
-                    className={`border-b border-border/50 last:border-0${!ex.confirmed ? ' opacity-60' : ''}`}
+                    key={ex.id}
+                    className={`border-b border-border/50 last:border-0${ex.status !== 'confirmed' && ex.status !== 'verified' ? ' opacity-60' : ''}`}
                   >
                     <td className="py-2 pr-4">
                       <button
@@ -52,15 +68,9 @@ export default function ExchangeList({ exchanges }: { exchanges: Exchange[] }) {
                       </button>
                     </td>
                     <td className="py-2 pr-4">
-                      {ex.confirmed ? (
-                        <span className="inline-block rounded bg-green-900/40 px-1.5 py-0.5 text-xs text-green-400">
-                          Confirmed
-                        </span>
-                      ) : (
-                        <span className="inline-block rounded bg-yellow-900/40 px-1.5 py-0.5 text-xs text-yellow-400">
-                          Estimate
-                        </span>
-                      )}
+                      <span className={`inline-block rounded px-1.5 py-0.5 text-xs ${STATUS_STYLES[ex.status]}`}>
+                        {STATUS_LABELS[ex.status]}
+                      </span>
                     </td>
                     <td className="py-2 pr-4 text-muted-foreground">
                       {ex.scan_duration_secs != null
@@ -73,7 +83,7 @@ export default function ExchangeList({ exchanges }: { exchanges: Exchange[] }) {
                     <td className="py-2">
                       <button
                         type="button"
-                        onClick={() => downloadScreenshot(i, ex)}
+                        onClick={() => downloadScreenshot(ex)}
                         className="text-xs text-primary hover:underline"
                       >
                         Screenshot
@@ -22,6 +22,7 @@ export default function ManualScanForm({
   const disabled =
     !status ||
     status.phase === 'preparing' ||
+    status.phase === 'waiting_for_2fa' ||
     status.manual_scan_kingdom != null;
 
   async function handleSubmit(e: React.FormEvent) {
@@ -7,6 +7,7 @@ import type { StatusResponse } from '@/lib/api';
 const phaseVariant: Record<string, 'default' | 'secondary' | 'success' | 'warning' | 'destructive'> = {
   idle: 'secondary',
   preparing: 'warning',
+  waiting_for_2fa: 'destructive',
   ready: 'default',
   scanning: 'success',
   paused: 'warning',
@@ -0,0 +1,69 @@
+'use client';
+
+import { useState } from 'react';
+import { Button } from '@/components/ui/Button';
+import { Input } from '@/components/ui/Input';
+import { Label } from '@/components/ui/Label';
+import { Card, CardHeader, CardTitle, CardContent } from '@/components/ui/Card';
+import { proxyPostJson } from '@/lib/api';
+
+export default function TwoFactorForm({ onAction }: { onAction: () => void }) {
+  const [code, setCode] = useState('');
+  const [loading, setLoading] = useState(false);
+  const [error, setError] = useState<string | null>(null);
+
+  async function handleSubmit(e: React.FormEvent) {
+    e.preventDefault();
+    if (!code) return;
+    setLoading(true);
+    setError(null);
+    try {
+      const res = await proxyPostJson('login/2fa', { code });
+      if (res.ok) {
+        setCode('');
+        onAction();
+      } else if (res.status === 400) {
+        setError('The code should be digits only');
+      } else if (res.status === 409) {
+        setError('Login is no longer waiting for a code');
+      } else {
+        setError(`Error: ${res.status}`);
+      }
+    } catch {
+      setError('Request failed');
+    } finally {
+      setLoading(false);
+    }
+  }
+
+  return (
+    <Card>
+      <CardHeader>
+        <CardTitle>Two-factor code</CardTitle>
+      </CardHeader>
+      <CardContent>
+        <form onSubmit={handleSubmit} className="flex items-end gap-3">
+          <div className="space-y-1">
+            <Label htmlFor="two-factor-code">Code from your authenticator</Label>
+            <Input
+              id="two-factor-code"
+              value={code}
+              onChange={(e) => setCode(e.target.value)}
+              inputMode="numeric"
+              autoComplete="one-time-code"
+              placeholder="123456"
+            />
+          </div>
+          <Button type="submit" loading={loading}>
+            Submit
+          </Button>
+        </form>
+        {error && (
+          <div className="mt-3 rounded-lg border border-destructive/50 bg-destructive/10 p-3 text-sm text-destructive">
+            {error}
+          </div>
+        )}
+      </CardContent>
+    </Card>
+  );
+}
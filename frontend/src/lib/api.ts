@@ -18,7 +18,7 @@ export async function proxyPostJson(path: string, body: unknown): Promise<Respon
 }
 
 export interface StatusResponse {
-  phase: 'idle' | 'preparing' | 'ready' | 'scanning' | 'paused';
+  phase: 'idle' | 'preparing' | 'waiting_for_2fa' | 'ready' | 'scanning' | 'paused';
   running: boolean;
   paused: boolean;
   current_kingdom: number | null;
@@ -26,11 +26,17 @@ export interface StatusResponse {
   manual_scan_kingdom: number | null;
 }
 
+export type ExchangeStatus = 'candidate' | 'confirmed' | 'verified' | 'gone' | 'expired';
+
 export interface Exchange {
+  id: number;
   kingdom: number;
   x: number;
   y: number;
+  status: ExchangeStatus;
   found_at: string;
+  confirmed_at: string | null;
+  verified_at: string | null;
+  ended_at: string | null;
   scan_duration_secs: number | null;
-  confirmed: boolean;
 }
@@ -13,6 +13,15 @@ let
     export MERCY_AUTH_TOKEN="$(cat ${cfg.authTokenFile})"
     export MERCY_TB_EMAIL="$(cat ${cfg.tbEmailFile})"
     export MERCY_TB_PASSWORD="$(cat ${cfg.tbPasswordFile})"
+    ${lib.optionalString (cfg.adminTokenFile != null) ''
+      export MERCY_ADMIN_TOKEN="$(cat ${cfg.adminTokenFile})"
+    ''}
+    ${lib.optionalString (cfg.pushoverTokenFile != null) ''
+      export MERCY_PUSHOVER_TOKEN="$(cat ${cfg.pushoverTokenFile})"
+    ''}
+    ${lib.optionalString (cfg.mqttPasswordFile != null) ''
+      export MERCY_MQTT_PASSWORD="$(cat ${cfg.mqttPasswordFile})"
+    ''}
     exec ${pkgs.xvfb-run}/bin/xvfb-run -s '-screen 0 1920x1080x24' ${cfg.backendPackage}/bin/mercy
   '';
 
@@ -62,6 +71,24 @@ in
       description = "File containing the API auth token (shared by backend and frontend)";
     };
 
+    adminTokenFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.path;
+      default = null;
+      description = "File containing the backend admin token for manual browser control (POST /control); disabled when null";
+    };
+
+    pushoverTokenFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.path;
+      default = null;
+      description = "File containing the Pushover application token for push notifications; MERCY_PUSHOVER_USER goes in extraEnvironment";
+    };
+
+    mqttPasswordFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.path;
+      default = null;
+      description = "File containing the MQTT broker password; MERCY_MQTT_HOST and MERCY_MQTT_USER go in extraEnvironment";
+    };
+
     tbEmailFile = lib.mkOption {
       type = lib.types.path;
       description = "File containing Total Battle login email";
@@ -129,6 +156,15 @@ in
       description = "Path to exchange detection JSONL log file";
     };
 
+    extraEnvironment = lib.mkOption {
+      type = lib.types.attrsOf lib.types.str;
+      default = { };
+      example = {
+        MERCY_PHASH_MAX_DISTANCE = "10";
+      };
+      description = "Additional MERCY_* environment variables for the backend (tuning options without a dedicated module option)";
+    };
+
     chromiumPackage = lib.mkOption {
       type = lib.types.package;
       default = pkgs.chromium;
@@ -136,6 +172,13 @@ in
       description = "Chromium package to use for headless browsing";
     };
 
+    cdpUrl = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "ws://chrome.internal:9222/devtools/browser/<id>";
+      description = "DevTools URL of a remote Chrome to attach to instead of launching chromiumPackage locally (ws://... or http://host:port)";
+    };
+
     domain = lib.mkOption {
       type = lib.types.nullOr lib.types.str;
       default = null;
@@ -165,6 +208,14 @@ in
       after = [ "network-online.target" ];
       wants = [ "network-online.target" ];
       wantedBy = [ "multi-user.target" ];
+      # Push notifications (MERCY_NTFY_TOPIC, Pushover) are sent with curl,
+      # MQTT with the mosquitto clients; MERCY_COORDS_REGION is read with
+      # tesseract
+      path = [
+        pkgs.curl
+        pkgs.mosquitto
+        pkgs.tesseract
+      ];
 
       environment = {
         MERCY_KINGDOMS = cfg.kingdoms;
@@ -174,12 +225,21 @@ in
         MERCY_NAVIGATE_DELAY_MS = toString cfg.navigateDelayMs;
         MERCY_SCAN_PATTERN = cfg.scanPattern;
         MERCY_EXCHANGE_LOG = cfg.exchangeLog;
+        MERCY_AUDIT_LOG = "/var/lib/mercy/audit.jsonl";
+        MERCY_SCREENSHOT_DIR = "/var/lib/mercy/screenshots";
+        MERCY_SCAN_TIMES = "/var/lib/mercy/last_scans.json";
+        # Outside PrivateTmp, so a manual run with the same account is refused too
+        MERCY_LOCK_DIR = "/var/lib/mercy";
         MERCY_KNOWN_COVERAGE = toString cfg.knownCoverage;
         MERCY_MAX_DETECT_TASKS = toString cfg.maxDetectTasks;
       }
       // lib.optionalAttrs (cfg.scanRings != null) {
         MERCY_SCAN_RINGS = toString cfg.scanRings;
-      };
+      }
+      // lib.optionalAttrs (cfg.cdpUrl != null) {
+        MERCY_CDP_URL = cfg.cdpUrl;
+      }
+      // cfg.extraEnvironment;
 
       serviceConfig = {
         Type = "simple";